@@ -1,49 +1,82 @@
-pub use crate::api::callback::get_callback;
+pub use crate::api::{
+    callback::{get_callback, get_command, get_command_names},
+    compression::decompress,
+    image_handle::call_load_callback,
+};
 use crate::{
     api::{
-        callback::{get_custom_callback, set_custom_callback, set_main_object},
-        clipboard::{copy, paste},
-        compression::{deflate, inflate},
+        callback::{get_custom_callback, register_command, set_custom_callback, set_main_object},
+        clipboard::{copy, copy_primary, get_clipboard_sequence, paste, paste_primary},
+        compression::{deflate, inflate, new_deflate_stream, new_inflate_stream},
+        config::{get_rusty_option, set_rusty_option},
         console::{console_clear, console_execute, console_print_table, console_printf},
+        crypto::{hmac_sha256, md5, sha1, sha256},
+        download_handle::{get_rate_limit_status, is_online, new_download},
+        encoding::{base64_decode, base64_encode, url_decode, url_encode},
+        fs::{copy_file, file_exists, read_file, remove_file, write_file},
         image_handle::new_image_handle,
-        input::{get_cursor_pos, is_key_down},
+        input::{get_cursor_pos, is_key_down, set_cursor_capture},
         lua::{load_module, protected_call, protected_load_module},
+        oauth::{is_oauth_listening, start_oauth_listener, stop_oauth_listener},
         paths::{
-            get_runtime_path, get_script_path, get_user_path, get_work_dir, make_dir, remove_dir,
-            set_work_dir,
+            get_runtime_path, get_script_path, get_user_path, get_work_dir, make_dir, move_path,
+            open_folder, remove_dir, rename_path, set_work_dir, unwatch_path, watch_path,
         },
+        process::spawn_process,
         rendering::PoBString,
         search_handle::new_search_handle,
+        storage::{Storage, store_delete, store_get, store_iterate, store_set},
+        utf8::{utf8_len, utf8_lower, utf8_sub, utf8_upper},
         window::{
-            get_dpi_scale_override, get_screen_scale, get_screen_size, set_dpi_scale_override,
-            set_foreground, set_window_title,
+            capture_region, export_svg, get_dpi_scale_override, get_frame_time, get_refresh_rate,
+            get_screen_scale, get_screen_size, get_system_theme, set_animation_hint,
+            set_dpi_scale_override, set_foreground, set_minimum_window_size, set_needs_redraw,
+            set_taskbar_progress, set_window_title,
         },
+        xml::{compose_xml, parse_xml},
     },
+    args::{Game, launch_args},
+    dpi::LogicalSize,
     lua::Context,
 };
-use mlua::{IntoLuaMulti, Lua, MultiValue, Result as LuaResult, Variadic};
-use std::time::{SystemTime, UNIX_EPOCH};
+use mlua::{IntoLuaMulti, Lua, MultiValue, Result as LuaResult, Table, Variadic};
+use std::{
+    sync::LazyLock,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
 
 mod callback;
 mod clipboard;
 mod compression;
+mod config;
 mod console;
+mod crypto;
+mod download_handle;
+mod encoding;
+mod fs;
 mod image_handle;
 mod input;
 mod lua;
+mod oauth;
 mod paths;
+mod process;
 mod rendering;
 mod search_handle;
+mod stack;
+mod storage;
+mod utf8;
 mod window;
+mod xml;
 
 /// Register functions that can be called from lua
-pub fn register_globals(lua: &Lua) -> LuaResult<()> {
+pub fn register_globals(lua: &Lua, game: Game) -> LuaResult<()> {
     let globals = lua.globals();
 
     // callbacks
     globals.set("SetMainObject", lua.create_function(set_main_object)?)?;
     globals.set("SetCallback", lua.create_function(set_custom_callback)?)?;
     globals.set("GetCallback", lua.create_function(get_custom_callback)?)?;
+    globals.set("RegisterCommand", lua.create_function(register_command)?)?;
 
     // paths
     globals.set("GetUserPath", lua.create_function(get_user_path)?)?;
@@ -53,6 +86,18 @@ pub fn register_globals(lua: &Lua) -> LuaResult<()> {
     globals.set("SetWorkDir", lua.create_function(set_work_dir)?)?;
     globals.set("MakeDir", lua.create_function(make_dir)?)?;
     globals.set("RemoveDir", lua.create_function(remove_dir)?)?;
+    globals.set("MovePath", lua.create_function(move_path)?)?;
+    globals.set("RenamePath", lua.create_function(rename_path)?)?;
+    globals.set("WatchPath", lua.create_function(watch_path)?)?;
+    globals.set("UnwatchPath", lua.create_function(unwatch_path)?)?;
+    globals.set("OpenFolder", lua.create_function(open_folder)?)?;
+
+    // files
+    globals.set("ReadFile", lua.create_function(read_file)?)?;
+    globals.set("WriteFile", lua.create_function(write_file)?)?;
+    globals.set("RemoveFile", lua.create_function(remove_file)?)?;
+    globals.set("CopyFile", lua.create_function(copy_file)?)?;
+    globals.set("FileExists", lua.create_function(file_exists)?)?;
 
     // console
     globals.set("ConPrintf", lua.create_function(console_printf)?)?;
@@ -62,10 +107,12 @@ pub fn register_globals(lua: &Lua) -> LuaResult<()> {
 
     // general
     globals.set("GetTime", lua.create_function(get_time)?)?;
+    globals.set("GetTimeUS", lua.create_function(get_time_us)?)?;
     globals.set("StripEscapes", lua.create_function(strip_escapes)?)?;
     globals.set("Exit", lua.create_function(exit)?)?;
     globals.set("Restart", lua.create_function(restart)?)?;
     globals.set("OpenURL", lua.create_function(open_url)?)?;
+    globals.set("SpawnProcess", lua.create_function(spawn_process)?)?;
     globals.set("RenderInit", lua.create_function(render_init)?)?;
 
     let take_screenshot = |_: &Lua, ()| -> LuaResult<()> { Ok(()) }; // stub
@@ -74,6 +121,48 @@ pub fn register_globals(lua: &Lua) -> LuaResult<()> {
     // compression
     globals.set("Inflate", lua.create_function(inflate)?)?;
     globals.set("Deflate", lua.create_function(deflate)?)?;
+    globals.set("NewInflateStream", lua.create_function(new_inflate_stream)?)?;
+    globals.set("NewDeflateStream", lua.create_function(new_deflate_stream)?)?;
+
+    // hashing
+    globals.set("Sha1", lua.create_function(sha1)?)?;
+    globals.set("Sha256", lua.create_function(sha256)?)?;
+    globals.set("Md5", lua.create_function(md5)?)?;
+    globals.set("HmacSha256", lua.create_function(hmac_sha256)?)?;
+
+    // utf-8 strings
+    globals.set("Utf8Len", lua.create_function(utf8_len)?)?;
+    globals.set("Utf8Sub", lua.create_function(utf8_sub)?)?;
+    globals.set("Utf8Lower", lua.create_function(utf8_lower)?)?;
+    globals.set("Utf8Upper", lua.create_function(utf8_upper)?)?;
+
+    // encoding
+    globals.set("Base64Encode", lua.create_function(base64_encode)?)?;
+    globals.set("Base64Decode", lua.create_function(base64_decode)?)?;
+    globals.set("UrlEncode", lua.create_function(url_encode)?)?;
+    globals.set("UrlDecode", lua.create_function(url_decode)?)?;
+
+    // xml
+    globals.set("ParseXML", lua.create_function(parse_xml)?)?;
+    globals.set("ComposeXML", lua.create_function(compose_xml)?)?;
+
+    // oauth
+    globals.set(
+        "StartOAuthListener",
+        lua.create_function(start_oauth_listener)?,
+    )?;
+    globals.set(
+        "StopOAuthListener",
+        lua.create_function(stop_oauth_listener)?,
+    )?;
+    globals.set("IsOAuthListening", lua.create_function(is_oauth_listening)?)?;
+
+    // storage
+    lua.set_app_data(Storage::new(game));
+    globals.set("StoreGet", lua.create_function(store_get)?)?;
+    globals.set("StoreSet", lua.create_function(store_set)?)?;
+    globals.set("StoreDelete", lua.create_function(store_delete)?)?;
+    globals.set("StoreIterate", lua.create_function(store_iterate)?)?;
 
     // search handle
     globals.set("NewFileSearch", lua.create_function(new_search_handle)?)?;
@@ -81,19 +170,53 @@ pub fn register_globals(lua: &Lua) -> LuaResult<()> {
     // image handle
     globals.set("NewImageHandle", lua.create_function(new_image_handle)?)?;
 
+    // download handle
+    globals.set("NewDownload", lua.create_function(new_download)?)?;
+    globals.set(
+        "GetRateLimitStatus",
+        lua.create_function(get_rate_limit_status)?,
+    )?;
+    globals.set("IsOnline", lua.create_function(is_online)?)?;
+
     // clipboard
     globals.set("Copy", lua.create_function(copy)?)?;
     globals.set("Paste", lua.create_function(paste)?)?;
+    globals.set("CopyPrimary", lua.create_function(copy_primary)?)?;
+    globals.set("PastePrimary", lua.create_function(paste_primary)?)?;
+    globals.set(
+        "GetClipboardSequence",
+        lua.create_function(get_clipboard_sequence)?,
+    )?;
+
+    // app options
+    globals.set("GetRustyOption", lua.create_function(get_rusty_option)?)?;
+    globals.set("SetRustyOption", lua.create_function(set_rusty_option)?)?;
 
     // input
     globals.set("GetCursorPos", lua.create_function(get_cursor_pos)?)?;
     globals.set("IsKeyDown", lua.create_function(is_key_down)?)?;
+    globals.set("SetCursorCapture", lua.create_function(set_cursor_capture)?)?;
 
     // window
     globals.set("GetScreenSize", lua.create_function(get_screen_size)?)?;
     globals.set("GetScreenScale", lua.create_function(get_screen_scale)?)?;
+    globals.set("GetSystemTheme", lua.create_function(get_system_theme)?)?;
     globals.set("SetWindowTitle", lua.create_function(set_window_title)?)?;
+    globals.set("CaptureRegion", lua.create_function(capture_region)?)?;
+    globals.set("ExportSVG", lua.create_function(export_svg)?)?;
+    globals.set(
+        "SetTaskbarProgress",
+        lua.create_function(set_taskbar_progress)?,
+    )?;
+    globals.set(
+        "SetMinimumWindowSize",
+        lua.create_function(set_minimum_window_size)?,
+    )?;
     globals.set("SetForeground", lua.create_function(set_foreground)?)?;
+    globals.set("GetFrameTime", lua.create_function(get_frame_time)?)?;
+    globals.set("GetRefreshRate", lua.create_function(get_refresh_rate)?)?;
+    globals.set("SetNeedsRedraw", lua.create_function(set_needs_redraw)?)?;
+    globals.set("SetAnimationHint", lua.create_function(set_animation_hint)?)?;
     globals.set(
         "SetDPIScaleOverridePercent",
         lua.create_function(set_dpi_scale_override)?,
@@ -120,11 +243,26 @@ pub fn register_globals(lua: &Lua) -> LuaResult<()> {
     Ok(())
 }
 
-fn get_time(_l: &Lua, _: ()) -> LuaResult<u128> {
-    Ok(SystemTime::now()
+/// Monotonic clock and the wall-clock time at which it started. Timestamps are
+/// derived from the elapsed time so that clock adjustments don't make them jump.
+static CLOCK_START: LazyLock<(Instant, u128)> = LazyLock::new(|| {
+    let epoch_millis = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
-        .as_millis())
+        .as_millis();
+    (Instant::now(), epoch_millis)
+});
+
+/// Milliseconds since the Unix epoch
+fn get_time(_l: &Lua, _: ()) -> LuaResult<u128> {
+    let (start, epoch_millis) = *CLOCK_START;
+    Ok(epoch_millis + start.elapsed().as_millis())
+}
+
+/// Microseconds of a monotonic clock, for measuring elapsed time
+fn get_time_us(_l: &Lua, _: ()) -> LuaResult<u128> {
+    let (start, _) = *CLOCK_START;
+    Ok(start.elapsed().as_micros())
 }
 
 fn strip_escapes(_: &Lua, text: String) -> LuaResult<String> {
@@ -153,12 +291,41 @@ fn open_url(l: &Lua, url: String) -> LuaResult<MultiValue> {
     }
 }
 
-fn render_init(l: &Lua, features: Variadic<String>) -> LuaResult<()> {
+/// Smallest window size in logical pixels at which PoB's UI is still usable.
+/// Applied by `RenderInit`, PoB can change it with `SetMinimumWindowSize`.
+const DEFAULT_MIN_WINDOW_SIZE: LogicalSize<u32> = LogicalSize::new(800, 600);
+
+/// Whether linear blending was turned on or off with `--linear-blending` or the
+/// config, e.g. through the settings overlay.
+fn is_linear_blending_set(ctx: &Context) -> bool {
+    launch_args().is_some_and(|args| args.linear_blending.is_some())
+        || ctx
+            .config()
+            .get_bool(crate::config::LINEAR_BLENDING)
+            .is_some()
+}
+
+/// Enables the requested features and returns a table of the features that are
+/// supported, e.g. `{ DPI_AWARE = true, COLOR_MANAGED = false }`
+fn render_init(l: &Lua, features: Variadic<String>) -> LuaResult<Table> {
     let ctx = l.app_data_ref::<&'static Context>().unwrap();
     for feature in features {
-        if feature == "DPI_AWARE" {
-            *ctx.is_dpi_aware() = true;
+        match feature.as_str() {
+            "DPI_AWARE" => *ctx.is_dpi_aware() = true,
+            // the linear blending option of the user takes precedence, so that
+            // PoB's blending in sRGB space can still be compared
+            "COLOR_MANAGED" if !is_linear_blending_set(*ctx) => {
+                ctx.graphics_options().linear_blending = true;
+            }
+            "COLOR_MANAGED" => {}
+            _ => log::warn!("Unknown RenderInit feature: {feature}"),
         }
     }
-    Ok(())
+
+    ctx.window().set_min_size(Some(DEFAULT_MIN_WINDOW_SIZE));
+
+    let supported = l.create_table()?;
+    supported.set("DPI_AWARE", true)?;
+    supported.set("COLOR_MANAGED", ctx.graphics_capabilities().linear_blending)?;
+    Ok(supported)
 }
@@ -42,3 +42,36 @@ pub fn get_custom_callback(l: &Lua, name: String) -> LuaResult<Function> {
     let callback_function: Function = callback_table.get(name)?;
     Ok(callback_function)
 }
+
+// Commands registered with `RegisterCommand` are listed in the command palette.
+const COMMAND_REGISTRY_NAME: &str = "commands";
+
+fn command_table(l: &Lua) -> LuaResult<Table> {
+    match l.named_registry_value::<Option<Table>>(COMMAND_REGISTRY_NAME)? {
+        Some(command_table) => Ok(command_table),
+        None => {
+            let command_table = l.create_table()?;
+            l.set_named_registry_value(COMMAND_REGISTRY_NAME, &command_table)?;
+            Ok(command_table)
+        }
+    }
+}
+
+/// Registers a command. Passing `nil` as callback removes the command.
+pub fn register_command(l: &Lua, (name, func): (String, Option<Function>)) -> LuaResult<()> {
+    command_table(l)?.set(name, func)
+}
+
+/// Names of all registered commands in alphabetical order.
+pub fn get_command_names(l: &Lua) -> LuaResult<Vec<String>> {
+    let mut names = command_table(l)?
+        .pairs::<String, Function>()
+        .map(|pair| pair.map(|(name, _)| name))
+        .collect::<LuaResult<Vec<_>>>()?;
+    names.sort();
+    Ok(names)
+}
+
+pub fn get_command(l: &Lua, name: &str) -> LuaResult<Function> {
+    command_table(l)?.get(name)
+}
@@ -7,7 +7,30 @@ pub fn copy(l: &Lua, text: String) -> LuaResult<()> {
     Ok(())
 }
 
+/// Number that changes whenever the clipboard content changes. The first call
+/// starts watching the clipboard, after which `OnClipboardChanged(sequence)` is
+/// called on changes.
+pub fn get_clipboard_sequence(l: &Lua, _: ()) -> LuaResult<u64> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    let display = ctx.window().raw_display_handle();
+    Ok(ctx.clipboard_watcher().sequence(display))
+}
+
 pub fn paste(l: &Lua, _: ()) -> LuaResult<Option<String>> {
     let ctx = l.app_data_ref::<&'static Context>().unwrap();
     Ok(ctx.window().get_clipboard_text())
 }
+
+/// Like `Copy`, but sets the primary selection on Linux, e.g. for selected text
+pub fn copy_primary(l: &Lua, text: String) -> LuaResult<()> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    ctx.window().set_primary_selection_text(text);
+    Ok(())
+}
+
+/// Like `Paste`, but reads the primary selection, which is pasted with the
+/// middle mouse button on Linux. Returns nil on other platforms.
+pub fn paste_primary(l: &Lua, _: ()) -> LuaResult<Option<String>> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    Ok(ctx.window().get_primary_selection_text())
+}
@@ -1,50 +1,337 @@
 use flate2::{
     Compression,
-    read::{ZlibDecoder, ZlibEncoder},
+    read::{DeflateDecoder, DeflateEncoder, GzDecoder, GzEncoder, ZlibDecoder, ZlibEncoder},
+    write,
 };
-use mlua::{IntoLuaMulti, Lua, MultiValue, Result as LuaResult, String as LuaString, Value};
-use std::io::Read;
+use mlua::{
+    IntoLuaMulti, Lua, MultiValue, Result as LuaResult, String as LuaString, UserData, Value,
+};
+use std::io::{self, Read, Write};
+
+/// Larger inputs are rejected by `Inflate` and `Deflate`. Streams take any input.
+const MAX_INPUT_SIZE: usize = 128 << 20;
+/// Decompressing or compressing more than this fails, so that small inputs can't
+/// exhaust the memory
+const MAX_OUTPUT_SIZE: usize = 256 << 20;
+/// Streams pass their input to the codec in chunks of this size and check the
+/// output size in between
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Container around deflate compressed data
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    Zlib,
+    Gzip,
+    /// Deflate stream without header or checksum
+    Raw,
+}
+
+impl Format {
+    /// Parses the optional format argument. `None` means the format is detected.
+    fn parse(name: Option<&str>) -> Result<Option<Self>, String> {
+        match name {
+            None | Some("auto") => Ok(None),
+            Some("zlib") => Ok(Some(Format::Zlib)),
+            Some("gzip") => Ok(Some(Format::Gzip)),
+            Some("raw") => Ok(Some(Format::Raw)),
+            Some(name) => Err(format!(
+                "Unknown format {name}, expected zlib, gzip, raw or auto"
+            )),
+        }
+    }
+
+    /// Detects the format from the first two bytes of compressed data.
+    fn detect(data: &[u8]) -> Self {
+        match data {
+            [0x1f, 0x8b, ..] => Format::Gzip,
+            // compression method 8 (deflate) and a valid header checksum
+            [cmf, flg, ..]
+                if cmf & 0x0f == 8 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0 =>
+            {
+                Format::Zlib
+            }
+            _ => Format::Raw,
+        }
+    }
+
+    fn decoder<'a>(&self, compressed: &'a [u8]) -> Box<dyn Read + 'a> {
+        match self {
+            Format::Zlib => Box::new(ZlibDecoder::new(compressed)),
+            Format::Gzip => Box::new(GzDecoder::new(compressed)),
+            Format::Raw => Box::new(DeflateDecoder::new(compressed)),
+        }
+    }
+
+    /// Fails if the output is larger than `MAX_OUTPUT_SIZE`.
+    fn decompress(&self, compressed: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decompressed = Vec::new();
+        self.decoder(compressed)
+            .take(MAX_OUTPUT_SIZE as u64 + 1)
+            .read_to_end(&mut decompressed)?;
+        if decompressed.len() > MAX_OUTPUT_SIZE {
+            return Err(io::Error::other("Output larger than 256 MiB"));
+        }
+        Ok(decompressed)
+    }
+
+    fn encoder<'a>(&self, uncompressed: &'a [u8]) -> Box<dyn Read + 'a> {
+        match self {
+            Format::Zlib => Box::new(ZlibEncoder::new(uncompressed, Compression::fast())),
+            Format::Gzip => Box::new(GzEncoder::new(uncompressed, Compression::fast())),
+            Format::Raw => Box::new(DeflateEncoder::new(uncompressed, Compression::fast())),
+        }
+    }
+}
 
-pub fn inflate(l: &Lua, compressed: LuaString) -> LuaResult<MultiValue> {
+/// Decompresses zlib, gzip or raw deflate data like `Inflate`, e.g. build codes.
+pub fn decompress(compressed: &[u8]) -> io::Result<Vec<u8>> {
+    Format::detect(compressed).decompress(compressed)
+}
+
+fn error(l: &Lua, message: impl ToString) -> LuaResult<MultiValue> {
+    (Value::Nil, message.to_string()).into_lua_multi(l)
+}
+
+/// Decompresses zlib, gzip or raw deflate data. The format is detected unless given.
+pub fn inflate(
+    l: &Lua,
+    (compressed, format): (LuaString, Option<String>),
+) -> LuaResult<MultiValue> {
     let compressed_bytes = &compressed.as_bytes()[..];
 
     // prevent decompression of input larger than 128MiB
-    if compressed_bytes.len() > (128 << 20) {
-        return Ok((Value::Nil, "Input larger than 128 MiB")
-            .into_lua_multi(l)
-            .unwrap());
+    if compressed_bytes.len() > MAX_INPUT_SIZE {
+        return error(l, "Input larger than 128 MiB");
     }
 
-    let mut decoder = ZlibDecoder::new(compressed_bytes);
-    let mut decompressed = Vec::new();
-    match decoder.read_to_end(&mut decompressed) {
-        Ok(_) => Ok(l
-            .create_string(&decompressed)
-            .unwrap()
-            .into_lua_multi(l)
-            .unwrap()),
-        Err(e) => Ok((Value::Nil, e.to_string()).into_lua_multi(l).unwrap()),
+    let format = match Format::parse(format.as_deref()) {
+        Ok(format) => format.unwrap_or_else(|| Format::detect(compressed_bytes)),
+        Err(e) => return error(l, e),
+    };
+
+    match format.decompress(compressed_bytes) {
+        Ok(decompressed) => l.create_string(&decompressed)?.into_lua_multi(l),
+        Err(e) => error(l, e),
     }
 }
 
-pub fn deflate(l: &Lua, uncompressed: LuaString) -> LuaResult<MultiValue> {
+/// Compresses data as zlib unless another format is given.
+pub fn deflate(
+    l: &Lua,
+    (uncompressed, format): (LuaString, Option<String>),
+) -> LuaResult<MultiValue> {
     let uncompressed_bytes = &uncompressed.as_bytes()[..];
 
     // prevent compression of input larger than 128MiB
-    if uncompressed_bytes.len() > (128 << 20) {
-        return Ok((Value::Nil, "Input larger than 128 MiB")
-            .into_lua_multi(l)
-            .unwrap());
+    if uncompressed_bytes.len() > MAX_INPUT_SIZE {
+        return error(l, "Input larger than 128 MiB");
     }
 
-    let mut encoder = ZlibEncoder::new(uncompressed_bytes, Compression::fast());
+    let format = match Format::parse(format.as_deref()) {
+        Ok(format) => format.unwrap_or(Format::Zlib),
+        Err(e) => return error(l, e),
+    };
+
     let mut compressed = Vec::new();
-    match encoder.read_to_end(&mut compressed) {
-        Ok(_) => Ok(l
-            .create_string(&compressed)
-            .unwrap()
-            .into_lua_multi(l)
-            .unwrap()),
-        Err(e) => Ok((Value::Nil, e.to_string()).into_lua_multi(l).unwrap()),
+    match format
+        .encoder(uncompressed_bytes)
+        .read_to_end(&mut compressed)
+    {
+        Ok(_) => l.create_string(&compressed)?.into_lua_multi(l),
+        Err(e) => error(l, e),
+    }
+}
+
+pub fn new_inflate_stream(l: &Lua, format: Option<String>) -> LuaResult<MultiValue> {
+    match Format::parse(format.as_deref()) {
+        Ok(format) => CompressionStream::new(Direction::Inflate, format).into_lua_multi(l),
+        Err(e) => error(l, e),
+    }
+}
+
+pub fn new_deflate_stream(l: &Lua, format: Option<String>) -> LuaResult<MultiValue> {
+    match Format::parse(format.as_deref()) {
+        Ok(format) => {
+            let format = format.unwrap_or(Format::Zlib);
+            CompressionStream::new(Direction::Deflate, Some(format)).into_lua_multi(l)
+        }
+        Err(e) => error(l, e),
+    }
+}
+
+/// Encoder or decoder that writes its output into a buffer.
+trait Codec: Write {
+    fn output(&mut self) -> &mut Vec<u8>;
+    fn finish_stream(&mut self) -> io::Result<()>;
+}
+
+macro_rules! impl_codec {
+    ($($codec:ident),*) => {
+        $(
+            impl Codec for write::$codec<Vec<u8>> {
+                fn output(&mut self) -> &mut Vec<u8> {
+                    self.get_mut()
+                }
+
+                fn finish_stream(&mut self) -> io::Result<()> {
+                    self.try_finish()
+                }
+            }
+        )*
+    };
+}
+
+impl_codec!(
+    ZlibDecoder,
+    ZlibEncoder,
+    GzDecoder,
+    GzEncoder,
+    DeflateDecoder,
+    DeflateEncoder
+);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Direction {
+    Inflate,
+    Deflate,
+}
+
+/// Compresses or decompresses data chunk by chunk, so that neither the whole
+/// input nor the whole output needs to be in memory at once.
+pub struct CompressionStream {
+    direction: Direction,
+    format: Option<Format>,
+    codec: Option<Box<dyn Codec>>,
+    // input held back until there is enough of it to detect the format
+    pending: Vec<u8>,
+    // total size of the output, which is limited to `MAX_OUTPUT_SIZE`
+    output_size: usize,
+    is_finished: bool,
+}
+
+impl CompressionStream {
+    fn new(direction: Direction, format: Option<Format>) -> Self {
+        Self {
+            direction,
+            format,
+            codec: None,
+            pending: Vec::new(),
+            output_size: 0,
+            is_finished: false,
+        }
+    }
+
+    fn create_codec(&self, format: Format) -> Box<dyn Codec> {
+        let output = Vec::new();
+        let level = Compression::fast();
+        match (self.direction, format) {
+            (Direction::Inflate, Format::Zlib) => Box::new(write::ZlibDecoder::new(output)),
+            (Direction::Inflate, Format::Gzip) => Box::new(write::GzDecoder::new(output)),
+            (Direction::Inflate, Format::Raw) => Box::new(write::DeflateDecoder::new(output)),
+            (Direction::Deflate, Format::Zlib) => Box::new(write::ZlibEncoder::new(output, level)),
+            (Direction::Deflate, Format::Gzip) => Box::new(write::GzEncoder::new(output, level)),
+            (Direction::Deflate, Format::Raw) => {
+                Box::new(write::DeflateEncoder::new(output, level))
+            }
+        }
+    }
+
+    /// Returns the codec once the format is known, i.e. `None` if more input is
+    /// needed for detection and `at_end` is false.
+    fn codec(&mut self, at_end: bool) -> io::Result<Option<&mut Box<dyn Codec>>> {
+        if self.codec.is_none() {
+            let format = match self.format {
+                Some(format) => format,
+                None if self.pending.len() >= 2 || at_end => Format::detect(&self.pending),
+                None => return Ok(None),
+            };
+            let mut codec = self.create_codec(format);
+            codec.write_all(&std::mem::take(&mut self.pending))?;
+            self.codec = Some(codec);
+        }
+        Ok(self.codec.as_mut())
+    }
+
+    fn write(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        if self.is_finished {
+            return Err(io::Error::other("Stream is already finished"));
+        }
+        let mut output = Vec::new();
+        for chunk in chunk.chunks(STREAM_CHUNK_SIZE) {
+            match self.codec {
+                Some(ref mut codec) => codec.write_all(chunk)?,
+                None => self.pending.extend_from_slice(chunk),
+            }
+            if let Some(codec) = self.codec(false)? {
+                output.append(codec.output());
+            }
+            self.check_output_size(output.len())?;
+        }
+        self.output_size += output.len();
+        Ok(output)
+    }
+
+    /// Fails and finishes the stream if `len` more bytes of output exceed the limit.
+    fn check_output_size(&mut self, len: usize) -> io::Result<()> {
+        if self.output_size + len > MAX_OUTPUT_SIZE {
+            self.is_finished = true;
+            return Err(io::Error::other("Output larger than 256 MiB"));
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<Vec<u8>> {
+        if self.is_finished {
+            return Err(io::Error::other("Stream is already finished"));
+        }
+        self.is_finished = true;
+        let codec = self.codec(true)?.unwrap();
+        codec.finish_stream()?;
+        let output = std::mem::take(codec.output());
+        self.check_output_size(output.len())?;
+        self.output_size += output.len();
+        Ok(output)
+    }
+}
+
+impl UserData for CompressionStream {
+    fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
+        // returns the output produced so far, or nil and an error message
+        methods.add_method_mut("Write", |l, this, chunk: LuaString| {
+            match this.write(&chunk.as_bytes()) {
+                Ok(output) => l.create_string(&output)?.into_lua_multi(l),
+                Err(e) => error(l, e),
+            }
+        });
+        // returns the remaining output, or nil and an error message
+        methods.add_method_mut("Finish", |l, this, ()| match this.finish() {
+            Ok(output) => l.create_string(&output)?.into_lua_multi(l),
+            Err(e) => error(l, e),
+        });
+        methods.add_method("IsFinished", |_, this, ()| Ok(this.is_finished));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_roundtrip() {
+        let data = b"<PathOfBuilding>".repeat(1000);
+        for format in [Format::Zlib, Format::Gzip, Format::Raw] {
+            let mut compressed = Vec::new();
+            format.encoder(&data).read_to_end(&mut compressed).unwrap();
+            assert_eq!(Format::detect(&compressed), format);
+
+            // single byte chunks exercise format detection across writes
+            let mut stream = CompressionStream::new(Direction::Inflate, None);
+            let mut decompressed = Vec::new();
+            for chunk in compressed.chunks(1) {
+                decompressed.extend(stream.write(chunk).unwrap());
+            }
+            decompressed.extend(stream.finish().unwrap());
+            assert_eq!(decompressed, data);
+        }
     }
 }
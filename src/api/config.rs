@@ -0,0 +1,62 @@
+use crate::{config, lua::Context};
+use mlua::{IntoLuaMulti, Lua, MultiValue, Result as LuaResult, Value};
+
+/// Value of one of the app's own options, e.g. `GetRustyOption("render_scale")`.
+/// nil if it isn't saved.
+pub fn get_rusty_option(l: &Lua, name: String) -> LuaResult<Value> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    let value = match ctx.config().get(&name) {
+        Some(toml::Value::Boolean(value)) => Value::Boolean(*value),
+        Some(toml::Value::Integer(value)) => Value::Integer(*value),
+        Some(toml::Value::Float(value)) => Value::Number(*value),
+        Some(toml::Value::String(value)) => Value::String(l.create_string(value)?),
+        _ => Value::Nil,
+    };
+    Ok(value)
+}
+
+/// Saves one of the app's own options and applies it right away. Returns true,
+/// or nil and an error message if the option is unknown or the value invalid.
+pub fn set_rusty_option(l: &Lua, (name, value): (String, Value)) -> LuaResult<MultiValue> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    let value = match value {
+        Value::Boolean(value) => toml::Value::Boolean(value),
+        Value::Integer(value) => toml::Value::Integer(value),
+        Value::Number(value) => toml::Value::Float(value),
+        Value::String(value) => toml::Value::String(value.to_str()?.to_owned()),
+        value => {
+            let error = format!("Unsupported value type {}", value.type_name());
+            return (Value::Nil, error).into_lua_multi(l);
+        }
+    };
+    if let Err(err) = ctx.config().set(&name, value) {
+        return (Value::Nil, err.to_string()).into_lua_multi(l);
+    }
+
+    let config = ctx.config();
+    match name.as_str() {
+        config::RENDER_SCALE => {
+            ctx.window().render_scale = config.get_f32(&name).unwrap_or(1.0);
+        }
+        config::INTEGER_SCALING => {
+            ctx.window().integer_scaling = config.get_bool(&name).unwrap_or(false);
+        }
+        config::POWER_SAVING => {
+            ctx.window().power_saving = config.get_bool(&name).unwrap_or(false);
+        }
+        config::MSAA => {
+            ctx.graphics_options().msaa_sample_count = config.get_u32(&name).unwrap_or(1);
+        }
+        config::HDR => ctx.graphics_options().hdr = config.get_bool(&name).unwrap_or(false),
+        config::LINEAR_BLENDING => {
+            ctx.graphics_options().linear_blending = config.get_bool(&name).unwrap_or(false);
+        }
+        config::VSYNC => ctx.graphics_options().vsync = config.get_bool(&name).unwrap_or(true),
+        config::DPI_SCALE_OVERRIDE => ctx.window().load_scale_factor_override(config),
+        config::PREMULTIPLIED_ALPHA => ctx
+            .texture_manager()
+            .set_premultiply_alpha(config.get_bool(&name).unwrap_or(false)),
+        _ => {}
+    }
+    true.into_lua_multi(l)
+}
@@ -1,56 +1,127 @@
+use crate::{api::rendering::PoBString, log_file, lua::Context};
 use mlua::{Function, Lua, MultiValue, Result as LuaResult, Table, Value};
-use std::io::{Write, stdout};
+use std::fmt::Write;
+
+/// Commands of `ConExecute` and their descriptions
+const COMMANDS: [(&str, &str); 8] = [
+    ("help", "List the available commands"),
+    (
+        "set <name> <value>",
+        "Set a variable, e.g. vid_mode. Ignored",
+    ),
+    ("console", "Show or hide the console overlay (Ctrl+Shift+L)"),
+    ("clear", "Clear the console overlay"),
+    (
+        "perf_overlay",
+        "Show or hide the performance overlay (Ctrl+Shift+F)",
+    ),
+    ("reload", "Restart PoB's Lua code"),
+    (
+        "reload_textures",
+        "Reload all textures from their image files",
+    ),
+    ("dump_textures", "List the allocated textures"),
+];
 
 pub fn console_printf(l: &Lua, (fmt, args): (String, MultiValue)) -> LuaResult<()> {
     // uses lua's builtin string.format function
     let string_module: Table = l.globals().get("string")?;
     let format_func: Function = string_module.get("format")?;
     let formatted_string = format_func.call::<String>((fmt, args))?;
-    println!("{formatted_string}");
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    ctx.console()
+        .print(&PoBString(&formatted_string).strip_escapes());
     Ok(())
 }
 
-pub fn console_execute(_l: &Lua, _cmd: String) -> LuaResult<()> {
+pub fn console_execute(l: &Lua, cmd: String) -> LuaResult<()> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    let mut words = cmd.split_whitespace();
+    match words.next() {
+        None => {}
+        Some("help") => {
+            let mut text = String::from("Commands:");
+            for (command, description) in COMMANDS {
+                let _ = write!(text, "\n  {command:<20} {description}");
+            }
+            if let Some(path) = log_file::path() {
+                let _ = write!(text, "\nLog file: {}", path.display());
+            }
+            ctx.console().print(&text);
+        }
+        // PoB sets the window mode and whether it's resizable on startup, which
+        // the app decides itself
+        Some("set") => log::debug!("Ignoring ConExecute: {cmd}"),
+        Some("console") => ctx.console().toggle(),
+        Some("clear") => ctx.console().clear(),
+        Some("perf_overlay") => ctx.perf_overlay().toggle(),
+        Some("reload") => *ctx.needs_restart() = true,
+        Some("reload_textures") => {
+            ctx.fonts().invalidate_font_atlas();
+            let count = ctx.texture_manager().reload_textures();
+            ctx.console().print(&format!("Reloading {count} textures"));
+        }
+        Some("dump_textures") => {
+            let textures = ctx.texture_manager().textures();
+            let mut text = format!("{} textures:", textures.len());
+            for (id, meta_data) in textures {
+                let [width, height] = meta_data.size;
+                let _ = write!(
+                    text,
+                    "\n  {id:>5} {width:>5}x{height:<5} {:>8.1} KiB  {}",
+                    meta_data.gpu_bytes as f64 / 1024.0,
+                    meta_data.source_path().unwrap_or(&meta_data.name),
+                );
+            }
+            ctx.console().print(&text);
+        }
+        Some(command) => {
+            log::warn!("Unknown ConExecute command: {cmd}");
+            ctx.console()
+                .print(&format!("Unknown command {command}, see `help`"));
+        }
+    }
     Ok(())
 }
 
-pub fn console_clear(_l: &Lua, _: ()) -> LuaResult<()> {
+pub fn console_clear(l: &Lua, _: ()) -> LuaResult<()> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    ctx.console().clear();
     Ok(())
 }
 
-pub fn console_print_table(
-    _l: &Lua,
-    (table, no_recursive): (Table, Option<bool>),
-) -> LuaResult<()> {
-    print_table(&table, 0, !no_recursive.unwrap_or(true))?;
+pub fn console_print_table(l: &Lua, (table, no_recursive): (Table, Option<bool>)) -> LuaResult<()> {
+    let mut text = String::new();
+    print_table(&mut text, &table, 0, !no_recursive.unwrap_or(true))?;
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    ctx.console().print(text.trim_end());
     Ok(())
 }
 
-fn print_table(table: &Table, indent: usize, recursive: bool) -> LuaResult<()> {
-    let mut lock = stdout().lock();
-    writeln!(lock, "{{")?;
+fn print_table(out: &mut String, table: &Table, indent: usize, recursive: bool) -> LuaResult<()> {
+    let _ = writeln!(out, "{{");
     for pair in table.pairs::<Value, Value>() {
         let inner_ind = indent + 2;
         let (key, value) = pair?;
 
         if key.is_string() {
-            write!(lock, "{0:>1$}\"{2}\" = ", "", inner_ind, key.to_string()?,)?;
+            let _ = write!(out, "{0:>1$}\"{2}\" = ", "", inner_ind, key.to_string()?);
         } else {
-            write!(lock, "{0:>1$}{2} = ", "", inner_ind, key.to_string()?,)?;
+            let _ = write!(out, "{0:>1$}{2} = ", "", inner_ind, key.to_string()?);
         }
 
         if value.is_table() {
             if recursive {
-                print_table(value.as_table().unwrap(), indent + 2, recursive)?;
+                print_table(out, value.as_table().unwrap(), indent + 2, recursive)?;
             } else {
-                writeln!(lock, "{}", value.to_string()?)?;
+                let _ = writeln!(out, "{}", value.to_string()?);
             }
         } else if value.is_string() {
-            writeln!(lock, "\"{}\"", value.to_string()?)?;
+            let _ = writeln!(out, "\"{}\"", value.to_string()?);
         } else {
-            writeln!(lock, "{}", value.to_string()?)?;
+            let _ = writeln!(out, "{}", value.to_string()?);
         }
     }
-    writeln!(lock, "{0:>1$}}}", "", indent)?;
+    let _ = writeln!(out, "{0:>1$}}}", "", indent);
     Ok(())
 }
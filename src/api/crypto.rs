@@ -0,0 +1,88 @@
+//! Hash functions. Digests are returned hex encoded unless `raw` is true, in which
+//! case the bytes are returned as is, e.g. for base64 encoding a PKCE challenge.
+
+use mlua::{Lua, Result as LuaResult, String as LuaString};
+use ring::{
+    digest::{SHA1_FOR_LEGACY_USE_ONLY, SHA256, digest},
+    hmac,
+};
+
+use crate::util::to_hex;
+
+fn to_lua_digest(l: &Lua, digest: &[u8], raw: Option<bool>) -> LuaResult<LuaString> {
+    if raw.unwrap_or(false) {
+        return l.create_string(digest);
+    }
+    l.create_string(to_hex(digest))
+}
+
+pub fn sha1(l: &Lua, (data, raw): (LuaString, Option<bool>)) -> LuaResult<LuaString> {
+    let digest = digest(&SHA1_FOR_LEGACY_USE_ONLY, &data.as_bytes());
+    to_lua_digest(l, digest.as_ref(), raw)
+}
+
+pub fn sha256(l: &Lua, (data, raw): (LuaString, Option<bool>)) -> LuaResult<LuaString> {
+    let digest = digest(&SHA256, &data.as_bytes());
+    to_lua_digest(l, digest.as_ref(), raw)
+}
+
+pub fn md5(l: &Lua, (data, raw): (LuaString, Option<bool>)) -> LuaResult<LuaString> {
+    let digest = md5::compute(&*data.as_bytes());
+    to_lua_digest(l, &digest.0, raw)
+}
+
+pub fn hmac_sha256(
+    l: &Lua,
+    (key, data, raw): (LuaString, LuaString, Option<bool>),
+) -> LuaResult<LuaString> {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, &key.as_bytes());
+    let tag = hmac::sign(&key, &data.as_bytes());
+    to_lua_digest(l, tag.as_ref(), raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_digests() {
+        let lua = Lua::new();
+        let globals = lua.globals();
+        globals
+            .set("Sha1", lua.create_function(sha1).unwrap())
+            .unwrap();
+        globals
+            .set("Sha256", lua.create_function(sha256).unwrap())
+            .unwrap();
+        globals
+            .set("Md5", lua.create_function(md5).unwrap())
+            .unwrap();
+        globals
+            .set("HmacSha256", lua.create_function(hmac_sha256).unwrap())
+            .unwrap();
+        let eval = |code: &str| lua.load(code).eval::<String>().unwrap();
+
+        assert_eq!(
+            eval(r#"return Sha1("abc")"#),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(
+            eval(r#"return Sha256("abc")"#),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            eval(r#"return Md5("abc")"#),
+            "900150983cd24fb0d6963f7d28e17f72"
+        );
+        // RFC 4231, test case 2
+        assert_eq!(
+            eval(r#"return HmacSha256("Jefe", "what do ya want for nothing?")"#),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(eval(r#"return tostring(#Sha256("abc", true))"#), "32");
+        assert_eq!(
+            eval(r#"return tostring(Sha256("abc", true):byte(1))"#),
+            "186"
+        );
+    }
+}
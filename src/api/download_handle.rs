@@ -0,0 +1,87 @@
+use crate::{
+    download::{Download, DownloadRequest},
+    http,
+    lua::Context,
+    rate_limit,
+};
+use mlua::{IntoLuaMulti, Lua, MultiValue, Result as LuaResult, Table, UserData, Value};
+
+/// `NewDownload(url, options)` starts a download and returns a handle to poll it.
+/// `options` may contain `headers` (table of name to value) and `body`, which
+/// turns the request into a POST request.
+pub fn new_download(l: &Lua, (url, options): (String, Option<Table>)) -> LuaResult<MultiValue> {
+    let mut request = DownloadRequest {
+        url,
+        ..Default::default()
+    };
+    if let Some(options) = options {
+        if let Some(headers) = options.get::<Option<Table>>("headers")? {
+            request.headers = headers
+                .pairs::<String, String>()
+                .collect::<LuaResult<_>>()?;
+        }
+        // Lua strings may hold binary data, e.g. compressed build codes
+        request.body = options
+            .get::<Option<mlua::String>>("body")?
+            .map(|body| body.as_bytes().to_vec());
+    }
+
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    let download = ctx.downloads().start(request);
+    DownloadHandle(download).into_lua_multi(l)
+}
+
+/// `IsOnline()` returns whether the internet could be reached on startup.
+pub fn is_online(_: &Lua, _: ()) -> LuaResult<bool> {
+    Ok(http::is_online())
+}
+
+/// `GetRateLimitStatus()` returns the queue of each rate limit policy as list of
+/// `{ policy, queued, waitTime }` tables. `waitTime` is in seconds.
+pub fn get_rate_limit_status(l: &Lua, _: ()) -> LuaResult<Table> {
+    l.create_sequence_from(
+        rate_limit::status()
+            .into_iter()
+            .map(|status| {
+                l.create_table_from([
+                    ("policy", Value::String(l.create_string(&status.name)?)),
+                    ("queued", Value::Integer(status.queued as i64)),
+                    ("waitTime", Value::Number(status.wait_time.as_secs_f64())),
+                ])
+            })
+            .collect::<LuaResult<Vec<_>>>()?,
+    )
+}
+
+/// The download is cancelled when the handle is garbage collected
+pub struct DownloadHandle(Download);
+
+impl UserData for DownloadHandle {
+    fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
+        // "queued", "running", "done", "failed" or "cancelled"
+        methods.add_method("GetStatus", |_, this, ()| {
+            Ok(this.0.with_state(|state| state.status.as_str()))
+        });
+
+        // bytes received and total size, which is nil if the server didn't send it
+        methods.add_method("GetProgress", |_, this, ()| {
+            Ok(this.0.with_state(|state| (state.received, state.total)))
+        });
+
+        // data received since the last call, PoB concatenates the parts
+        methods.add_method("GetData", |l, this, ()| l.create_string(this.0.take_data()));
+
+        methods.add_method("GetResponseCode", |_, this, ()| {
+            Ok(this.0.with_state(|state| state.response_code))
+        });
+
+        methods.add_method("GetError", |_, this, ()| {
+            Ok(this.0.with_state(|state| state.error.clone()))
+        });
+
+        methods.add_method("Cancel", |_, this, ()| {
+            this.0.cancel();
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,41 @@
+use crate::util::{decode_base64, percent_decode};
+use base64::{
+    Engine,
+    engine::general_purpose::{STANDARD, URL_SAFE},
+};
+use mlua::{IntoLuaMulti, Lua, MultiValue, Result as LuaResult, String as LuaString, Value};
+
+/// Encodes with the URL-safe alphabet used in build codes unless `url_safe` is false.
+pub fn base64_encode(_: &Lua, (data, url_safe): (LuaString, Option<bool>)) -> LuaResult<String> {
+    let data = &data.as_bytes()[..];
+    Ok(match url_safe {
+        Some(false) => STANDARD.encode(data),
+        _ => URL_SAFE.encode(data),
+    })
+}
+
+/// Accepts both alphabets, with or without padding
+pub fn base64_decode(l: &Lua, text: LuaString) -> LuaResult<MultiValue> {
+    match decode_base64(&text.to_string_lossy()) {
+        Ok(data) => l.create_string(&data)?.into_lua_multi(l),
+        Err(e) => (Value::Nil, e.to_string()).into_lua_multi(l),
+    }
+}
+
+/// Percent-encodes everything except unreserved characters (RFC 3986)
+pub fn url_encode(_: &Lua, text: LuaString) -> LuaResult<String> {
+    let mut encoded = String::new();
+    for &byte in &text.as_bytes()[..] {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    Ok(encoded)
+}
+
+/// Decodes `%XX` escapes. Invalid escapes are kept as they are.
+pub fn url_decode(l: &Lua, text: LuaString) -> LuaResult<LuaString> {
+    l.create_string(percent_decode(&text.as_bytes()))
+}
@@ -0,0 +1,40 @@
+//! File functions that take UTF-8 paths, unlike Lua's io library, which uses the
+//! ANSI code page on Windows and fails on non-ASCII user names. Writes are atomic.
+
+use crate::util::write_atomic;
+use mlua::{IntoLuaMulti, Lua, MultiValue, Result as LuaResult, String as LuaString, Value};
+use std::{fs, io, path::Path};
+
+fn to_lua_result(l: &Lua, result: io::Result<()>) -> LuaResult<MultiValue> {
+    match result {
+        // callers expect first return value to be true on success
+        Ok(()) => Value::Boolean(true).into_lua_multi(l),
+        // otherwise it is set to Nil and second return value is set to error msg
+        Err(err) => (Value::Nil, err.to_string()).into_lua_multi(l),
+    }
+}
+
+pub fn read_file(l: &Lua, path: String) -> LuaResult<MultiValue> {
+    match fs::read(path) {
+        Ok(contents) => l.create_string(contents)?.into_lua_multi(l),
+        Err(err) => (Value::Nil, err.to_string()).into_lua_multi(l),
+    }
+}
+
+pub fn write_file(l: &Lua, (path, contents): (String, LuaString)) -> LuaResult<MultiValue> {
+    to_lua_result(l, write_atomic(path, &contents.as_bytes()))
+}
+
+pub fn remove_file(l: &Lua, path: String) -> LuaResult<MultiValue> {
+    to_lua_result(l, fs::remove_file(path))
+}
+
+/// Copies via a temporary file, so `destination` is either the old or the new file.
+pub fn copy_file(l: &Lua, (source, destination): (String, String)) -> LuaResult<MultiValue> {
+    let result = fs::read(source).and_then(|contents| write_atomic(destination, &contents));
+    to_lua_result(l, result)
+}
+
+pub fn file_exists(_: &Lua, path: String) -> LuaResult<bool> {
+    Ok(Path::new(&path).is_file())
+}
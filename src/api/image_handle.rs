@@ -1,62 +1,219 @@
 use crate::{
     lua::Context,
-    renderer::textures::{TextureHandle, TextureOptions},
+    renderer::textures::{TextureHandle, TextureId, TextureOptions},
 };
-use mlua::{Lua, MultiValue, Result as LuaResult, UserData};
+use mlua::{AnyUserData, Function, Lua, MultiValue, Result as LuaResult, Table, UserData, Value};
+use std::{sync::Arc, time::Duration};
+
+// shown for frames of an animation without a delay, as browsers do
+const DEFAULT_FRAME_DURATION: Duration = Duration::from_millis(100);
 
 pub fn new_image_handle(_: &Lua, _: ()) -> LuaResult<ImageHandle> {
-    Ok(ImageHandle::Unloaded)
+    Ok(ImageHandle::default())
+}
+
+#[derive(Clone)]
+enum ImageSource {
+    File(String),
+    // encoded image passed to `LoadFromBuffer`
+    Buffer(Arc<[u8]>),
 }
 
+/// Arguments of a `Load` or `LoadFromBuffer` call. Kept around so that it can be
+/// retried.
 #[derive(Clone)]
-pub enum ImageHandle {
-    Loaded(TextureHandle),
-    Unloaded,
+struct LoadRequest {
+    source: ImageSource,
+    options: TextureOptions,
+    is_async: bool,
+}
+
+#[derive(Clone, Default)]
+pub struct ImageHandle {
+    texture: Option<TextureHandle>,
+    last_load: Option<LoadRequest>,
+    // Error of the last load that didn't result in a texture.
+    // Errors of loads into an existing texture are stored in its metadata.
+    last_error: Option<String>,
+    // see `SetLoadingPriority`
+    load_priority: i32,
+    // frame of an animation that's drawn if no layer is passed, see `AdvanceFrame`
+    frame: u32,
+    // how long the current frame has been shown
+    frame_elapsed: Duration,
+}
+
+impl ImageHandle {
+    pub fn texture_id(&self) -> Option<TextureId> {
+        self.texture.as_ref().map(TextureHandle::id)
+    }
+
+    /// Array layer of the current animation frame, 0 for still images
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    /// Moves the animation forward by `delta`. `durations` are the durations of
+    /// its frames.
+    fn advance_frame(&mut self, durations: &[Duration], delta: Duration) {
+        if durations.len() < 2 {
+            return;
+        }
+        if self.frame as usize >= durations.len() {
+            self.frame = 0;
+            self.frame_elapsed = Duration::ZERO;
+        }
+        let duration = |frame: u32| match durations[frame as usize] {
+            Duration::ZERO => DEFAULT_FRAME_DURATION,
+            duration => duration,
+        };
+        // skips whole loops, e.g. after the window was hidden for a while
+        let loop_duration: Duration = (0..durations.len() as u32).map(duration).sum();
+        let delta = Duration::from_nanos((delta.as_nanos() % loop_duration.as_nanos()) as u64);
+
+        self.frame_elapsed += delta;
+        while self.frame_elapsed >= duration(self.frame) {
+            self.frame_elapsed -= duration(self.frame);
+            self.frame = (self.frame + 1) % durations.len() as u32;
+        }
+    }
+
+    fn last_error(&self) -> Option<String> {
+        self.last_error.clone().or_else(|| {
+            self.texture
+                .as_ref()
+                .and_then(|texture_handle| texture_handle.last_error())
+        })
+    }
 }
 
 impl UserData for ImageHandle {
     fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
-        methods.add_method_mut("Load", load);
+        methods.add_function("Load", load);
+        methods.add_function("LoadFromBuffer", load_from_buffer);
 
         methods.add_method_mut("Unload", |_, this, ()| {
-            match this {
-                ImageHandle::Loaded(_) => {
-                    // dropping the handle frees the texture
-                    *this = ImageHandle::Unloaded;
-                }
-                ImageHandle::Unloaded => {}
+            // dropping the handle frees the texture
+            *this = ImageHandle::default();
+            Ok(())
+        });
+
+        methods.add_function("Retry", |lua, ud: AnyUserData| {
+            let Some(request) = ud.borrow::<ImageHandle>()?.last_load.clone() else {
+                return Ok(());
+            };
+            load_request(lua, &ud, request)
+        });
+
+        methods.add_method("GetLastError", |_, this, ()| Ok(this.last_error()));
+
+        // async loads with higher priority are started first, e.g. for images
+        // that are visible. can be changed while the load is queued.
+        methods.add_method_mut("SetLoadingPriority", |lua, this, priority: i32| {
+            this.load_priority = priority;
+            if let Some(texture_handle) = &this.texture {
+                let ctx = lua.app_data_ref::<&'static Context>().unwrap();
+                ctx.texture_manager()
+                    .set_load_priority(texture_handle.id(), priority);
             }
             Ok(())
         });
 
-        methods.add_method("IsValid", |_, this, ()| {
-            Ok(matches!(this, ImageHandle::Loaded(_)))
+        // called with true once an async load finished, or false and the error
+        // message if it failed. nil removes the callback.
+        // it's a user value, so a callback that uses its own handle doesn't keep the
+        // handle alive.
+        methods.add_function(
+            "SetLoadCallback",
+            |_, (ud, callback): (AnyUserData, Option<Function>)| {
+                ud.set_named_user_value(LOAD_CALLBACK, callback)
+            },
+        );
+
+        methods.add_method("IsValid", |_, this, ()| Ok(this.texture.is_some()));
+
+        methods.add_method("IsLoading", |_, this, ()| {
+            if this.last_error().is_some() {
+                return Ok(false);
+            }
+            match &this.texture {
+                Some(texture_handle) => {
+                    let size = texture_handle.size();
+                    Ok(size[0] == 0)
+                }
+                None => Ok(true),
+            }
         });
 
-        methods.add_method("IsLoading", |_, this, ()| match &this {
-            ImageHandle::Loaded(texture_handle) => {
-                let size = texture_handle.size();
-                Ok(size[0] == 0)
+        // advances an animated image by `seconds`, the last frame time by default,
+        // and returns the 1-based frame that's drawn if no layer is passed.
+        // still images stay at frame 1.
+        methods.add_method_mut("AdvanceFrame", |lua, this, seconds: Option<f64>| {
+            let Some(texture_handle) = &this.texture else {
+                return Ok(1);
+            };
+            let durations = texture_handle.frame_durations();
+            if durations.len() > 1 {
+                let ctx = lua.app_data_ref::<&'static Context>().unwrap();
+                let delta = match seconds {
+                    Some(seconds) => Duration::try_from_secs_f64(seconds).unwrap_or_default(),
+                    None => ctx.window().frame_time(),
+                };
+                this.advance_frame(&durations, delta);
+                // keeps the animation running without input
+                ctx.window().set_needs_redraw();
             }
-            ImageHandle::Unloaded => Ok(true),
+            Ok(this.frame + 1)
         });
 
-        methods.add_method("ImageSize", |_, this, ()| match &this {
-            ImageHandle::Loaded(texture_handle) => {
+        methods.add_method("GetFrameCount", |_, this, ()| {
+            Ok(this.texture.as_ref().map_or(0, |texture_handle| {
+                texture_handle.frame_durations().len().max(1)
+            }))
+        });
+
+        methods.add_method("ImageSize", |_, this, ()| match &this.texture {
+            Some(texture_handle) => {
                 let size = texture_handle.size();
                 Ok((size[0], size[1]))
             }
-            ImageHandle::Unloaded => Ok((0, 0)),
+            None => Ok((0, 0)),
         });
     }
 }
 
-fn load(
+fn load(lua: &Lua, (ud, image_path, flags): (AnyUserData, String, MultiValue)) -> LuaResult<()> {
+    let (options, is_async) = parse_flags(&flags);
+    load_request(
+        lua,
+        &ud,
+        LoadRequest {
+            source: ImageSource::File(image_path),
+            options,
+            is_async,
+        },
+    )
+}
+
+/// Loads an encoded image, e.g. PNG bytes downloaded from the trade API. Takes the
+/// same flags as `Load`.
+fn load_from_buffer(
     lua: &Lua,
-    handle: &mut ImageHandle,
-    (image_path, flags): (String, MultiValue),
+    (ud, data, flags): (AnyUserData, mlua::String, MultiValue),
 ) -> LuaResult<()> {
-    let ctx = lua.app_data_ref::<&'static Context>().unwrap();
+    let (options, is_async) = parse_flags(&flags);
+    load_request(
+        lua,
+        &ud,
+        LoadRequest {
+            source: ImageSource::Buffer(Arc::from(&*data.as_bytes())),
+            options,
+            is_async,
+        },
+    )
+}
+
+fn parse_flags(flags: &MultiValue) -> (TextureOptions, bool) {
     let mut is_async = false;
     let mut options = TextureOptions::LINEAR_REPEAT;
 
@@ -67,31 +224,160 @@ fn load(
                 "NEAREST" => options.magnification = wgpu::FilterMode::Nearest,
                 "ASYNC" => is_async = true,
                 "MIPMAP" => options.generate_mipmaps = true,
+                "PREMULTIPLIED_ALPHA" => options.premultiply_alpha = Some(true),
+                "STRAIGHT_ALPHA" => options.premultiply_alpha = Some(false),
                 _ => {}
             }
         }
     }
+    (options, is_async)
+}
 
-    match handle {
-        // replace image data if already allocated
-        ImageHandle::Loaded(texture_handle) => {
-            // in case of error, stay loaded with current texture
-            let _ = ctx.texture_manager().update_texture(
+fn load_request(lua: &Lua, ud: &AnyUserData, request: LoadRequest) -> LuaResult<()> {
+    let ctx = lua.app_data_ref::<&'static Context>().unwrap();
+    let mut handle = ud.borrow_mut::<ImageHandle>()?;
+    let LoadRequest {
+        source,
+        options,
+        is_async,
+    } = request.clone();
+
+    handle.last_load = Some(request);
+    handle.last_error = None;
+    handle.frame = 0;
+    handle.frame_elapsed = Duration::ZERO;
+
+    let texture_manager = ctx.texture_manager();
+    match (&handle.texture, source) {
+        // replace image data if already allocated.
+        // in case of error, stay loaded with current texture.
+        // the error is stored in the texture's metadata
+        (Some(texture_handle), ImageSource::File(image_path)) => {
+            let _ =
+                texture_manager.update_texture(texture_handle.id(), image_path, options, is_async);
+        }
+        (Some(texture_handle), ImageSource::Buffer(bytes)) => {
+            let _ = texture_manager.update_texture_from_bytes(
                 texture_handle.id(),
-                image_path,
+                buffer_name(&bytes),
+                bytes,
                 options,
                 is_async,
             );
         }
         // create new texture handle
-        ImageHandle::Unloaded => {
-            if let Ok(tex_handle) = ctx
-                .texture_manager()
-                .load_texture(image_path, options, is_async)
-            {
-                *handle = ImageHandle::Loaded(tex_handle);
+        (None, source) => {
+            let result = match source {
+                ImageSource::File(image_path) => {
+                    texture_manager.load_texture(image_path, options, is_async)
+                }
+                ImageSource::Buffer(bytes) => texture_manager.load_texture_from_bytes(
+                    buffer_name(&bytes),
+                    bytes,
+                    options,
+                    is_async,
+                ),
+            };
+            match result {
+                Ok(tex_handle) => handle.texture = Some(tex_handle),
+                Err(e) => handle.last_error = Some(e.to_string()),
             }
         }
     }
+
+    if is_async && let Some(texture_handle) = &handle.texture {
+        texture_manager.set_load_priority(texture_handle.id(), handle.load_priority);
+        pending_loads(lua)?.raw_set(texture_handle.id(), ud)?;
+    }
     Ok(())
 }
+
+const LOAD_CALLBACK: &str = "load_callback";
+const PENDING_LOADS: &str = "image_pending_loads";
+
+// handles that started the pending async loads by texture id. the handles are
+// referenced weakly, the load of a collected handle has no one left to notify.
+fn pending_loads(lua: &Lua) -> LuaResult<Table> {
+    if let Some(handles) = lua.named_registry_value::<Option<Table>>(PENDING_LOADS)? {
+        return Ok(handles);
+    }
+    let handles = lua.create_table()?;
+    let metatable = lua.create_table()?;
+    metatable.raw_set("__mode", "v")?;
+    handles.set_metatable(Some(metatable))?;
+    lua.set_named_registry_value(PENDING_LOADS, &handles)?;
+    Ok(handles)
+}
+
+/// Calls the callback of the image handle that started the async load into texture
+/// `id`, see `SetLoadCallback`. `error` is set if the load failed.
+pub fn call_load_callback(lua: &Lua, id: TextureId, error: Option<String>) -> LuaResult<()> {
+    let handles = pending_loads(lua)?;
+    let Some(ud) = handles.raw_get::<Option<AnyUserData>>(id)? else {
+        return Ok(());
+    };
+    handles.raw_set(id, Value::Nil)?;
+    // the handle may have been unloaded since
+    if ud.borrow::<ImageHandle>()?.texture_id() != Some(id) {
+        return Ok(());
+    }
+    let Some(callback) = ud.named_user_value::<Option<Function>>(LOAD_CALLBACK)? else {
+        return Ok(());
+    };
+    match error {
+        Some(error) => callback.call((false, error)),
+        None => callback.call(true),
+    }
+}
+
+// names images loaded from memory in errors and the texture list
+fn buffer_name(bytes: &[u8]) -> String {
+    format!("<buffer of {} bytes>", bytes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_advance_frame() {
+        let mut handle = ImageHandle::default();
+        let ms = Duration::from_millis;
+        let durations = [ms(50), Duration::ZERO, ms(20)];
+
+        handle.advance_frame(&durations, ms(40));
+        assert_eq!(handle.frame(), 0);
+        // frames without a delay are shown for 100ms
+        handle.advance_frame(&durations, ms(20));
+        assert_eq!(handle.frame(), 1);
+        handle.advance_frame(&durations, ms(100));
+        assert_eq!(handle.frame(), 2);
+        // wraps around after a whole loop of 170ms
+        handle.advance_frame(&durations, ms(170 * 3 + 20));
+        assert_eq!(handle.frame(), 0);
+    }
+
+    #[test]
+    fn test_load_callback_doesnt_keep_handle_alive() -> LuaResult<()> {
+        let lua = Lua::new();
+        lua.globals()
+            .set("NewImageHandle", lua.create_function(new_image_handle)?)?;
+        let is_collected: bool = lua
+            .load(
+                r#"
+                local handles = setmetatable({}, { __mode = "v" })
+                do
+                    local handle = NewImageHandle()
+                    handle:SetLoadCallback(function() handle:IsValid() end)
+                    handles[1] = handle
+                end
+                collectgarbage()
+                collectgarbage()
+                return handles[1] == nil
+                "#,
+            )
+            .eval()?;
+        assert!(is_collected);
+        Ok(())
+    }
+}
@@ -2,12 +2,28 @@ use crate::{
     input::{str_as_key, str_as_mousebutton},
     lua::Context,
 };
-use mlua::{Lua, Result as LuaResult};
+use mlua::{IntoLuaMulti, Lua, MultiValue, Result as LuaResult, Value};
 
-pub fn get_cursor_pos(l: &Lua, _: ()) -> LuaResult<(u32, u32)> {
+/// The position may be outside of the window while the cursor is captured.
+pub fn get_cursor_pos(l: &Lua, _: ()) -> LuaResult<(i32, i32)> {
     let ctx = l.app_data_ref::<&'static Context>().unwrap();
     let pos = ctx.input().mouse_pos();
-    Ok((pos.x as u32, pos.y as u32))
+    Ok((pos.x as i32, pos.y as i32))
+}
+
+/// `SetCursorCapture(true)` keeps drags going past the window bounds, e.g. when
+/// panning the passive tree. Released when the mouse button is released or the
+/// window loses focus. Returns true or nil and an error message.
+pub fn set_cursor_capture(l: &Lua, capture: bool) -> LuaResult<MultiValue> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    if !capture {
+        ctx.window().release_cursor();
+        return Value::Boolean(true).into_lua_multi(l);
+    }
+    match ctx.window().capture_cursor() {
+        Ok(()) => Value::Boolean(true).into_lua_multi(l),
+        Err(err) => (Value::Nil, err.to_string()).into_lua_multi(l),
+    }
 }
 
 pub fn is_key_down(l: &Lua, key_name: String) -> LuaResult<bool> {
@@ -0,0 +1,23 @@
+use crate::lua::Context;
+use mlua::{IntoLuaMulti, Lua, MultiValue, Result as LuaResult, Value};
+
+/// Returns the port the listener is bound to, which is part of the redirect URI.
+/// The redirect is passed to `OnOAuthRedirect(code, state, error)`.
+pub fn start_oauth_listener(l: &Lua, port: Option<u16>) -> LuaResult<MultiValue> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    match ctx.oauth_listener().start(port.unwrap_or(0)) {
+        Ok(port) => port.into_lua_multi(l),
+        Err(err) => (Value::Nil, err.to_string()).into_lua_multi(l),
+    }
+}
+
+pub fn stop_oauth_listener(l: &Lua, _: ()) -> LuaResult<()> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    ctx.oauth_listener().stop();
+    Ok(())
+}
+
+pub fn is_oauth_listening(l: &Lua, _: ()) -> LuaResult<bool> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    Ok(ctx.oauth_listener().is_listening())
+}
@@ -1,8 +1,12 @@
 use mlua::{IntoLuaMulti, Lua, MultiValue, Result as LuaResult, Value};
-use std::{fs, path::PathBuf};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
 
 use crate::{
     lua::Context,
+    process::ProcessAction,
     util::{change_working_directory, get_executable_dir},
 };
 
@@ -48,7 +52,15 @@ pub fn make_dir(l: &Lua, path: String) -> LuaResult<MultiValue> {
     }
 }
 
+/// Removes a directory inside the script directory, which contains the user path.
+/// Other directories are refused so that a bad path can't delete e.g. the home
+/// directory.
 pub fn remove_dir(l: &Lua, (path, recursive): (String, Option<bool>)) -> LuaResult<MultiValue> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    if !is_inside(Path::new(&path), ctx.script_dir()) {
+        let error = format!("{path} is not inside {}", ctx.script_dir().display());
+        return (Value::Nil, error).into_lua_multi(l);
+    }
     let result = if recursive.unwrap_or(false) {
         fs::remove_dir_all(&path)
     } else {
@@ -59,3 +71,102 @@ pub fn remove_dir(l: &Lua, (path, recursive): (String, Option<bool>)) -> LuaResu
         Err(err) => Ok((Value::Nil, err.to_string()).into_lua_multi(l)?),
     }
 }
+
+// whether `path` exists and is inside `root`, but isn't `root` itself
+fn is_inside(path: &Path, root: &Path) -> bool {
+    // resolves `..` and symlinks
+    match (path.canonicalize(), root.canonicalize()) {
+        (Ok(path), Ok(root)) => path != root && path.starts_with(root),
+        _ => false,
+    }
+}
+
+/// Moves a file or directory, e.g. a build into another folder. Files are copied
+/// if they can't be renamed because the destination is on another drive.
+pub fn move_path(l: &Lua, (source, destination): (String, String)) -> LuaResult<MultiValue> {
+    let (source, destination) = (Path::new(&source), Path::new(&destination));
+    if destination.exists() {
+        let error = format!("{} already exists", destination.display());
+        return (Value::Nil, error).into_lua_multi(l);
+    }
+    let result = fs::rename(source, destination).or_else(|err| {
+        if err.kind() != io::ErrorKind::CrossesDevices || !source.is_file() {
+            return Err(err);
+        }
+        fs::copy(source, destination)?;
+        fs::remove_file(source)
+    });
+    match result {
+        Ok(_) => Ok(Value::Boolean(true).into_lua_multi(l)?),
+        Err(err) => Ok((Value::Nil, err.to_string()).into_lua_multi(l)?),
+    }
+}
+
+/// Renames a file or directory within its directory, e.g. `RenamePath(path, "New.xml")`.
+pub fn rename_path(l: &Lua, (path, new_name): (String, String)) -> LuaResult<MultiValue> {
+    if new_name.is_empty() || new_name.contains(['/', '\\']) || new_name == ".." {
+        let error = format!("{new_name} is not a valid name");
+        return (Value::Nil, error).into_lua_multi(l);
+    }
+    let path = Path::new(&path);
+    let destination = path.with_file_name(&new_name);
+    move_path(
+        l,
+        (
+            path.to_string_lossy().into_owned(),
+            destination.to_string_lossy().into_owned(),
+        ),
+    )
+}
+
+/// Shows the directory in the platform's file manager, e.g. the builds folder. Like
+/// `SpawnProcess("OPEN_FOLDER", path)`, but returns `true` instead of the process id.
+pub fn open_folder(l: &Lua, path: String) -> LuaResult<MultiValue> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    match ctx
+        .process_spawner()
+        .spawn(ProcessAction::OpenFolder, Path::new(&path))
+    {
+        Ok(_) => Ok(Value::Boolean(true).into_lua_multi(l)?),
+        Err(err) => Ok((Value::Nil, err.to_string()).into_lua_multi(l)?),
+    }
+}
+
+/// Calls `OnFileChanged(path)` when the file, or anything in the directory, changes.
+pub fn watch_path(l: &Lua, (path, recursive): (String, Option<bool>)) -> LuaResult<MultiValue> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    match ctx
+        .file_watcher()
+        .watch(Path::new(&path), recursive.unwrap_or(false))
+    {
+        Ok(_) => Ok(Value::Boolean(true).into_lua_multi(l)?),
+        Err(err) => Ok((Value::Nil, err.to_string()).into_lua_multi(l)?),
+    }
+}
+
+pub fn unwatch_path(l: &Lua, path: String) -> LuaResult<MultiValue> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    match ctx.file_watcher().unwatch(Path::new(&path)) {
+        Ok(_) => Ok(Value::Boolean(true).into_lua_multi(l)?),
+        Err(err) => Ok((Value::Nil, err.to_string()).into_lua_multi(l)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_inside() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        let builds = root.join("userdata").join("Builds");
+        fs::create_dir_all(&builds).unwrap();
+
+        assert!(is_inside(&builds, root));
+        assert!(!is_inside(root, root));
+        assert!(!is_inside(&builds.join("../../.."), root));
+        // doesn't exist
+        assert!(!is_inside(&root.join("missing"), root));
+    }
+}
@@ -0,0 +1,16 @@
+use crate::{lua::Context, process::ProcessAction};
+use mlua::{IntoLuaMulti, Lua, MultiValue, Result as LuaResult, Value};
+use std::path::Path;
+
+/// Returns an id that is passed to `OnProcessExited(id, code, error)` once the
+/// process exits. Actions are `OPEN_FOLDER` and `EDIT_FILE`.
+pub fn spawn_process(l: &Lua, (action, path): (String, String)) -> LuaResult<MultiValue> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    let result = action
+        .parse::<ProcessAction>()
+        .and_then(|action| ctx.process_spawner().spawn(action, Path::new(&path)));
+    match result {
+        Ok(id) => id.into_lua_multi(l),
+        Err(err) => (Value::Nil, err.to_string()).into_lua_multi(l),
+    }
+}
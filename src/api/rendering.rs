@@ -1,19 +1,20 @@
 use crate::{
-    api::image_handle::ImageHandle,
+    api::{image_handle::ImageHandle, stack::Args},
     color::Srgba,
     dpi::Uv,
     fonts::{Alignment, FontStyle, LayoutJob},
     lua::Context,
     math::{Point, Quad, Rect, Size},
 };
+use ahash::HashMap;
 use core::ffi::{c_int, c_void};
 use mlua::{
-    LightUserData, Lua, Result as LuaResult, UserDataRefMut, Value,
+    LightUserData, Lua, Result as LuaResult, Table, UserDataRefMut, Value,
     ffi::{self},
 };
 use parley::FontFamily;
 use regex::Regex;
-use std::{borrow::Cow, sync::LazyLock};
+use std::{borrow::Cow, cell::RefCell, ops::Range, rc::Rc, sync::LazyLock};
 
 pub fn register_globals(lua: &Lua) -> LuaResult<()> {
     let globals = lua.globals();
@@ -21,13 +22,13 @@ pub fn register_globals(lua: &Lua) -> LuaResult<()> {
     // unused functions
     let get_draw_layer = |_: &Lua, ()| -> LuaResult<()> { unimplemented!() };
     let set_blend_mode = |_: &Lua, ()| -> LuaResult<()> { unimplemented!() };
-    let get_async_count = |_: &Lua, ()| -> LuaResult<()> { unimplemented!() };
     let set_clear_color = |_: &Lua, ()| -> LuaResult<()> { unimplemented!() };
     globals.set("GetDrawLayer", lua.create_function(get_draw_layer)?)?;
     globals.set("SetBlendMode", lua.create_function(set_blend_mode)?)?;
-    globals.set("GetAsyncCount", lua.create_function(get_async_count)?)?;
     globals.set("SetClearColor", lua.create_function(set_clear_color)?)?;
 
+    globals.set("GetAsyncCount", lua.create_function(get_async_count)?)?;
+
     // rendering functions
     // NOTE: unfortunately, mlua's conversion of function arguments adds a lot of
     // overhead. this is very noticeable for the draw functions which can be called
@@ -41,21 +42,55 @@ pub fn register_globals(lua: &Lua) -> LuaResult<()> {
     }
     unsafe { globals.set("DrawImage", lua.create_c_function(draw_image)?)? };
     unsafe { globals.set("DrawImageQuad", lua.create_c_function(draw_image_quad)?)? };
+    unsafe {
+        globals.set(
+            "DrawImageQuadGradient",
+            lua.create_c_function(draw_image_quad_gradient)?,
+        )?;
+    }
+    unsafe {
+        globals.set(
+            "DrawImageNineSlice",
+            lua.create_c_function(draw_image_nine_slice)?,
+        )?;
+    }
+    unsafe { globals.set("DrawLine", lua.create_c_function(draw_line)?)? };
+    unsafe { globals.set("DrawPolyline", lua.create_c_function(draw_polyline)?)? };
     unsafe {
         globals.set("DrawString", lua.create_c_function(draw_string)?)?;
     }
     unsafe {
         globals.set("DrawStringWidth", lua.create_c_function(get_string_width)?)?;
     }
+    unsafe {
+        globals.set(
+            "DrawStringWidthMulti",
+            lua.create_c_function(get_string_width_multi)?,
+        )?;
+    }
     globals.set(
         "DrawStringCursorIndex",
         lua.create_function_mut(get_index_at_cur)?,
     )?;
+    globals.set("DrawStringLinkAt", lua.create_function(get_link_at_cur)?)?;
+    globals.set(
+        "DrawStringSelectionRects",
+        lua.create_function(get_selection_rects)?,
+    )?;
+    globals.set(
+        "DrawStringSelectionRange",
+        lua.create_function(get_selection_range)?,
+    )?;
+    globals.set(
+        "SetUnderlineLinks",
+        lua.create_function(set_underline_links)?,
+    )?;
 
     // NOTE: mlua wraps UserData in a special way to maintain safety guarantees.
     // This wrapper is not exposed by mlua, making it difficult to access the
     // underlying user data from within C functions.
-    // This is a helper function that unwraps an ImageHandle and returns a pointer to it
+    // This is a helper function that unwraps an ImageHandle and returns a pointer to it,
+    // it's used by `Args::image`.
     // See: https://github.com/mlua-rs/mlua/discussions/545#discussioncomment-12530475
     let get_img_handle = lua.create_function(|_, mut ud: UserDataRefMut<ImageHandle>| {
         let vec: *mut ImageHandle = &mut *ud;
@@ -65,98 +100,29 @@ pub fn register_globals(lua: &Lua) -> LuaResult<()> {
     Ok(())
 }
 
-macro_rules! str_from_stack {
-    ($s:ident, $i:expr) => {
-        unsafe {
-            let mut size = 0;
-            let data = ffi::luaL_checklstring($s, $i, &mut size);
-            let bytes = std::slice::from_raw_parts(data as *const u8, size);
-            std::str::from_utf8_unchecked(bytes)
-        }
-    };
-}
-
-macro_rules! f32_from_stack {
-    ($s:ident, $i:expr) => {
-        unsafe { ffi::luaL_checknumber($s, $i) } as f32
-    };
-}
-
-macro_rules! i32_from_stack {
-    ($s:ident, $i:expr) => {
-        unsafe { ffi::luaL_checkinteger($s, $i) } as i32
-    };
-}
-
-macro_rules! img_handle_from_stack {
-    ($s:ident, $i:expr) => {
-        unsafe {
-            match ffi::lua_type($s, $i) {
-                ffi::LUA_TNIL => None,
-                ffi::LUA_TUSERDATA => {
-                    let img_handle = lua_toimghandle($s, $i);
-                    if !img_handle.is_null() {
-                        if let ImageHandle::Loaded(ref handle) = *img_handle {
-                            Some(handle.id())
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    }
-                }
-                t => panic!("Expected Nil or ImageHandle, got {:?}", t),
-            }
-        }
-    };
-}
-
-unsafe extern "C" fn lua_toimghandle(state: *mut ffi::lua_State, idx: c_int) -> *mut ImageHandle {
-    unsafe {
-        let idx = ffi::lua_absindex(state, idx);
-        ffi::lua_getfield(state, ffi::LUA_REGISTRYINDEX, c"get_img_handle".as_ptr());
-        ffi::lua_pushvalue(state, idx);
-        let img_handle = match ffi::lua_pcall(state, 1, 1, 0) {
-            ffi::LUA_OK => ffi::lua_touserdata(state, -1) as *mut ImageHandle,
-            _ => std::ptr::null_mut(),
-        };
-        ffi::lua_pop(state, 1);
-        img_handle
-    }
+// Copies the context out of the app data, so no borrow guard is alive in the C
+// functions. Their errors are raised with `lua_error`, which longjmps over the
+// guard's destructor and would leave the app data borrowed.
+fn context(lua: &Lua) -> &'static Context {
+    *lua.app_data_ref::<&'static Context>().unwrap()
 }
 
 unsafe extern "C-unwind" fn set_draw_color(state: *mut ffi::lua_State) -> c_int {
     //profiling::scope!("set_draw_color");
     let lua_instance = unsafe { Lua::get_or_init_from_ptr(state) };
-    let ctx = lua_instance.app_data_ref::<&'static Context>().unwrap();
+    let ctx = context(lua_instance);
+    let args = unsafe { Args::new(state) };
 
-    let nargs = unsafe { ffi::lua_gettop(state) };
-    match nargs {
+    let color = match args.count() {
         // escape_code
-        1 => {
-            let esc_str = str_from_stack!(state, -nargs);
-            let color = Srgba::from_escape_code(esc_str);
-            ctx.layers().set_draw_color(color);
-        }
+        1 => Srgba::from_escape_code(args.str(1)),
         // rgb
-        3 => {
-            let r = f32_from_stack!(state, -nargs);
-            let g = f32_from_stack!(state, -nargs + 1);
-            let b = f32_from_stack!(state, -nargs + 2);
-            let color = Srgba::new_f32(r, g, b, 1.0);
-            ctx.layers().set_draw_color(color);
-        }
+        3 => Srgba::new_f32(args.f32(1), args.f32(2), args.f32(3), 1.0),
         // rgba
-        4 => {
-            let r = f32_from_stack!(state, -nargs);
-            let g = f32_from_stack!(state, -nargs + 1);
-            let b = f32_from_stack!(state, -nargs + 2);
-            let a = f32_from_stack!(state, -nargs + 3);
-            let color = Srgba::new_f32(r, g, b, a);
-            ctx.layers().set_draw_color(color);
-        }
-        _ => panic!("Unexpected number of arguments"),
+        4 => Srgba::new_f32(args.f32(1), args.f32(2), args.f32(3), args.f32(4)),
+        nargs => args.error(format_args!("unexpected number of arguments ({nargs})")),
     };
+    ctx.layers().set_draw_color(color);
 
     0
 }
@@ -164,7 +130,7 @@ unsafe extern "C-unwind" fn set_draw_color(state: *mut ffi::lua_State) -> c_int
 unsafe extern "C-unwind" fn get_draw_color(state: *mut ffi::lua_State) -> c_int {
     //profiling::scope!("get_draw_color");
     let lua_instance = unsafe { Lua::get_or_init_from_ptr(state) };
-    let ctx = lua_instance.app_data_ref::<&'static Context>().unwrap();
+    let ctx = context(lua_instance);
 
     let color: [f32; 4] = ctx.layers().get_draw_color().into();
     unsafe { ffi::lua_pushnumber(state, color[0] as f64) };
@@ -178,22 +144,20 @@ unsafe extern "C-unwind" fn get_draw_color(state: *mut ffi::lua_State) -> c_int
 unsafe extern "C-unwind" fn set_viewport(state: *mut ffi::lua_State) -> c_int {
     //profiling::scope!("set_viewport");
     let lua_instance = unsafe { Lua::get_or_init_from_ptr(state) };
-    let ctx = lua_instance.app_data_ref::<&'static Context>().unwrap();
+    let ctx = context(lua_instance);
+    let args = unsafe { Args::new(state) };
 
-    let nargs = unsafe { ffi::lua_gettop(state) };
-    match nargs {
+    match args.count() {
         0 => ctx
             .layers()
             .set_viewport_from_size(ctx.window().logical_size()),
         4 => {
-            let x = f32_from_stack!(state, -nargs);
-            let y = f32_from_stack!(state, -nargs + 1);
-            let w = f32_from_stack!(state, -nargs + 2);
-            let h = f32_from_stack!(state, -nargs + 3);
-            let rect = Rect::from_origin_and_size(Point::new(x, y), Size::new(w, h));
-            ctx.layers().set_viewport(rect);
+            let origin = Point::new(args.f32(1), args.f32(2));
+            let size = Size::new(args.f32(3), args.f32(4));
+            ctx.layers()
+                .set_viewport(Rect::from_origin_and_size(origin, size));
         }
-        _ => panic!("Unexpected number of arguments"),
+        nargs => args.error(format_args!("unexpected number of arguments ({nargs})")),
     };
 
     0
@@ -202,32 +166,19 @@ unsafe extern "C-unwind" fn set_viewport(state: *mut ffi::lua_State) -> c_int {
 unsafe extern "C-unwind" fn set_draw_layer(state: *mut ffi::lua_State) -> c_int {
     //profiling::scope!("set_draw_layer");
     let lua_instance = unsafe { Lua::get_or_init_from_ptr(state) };
-    let ctx = lua_instance.app_data_ref::<&'static Context>().unwrap();
-
-    let nargs = unsafe { ffi::lua_gettop(state) };
+    let ctx = context(lua_instance);
+    let args = unsafe { Args::new(state) };
 
-    match nargs {
-        1 => {
-            let layer = i32_from_stack!(state, -nargs);
-            ctx.layers().set_draw_layer(layer, 0);
-        }
+    match args.count() {
+        1 => ctx.layers().set_draw_layer(args.i32(1), 0),
         2 => {
-            let layer = match unsafe { ffi::lua_type(state, -nargs) } {
-                ffi::LUA_TNIL => None,
-                ffi::LUA_TNUMBER => {
-                    let layer = i32_from_stack!(state, -nargs);
-                    Some(layer)
-                }
-                t => panic!("Expected Nil or Number, got {:?}", t),
-            };
-            let sublayer = i32_from_stack!(state, -nargs + 1);
-            if let Some(layer) = layer {
-                ctx.layers().set_draw_layer(layer, sublayer);
-            } else {
-                ctx.layers().set_draw_sublayer(sublayer);
+            let sublayer = args.i32(2);
+            match args.opt_i32(1) {
+                Some(layer) => ctx.layers().set_draw_layer(layer, sublayer),
+                None => ctx.layers().set_draw_sublayer(sublayer),
             }
         }
-        _ => panic!("Unexpected number of arguments"),
+        nargs => args.error(format_args!("unexpected number of arguments ({nargs})")),
     };
 
     0
@@ -236,44 +187,43 @@ unsafe extern "C-unwind" fn set_draw_layer(state: *mut ffi::lua_State) -> c_int
 unsafe extern "C-unwind" fn draw_image(state: *mut ffi::lua_State) -> c_int {
     //profiling::scope!("draw_image");
     let lua_instance = unsafe { Lua::get_or_init_from_ptr(state) };
-    let ctx = lua_instance.app_data_ref::<&'static Context>().unwrap();
+    let ctx = context(lua_instance);
+    let args = unsafe { Args::new(state) };
 
-    let nargs = unsafe { ffi::lua_gettop(state) };
+    let nargs = args.count();
     if !matches!(nargs, 5 | 6 | 7 | 9 | 10 | 11) {
-        panic!("Unexpected number of arguments");
+        args.error(format_args!("unexpected number of arguments ({nargs})"));
     }
 
     #[allow(clippy::manual_range_patterns)]
     let parse_uv = matches!(nargs, 9 | 10 | 11);
     let parse_layer_idx = matches!(nargs, 6 | 7 | 10 | 11);
 
-    let texture_id = img_handle_from_stack!(state, -nargs);
+    let (texture_id, frame) = args.image(1).unzip();
 
     // left, top, width, height
-    let x = f32_from_stack!(state, -nargs + 1);
-    let y = f32_from_stack!(state, -nargs + 2);
-    let w = f32_from_stack!(state, -nargs + 3);
-    let h = f32_from_stack!(state, -nargs + 4);
-    let rect = Rect::from_origin_and_size(Point::new(x, y), Size::new(w, h));
+    let origin = Point::new(args.f32(2), args.f32(3));
+    let size = Size::new(args.f32(4), args.f32(5));
+    let rect = Rect::from_origin_and_size(origin, size);
 
     // u1, v1, u2, v2
-    let mut i = 5;
+    let mut i = 6;
     let uv = if parse_uv {
-        let u1 = f32_from_stack!(state, -nargs + i);
-        let v1 = f32_from_stack!(state, -nargs + i + 1);
-        let u2 = f32_from_stack!(state, -nargs + i + 2);
-        let v2 = f32_from_stack!(state, -nargs + i + 3);
+        let uv = Rect::new(
+            Point::new(args.f32(i), args.f32(i + 1)),
+            Point::new(args.f32(i + 2), args.f32(i + 3)),
+        );
         i += 4;
-        Rect::new(Point::new(u1, v1), Point::new(u2, v2))
+        uv
     } else {
         Rect::default_uv()
     };
 
     let layer_idx = if parse_layer_idx {
-        let layer_idx = i32_from_stack!(state, -nargs + i);
-        (layer_idx - 1) as u32
+        (args.i32(i) - 1) as u32
     } else {
-        0
+        // the current frame of animated images
+        frame.unwrap_or(0)
     };
 
     ctx.layers().draw_rect(texture_id, rect, uv, layer_idx);
@@ -281,65 +231,50 @@ unsafe extern "C-unwind" fn draw_image(state: *mut ffi::lua_State) -> c_int {
     0
 }
 
+// Reads the four corners of a quad, starting at argument `i`
+fn quad_from_args<U>(args: &Args, i: c_int) -> Quad<f32, U> {
+    Quad::new(
+        Point::new(args.f32(i), args.f32(i + 1)),
+        Point::new(args.f32(i + 2), args.f32(i + 3)),
+        Point::new(args.f32(i + 4), args.f32(i + 5)),
+        Point::new(args.f32(i + 6), args.f32(i + 7)),
+    )
+}
+
 unsafe extern "C-unwind" fn draw_image_quad(state: *mut ffi::lua_State) -> c_int {
     //profiling::scope!("draw_image_quad", format!("args: {:?}", args));
     let lua_instance = unsafe { Lua::get_or_init_from_ptr(state) };
-    let ctx = lua_instance.app_data_ref::<&'static Context>().unwrap();
+    let ctx = context(lua_instance);
+    let args = unsafe { Args::new(state) };
 
-    let nargs = unsafe { ffi::lua_gettop(state) };
+    let nargs = args.count();
     if !matches!(nargs, 9 | 10 | 11 | 17 | 18 | 19) {
-        panic!("Unexpected number of arguments");
+        args.error(format_args!("unexpected number of arguments ({nargs})"));
     }
 
     #[allow(clippy::manual_range_patterns)]
     let parse_uv = matches!(nargs, 17 | 18 | 19);
     let parse_layer_idx = matches!(nargs, 10 | 11 | 18 | 19);
 
-    let texture_id = img_handle_from_stack!(state, -nargs);
+    let (texture_id, frame) = args.image(1).unzip();
 
     // x1, y1, x2, y2, ...
-    let x1 = f32_from_stack!(state, -nargs + 1);
-    let y1 = f32_from_stack!(state, -nargs + 2);
-    let x2 = f32_from_stack!(state, -nargs + 3);
-    let y2 = f32_from_stack!(state, -nargs + 4);
-    let x3 = f32_from_stack!(state, -nargs + 5);
-    let y3 = f32_from_stack!(state, -nargs + 6);
-    let x4 = f32_from_stack!(state, -nargs + 7);
-    let y4 = f32_from_stack!(state, -nargs + 8);
-    let quad = Quad::new(
-        Point::new(x1, y1),
-        Point::new(x2, y2),
-        Point::new(x3, y3),
-        Point::new(x4, y4),
-    );
+    let quad = quad_from_args(&args, 2);
 
     // u1, v1, u2, v2, ...
-    let mut i = 9;
+    let mut i = 10;
     let uv = if parse_uv {
-        let u1 = f32_from_stack!(state, -nargs + i);
-        let v1 = f32_from_stack!(state, -nargs + i + 1);
-        let u2 = f32_from_stack!(state, -nargs + i + 2);
-        let v2 = f32_from_stack!(state, -nargs + i + 3);
-        let u3 = f32_from_stack!(state, -nargs + i + 4);
-        let v3 = f32_from_stack!(state, -nargs + i + 5);
-        let u4 = f32_from_stack!(state, -nargs + i + 6);
-        let v4 = f32_from_stack!(state, -nargs + i + 7);
+        let uv = quad_from_args(&args, i);
         i += 8;
-        Quad::new(
-            Point::new(u1, v1),
-            Point::new(u2, v2),
-            Point::new(u3, v3),
-            Point::new(u4, v4),
-        )
+        uv
     } else {
         Quad::default_uv()
     };
 
     let layer_idx = if parse_layer_idx {
-        let layer_idx = i32_from_stack!(state, -nargs + i);
-        (layer_idx - 1) as u32
+        (args.i32(i) - 1) as u32
     } else {
-        0
+        frame.unwrap_or(0)
     };
 
     ctx.layers().draw_quad(texture_id, quad, uv, layer_idx);
@@ -347,27 +282,200 @@ unsafe extern "C-unwind" fn draw_image_quad(state: *mut ffi::lua_State) -> c_int
     0
 }
 
-unsafe extern "C-unwind" fn draw_string(state: *mut ffi::lua_State) -> c_int {
-    //profiling::scope!("draw_string");
+// DrawImageQuadGradient(imgHandle, x1, y1, ..., x4, y4, color1, ..., color4, [u1, v1, ..., u4, v4], [layer])
+//
+// Like DrawImageQuad, but with a color for each corner that is interpolated across the
+// quad. Colors are tables of the form {r, g, b, [a]}. The current draw color is ignored.
+unsafe extern "C-unwind" fn draw_image_quad_gradient(state: *mut ffi::lua_State) -> c_int {
+    //profiling::scope!("draw_image_quad_gradient");
+    let lua_instance = unsafe { Lua::get_or_init_from_ptr(state) };
+    let ctx = context(lua_instance);
+    let args = unsafe { Args::new(state) };
+
+    let nargs = args.count();
+    if !matches!(nargs, 13 | 14 | 21 | 22) {
+        args.error(format_args!("unexpected number of arguments ({nargs})"));
+    }
+
+    let parse_uv = matches!(nargs, 21 | 22);
+    let parse_layer_idx = matches!(nargs, 14 | 22);
+
+    let (texture_id, frame) = args.image(1).unzip();
+
+    // x1, y1, x2, y2, ...
+    let quad = quad_from_args(&args, 2);
+
+    // color1, ..., color4
+    let colors = [
+        args.color(10),
+        args.color(11),
+        args.color(12),
+        args.color(13),
+    ];
+
+    // u1, v1, u2, v2, ...
+    let mut i = 14;
+    let uv = if parse_uv {
+        let uv = quad_from_args(&args, i);
+        i += 8;
+        uv
+    } else {
+        Quad::default_uv()
+    };
+
+    let layer_idx = if parse_layer_idx {
+        (args.i32(i) - 1) as u32
+    } else {
+        frame.unwrap_or(0)
+    };
+
+    ctx.layers()
+        .draw_gradient_quad(texture_id, quad, uv, colors, layer_idx);
+
+    0
+}
+
+// DrawImageNineSlice(imgHandle, left, top, width, height, insetLeft, insetTop, insetRight, insetBottom, [u1, v1, u2, v2], [layer])
+//
+// Draws an image as a 3x3 grid, e.g. a panel border, in one call instead of one per
+// corner and edge. The insets are measured in pixels of the image, and the corners are
+// drawn at that size. The edges and the center stretch to fill the rect.
+unsafe extern "C-unwind" fn draw_image_nine_slice(state: *mut ffi::lua_State) -> c_int {
+    //profiling::scope!("draw_image_nine_slice");
     let lua_instance = unsafe { Lua::get_or_init_from_ptr(state) };
-    let ctx = lua_instance.app_data_ref::<&'static Context>().unwrap();
+    let ctx = context(lua_instance);
+    let args = unsafe { Args::new(state) };
+
+    let nargs = args.count();
+    if !matches!(nargs, 9 | 10 | 13 | 14) {
+        args.error(format_args!("unexpected number of arguments ({nargs})"));
+    }
 
-    let nargs = unsafe { ffi::lua_gettop(state) };
+    let parse_uv = matches!(nargs, 13 | 14);
+    let parse_layer_idx = matches!(nargs, 10 | 14);
+
+    let (texture_id, frame) = args.image(1).unzip();
+
+    // left, top, width, height
+    let origin = Point::new(args.f32(2), args.f32(3));
+    let size = Size::new(args.f32(4), args.f32(5));
+    let rect = Rect::from_origin_and_size(origin, size);
 
-    let x = f32_from_stack!(state, -nargs);
-    let y = f32_from_stack!(state, -nargs + 1);
-    let alignment = str_from_stack!(state, -nargs + 2);
-    let line_height = i32_from_stack!(state, -nargs + 3);
-    let font_type = str_from_stack!(state, -nargs + 4);
-    let text = str_from_stack!(state, -nargs + 5);
+    // left, top, right, bottom
+    let insets = [args.f32(6), args.f32(7), args.f32(8), args.f32(9)];
 
-    let alignment = match alignment.parse::<PoBTextAlignment>() {
-        Ok(alignment) => alignment,
-        Err(_) => panic!("Invalid alignment"),
+    // u1, v1, u2, v2
+    let mut i = 10;
+    let uv = if parse_uv {
+        let uv = Rect::new(
+            Point::new(args.f32(i), args.f32(i + 1)),
+            Point::new(args.f32(i + 2), args.f32(i + 3)),
+        );
+        i += 4;
+        uv
+    } else {
+        Rect::default_uv()
     };
-    let font_type = match font_type.parse::<PoBFontType>() {
-        Ok(font_type) => font_type,
-        Err(_) => panic!("Invalid font type"),
+
+    let layer_idx = if parse_layer_idx {
+        (args.i32(i) - 1) as u32
+    } else {
+        frame.unwrap_or(0)
+    };
+
+    let texture_size = texture_id.map(|id| ctx.texture_manager().texture_size(id));
+    let uv_insets = match texture_size {
+        Some([width, height]) if width > 0 && height > 0 => {
+            let [left, top, right, bottom] = insets;
+            let (width, height) = (width as f32, height as f32);
+            [left / width, top / height, right / width, bottom / height]
+        }
+        _ => [0.0; 4],
+    };
+
+    ctx.layers()
+        .draw_nine_slice(texture_id, rect, insets, uv, uv_insets, layer_idx);
+
+    0
+}
+
+// DrawLine(x1, y1, x2, y2, width)
+//
+// Draws an anti-aliased line with round caps in the current draw color.
+unsafe extern "C-unwind" fn draw_line(state: *mut ffi::lua_State) -> c_int {
+    //profiling::scope!("draw_line");
+    let lua_instance = unsafe { Lua::get_or_init_from_ptr(state) };
+    let ctx = context(lua_instance);
+    let args = unsafe { Args::new(state) };
+
+    let nargs = args.count();
+    if nargs != 5 {
+        args.error(format_args!("unexpected number of arguments ({nargs})"));
+    }
+
+    let from = Point::new(args.f32(1), args.f32(2));
+    let to = Point::new(args.f32(3), args.f32(4));
+    let width = args.f32(5);
+
+    ctx.layers().draw_line(vec![from, to], width);
+
+    0
+}
+
+// DrawPolyline({x1, y1, x2, y2, ...}, width)
+//
+// Draws connected, anti-aliased line segments with round joins in the current draw color.
+unsafe extern "C-unwind" fn draw_polyline(state: *mut ffi::lua_State) -> c_int {
+    //profiling::scope!("draw_polyline");
+    let lua_instance = unsafe { Lua::get_or_init_from_ptr(state) };
+    let ctx = context(lua_instance);
+    let args = unsafe { Args::new(state) };
+
+    let nargs = args.count();
+    if nargs != 2 {
+        args.error(format_args!("unexpected number of arguments ({nargs})"));
+    }
+
+    let len = args.table_len(1);
+    if len % 2 != 0 {
+        args.error(format_args!("odd number of coordinates ({len})"));
+    }
+    let width = args.f32(2);
+
+    // a coordinate that isn't a number raises an error, which would leak the
+    // partially collected points, so all of them are checked before allocating
+    for i in 1..=len {
+        args.table_f32(1, i);
+    }
+    let points = (1..len)
+        .step_by(2)
+        .map(|i| Point::new(args.table_f32(1, i), args.table_f32(1, i + 1)))
+        .collect();
+
+    ctx.layers().draw_line(points, width);
+
+    0
+}
+
+unsafe extern "C-unwind" fn draw_string(state: *mut ffi::lua_State) -> c_int {
+    //profiling::scope!("draw_string");
+    let lua_instance = unsafe { Lua::get_or_init_from_ptr(state) };
+    let ctx = context(lua_instance);
+    let args = unsafe { Args::new(state) };
+
+    let x = args.f32(1);
+    let y = args.f32(2);
+    let alignment = args.parse::<PoBTextAlignment>(3, "alignment");
+    let line_height = args.i32(4);
+    let font_type = args.parse::<PoBFontType>(5, "font type");
+    let text = args.str(6);
+    // optional clockwise rotation in radians around the text's anchor point
+    let rotation = if args.count() >= 7 { args.f32(7) } else { 0.0 };
+    // optional vertical alignment, the y coordinate describes the top by default
+    let vertical_alignment = if args.count() >= 8 {
+        args.parse::<PoBVerticalAlignment>(8, "vertical alignment")
+    } else {
+        PoBVerticalAlignment::Top
     };
 
     let mut position = Point::new(x, y);
@@ -405,9 +513,15 @@ unsafe extern "C-unwind" fn draw_string(state: *mut ffi::lua_State) -> c_int {
         ctx.layers().set_draw_color(last_segment.color);
     }
 
-    let layout = ctx.fonts().layout(job, ctx.window().scale_factor());
+    let layout = ctx.fonts().layout(job, ctx.window().pixels_per_point());
+    position.y -= match vertical_alignment {
+        PoBVerticalAlignment::Top => 0.0,
+        PoBVerticalAlignment::Middle => layout.height() / 2.0,
+        PoBVerticalAlignment::Bottom => layout.height(),
+        PoBVerticalAlignment::Baseline => layout.baseline,
+    };
     ctx.layers()
-        .draw_text(position, layout, is_absolute_position);
+        .draw_text(position, layout, rotation, is_absolute_position);
 
     0
 }
@@ -415,26 +529,126 @@ unsafe extern "C-unwind" fn draw_string(state: *mut ffi::lua_State) -> c_int {
 unsafe extern "C-unwind" fn get_string_width(state: *mut ffi::lua_State) -> c_int {
     //profiling::scope!("get_string_width");
     let lua_instance = unsafe { Lua::get_or_init_from_ptr(state) };
-    let ctx = lua_instance.app_data_ref::<&'static Context>().unwrap();
-
-    let nargs = unsafe { ffi::lua_gettop(state) };
+    let ctx = context(lua_instance);
+    let args = unsafe { Args::new(state) };
 
-    let line_height = i32_from_stack!(state, -nargs);
-    let font_type = str_from_stack!(state, -nargs + 1);
-    let text = str_from_stack!(state, -nargs + 2);
-
-    let font_type = match font_type.parse::<PoBFontType>() {
-        Ok(font_type) => font_type,
-        Err(_) => panic!("Invalid font type"),
-    };
+    let line_height = args.i32(1);
+    let font_type = args.parse::<PoBFontType>(2, "font type");
+    let text = args.str(3);
 
     let job = build_layout_job(text, Srgba::WHITE, font_type, line_height, None);
-    let width = ctx.fonts().get_text_width(job, ctx.window().scale_factor());
+    let width = ctx
+        .fonts()
+        .get_text_width(job, ctx.window().pixels_per_point());
 
     unsafe { ffi::lua_pushnumber(state, width as f64) };
     1
 }
 
+// widths = DrawStringWidthMulti(height, font, {strings})
+//
+// Measures a whole list of strings in a single call to avoid paying the Lua -> Rust
+// crossing for every string. Layouts end up in the layout cache, so drawing the
+// measured strings afterwards doesn't lay them out again.
+unsafe extern "C-unwind" fn get_string_width_multi(state: *mut ffi::lua_State) -> c_int {
+    //profiling::scope!("get_string_width_multi");
+    let lua_instance = unsafe { Lua::get_or_init_from_ptr(state) };
+    let ctx = context(lua_instance);
+    let args = unsafe { Args::new(state) };
+
+    let line_height = args.i32(1);
+    let font_type = args.parse::<PoBFontType>(2, "font type");
+    let len = args.table_len(3);
+
+    unsafe { ffi::lua_createtable(state, len as c_int, 0) };
+    for i in 1..=len {
+        let text = args.table_str(3, i);
+        let job = build_layout_job(text, Srgba::WHITE, font_type, line_height, None);
+        let width = ctx
+            .fonts()
+            .get_text_width(job, ctx.window().pixels_per_point());
+        unsafe {
+            ffi::lua_pushnumber(state, width as f64);
+            ffi::lua_rawseti(state, -2, i as ffi::lua_Integer);
+        }
+    }
+
+    1
+}
+
+fn get_async_count(l: &Lua, _: ()) -> LuaResult<usize> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    Ok(ctx.texture_manager().pending_loads())
+}
+
+/// URL under the cursor, which is relative to the position the text is drawn at.
+/// Returns nil if the cursor isn't over a link.
+fn get_link_at_cur(
+    l: &Lua,
+    (line_height, font_type, text, cur_x, cur_y): (i32, String, String, f32, f32),
+) -> LuaResult<Option<String>> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+
+    let font_type = font_type.parse::<PoBFontType>()?;
+
+    let job = build_layout_job(&text, Srgba::WHITE, font_type, line_height, None);
+    let layout = ctx.fonts().layout(job, ctx.window().pixels_per_point());
+    Ok(layout.link_at(Point::new(cur_x, cur_y)))
+}
+
+/// Rects `{ x, y, width, height }` covering the text between the 1-based cursor
+/// positions `start` and `finish`, as returned by `DrawStringCursorIndex`.
+fn get_selection_rects(
+    l: &Lua,
+    (line_height, font_type, text, start, finish): (i32, String, String, usize, usize),
+) -> LuaResult<Table> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+
+    let font_type = font_type.parse::<PoBFontType>()?;
+
+    let job = build_layout_job(&text, Srgba::WHITE, font_type, line_height, None);
+    let layout = ctx.fonts().layout(job, ctx.window().pixels_per_point());
+    // convert from lua's 1-based indexing
+    let start = stripped_index(&text, start.saturating_sub(1));
+    let finish = stripped_index(&text, finish.saturating_sub(1));
+    let rects = l.create_table()?;
+    for rect in layout.range_rects(start.min(finish)..start.max(finish)) {
+        let table = l.create_table()?;
+        table.set("x", rect.min.x)?;
+        table.set("y", rect.min.y)?;
+        table.set("width", rect.width())?;
+        table.set("height", rect.height())?;
+        rects.push(table)?;
+    }
+    Ok(rects)
+}
+
+/// 1-based cursor positions of the start and end of the text selected by dragging
+/// from one point to the other.
+fn get_selection_range(
+    l: &Lua,
+    (line_height, font_type, text, x1, y1, x2, y2): (i32, String, String, f32, f32, f32, f32),
+) -> LuaResult<(usize, usize)> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+
+    let font_type = font_type.parse::<PoBFontType>()?;
+
+    let job = build_layout_job(&text, Srgba::WHITE, font_type, line_height, None);
+    let layout = ctx.fonts().layout(job, ctx.window().pixels_per_point());
+    let range = layout.range_between(Point::new(x1, y1), Point::new(x2, y2));
+    // convert to lua's 1-based indexing
+    Ok((
+        source_index(&text, range.start) + 1,
+        source_index(&text, range.end) + 1,
+    ))
+}
+
+fn set_underline_links(l: &Lua, underline_links: bool) -> LuaResult<()> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    ctx.layers().set_underline_links(underline_links);
+    Ok(())
+}
+
 fn get_index_at_cur(
     l: &Lua,
     (line_height, font_type, text, cur_x, cur_y): (i32, String, String, f32, f32),
@@ -448,15 +662,50 @@ fn get_index_at_cur(
     let index = ctx.fonts().get_text_index_at_cursor(
         job,
         Point::new(cur_x, cur_y),
-        ctx.window().scale_factor(),
+        ctx.window().pixels_per_point(),
     );
 
     // convert to lua's 1-based indexing
-    Ok(index + 1)
+    Ok(source_index(&text, index) + 1)
+}
+
+/// Byte index in `text` of `index`, which is a byte index in `text` without escape
+/// codes. Indices right after an escape code are placed after it.
+fn source_index(text: &str, index: usize) -> usize {
+    if !text.contains('^') {
+        return index;
+    }
+
+    let segments = SEGMENT_CACHE.with_borrow_mut(|cache| cache.get(text));
+    let mut segment_start = 0;
+    for (_, range) in segments.iter() {
+        if index < segment_start + range.len() {
+            return range.start + index - segment_start;
+        }
+        segment_start += range.len();
+    }
+    text.len()
+}
+
+/// Inverse of [`source_index`]. Indices inside an escape code are placed after it.
+fn stripped_index(text: &str, index: usize) -> usize {
+    if !text.contains('^') {
+        return index.min(text.len());
+    }
+
+    let segments = SEGMENT_CACHE.with_borrow_mut(|cache| cache.get(text));
+    let mut segment_start = 0;
+    for (_, range) in segments.iter() {
+        if index < range.end {
+            return segment_start + index.saturating_sub(range.start);
+        }
+        segment_start += range.len();
+    }
+    segment_start
 }
 
 pub static ESCAPE_STR_REGEX: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r"\^(?<idx>[0-9])|\^[x|X](?<hex>[0-9A-Fa-f]{6})").unwrap());
+    LazyLock::new(|| Regex::new(r"\^(?<idx>[0-9])|\^[xX](?<hex>[0-9A-Fa-f]{6})").unwrap());
 
 fn build_layout_job<'a>(
     text: &'a str,
@@ -492,6 +741,7 @@ fn build_layout_job<'a>(
     //
     // TODO: font size in some dropdowns is too small, e.g. socket group selection in
     // 'Calcs' tab
+    let line_height = clamp_line_height(line_height);
     let font_size = (line_height - 2).max(1) as f32;
 
     let mut job = LayoutJob::new(
@@ -503,13 +753,76 @@ fn build_layout_job<'a>(
         font_style,
     );
 
-    for (color, segment) in PoBString(text).into_iter() {
-        job.append(segment, color.unwrap_or(current_color));
+    // most strings don't contain escape codes
+    if !text.contains('^') {
+        job.append(text, current_color);
+        return job;
+    }
+
+    let segments = SEGMENT_CACHE.with_borrow_mut(|cache| cache.get(text));
+    for (color, range) in segments.iter() {
+        job.append(&text[range.clone()], color.unwrap_or(current_color));
     }
 
     job
 }
 
+thread_local! {
+    // strings are drawn on the main thread only
+    static SEGMENT_CACHE: RefCell<SegmentCache> = RefCell::default();
+}
+
+/// Strings after which the older generation of the segment cache is dropped
+const SEGMENT_CACHE_CAPACITY: usize = 4096;
+
+/// Colored segment of a string as byte range
+type ColoredRange = (Option<Srgba>, Range<usize>);
+
+/// Segments of recently drawn strings with escape codes. PoB draws mostly the same
+/// strings every frame, which saves scanning them with the regex again.
+#[derive(Default)]
+struct SegmentCache {
+    current: HashMap<Box<str>, Rc<[ColoredRange]>>,
+    // strings that weren't used since the current generation started
+    previous: HashMap<Box<str>, Rc<[ColoredRange]>>,
+}
+
+impl SegmentCache {
+    fn get(&mut self, text: &str) -> Rc<[ColoredRange]> {
+        if let Some(segments) = self.current.get(text) {
+            return Rc::clone(segments);
+        }
+
+        let segments = self.previous.remove(text).unwrap_or_else(|| {
+            PoBString(text)
+                .into_iter()
+                .map(|(color, segment)| {
+                    let start = segment.as_ptr() as usize - text.as_ptr() as usize;
+                    (color, start..start + segment.len())
+                })
+                .collect()
+        });
+
+        if self.current.len() >= SEGMENT_CACHE_CAPACITY {
+            self.previous = std::mem::take(&mut self.current);
+        }
+        self.current.insert(text.into(), Rc::clone(&segments));
+        segments
+    }
+}
+
+/// Largest line height accepted from Lua. Anything bigger is most likely a bug and
+/// would produce glyphs that don't fit into the font atlas.
+const MAX_LINE_HEIGHT: i32 = 256;
+
+fn clamp_line_height(line_height: i32) -> i32 {
+    // debug level, since scripts draw the same text every frame
+    if line_height > MAX_LINE_HEIGHT {
+        log::debug!("Line height {line_height} clamped to {MAX_LINE_HEIGHT}");
+    }
+    line_height.clamp(0, MAX_LINE_HEIGHT)
+}
+
 // PoB strings can contain escape codes that affect the color of subsequent text
 pub struct PoBString<'a>(pub &'a str);
 
@@ -633,6 +946,31 @@ impl std::str::FromStr for PoBTextAlignment {
     }
 }
 
+enum PoBVerticalAlignment {
+    // y coordinate describes the top of the text
+    Top,
+    // y coordinate describes the vertical center of all lines
+    Middle,
+    // y coordinate describes the bottom of the last line
+    Bottom,
+    // y coordinate describes the baseline of the first line
+    Baseline,
+}
+
+impl std::str::FromStr for PoBVerticalAlignment {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "TOP" => Ok(Self::Top),
+            "MIDDLE" => Ok(Self::Middle),
+            "BOTTOM" => Ok(Self::Bottom),
+            "BASELINE" => Ok(Self::Baseline),
+            _ => Err(anyhow::anyhow!("'{}' is not a valid vertical alignment", s)),
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 enum PoBFontType {
     Fixed,
@@ -691,3 +1029,90 @@ impl Srgba {
         Srgba::WHITE
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dpi::LogicalSize,
+        gfx::offscreen::{assert_matches_golden, render_scene},
+    };
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_escape_coded_strings() {
+        let strings = [
+            "^7White ^1red ^2green ^4blue",
+            "^xE5B428Hex ^xFFFFFFand ^8gray",
+            "No escape codes",
+            "^3Fixed ^5width",
+        ];
+        let Some(image) = render_scene(LogicalSize::new(192, 96), |layers, fonts| {
+            for (i, text) in strings.into_iter().enumerate() {
+                let font_type = match i {
+                    3 => PoBFontType::Fixed,
+                    _ => PoBFontType::Var,
+                };
+                let job = build_layout_job(text, Srgba::WHITE, font_type, 16, None);
+                let layout = fonts.layout(job, 1.0);
+                layers.draw_text(Point::new(4.0, 4.0 + i as f32 * 22.0), layout, 0.0, false);
+            }
+        }) else {
+            return;
+        };
+        assert_matches_golden("escape_coded_strings", &image);
+    }
+
+    #[test]
+    fn test_index_mapping() {
+        let text = "^1Lv 90 ^xFFFFFF🔥 ビルド";
+        let stripped = ESCAPE_STR_REGEX.replace_all(text, "");
+        for (index, ch) in stripped.char_indices() {
+            assert!(text[source_index(text, index)..].starts_with(ch));
+            assert_eq!(stripped_index(text, source_index(text, index)), index);
+        }
+        assert_eq!(source_index(text, stripped.len()), text.len());
+        assert_eq!(stripped_index(text, text.len()), stripped.len());
+        // inside an escape code
+        assert_eq!(stripped_index(text, 0), 0);
+        assert_eq!(stripped_index(text, 10), 6);
+        assert_eq!(source_index("🔥 ビルド", 5), 5);
+        assert_eq!(stripped_index("🔥 ビルド", 5), 5);
+    }
+
+    // mostly escape codes and fragments of them, mixed with some text
+    fn pob_string() -> impl Strategy<Value = String> {
+        let part = prop_oneof![
+            "\\^[0-9]",
+            "\\^[xX][0-9a-fA-F]{0,7}",
+            Just("^".to_owned()),
+            "\\PC{0,4}",
+        ];
+        prop::collection::vec(part, 0..8).prop_map(|parts| parts.concat())
+    }
+
+    proptest! {
+        #[test]
+        fn segments_match_stripped_text(text in pob_string()) {
+            let stripped = PoBString(&text).strip_escapes();
+            let segments: String = PoBString(&text).into_iter().map(|(_, s)| s).collect();
+            prop_assert_eq!(segments, stripped);
+        }
+
+        #[test]
+        fn from_escape_code_never_panics(code in "\\^[xX0-9]\\PC{0,8}") {
+            let _ = Srgba::from_escape_code(&code);
+        }
+
+        #[test]
+        fn source_index_maps_stripped_chars(text in pob_string()) {
+            let stripped = PoBString(&text).strip_escapes();
+            for (index, ch) in stripped.char_indices() {
+                let index = source_index(&text, index);
+                prop_assert!(text.is_char_boundary(index));
+                prop_assert!(text[index..].starts_with(ch));
+            }
+            prop_assert_eq!(source_index(&text, stripped.len()), text.len());
+        }
+    }
+}
@@ -1,57 +1,147 @@
-use glob::{Paths, glob};
-use mlua::{IntoLua, Lua, Result as LuaResult, UserData, Value};
+use glob::{MatchOptions, glob_with};
+use mlua::{IntoLua, Lua, Result as LuaResult, Table, UserData, Value};
 use std::{
+    ffi::OsStr,
     fs,
     path::{Path, PathBuf},
     time::SystemTime,
 };
 
+/// `NewFileSearch(pattern, [findDirectories], [options])`. Returns nil if nothing
+/// matches. `options` is a table with:
+/// - `recursive`: also searches the subdirectories of the pattern's directory.
+///   `GetFileName` returns the path relative to that directory then
+/// - `caseSensitive`: matches wildcards case-sensitively, off by default like on
+///   Windows
 pub fn new_search_handle(
     l: &Lua,
-    (pattern, find_directories): (String, Option<bool>),
+    (pattern, find_directories, options): (String, Option<bool>, Option<Table>),
 ) -> LuaResult<Value> {
-    if let Ok(paths) = glob(&pattern) {
-        let directories_only = find_directories.is_some_and(|x| x);
-        let mut handle = SearchHandle::new(paths, directories_only);
-        // try to get the first result
-        handle.next();
-        // only return a handle if at least one file/directory is found
-        if handle.current.is_some() {
-            return handle.into_lua(l);
-        }
+    let options = SearchOptions {
+        directories_only: find_directories.unwrap_or(false),
+        recursive: get_option(&options, "recursive")?,
+        case_sensitive: get_option(&options, "caseSensitive")?,
     };
+    let root = options.recursive.then(|| {
+        PathBuf::from(
+            split_pattern(&normalize_separators(&pattern))
+                .0
+                .unwrap_or(""),
+        )
+    });
+    let mut handle = SearchHandle::new(search(&pattern, options), root);
+    // try to get the first result
+    handle.next();
+    // only return a handle if at least one file/directory is found
+    if handle.current.is_some() {
+        return handle.into_lua(l);
+    }
     Ok(Value::Nil)
 }
 
-pub struct SearchHandle {
-    paths: Paths,
+fn get_option(options: &Option<Table>, name: &str) -> LuaResult<bool> {
+    match options {
+        Some(options) => Ok(options.get::<Option<bool>>(name)?.unwrap_or(false)),
+        None => Ok(false),
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct SearchOptions {
     // only yield directories if true, otherwise only files
     directories_only: bool,
+    recursive: bool,
+    case_sensitive: bool,
+}
+
+/// Paths matching a Windows-style pattern like `C:\Builds\*.xml`, sorted.
+fn search(pattern: &str, options: SearchOptions) -> Vec<PathBuf> {
+    let pattern = windows_pattern_to_glob(pattern, options.recursive);
+    let match_options = MatchOptions {
+        case_sensitive: options.case_sensitive,
+        require_literal_separator: true,
+        require_literal_leading_dot: false,
+    };
+    let Ok(paths) = glob_with(&pattern, match_options) else {
+        return Vec::new();
+    };
+    // entries that can't be read are skipped
+    let mut paths: Vec<_> = paths
+        .filter_map(Result::ok)
+        .filter(|path| path.is_dir() == options.directories_only)
+        .collect();
+    // case-insensitively like Windows. the order of recursive matches depends on
+    // the directory structure otherwise
+    paths.sort_by_cached_key(|path| path.to_string_lossy().to_lowercase());
+    paths
+}
+
+fn normalize_separators(pattern: &str) -> String {
+    // backslashes are literal characters in patterns on other platforms
+    if cfg!(windows) {
+        pattern.to_owned()
+    } else {
+        pattern.replace('\\', "/")
+    }
+}
+
+/// Splits a pattern into its directory, if it has one, and the name to match.
+fn split_pattern(pattern: &str) -> (Option<&str>, &str) {
+    match pattern.rfind(['/', '\\']) {
+        Some(idx) => (Some(&pattern[..idx]), &pattern[idx + 1..]),
+        None => (None, pattern),
+    }
+}
+
+fn windows_pattern_to_glob(pattern: &str, recursive: bool) -> String {
+    let pattern = normalize_separators(pattern);
+    let (dir, name) = split_pattern(&pattern);
+    // on Windows `*.*` also matches names without an extension
+    let name = if name == "*.*" { "*" } else { name };
+    let name = if recursive {
+        format!("**/{name}")
+    } else {
+        name.to_owned()
+    };
+    match dir {
+        Some(dir) => format!("{dir}/{name}"),
+        None => name,
+    }
+}
+
+pub struct SearchHandle {
+    paths: std::vec::IntoIter<PathBuf>,
     pub current: Option<PathBuf>,
+    // directory of the pattern in recursive searches, names are relative to it
+    root: Option<PathBuf>,
 }
 
 impl SearchHandle {
-    pub fn new(paths: Paths, directories_only: bool) -> Self {
+    fn new(paths: Vec<PathBuf>, root: Option<PathBuf>) -> Self {
         Self {
-            paths,
-            directories_only,
+            paths: paths.into_iter(),
             current: None,
+            root,
+        }
+    }
+
+    /// Name of the current match, or its path relative to the pattern's directory
+    /// in recursive searches
+    fn file_name(&self) -> Option<&OsStr> {
+        let path = self.current.as_ref()?;
+        match self
+            .root
+            .as_ref()
+            .and_then(|root| path.strip_prefix(root).ok())
+        {
+            Some(relative) => Some(relative.as_os_str()),
+            None => path.file_name(),
         }
     }
 
-    // sets current to the next file/directory if it exists and doesn't cause errors,
-    // otherwise None
+    // sets current to the next file/directory if it exists, otherwise None
     pub fn next(&mut self) {
-        self.current = self
-            .paths
-            .find(|candidate| {
-                candidate
-                    .as_ref()
-                    .is_ok_and(|c| c.is_dir() == self.directories_only)
-            })
-            .transpose()
-            .ok()
-            .flatten();
+        self.current = self.paths.next();
     }
 }
 
@@ -62,7 +152,7 @@ impl UserData for SearchHandle {
             Ok(this.current.is_some())
         });
         methods.add_method_mut("GetFileName", |l, this, ()| {
-            Ok(this.current.as_ref().unwrap().file_name().into_lua(l))
+            Ok(this.file_name().into_lua(l))
         });
         methods.add_method("GetFileSize", |_, this, ()| match &this.current {
             Some(path) => match fs::metadata(path) {
@@ -85,3 +175,101 @@ fn get_time_modified<P: AsRef<Path>>(path: P) -> anyhow::Result<u64> {
     let seconds_since_epoch = duration_since_epoch.as_secs();
     Ok(seconds_since_epoch)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(paths: Vec<PathBuf>, root: &Path) -> Vec<String> {
+        paths
+            .iter()
+            .map(|path| {
+                path.strip_prefix(root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .replace('\\', "/")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_search() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        for dir in ["Builds/Witch", "Builds/Ranger/Old"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in [
+            "Builds/b.xml",
+            "Builds/A.XML",
+            "Builds/README",
+            "Builds/Witch/c.xml",
+            "Builds/Ranger/Old/d.xml",
+        ] {
+            fs::write(root.join(file), "").unwrap();
+        }
+        let root_pattern = root.to_string_lossy().replace('\\', "/");
+        let search = |pattern: &str, options| {
+            names(search(&format!("{root_pattern}/{pattern}"), options), root)
+        };
+
+        let options = SearchOptions::default();
+        assert_eq!(
+            search("Builds/*.xml", options),
+            ["Builds/A.XML", "Builds/b.xml"]
+        );
+        assert_eq!(
+            search("Builds\\*.*", options),
+            ["Builds/A.XML", "Builds/b.xml", "Builds/README"]
+        );
+        assert!(search("Builds/*.json", options).is_empty());
+
+        let case_sensitive = SearchOptions {
+            case_sensitive: true,
+            ..options
+        };
+        assert_eq!(search("Builds/*.xml", case_sensitive), ["Builds/b.xml"]);
+
+        let recursive = SearchOptions {
+            recursive: true,
+            ..options
+        };
+        assert_eq!(
+            search("Builds/*.xml", recursive),
+            [
+                "Builds/A.XML",
+                "Builds/b.xml",
+                "Builds/Ranger/Old/d.xml",
+                "Builds/Witch/c.xml"
+            ]
+        );
+
+        let directories = SearchOptions {
+            directories_only: true,
+            ..recursive
+        };
+        assert_eq!(
+            search("Builds/*", directories),
+            ["Builds/Ranger", "Builds/Ranger/Old", "Builds/Witch"]
+        );
+
+        // recursive matches are named relative to the pattern's directory
+        let builds = root.join("Builds");
+        let mut handle = SearchHandle::new(vec![builds.join("Witch/c.xml")], Some(builds));
+        handle.next();
+        assert_eq!(
+            handle.file_name().map(Path::new),
+            Some(Path::new("Witch/c.xml"))
+        );
+    }
+
+    #[test]
+    fn test_split_pattern() {
+        assert_eq!(
+            split_pattern("C:\\PoB\\Builds\\*.xml"),
+            (Some("C:\\PoB\\Builds"), "*.xml")
+        );
+        assert_eq!(split_pattern("Builds/Old/*"), (Some("Builds/Old"), "*"));
+        assert_eq!(split_pattern("*.*"), (None, "*.*"));
+    }
+}
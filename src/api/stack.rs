@@ -0,0 +1,224 @@
+//! Typed access to the arguments of the C functions in `rendering.rs`.
+//!
+//! The readers are thin wrappers around the `luaL_check*` functions, so they cost
+//! the same as using the raw stack. Arguments are addressed by their 1-based
+//! position, as in error messages. Invalid arguments raise Lua errors, which unwind
+//! through the C function like any other Lua error. The unwinding is a longjmp that
+//! skips destructors, so nothing that needs to be dropped, like an app data guard,
+//! may be alive while a reader is called.
+//!
+//! Strings are borrowed from the stack without copying. They are assumed to be
+//! valid UTF-8, which is what PoB passes. Enable the `checked-lua-strings` feature
+//! to validate them, e.g. while debugging garbled text.
+
+use crate::{api::image_handle::ImageHandle, color::Srgba, renderer::textures::TextureId};
+use core::ffi::{CStr, c_char, c_int};
+use mlua::ffi;
+use std::fmt::Display;
+
+pub struct Args {
+    state: *mut ffi::lua_State,
+    nargs: c_int,
+}
+
+impl Args {
+    /// # Safety
+    ///
+    /// `state` has to be the state passed to the running C function, and the
+    /// arguments must not be removed from the stack while `Args` is used.
+    #[inline]
+    pub unsafe fn new(state: *mut ffi::lua_State) -> Self {
+        let nargs = unsafe { ffi::lua_gettop(state) };
+        Self { state, nargs }
+    }
+
+    /// Number of arguments passed to the function
+    #[inline]
+    pub fn count(&self) -> c_int {
+        self.nargs
+    }
+
+    #[inline]
+    pub fn f32(&self, arg: c_int) -> f32 {
+        self.check_arg(arg);
+        unsafe { ffi::luaL_checknumber(self.state, arg) as f32 }
+    }
+
+    #[inline]
+    pub fn i32(&self, arg: c_int) -> i32 {
+        self.check_arg(arg);
+        unsafe { ffi::luaL_checkinteger(self.state, arg) as i32 }
+    }
+
+    /// An integer or nil
+    #[inline]
+    pub fn opt_i32(&self, arg: c_int) -> Option<i32> {
+        self.check_arg(arg);
+        match unsafe { ffi::lua_type(self.state, arg) } {
+            ffi::LUA_TNIL => None,
+            ffi::LUA_TNUMBER => Some(self.i32(arg)),
+            t => self.type_error(arg, "nil or number", t),
+        }
+    }
+
+    #[inline]
+    pub fn str(&self, arg: c_int) -> &str {
+        self.check_arg(arg);
+        unsafe { self.to_str(arg) }
+    }
+
+    /// Parses a string argument such as an alignment. `what` names it in errors.
+    #[inline]
+    pub fn parse<T: std::str::FromStr>(&self, arg: c_int, what: &str) -> T {
+        let value = self.str(arg);
+        match value.parse() {
+            Ok(value) => value,
+            Err(_) => self.error(format_args!("invalid {what} {value}")),
+        }
+    }
+
+    /// Texture and current animation frame of an image handle, or `None` if the
+    /// argument is nil or the image isn't loaded
+    #[inline]
+    pub fn image(&self, arg: c_int) -> Option<(TextureId, u32)> {
+        self.check_arg(arg);
+        match unsafe { ffi::lua_type(self.state, arg) } {
+            ffi::LUA_TNIL => None,
+            ffi::LUA_TUSERDATA => {
+                let img_handle = unsafe { lua_toimghandle(self.state, arg) };
+                if !img_handle.is_null() {
+                    let img_handle = unsafe { &*img_handle };
+                    img_handle.texture_id().map(|id| (id, img_handle.frame()))
+                } else {
+                    None
+                }
+            }
+            t => self.type_error(arg, "nil or ImageHandle", t),
+        }
+    }
+
+    /// Reads a color table of the form {r, g, b, [a]}
+    #[inline]
+    pub fn color(&self, arg: c_int) -> Srgba {
+        self.check_arg(arg);
+        let mut components = [1.0; 4];
+        unsafe {
+            ffi::luaL_checktype(self.state, arg, ffi::LUA_TTABLE);
+            for (n, component) in components.iter_mut().enumerate() {
+                ffi::lua_rawgeti(self.state, arg, n as ffi::lua_Integer + 1);
+                // alpha is optional
+                if n < 3 || ffi::lua_isnil(self.state, -1) == 0 {
+                    *component = ffi::luaL_checknumber(self.state, -1) as f32;
+                }
+                ffi::lua_pop(self.state, 1);
+            }
+        }
+        let [r, g, b, a] = components;
+        Srgba::new_f32(r, g, b, a)
+    }
+
+    /// Length of a table argument, raises an error if it isn't a table
+    #[inline]
+    pub fn table_len(&self, arg: c_int) -> usize {
+        self.check_arg(arg);
+        unsafe {
+            ffi::luaL_checktype(self.state, arg, ffi::LUA_TTABLE);
+            ffi::lua_objlen(self.state, arg)
+        }
+    }
+
+    /// Number at the 1-based index `i` of a table argument
+    #[inline]
+    pub fn table_f32(&self, arg: c_int, i: usize) -> f32 {
+        unsafe {
+            ffi::lua_rawgeti(self.state, arg, i as ffi::lua_Integer);
+            let value = ffi::luaL_checknumber(self.state, -1) as f32;
+            ffi::lua_pop(self.state, 1);
+            value
+        }
+    }
+
+    /// String at the 1-based index `i` of a table argument. The table keeps the
+    /// string alive, so it must not be modified while the string is used.
+    #[inline]
+    pub fn table_str(&self, arg: c_int, i: usize) -> &str {
+        unsafe {
+            ffi::lua_rawgeti(self.state, arg, i as ffi::lua_Integer);
+            // numbers would be converted on the stack only, and not be kept alive
+            if ffi::lua_type(self.state, -1) != ffi::LUA_TSTRING {
+                self.error(format_args!(
+                    "bad argument #{arg} (table of strings expected)"
+                ));
+            }
+            let value = self.to_str(-1);
+            ffi::lua_pop(self.state, 1);
+            value
+        }
+    }
+
+    /// Raises a Lua error with the caller's position prepended, like luaL_error.
+    /// Don't panic instead, a panic can't unwind through LuaJIT's frames.
+    pub fn error(&self, msg: impl Display) -> ! {
+        let msg = msg.to_string();
+        unsafe {
+            ffi::luaL_where(self.state, 1);
+            ffi::lua_pushlstring(self.state, msg.as_ptr() as *const c_char, msg.len());
+        }
+        drop(msg);
+        unsafe {
+            ffi::lua_concat(self.state, 2);
+            ffi::lua_error(self.state)
+        }
+    }
+
+    fn type_error(&self, arg: c_int, expected: &str, t: c_int) -> ! {
+        let got = unsafe { CStr::from_ptr(ffi::lua_typename(self.state, t)) };
+        self.error(format_args!(
+            "bad argument #{arg} ({expected} expected, got {})",
+            got.to_string_lossy()
+        ))
+    }
+
+    /// Catches index mistakes in debug builds. Release builds rely on the
+    /// `luaL_check*` functions, which treat missing arguments as nil.
+    #[inline(always)]
+    fn check_arg(&self, arg: c_int) {
+        if cfg!(debug_assertions) && !(1..=self.nargs).contains(&arg) {
+            self.error(format_args!(
+                "argument #{arg} read, but only {} were passed",
+                self.nargs
+            ));
+        }
+    }
+
+    unsafe fn to_str(&self, idx: c_int) -> &str {
+        let bytes = unsafe {
+            let mut size = 0;
+            let data = ffi::luaL_checklstring(self.state, idx, &mut size);
+            std::slice::from_raw_parts(data as *const u8, size)
+        };
+        if cfg!(feature = "checked-lua-strings") {
+            match std::str::from_utf8(bytes) {
+                Ok(value) => value,
+                Err(err) => self.error(format_args!("invalid UTF-8 in argument: {err}")),
+            }
+        } else {
+            unsafe { std::str::from_utf8_unchecked(bytes) }
+        }
+    }
+}
+
+// unwraps the ImageHandle with the helper registered in `rendering::register_globals`
+unsafe fn lua_toimghandle(state: *mut ffi::lua_State, idx: c_int) -> *mut ImageHandle {
+    unsafe {
+        let idx = ffi::lua_absindex(state, idx);
+        ffi::lua_getfield(state, ffi::LUA_REGISTRYINDEX, c"get_img_handle".as_ptr());
+        ffi::lua_pushvalue(state, idx);
+        let img_handle = match ffi::lua_pcall(state, 1, 1, 0) {
+            ffi::LUA_OK => ffi::lua_touserdata(state, -1) as *mut ImageHandle,
+            _ => std::ptr::null_mut(),
+        };
+        ffi::lua_pop(state, 1);
+        img_handle
+    }
+}
@@ -0,0 +1,179 @@
+//! Persistent key-value store backed by SQLite.
+//!
+//! Every write is its own transaction, so values survive crashes without PoB
+//! having to rewrite whole files. Every game and profile has its own database in
+//! its data directory, which is opened on first use.
+
+use crate::args::Game;
+use mlua::{
+    Function, IntoLuaMulti, Lua, MultiValue, Result as LuaResult, String as LuaString, Value,
+};
+use rusqlite::{Connection, OptionalExtension, params};
+use std::{cell::RefCell, path::PathBuf};
+
+const DATABASE_FILE: &str = "storage.sqlite3";
+
+pub struct Storage {
+    path: PathBuf,
+    connection: RefCell<Option<Connection>>,
+}
+
+impl Storage {
+    pub fn new(game: Game) -> Self {
+        Self {
+            path: game.data_dir().join(DATABASE_FILE),
+            connection: RefCell::new(None),
+        }
+    }
+
+    fn init(connection: &Connection) -> rusqlite::Result<()> {
+        // write-ahead log keeps the database consistent if the app crashes mid-write
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS kv (
+                key TEXT NOT NULL PRIMARY KEY,
+                value BLOB NOT NULL
+            ) WITHOUT ROWID",
+            (),
+        )?;
+        Ok(())
+    }
+
+    fn with_connection<T>(
+        &self,
+        f: impl FnOnce(&Connection) -> rusqlite::Result<T>,
+    ) -> anyhow::Result<T> {
+        let mut connection = self.connection.borrow_mut();
+        if connection.is_none() {
+            if let Some(dir) = self.path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            let new_connection = Connection::open(&self.path)?;
+            Self::init(&new_connection)?;
+            *connection = Some(new_connection);
+        }
+        Ok(f(connection.as_ref().unwrap())?)
+    }
+
+    fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        self.with_connection(|connection| {
+            connection
+                .query_row("SELECT value FROM kv WHERE key = ?1", params![key], |row| {
+                    row.get(0)
+                })
+                .optional()
+        })
+    }
+
+    fn set(&self, key: &str, value: &[u8]) -> anyhow::Result<()> {
+        self.with_connection(|connection| {
+            connection.execute(
+                "INSERT INTO kv (key, value) VALUES (?1, ?2)
+                 ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+                params![key, value],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn delete(&self, key: &str) -> anyhow::Result<()> {
+        self.with_connection(|connection| {
+            connection.execute("DELETE FROM kv WHERE key = ?1", params![key])?;
+            Ok(())
+        })
+    }
+
+    /// Returns all entries whose key starts with `prefix`, sorted by key.
+    fn entries(&self, prefix: &str) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+        self.with_connection(|connection| {
+            let mut statement = connection.prepare(
+                "SELECT key, value FROM kv WHERE substr(key, 1, length(?1)) = ?1 ORDER BY key",
+            )?;
+            let rows = statement.query_map(params![prefix], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+            })?;
+            rows.collect()
+        })
+    }
+}
+
+fn error(l: &Lua, err: anyhow::Error) -> LuaResult<MultiValue> {
+    (Value::Nil, err.to_string()).into_lua_multi(l)
+}
+
+pub fn store_get(l: &Lua, key: String) -> LuaResult<MultiValue> {
+    let storage = l.app_data_ref::<Storage>().unwrap();
+    match storage.get(&key) {
+        Ok(Some(value)) => l.create_string(value)?.into_lua_multi(l),
+        Ok(None) => Value::Nil.into_lua_multi(l),
+        Err(err) => error(l, err),
+    }
+}
+
+/// Setting a key to nil deletes it.
+pub fn store_set(l: &Lua, (key, value): (String, Option<LuaString>)) -> LuaResult<MultiValue> {
+    let storage = l.app_data_ref::<Storage>().unwrap();
+    let result = match value {
+        Some(value) => storage.set(&key, &value.as_bytes()),
+        None => storage.delete(&key),
+    };
+    match result {
+        Ok(()) => true.into_lua_multi(l),
+        Err(err) => error(l, err),
+    }
+}
+
+pub fn store_delete(l: &Lua, key: String) -> LuaResult<MultiValue> {
+    let storage = l.app_data_ref::<Storage>().unwrap();
+    match storage.delete(&key) {
+        Ok(()) => true.into_lua_multi(l),
+        Err(err) => error(l, err),
+    }
+}
+
+/// `for key, value in StoreIterate("prefix") do ... end`
+pub fn store_iterate(l: &Lua, prefix: Option<String>) -> LuaResult<MultiValue> {
+    let storage = l.app_data_ref::<Storage>().unwrap();
+    let entries = match storage.entries(prefix.as_deref().unwrap_or_default()) {
+        Ok(entries) => entries,
+        Err(err) => return error(l, err),
+    };
+
+    let mut entries = entries.into_iter();
+    let iterator: Function = l.create_function_mut(move |l, ()| match entries.next() {
+        Some((key, value)) => (key, l.create_string(value)?).into_lua_multi(l),
+        None => Value::Nil.into_lua_multi(l),
+    })?;
+    iterator.into_lua_multi(l)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_storage() {
+        let connection = Connection::open_in_memory().unwrap();
+        Storage::init(&connection).unwrap();
+        let poe1 = Storage::new(Game::Poe1);
+        *poe1.connection.borrow_mut() = Some(connection);
+
+        poe1.set("trade.price.a", b"1").unwrap();
+        poe1.set("trade", b"0").unwrap();
+        poe1.set("TRADE.price", b"0").unwrap();
+        poe1.set("trade.price.b", b"2").unwrap();
+        poe1.set("window.width", b"1280").unwrap();
+        poe1.set("trade.price.a", b"3").unwrap();
+        assert_eq!(poe1.get("trade.price.a").unwrap(), Some(b"3".to_vec()));
+
+        let prices = poe1.entries("trade.").unwrap();
+        let keys: Vec<_> = prices.iter().map(|(key, _)| key.as_str()).collect();
+        assert_eq!(keys, ["trade.price.a", "trade.price.b"]);
+
+        poe1.delete("window.width").unwrap();
+        assert_eq!(poe1.get("window.width").unwrap(), None);
+
+        // every game and profile has its own database
+        assert_ne!(poe1.path, Storage::new(Game::Poe2).path);
+    }
+}
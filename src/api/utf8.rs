@@ -0,0 +1,65 @@
+//! Character-based string functions. Lua's string library works on bytes, which
+//! breaks on multi-byte characters, e.g. in build names.
+
+use mlua::{IntoLuaMulti, Lua, MultiValue, Result as LuaResult, String as LuaString, Value};
+
+/// Number of characters, or nil and the 1-based byte position of the first
+/// invalid byte if `text` isn't valid UTF-8
+pub fn utf8_len(l: &Lua, text: LuaString) -> LuaResult<MultiValue> {
+    match std::str::from_utf8(&text.as_bytes()) {
+        Ok(text) => text.chars().count().into_lua_multi(l),
+        Err(err) => (Value::Nil, err.valid_up_to() + 1).into_lua_multi(l),
+    }
+}
+
+/// Like `string.sub`, but `i` and `j` count characters
+pub fn utf8_sub(_: &Lua, (text, i, j): (LuaString, i64, Option<i64>)) -> LuaResult<String> {
+    let text = text.to_string_lossy();
+    let len = text.chars().count();
+    let chars = char_range(len, i, j.unwrap_or(-1));
+    Ok(text.chars().skip(chars.start).take(chars.len()).collect())
+}
+
+pub fn utf8_lower(_: &Lua, text: LuaString) -> LuaResult<String> {
+    Ok(text.to_string_lossy().to_lowercase())
+}
+
+pub fn utf8_upper(_: &Lua, text: LuaString) -> LuaResult<String> {
+    Ok(text.to_string_lossy().to_uppercase())
+}
+
+/// 0-based range of the characters from `i` to `j`, which are 1-based, inclusive
+/// and count from the end if negative, like the arguments of `string.sub`.
+fn char_range(len: usize, i: i64, j: i64) -> std::ops::Range<usize> {
+    let len = len as i64;
+    let start = match i {
+        i if i < 0 => (len + i + 1).max(1),
+        0 => 1,
+        i => i,
+    };
+    let end = match j {
+        j if j < 0 => len + j + 1,
+        j => j.min(len),
+    };
+    if start > end {
+        return 0..0;
+    }
+    (start - 1) as usize..end as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_char_range() {
+        assert_eq!(char_range(5, 2, 3), 1..3);
+        assert_eq!(char_range(5, 1, -1), 0..5);
+        assert_eq!(char_range(5, -3, -1), 2..5);
+        // clamped to the string like `string.sub`
+        assert_eq!(char_range(3, 0, 10), 0..3);
+        assert_eq!(char_range(3, -10, 2), 0..2);
+        assert_eq!(char_range(3, 3, 2), 0..0);
+        assert_eq!(char_range(0, 1, -1), 0..0);
+    }
+}
@@ -1,8 +1,11 @@
 use crate::{
-    dpi::{LogicalSize, PhysicalSize},
+    dpi::{LogicalPoint, LogicalRect, LogicalSize, PhysicalSize},
     lua::Context,
+    svg::SvgExport,
+    window::{RegionCapture, theme_name},
 };
-use mlua::{Lua, Result as LuaResult};
+use mlua::{IntoLuaMulti, Lua, MultiValue, Result as LuaResult, Value};
+use std::time::Duration;
 
 pub fn get_screen_size(l: &Lua, _: ()) -> LuaResult<(u32, u32)> {
     let ctx = l.app_data_ref::<&'static Context>().unwrap();
@@ -22,32 +25,189 @@ pub fn get_screen_scale(l: &Lua, _: ()) -> LuaResult<f32> {
     Ok(scale_factor)
 }
 
+/// `"DARK"` or `"LIGHT"`, nil if the system theme is unknown
+pub fn get_system_theme(l: &Lua, _: ()) -> LuaResult<Option<&'static str>> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    Ok(ctx.window().theme.map(theme_name))
+}
+
+/// Prevents the window from being shrunk below `width` x `height`, in the same
+/// units as `GetScreenSize`. 0 for both removes the limit.
+pub fn set_minimum_window_size(l: &Lua, (width, height): (u32, u32)) -> LuaResult<()> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    let min_size = if width == 0 && height == 0 {
+        None
+    } else if *ctx.is_dpi_aware() {
+        let scale_factor = ctx.window().scale_factor();
+        let to_logical = |size: u32| (size as f32 / scale_factor).round() as u32;
+        Some(LogicalSize::new(to_logical(width), to_logical(height)))
+    } else {
+        Some(LogicalSize::new(width, height))
+    };
+    ctx.window().set_min_size(min_size);
+    Ok(())
+}
+
+/// `CaptureRegion(x, y, width, height, path, [imageWidth, imageHeight])` renders a
+/// region of the current frame into an image once the frame is rendered, e.g. to
+/// export the passive tree as image. The region is in the coordinates of the draw
+/// functions and may extend beyond the window. The image has the resolution of the
+/// window unless its size is given, which needs to have the aspect ratio of the
+/// region. The format follows the extension of `path`,
+/// e.g. `.png` or `.jpg`. Returns true once the capture is queued, or nil and an
+/// error message, e.g. if the image is larger than the GPU supports. Errors while
+/// capturing or saving are logged.
+pub fn capture_region(
+    l: &Lua,
+    (x, y, width, height, path, image_width, image_height): (
+        f32,
+        f32,
+        f32,
+        f32,
+        String,
+        Option<u32>,
+        Option<u32>,
+    ),
+) -> LuaResult<MultiValue> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    if !(width > 0.0 && height > 0.0) {
+        return (Value::Nil, "Width and height need to be positive").into_lua_multi(l);
+    }
+    let size = match (image_width, image_height) {
+        (None, None) => None,
+        // the region is scaled by the same factor on both axes, allowing for rounding
+        (Some(image_width), Some(image_height)) if image_width > 0 && image_height > 0 => {
+            let expected_height = image_width as f32 * height / width;
+            if (image_height as f32 - expected_height).abs() > 1.0 {
+                return (Value::Nil, "The image needs the aspect ratio of the region")
+                    .into_lua_multi(l);
+            }
+            let max_size = ctx.graphics_capabilities().max_texture_size;
+            if image_width > max_size || image_height > max_size {
+                return (
+                    Value::Nil,
+                    format!("The image can't be larger than {max_size} pixels"),
+                )
+                    .into_lua_multi(l);
+            }
+            Some(PhysicalSize::new(image_width, image_height))
+        }
+        _ => {
+            return (Value::Nil, "The image needs a positive width and height").into_lua_multi(l);
+        }
+    };
+    if let Err(err) = image::ImageFormat::from_path(&path) {
+        return (Value::Nil, err.to_string()).into_lua_multi(l);
+    }
+    let path = match std::path::absolute(&path) {
+        Ok(path) => path,
+        Err(err) => return (Value::Nil, err.to_string()).into_lua_multi(l),
+    };
+
+    let rect =
+        LogicalRect::from_origin_and_size(LogicalPoint::new(x, y), LogicalSize::new(width, height));
+    ctx.window()
+        .capture_region(RegionCapture { rect, size, path });
+    true.into_lua_multi(l)
+}
+
+/// `ExportSVG(path, [x, y, width, height])` saves the current frame as SVG once
+/// it's drawn, e.g. to share a vector version of the passive tree. The optional
+/// region is in the coordinates of the draw functions. Returns true, or nil and an
+/// error message. Errors while exporting are logged.
+pub fn export_svg(
+    l: &Lua,
+    (path, x, y, width, height): (String, Option<f32>, Option<f32>, Option<f32>, Option<f32>),
+) -> LuaResult<MultiValue> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    let region = match (x, y, width, height) {
+        (None, None, None, None) => None,
+        (Some(x), Some(y), Some(width), Some(height)) if width > 0.0 && height > 0.0 => {
+            Some(LogicalRect::from_origin_and_size(
+                LogicalPoint::new(x, y),
+                LogicalSize::new(width, height),
+            ))
+        }
+        _ => return (Value::Nil, "Invalid region").into_lua_multi(l),
+    };
+    let path = match std::path::absolute(&path) {
+        Ok(path) => path,
+        Err(err) => return (Value::Nil, err.to_string()).into_lua_multi(l),
+    };
+
+    ctx.window().export_svg(SvgExport { region, path });
+    true.into_lua_multi(l)
+}
+
 pub fn set_window_title(l: &Lua, title: String) -> LuaResult<()> {
     let ctx = l.app_data_ref::<&'static Context>().unwrap();
     ctx.window().set_window_title(&title);
     Ok(())
 }
 
+/// Shows a progress indicator on the taskbar button. Percent outside of 0 to 100,
+/// or nil, hides it. Only supported on Windows.
+pub fn set_taskbar_progress(l: &Lua, percent: Option<f32>) -> LuaResult<()> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    let progress = percent.filter(|percent| (0.0..=100.0).contains(percent));
+    ctx.window().set_taskbar_progress(progress);
+    Ok(())
+}
+
 pub fn set_foreground(l: &Lua, _: ()) -> LuaResult<()> {
     let ctx = l.app_data_ref::<&'static Context>().unwrap();
     ctx.window().focus();
     Ok(())
 }
 
+/// Time between the previous and the current frame in milliseconds.
+pub fn get_frame_time(l: &Lua, _: ()) -> LuaResult<f64> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    Ok(ctx.window().frame_time().as_secs_f64() * 1000.0)
+}
+
+pub fn get_refresh_rate(l: &Lua, _: ()) -> LuaResult<f64> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    Ok(ctx.window().refresh_rate())
+}
+
+pub fn set_needs_redraw(l: &Lua, _: ()) -> LuaResult<()> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    ctx.window().set_needs_redraw();
+    Ok(())
+}
+
+/// Keeps redrawing every frame for the given number of milliseconds. 0 stops.
+pub fn set_animation_hint(l: &Lua, duration_ms: f64) -> LuaResult<()> {
+    let ctx = l.app_data_ref::<&'static Context>().unwrap();
+    let duration = Duration::from_secs_f64(duration_ms.max(0.0) / 1000.0);
+    ctx.window().animate_for(duration);
+    Ok(())
+}
+
 pub fn set_dpi_scale_override(l: &Lua, percent: i32) -> LuaResult<()> {
     let ctx = l.app_data_ref::<&'static Context>().unwrap();
-    match percent {
-        0 => ctx.window().scale_factor_override = None,
-        p if p > 0 => ctx.window().scale_factor_override = Some(p as f32 / 100.0),
-        _ => {}
+    let scale_factor_override = match percent {
+        0 => None,
+        p if p > 0 => Some(p as f32 / 100.0),
+        _ => return Ok(()),
+    };
+
+    let window = ctx.window();
+    if scale_factor_override != window.scale_factor_override() {
+        window.set_scale_factor_override(scale_factor_override);
+        // persist override so that it's applied on the next start before PoB runs
+        if let Err(err) = window.save_scale_factor_override(ctx.config()) {
+            log::warn!("Unable to save DPI scale override: {err}");
+        }
     }
     Ok(())
 }
 
 pub fn get_dpi_scale_override(l: &Lua, _: ()) -> LuaResult<i32> {
     let ctx = l.app_data_ref::<&'static Context>().unwrap();
-    match ctx.window().scale_factor_override {
-        Some(scale_factor) => Ok((scale_factor * 100.0) as i32),
+    match ctx.window().scale_factor_override() {
+        Some(scale_factor) => Ok((scale_factor * 100.0).round() as i32),
         None => Ok(0),
     }
 }
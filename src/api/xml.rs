@@ -0,0 +1,198 @@
+//! Native replacement for PoB's `xml.lua`, which is slow for large builds.
+//!
+//! Uses the same table schema: an element is a table with `elem` (the name),
+//! `attrib` (a table of attribute values) and its children in the array part.
+//! Text is a plain string.
+
+use mlua::{IntoLuaMulti, Lua, MultiValue, Result as LuaResult, String as LuaString, Table, Value};
+use quick_xml::{Reader, escape::escape, events::Event};
+use std::fmt::Write;
+
+/// Parses XML into a table holding the top-level elements, or returns nil and an
+/// error message. Invalid UTF-8 is replaced instead of raising an error.
+pub fn parse_xml(l: &Lua, text: LuaString) -> LuaResult<MultiValue> {
+    match parse(l, &text.to_string_lossy()) {
+        Ok(doc) => doc.into_lua_multi(l),
+        Err(e) => (Value::Nil, e.to_string()).into_lua_multi(l),
+    }
+}
+
+fn parse(l: &Lua, text: &str) -> anyhow::Result<Table> {
+    let mut reader = Reader::from_str(text);
+
+    let new_node = |start: &quick_xml::events::BytesStart| -> anyhow::Result<Table> {
+        let node = l.create_table()?;
+        let attrib = l.create_table()?;
+        for attr in start.attributes() {
+            let attr = attr?;
+            let value = attr.unescape_value()?;
+            let key = String::from_utf8_lossy(attr.key.as_ref());
+            attrib.raw_set(key, value)?;
+        }
+        let name = String::from_utf8_lossy(start.name().as_ref()).into_owned();
+        node.raw_set("elem", name)?;
+        node.raw_set("attrib", attrib)?;
+        Ok(node)
+    };
+
+    let doc = l.create_table()?;
+    let mut stack = vec![doc.clone()];
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|e| anyhow::anyhow!("{e} at position {}", reader.error_position()))?;
+        let parent = stack.last().unwrap();
+        match event {
+            Event::Start(start) => {
+                let node = new_node(&start)?;
+                parent.raw_push(&node)?;
+                stack.push(node);
+            }
+            Event::Empty(start) => {
+                let node = new_node(&start)?;
+                parent.raw_push(node)?;
+            }
+            Event::End(_) => {
+                stack.pop();
+            }
+            Event::Text(text) => {
+                let text = text.unescape()?;
+                let text = trim_formatting(&text);
+                if !text.is_empty() {
+                    parent.raw_push(text)?;
+                }
+            }
+            Event::CData(data) => {
+                let text = String::from_utf8_lossy(&data);
+                parent.raw_push(text)?;
+            }
+            Event::Eof => break,
+            // declarations, comments and processing instructions aren't kept
+            _ => {}
+        }
+    }
+
+    if stack.len() > 1 {
+        anyhow::bail!("Unexpected end of document");
+    }
+    Ok(doc)
+}
+
+/// Removes the whitespace before text that starts on a new line and after text
+/// that is followed by one, i.e. the indentation `compose_xml` adds. Other
+/// whitespace is part of the text, e.g. leading spaces in `<Notes>  a</Notes>`.
+fn trim_formatting(text: &str) -> &str {
+    let start = text.len() - text.trim_start().len();
+    let text = match text[..start].contains('\n') {
+        true => &text[start..],
+        false => text,
+    };
+    let trimmed = text.trim_end();
+    match text[trimmed.len()..].contains('\n') {
+        true => trimmed,
+        false => text,
+    }
+}
+
+/// Serializes an element table including an XML declaration, or returns nil and
+/// an error message.
+pub fn compose_xml(l: &Lua, node: Table) -> LuaResult<MultiValue> {
+    let mut text = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    match compose_node(&mut text, &node, 0) {
+        Ok(()) => text.into_lua_multi(l),
+        Err(e) => (Value::Nil, e.to_string()).into_lua_multi(l),
+    }
+}
+
+fn compose_node(text: &mut String, node: &Table, depth: usize) -> LuaResult<()> {
+    let indent = "\t".repeat(depth);
+    let name: String = node
+        .raw_get::<Option<String>>("elem")?
+        .ok_or_else(|| mlua::Error::runtime("Element without name"))?;
+    let _ = write!(text, "{indent}<{name}");
+
+    // sorted so that saving the same build twice gives the same file
+    if let Some(attrib) = node.raw_get::<Option<Table>>("attrib")? {
+        let mut attributes = Vec::new();
+        for pair in attrib.pairs::<String, Value>() {
+            let (key, value) = pair?;
+            attributes.push((key, value.to_string()?));
+        }
+        attributes.sort_unstable();
+        for (key, value) in attributes {
+            let _ = write!(text, " {key}=\"{}\"", escape(&value));
+        }
+    }
+
+    if node.raw_len() == 0 {
+        text.push_str("/>\n");
+        return Ok(());
+    }
+
+    text.push_str(">\n");
+    for child in node.sequence_values::<Value>() {
+        match child? {
+            Value::Table(child) => compose_node(text, &child, depth + 1)?,
+            value => {
+                let _ = writeln!(text, "{indent}\t{}", escape(&value.to_string()?));
+            }
+        }
+    }
+    let _ = writeln!(text, "{indent}</{name}>");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xml_roundtrip() {
+        let lua = Lua::new();
+        let xml = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                   <PathOfBuilding>\n\
+                   \t<Build className=\"Witch\" level=\"90\"/>\n\
+                   \t<Notes>\n\
+                   \t\ta &amp; b\n\
+                   \t</Notes>\n\
+                   </PathOfBuilding>\n";
+        let doc = parse(&lua, xml).unwrap();
+
+        let root: Table = doc.raw_get(1).unwrap();
+        let build: Table = root.raw_get(1).unwrap();
+        let attrib: Table = build.raw_get("attrib").unwrap();
+        assert_eq!(attrib.raw_get::<String>("level").unwrap(), "90");
+        let notes: Table = root.raw_get(2).unwrap();
+        assert_eq!(notes.raw_get::<String>(1).unwrap(), "a & b");
+
+        let composed = compose_xml(&lua, root).unwrap();
+        assert_eq!(composed[0].to_string().unwrap(), xml);
+
+        // e.g. builds saved in another encoding
+        let latin1 = lua.create_string(b"<Notes>caf\xe9</Notes>").unwrap();
+        let doc: Table = parse_xml(&lua, latin1).unwrap()[0]
+            .as_table()
+            .unwrap()
+            .clone();
+        let notes: Table = doc.raw_get(1).unwrap();
+        assert_eq!(notes.raw_get::<String>(1).unwrap(), "caf\u{fffd}");
+    }
+
+    #[test]
+    fn test_significant_whitespace() {
+        let lua = Lua::new();
+        let text = |xml: &str| {
+            let doc = parse(&lua, xml).unwrap();
+            let node: Table = doc.raw_get(1).unwrap();
+            node.raw_get::<Option<String>>(1).unwrap()
+        };
+
+        assert_eq!(text("<Notes>  a  b </Notes>").as_deref(), Some("  a  b "));
+        assert_eq!(
+            text("<Notes>\n\t\tline 1\n\t\t  line 2\n\t</Notes>").as_deref(),
+            Some("line 1\n\t\t  line 2")
+        );
+        assert_eq!(text("<Notes>\n\t</Notes>"), None);
+        assert_eq!(text("<Notes> </Notes>").as_deref(), Some(" "));
+    }
+}
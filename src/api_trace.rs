@@ -0,0 +1,156 @@
+//! Call counts and timings of the Lua API, see `--trace-lua-api`.
+
+use ahash::{HashMap, HashSet};
+use mlua::{Function, Lua, MultiValue, Result as LuaResult, Value};
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Write as _,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+#[derive(Default)]
+struct FunctionStats {
+    // calls and time of the current frame
+    frame_calls: u64,
+    frame_time: Duration,
+    calls: u64,
+    time: Duration,
+    max_frame_calls: u64,
+    max_frame_time: Duration,
+}
+
+/// Records calls of the functions PoB can call, grouped by frame.
+#[derive(Default)]
+pub struct ApiTrace {
+    functions: RefCell<HashMap<String, FunctionStats>>,
+    frames: Cell<u64>,
+}
+
+impl ApiTrace {
+    /// Replaces the global functions registered by the app with wrappers that
+    /// record their calls. Functions of Lua's standard library are kept.
+    pub fn wrap_globals(self: &Rc<Self>, lua: &Lua) -> LuaResult<()> {
+        // SAFETY: like the app's state, so that the same libraries are loaded
+        let std_globals: HashSet<String> = unsafe { Lua::unsafe_new() }
+            .globals()
+            .pairs::<String, Value>()
+            .filter_map(|pair| pair.ok().map(|(name, _)| name))
+            .collect();
+
+        let globals = lua.globals();
+        let api_functions: Vec<(String, Function)> = globals
+            .pairs::<String, Value>()
+            .filter_map(|pair| match pair {
+                Ok((name, Value::Function(function))) if !std_globals.contains(&name) => {
+                    Some((name, function))
+                }
+                _ => None,
+            })
+            .collect();
+
+        for (name, function) in api_functions {
+            let trace = Rc::clone(self);
+            let function_name = name.clone();
+            let wrapper = lua.create_function(move |_, args: MultiValue| {
+                let start = Instant::now();
+                let result = function.call::<MultiValue>(args);
+                trace.record(&function_name, start.elapsed());
+                result
+            })?;
+            globals.set(name, wrapper)?;
+        }
+        Ok(())
+    }
+
+    fn record(&self, name: &str, elapsed: Duration) {
+        let mut functions = self.functions.borrow_mut();
+        let stats = match functions.get_mut(name) {
+            Some(stats) => stats,
+            None => functions.entry(name.to_owned()).or_default(),
+        };
+        stats.frame_calls += 1;
+        stats.frame_time += elapsed;
+    }
+
+    /// Adds the calls of the current frame to the totals.
+    pub fn end_frame(&self) {
+        self.frames.set(self.frames.get() + 1);
+        for stats in self.functions.borrow_mut().values_mut() {
+            if stats.frame_calls == 0 {
+                continue;
+            }
+            stats.calls += stats.frame_calls;
+            stats.time += stats.frame_time;
+            stats.max_frame_calls = stats.max_frame_calls.max(stats.frame_calls);
+            stats.max_frame_time = stats.max_frame_time.max(stats.frame_time);
+            stats.frame_calls = 0;
+            stats.frame_time = Duration::ZERO;
+        }
+    }
+
+    /// Table of the called functions, sorted by total time. Calls since the last
+    /// frame are counted as another frame.
+    pub fn summary(&self) -> String {
+        self.end_frame();
+        let frames = self.frames.get().max(1);
+        let functions = self.functions.borrow();
+        let mut functions: Vec<_> = functions
+            .iter()
+            .filter(|(_, stats)| stats.calls > 0)
+            .collect();
+        functions.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.time));
+
+        let mut summary = format!(
+            "Lua API calls over {frames} frames\n{:<28} {:>10} {:>10} {:>10} {:>12} {:>10} {:>12}\n",
+            "Function", "Calls", "Per frame", "Max calls", "Total ms", "Avg µs", "Max frame ms"
+        );
+        for (name, stats) in functions {
+            let _ = writeln!(
+                summary,
+                "{:<28} {:>10} {:>10.1} {:>10} {:>12.2} {:>10.2} {:>12.3}",
+                name,
+                stats.calls,
+                stats.calls as f64 / frames as f64,
+                stats.max_frame_calls,
+                stats.time.as_secs_f64() * 1000.0,
+                stats.time.as_secs_f64() * 1e6 / stats.calls as f64,
+                stats.max_frame_time.as_secs_f64() * 1000.0,
+            );
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_wrapped_globals() {
+        let lua = Lua::new();
+        lua.globals()
+            .set(
+                "Double",
+                lua.create_function(|_, x: i64| Ok(x * 2)).unwrap(),
+            )
+            .unwrap();
+        let trace = Rc::new(ApiTrace::default());
+        trace.wrap_globals(&lua).unwrap();
+
+        let result: i64 = lua.load("return Double(Double(3))").eval().unwrap();
+        assert_eq!(result, 12);
+        trace.end_frame();
+        lua.load("Double(1)").exec().unwrap();
+        trace.end_frame();
+
+        let functions = trace.functions.borrow();
+        let stats = &functions["Double"];
+        assert_eq!(stats.calls, 3);
+        assert_eq!(stats.max_frame_calls, 2);
+        // standard library functions aren't wrapped
+        assert!(!functions.contains_key("print"));
+        drop(functions);
+        assert!(trace.summary().contains("Double"));
+    }
+}
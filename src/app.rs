@@ -1,26 +1,59 @@
 use crate::{
-    args::Game,
-    dpi::{ConvertToLogical, PhysicalPoint, PhysicalSize},
+    args::{Args, Game, set_profile},
+    build_source::{BuildSource, read_build_file},
+    capture::CaptureWriter,
+    config::{self, Config, config_path},
+    dpi::{ConvertToLogical, ConvertToPhysical, PhysicalPoint, PhysicalSize, PhysicalVector},
     fonts::{FontData, FontDefinitions, Fonts},
-    gfx::{GraphicsContext, RenderJob},
-    input::InputState,
-    installer::InstallMode,
+    gfx::{GraphicsCapabilities, GraphicsContext, GraphicsOptions, RenderJob, RenderScale},
+    input::{InputState, normalize_key},
+    installer::{InstallMode, PoBSource},
+    instance::InstanceMessage,
+    integrity::IntegrityMode,
+    log_file::log_fatal_error,
     mode::{AppEvent, AppMode, ModeTransition},
+    perf_overlay::{PerfOverlay, Timing},
     pob::PoBMode,
-    renderer::{tessellator::Tessellator, textures::WrappedTextureManager},
-    window::WindowState,
+    profile_select::ProfileSelectMode,
+    renderer::{
+        mesh::ClippedMesh,
+        primitives::ClippedPrimitive,
+        tessellator::Tessellator,
+        textures::{TextureId, WrappedTextureManager},
+    },
+    replay::ReplayMode,
+    window::{RegionCapture, WindowState, title_with_profile},
 };
+#[cfg(feature = "tray")]
+use crate::{
+    build_source::parse_build_source,
+    tray::{Tray, TrayAction},
+};
+use ahash::HashMap;
 use anyhow::Result;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, mpsc::Sender};
+use std::time::{Duration, Instant};
 use winit::{
-    application::ApplicationHandler, event::*, event_loop::ActiveEventLoop,
-    platform::modifier_supplement::KeyEventExtModifierSupplement, window::Window,
+    application::ApplicationHandler,
+    event::*,
+    event_loop::{ActiveEventLoop, ControlFlow, EventLoopClosed, EventLoopProxy},
+    platform::modifier_supplement::KeyEventExtModifierSupplement,
+    window::Window,
 };
 
+/// Idle work is skipped if less time than this is left until the next frame.
+const MIN_IDLE_BUDGET: Duration = Duration::from_millis(1);
+
+/// Logical pixels of trackpad scrolling that correspond to one wheel notch.
+const PIXELS_PER_WHEEL_LINE: f32 = 20.0;
+
 struct FrameOutput {
     pub render_job: RenderJob,
     pub should_continue: bool,
+    /// Regions to render once the frame is rendered, with the frame's meshes
+    pub captures: Vec<RegionCapture>,
+    pub capture_meshes: Vec<ClippedMesh>,
 }
 
 pub struct AppState {
@@ -29,13 +62,121 @@ pub struct AppState {
     pub fonts: Fonts,
     pub texture_manager: WrappedTextureManager,
     pub script_dir: PathBuf,
+    pub integrity_mode: IntegrityMode,
+    pub pob_source: PoBSource,
+    pub max_subscripts: usize,
+    pub event_proxy: EventProxy,
+    /// Options for the graphics context. It is recreated when they change
+    pub graphics_options: GraphicsOptions,
+    /// Capabilities of the current graphics context
+    pub graphics_capabilities: GraphicsCapabilities,
     pub should_exit: bool,
+    pub perf_overlay: PerfOverlay,
+    /// Saved options, see `config`
+    pub config: Config,
+    // last cursor position reported by the window
+    physical_mouse_pos: PhysicalPoint<f32>,
 }
 
 impl AppState {
+    /// State without a window, GPU or event loop, for tests that run Lua code.
+    #[cfg(test)]
+    pub fn headless(script_dir: PathBuf) -> Self {
+        let mut window = WindowState::default();
+        window.size = PhysicalSize::new(800, 600);
+        Self {
+            window,
+            input: InputState::default(),
+            fonts: Fonts::new(pob_font_definitions()),
+            texture_manager: WrappedTextureManager::new(),
+            config: Config::load(script_dir.join("config.toml")),
+            script_dir,
+            integrity_mode: IntegrityMode::Warn,
+            pob_source: PoBSource::default(),
+            max_subscripts: 1,
+            event_proxy: EventProxy::disconnected(),
+            graphics_options: GraphicsOptions {
+                msaa_sample_count: 1,
+                hdr: false,
+                hdr_white_level: 203.0,
+                vsync: true,
+                linear_blending: false,
+            },
+            graphics_capabilities: GraphicsCapabilities::default(),
+            should_exit: false,
+            perf_overlay: PerfOverlay::new(false),
+            physical_mouse_pos: PhysicalPoint::zero(),
+        }
+    }
+
     fn set_mouse_pos(&mut self, pos: PhysicalPoint<f32>) {
-        self.input
-            .set_mouse_pos(pos.to_logical(self.window.scale_factor()));
+        self.physical_mouse_pos = pos;
+        self.update_mouse_pos();
+    }
+
+    /// Moves the cursor by relative mouse motion while it's captured. The
+    /// position may leave the window.
+    fn move_captured_mouse(&mut self, delta: PhysicalVector<f32>) {
+        self.physical_mouse_pos += delta;
+        self.update_mouse_pos();
+    }
+
+    /// Converts the cursor position with the current scale factor, which changes
+    /// when the DPI scale override is set.
+    fn update_mouse_pos(&mut self) {
+        self.input.set_mouse_pos(
+            self.physical_mouse_pos
+                .to_logical(self.window.scale_factor()),
+        );
+    }
+}
+
+/// Events sent to the event loop from other threads.
+#[derive(Debug)]
+pub enum UserEvent {
+    /// Message from an instance that was launched while this one is running, and
+    /// where to send whether it was handled
+    Instance(InstanceMessage, Sender<bool>),
+    /// A path watched by PoB has changed
+    FilesChanged,
+    /// The OAuth listener received the redirect from the browser
+    OAuthRedirect,
+    /// A download started by PoB made progress or finished
+    DownloadProgress,
+    /// A process started by PoB has exited
+    ProcessExited,
+    /// The content of the clipboard changed, see `ClipboardWatcher`
+    ClipboardChanged,
+    /// An async image load finished or failed
+    ImageLoaded,
+    /// A tray menu item or the tray icon was clicked
+    #[cfg(feature = "tray")]
+    Tray(TrayAction),
+}
+
+/// Sends `UserEvent`s to the event loop, e.g. to wake it up when a background
+/// task finishes. Tests run without an event loop, which needs a display, and
+/// drop the events.
+#[derive(Clone)]
+pub struct EventProxy {
+    proxy: Option<EventLoopProxy<UserEvent>>,
+}
+
+impl EventProxy {
+    pub fn new(proxy: EventLoopProxy<UserEvent>) -> Self {
+        Self { proxy: Some(proxy) }
+    }
+
+    #[cfg(test)]
+    pub fn disconnected() -> Self {
+        Self { proxy: None }
+    }
+
+    pub fn send_event(&self, event: UserEvent) -> Result<(), EventLoopClosed<UserEvent>> {
+        match &self.proxy {
+            Some(proxy) => proxy.send_event(event),
+            None => Ok(()),
+        }
     }
 }
 
@@ -47,31 +188,101 @@ pub struct App {
     needs_reconfigure: bool,
     force_render: bool,
     current_mode: AppMode,
+    // subpixel text was requested, enabled once the renderer is known to support it
+    subpixel_text: bool,
+    // the current mode may have background work to do while idle
+    has_idle_work: bool,
+    // time at which the UI is updated next without any input, see `WindowState::next_wakeup`
+    next_wakeup: Option<Instant>,
+    // writes the primitives of each rendered frame, see `--capture`
+    capture: Option<CaptureWriter>,
+    // hides the window while it's minimized, see `--minimize-to-tray`
+    minimize_to_tray: bool,
+    #[cfg(feature = "tray")]
+    tray: Option<Tray>,
 }
 
 impl App {
-    pub fn new(game: Game, custom_script_dir: Option<PathBuf>) -> Result<Self> {
+    pub fn new(
+        args: &Args,
+        custom_script_dir: Option<PathBuf>,
+        event_proxy: EventLoopProxy<UserEvent>,
+    ) -> Result<Self> {
+        let game = args.game;
         let uses_custom_script_dir = custom_script_dir.is_some();
         let script_dir = custom_script_dir.unwrap_or_else(|| game.script_dir());
 
+        // options on the command line take precedence over saved ones, so that e.g.
+        // `--hdr=false` turns off a saved `hdr = true`
+        let config = Config::load(config_path());
+        let flag_or_config =
+            |flag: Option<bool>, name| flag.or(config.get_bool(name)).unwrap_or(false);
+        let integer_scaling = flag_or_config(args.integer_scaling, config::INTEGER_SCALING);
+        let power_saving = flag_or_config(args.power_saving, config::POWER_SAVING);
+        let premultiplied_alpha =
+            flag_or_config(args.premultiplied_alpha, config::PREMULTIPLIED_ALPHA);
+        let render_scale = args
+            .render_scale
+            .or(config.get_f32(config::RENDER_SCALE))
+            .unwrap_or(1.0);
+
         let mut state = AppState {
             window: WindowState::default(),
             input: InputState::default(),
             fonts: Fonts::new(pob_font_definitions()),
             texture_manager: WrappedTextureManager::new(),
             script_dir,
+            integrity_mode: args.integrity,
+            pob_source: PoBSource::from_args(args),
+            max_subscripts: args.max_subscripts as usize,
+            event_proxy: EventProxy::new(event_proxy),
+            graphics_options: GraphicsOptions {
+                msaa_sample_count: args.msaa.or(config.get_u32(config::MSAA)).unwrap_or(1),
+                hdr: flag_or_config(args.hdr, config::HDR),
+                hdr_white_level: args.hdr_white_level,
+                vsync: !args.no_vsync && config.get_bool(config::VSYNC).unwrap_or(true),
+                linear_blending: flag_or_config(args.linear_blending, config::LINEAR_BLENDING),
+            },
+            graphics_capabilities: GraphicsCapabilities::default(),
             should_exit: false,
+            perf_overlay: PerfOverlay::new(args.perf_overlay),
+            physical_mouse_pos: PhysicalPoint::zero(),
+            config,
         };
+        state.window.integer_scaling = integer_scaling;
+        state.window.render_scale = render_scale;
+        state.window.power_saving = power_saving;
+        state.texture_manager.memory_budget = args.texture_budget.map(|mib| mib << 20);
+        state.texture_manager.compression = args.texture_compression;
+        state.texture_manager.compression_min_size = args.texture_compression_min_size;
+        state.texture_manager.event_proxy = Some(state.event_proxy.clone());
+        state
+            .texture_manager
+            .set_premultiply_alpha(premultiplied_alpha);
+        state.config.migrate_dpi_scale_override(&state.script_dir);
+        state.window.load_scale_factor_override(&state.config);
 
-        let current_mode = if uses_custom_script_dir {
+        let current_mode = if let Some(path) = &args.replay {
+            AppMode::Replay(ReplayMode::new(path, &mut state)?)
+        } else if uses_custom_script_dir {
             // Skip installer if custom script dir is provided.
             // Used for local testing
-            let pob_mode = PoBMode::new(&mut state)?;
-            AppMode::PoB(pob_mode)
+            AppMode::Install(InstallMode::skip())
+        } else if args.profile.is_none() && !game.profiles().is_empty() {
+            AppMode::ProfileSelect(ProfileSelectMode::new(game))
         } else {
-            AppMode::Install(InstallMode::new(game))
+            AppMode::Install(InstallMode::new(
+                game,
+                args.integrity,
+                state.pob_source.clone(),
+            ))
         };
 
+        // a hidden window couldn't be opened again without the tray icon
+        if args.minimize_to_tray && !cfg!(feature = "tray") {
+            log::warn!("--minimize-to-tray requires a build with the tray feature");
+        }
+
         Ok(Self {
             gfx_context: None,
             state,
@@ -80,6 +291,17 @@ impl App {
             needs_reconfigure: true,
             force_render: true,
             current_mode,
+            subpixel_text: args.subpixel_text,
+            has_idle_work: true,
+            next_wakeup: None,
+            capture: args
+                .capture
+                .as_deref()
+                .map(CaptureWriter::create)
+                .transpose()?,
+            minimize_to_tray: args.minimize_to_tray && cfg!(feature = "tray"),
+            #[cfg(feature = "tray")]
+            tray: None,
         })
     }
 
@@ -87,9 +309,27 @@ impl App {
         let transition = self.current_mode.update(&mut self.state)?;
         if let Some(transition) = transition {
             self.current_mode = match transition {
+                ModeTransition::Install { profile } => {
+                    if let Some(profile) = profile {
+                        set_profile(&profile)?;
+                        self.state.script_dir = self.game.script_dir();
+                        self.state
+                            .config
+                            .migrate_dpi_scale_override(&self.state.script_dir);
+                        self.state
+                            .window
+                            .load_scale_factor_override(&self.state.config);
+                        self.state.window.set_window_title(self.game.window_title());
+                    }
+                    AppMode::Install(InstallMode::new(
+                        self.game,
+                        self.state.integrity_mode,
+                        self.state.pob_source.clone(),
+                    ))
+                }
                 ModeTransition::PoB => {
-                    let pob_mode = PoBMode::new(&mut self.state)?;
-                    AppMode::PoB(pob_mode)
+                    let pob_mode = PoBMode::new(&mut self.state, self.game)?;
+                    AppMode::PoB(Box::new(pob_mode))
                 }
             };
         }
@@ -97,12 +337,36 @@ impl App {
         Ok(())
     }
 
-    fn frame(&mut self) -> anyhow::Result<FrameOutput> {
+    fn frame(&mut self, frame_start: Instant) -> anyhow::Result<FrameOutput> {
+        self.state.window.begin_frame(frame_start);
+        self.state
+            .fonts
+            .set_pixels_per_point(self.state.window.pixels_per_point());
         self.state.fonts.begin_frame();
+        self.state.update_mouse_pos();
+
+        // exports requested during this frame are written with the next one, whose
+        // layouts keep the jobs needed to write their text
+        let svg_exports = self.state.window.take_svg_exports();
+        self.state
+            .fonts
+            .set_keep_jobs(self.capture.is_some() || !svg_exports.is_empty());
 
-        let mode_output = self.current_mode.frame(&mut self.state)?;
+        let mut mode_output = self.current_mode.frame(&mut self.state)?;
+
+        if self.state.perf_overlay.is_visible {
+            let overlay = self.state.perf_overlay.primitives(
+                &mut self.state.fonts,
+                self.state.window.logical_size().cast(),
+                self.state.window.pixels_per_point(),
+            );
+            mode_output.primitives = Box::new(mode_output.primitives.chain(overlay));
+            // the timings change every frame
+            mode_output.can_elide = false;
+        }
 
         let font_atlas_size = self.state.fonts.font_atlas().size();
+        let font_atlas_generation = self.state.fonts.font_atlas().generation();
 
         if let Some(font_image_delta) = self.state.fonts.font_atlas_delta() {
             self.state
@@ -111,16 +375,62 @@ impl App {
         }
 
         let textures_delta = self.state.texture_manager.take_delta();
+        let captures = self.state.window.take_captures();
+        let mut capture_meshes = Vec::new();
 
-        let render_job = if mode_output.can_elide && textures_delta.is_empty() && !self.force_render
+        let render_job = if mode_output.can_elide
+            && textures_delta.is_empty()
+            && !self.force_render
+            && svg_exports.is_empty()
+            && captures.is_empty()
         {
             RenderJob::Skip
         } else {
+            if self.capture.is_some() || !svg_exports.is_empty() {
+                let primitives: Vec<_> = mode_output.primitives.collect();
+                if let Some(capture) = &mut self.capture
+                    && let Err(err) = capture.write_frame(
+                        self.state.window.logical_size().cast(),
+                        self.state.window.pixels_per_point(),
+                        &primitives,
+                        &self.state.texture_manager,
+                    )
+                {
+                    log::warn!("Stopped capturing frames: {err}");
+                    self.capture = None;
+                }
+                if !svg_exports.is_empty() {
+                    let size = self.state.window.logical_size().cast();
+                    let shared: Arc<[ClippedPrimitive]> = primitives.as_slice().into();
+                    let textures = Arc::new(self.texture_sources());
+                    for export in svg_exports {
+                        export.save(size, shared.clone(), textures.clone());
+                    }
+                }
+                mode_output.primitives = Box::new(primitives.into_iter());
+            }
+
+            let tessellation_start = Instant::now();
             let meshes = self.tessellator.convert_clipped_primitives(
                 mode_output.primitives,
+                &mode_output.groups,
                 font_atlas_size,
-                self.state.window.scale_factor(),
+                font_atlas_generation,
+                self.state.window.pixels_per_point(),
             );
+            self.state
+                .perf_overlay
+                .record(Timing::Tessellation, tessellation_start.elapsed());
+
+            let texture_memory = self
+                .state
+                .texture_manager
+                .end_frame(meshes.iter().map(|clipped| clipped.mesh.texture_id));
+            self.state.perf_overlay.texture_memory = texture_memory;
+
+            if !captures.is_empty() {
+                capture_meshes = meshes.clone();
+            }
 
             RenderJob::Render {
                 meshes,
@@ -131,24 +441,75 @@ impl App {
         Ok(FrameOutput {
             render_job,
             should_continue: mode_output.should_continue,
+            captures,
+            capture_meshes,
         })
     }
 
+    /// Image files of the loaded textures, relative paths are resolved against the
+    /// script directory like PoB's
+    fn texture_sources(&self) -> HashMap<TextureId, PathBuf> {
+        self.state
+            .texture_manager
+            .textures()
+            .into_iter()
+            .filter_map(|(id, meta_data)| {
+                Some((id, self.state.script_dir.join(meta_data.source_path()?)))
+            })
+            .collect()
+    }
+
+    /// Gives the current mode the time left until the next frame is due to do
+    /// background work.
+    fn idle(&mut self, frame_start: Instant) {
+        // leave headroom so the next frame isn't delayed
+        let remaining = self
+            .state
+            .window
+            .frame_interval()
+            .saturating_sub(frame_start.elapsed());
+        let budget = remaining / 2;
+        if budget < MIN_IDLE_BUDGET {
+            return;
+        }
+
+        match self.current_mode.idle(&mut self.state, budget) {
+            Ok(has_idle_work) => {
+                self.has_idle_work = has_idle_work;
+                // keep going even if no frames are being rendered
+                if has_idle_work {
+                    self.state.window.request_redraw();
+                }
+            }
+            Err(err) => {
+                log::error!("{err}");
+                self.has_idle_work = false;
+            }
+        }
+    }
+
     fn handle_event(&mut self, event: AppEvent) {
+        // input is the only thing that wakes up the UI while power saving
+        self.state.window.request_redraw();
         if let Err(err) = self.current_mode.handle_event(&mut self.state, event) {
             log::error!("{err}");
         }
     }
 
+    fn open_build(&mut self, build: BuildSource) -> bool {
+        self.state.window.request_redraw();
+        self.current_mode.open_build(&mut self.state, build)
+    }
+
     fn create_window(&mut self, event_loop: &ActiveEventLoop) -> anyhow::Result<()> {
-        let (title, _app_id) = match self.game {
-            Game::Poe1 => ("Path of Building 1", "rusty-path-of-building-1"),
-            Game::Poe2 => ("Path of Building 2", "rusty-path-of-building-2"),
+        let _app_id = match self.game {
+            Game::Poe1 => "rusty-path-of-building-1",
+            Game::Poe2 => "rusty-path-of-building-2",
         };
 
         #[allow(unused_mut)]
         let mut window_attributes = Window::default_attributes()
-            .with_title(title)
+            .with_title(title_with_profile(self.game.window_title()))
             .with_window_icon(load_icon());
 
         #[cfg(target_os = "linux")]
@@ -168,18 +529,70 @@ impl App {
         let window = event_loop.create_window(window_attributes)?;
         let window = Arc::new(window);
         self.state.window.set_window(Arc::clone(&window));
-        self.gfx_context = Some(pollster::block_on(GraphicsContext::new(window))?);
+        self.create_graphics_context(window)
+    }
+
+    fn create_graphics_context(&mut self, window: Arc<Window>) -> anyhow::Result<()> {
+        let gfx_context =
+            pollster::block_on(GraphicsContext::new(window, self.state.graphics_options))?;
+
+        if self.subpixel_text {
+            if gfx_context.supports_subpixel_text() {
+                self.state.fonts.set_subpixel_text(true);
+            } else {
+                log::warn!("Subpixel text requires dual-source blending, which isn't supported");
+            }
+        }
+
+        self.state.graphics_capabilities = gfx_context.capabilities();
+        self.gfx_context = Some(gfx_context);
+        self.needs_reconfigure = true;
+
+        Ok(())
+    }
+
+    /// Rebuilds the graphics context after the GPU device or surface was lost, e.g.
+    /// because of a driver reset, or after the graphics options were changed. All
+    /// textures are uploaded again.
+    fn recover_graphics_context(&mut self) -> anyhow::Result<()> {
+        log::warn!("Recreating graphics context");
+
+        // drop old context first to release the surface
+        let Some(window) = self.gfx_context.take().map(|gfx| gfx.window) else {
+            return Ok(());
+        };
+        self.create_graphics_context(window)?;
+
+        self.state.fonts.invalidate_font_atlas();
+        let reload_count = self.state.texture_manager.reload_textures();
+        if reload_count > 0 {
+            // progress is visible to PoB through `GetAsyncCount`
+            log::info!("Reloading {reload_count} textures");
+        }
+        self.force_render = true;
+        self.state.window.request_redraw();
 
         Ok(())
     }
 }
 
-impl ApplicationHandler<GraphicsContext> for App {
+impl ApplicationHandler<UserEvent> for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         if let Err(err) = self.create_window(event_loop) {
-            log::error!("{err}");
+            log_fatal_error(&err);
             event_loop.exit();
         }
+
+        #[cfg(feature = "tray")]
+        if self.tray.is_none() {
+            match Tray::new(self.state.event_proxy.clone()) {
+                Ok(tray) => self.tray = Some(tray),
+                Err(err) => {
+                    log::warn!("Unable to create tray icon: {err}");
+                    self.minimize_to_tray = false;
+                }
+            }
+        }
     }
 
     fn window_event(
@@ -197,9 +610,10 @@ impl ApplicationHandler<GraphicsContext> for App {
             }
             WindowEvent::RedrawRequested => {
                 profiling::scope!("RedrawRequested");
+                let frame_start = Instant::now();
 
                 if let Err(err) = self.update() {
-                    log::error!("{err}");
+                    log_fatal_error(&err);
                     event_loop.exit();
                     return;
                 }
@@ -210,6 +624,29 @@ impl ApplicationHandler<GraphicsContext> for App {
                     return;
                 }
 
+                let needs_recovery = self.gfx_context.as_ref().is_some_and(|gfx| {
+                    gfx.needs_recovery() || gfx.options() != self.state.graphics_options
+                });
+                if needs_recovery && let Err(err) = self.recover_graphics_context() {
+                    log_fatal_error(&err);
+                    event_loop.exit();
+                    return;
+                }
+
+                if let Some(ref mut gfx) = self.gfx_context {
+                    let render_scale = match self.state.window.integer_scale_factor() {
+                        Some(factor) => RenderScale::integer_upscale(factor),
+                        None => RenderScale {
+                            factor: self.state.window.render_scale,
+                            nearest: false,
+                        },
+                    };
+                    if gfx.render_scale() != render_scale {
+                        gfx.set_render_scale(render_scale);
+                        self.needs_reconfigure = true;
+                    }
+                }
+
                 if self.needs_reconfigure {
                     if let Some(ref mut gfx) = self.gfx_context {
                         let size = gfx.window.inner_size();
@@ -222,27 +659,79 @@ impl ApplicationHandler<GraphicsContext> for App {
 
                 let is_focused = self.state.window.is_focused;
                 let is_hovered = self.state.window.is_hovered;
-                let should_render = is_focused || is_hovered || self.force_render;
+                let is_animating = self.state.window.is_animating();
+                let should_render = is_focused
+                    || is_hovered
+                    || self.force_render
+                    || is_animating
+                    || self.state.window.take_needs_redraw();
+                // nothing changed on screen and nothing is animating
+                let mut is_idle = !should_render;
 
                 if should_render {
                     let FrameOutput {
                         render_job,
                         should_continue,
-                    } = match self.frame() {
+                        captures,
+                        capture_meshes,
+                    } = match self.frame(frame_start) {
                         Ok(frame_output) => frame_output,
                         Err(err) => {
-                            log::error!("{err}");
+                            log_fatal_error(&err);
                             event_loop.exit();
                             return;
                         }
                     };
 
+                    is_idle = matches!(render_job, RenderJob::Skip) && !should_continue;
+                    if !is_idle {
+                        // changes may have created new work, which is picked up
+                        // by the next idle redraw
+                        self.has_idle_work = true;
+                        self.state.window.request_redraw();
+                    }
+
                     if let Some(ref mut gfx) = self.gfx_context {
-                        match gfx.render(render_job, self.state.window.scale_factor()) {
+                        gfx.measure_gpu_time = self.state.perf_overlay.is_visible;
+                        let result = gfx.render(render_job, self.state.window.pixels_per_point());
+
+                        let perf_overlay = &mut self.state.perf_overlay;
+                        perf_overlay.record(Timing::Interval, self.state.window.frame_time());
+                        perf_overlay.record(Timing::Cpu, frame_start.elapsed());
+                        if let Some(gpu_time) = gfx.gpu_time() {
+                            perf_overlay.record(Timing::Gpu, gpu_time);
+                        }
+
+                        match result {
                             Ok(_) => {
                                 self.force_render = should_continue;
 
-                                if is_focused || is_hovered || should_continue {
+                                let pixels_per_point = self.state.window.pixels_per_point();
+                                for capture in captures {
+                                    let size = capture.size.unwrap_or_else(|| {
+                                        capture
+                                            .rect
+                                            .to_physical::<f32, _>(pixels_per_point)
+                                            .size()
+                                            .round()
+                                            .to_u32()
+                                    });
+                                    match gfx.capture_region(
+                                        &capture_meshes,
+                                        capture.rect,
+                                        size,
+                                        pixels_per_point,
+                                    ) {
+                                        Ok(image) => capture.save(image),
+                                        Err(err) => log::warn!(
+                                            "Unable to capture {}: {err}",
+                                            capture.path.display()
+                                        ),
+                                    }
+                                }
+
+                                if self.state.window.is_polling() || is_animating || should_continue
+                                {
                                     self.state.window.request_redraw();
                                 }
                             }
@@ -258,11 +747,40 @@ impl ApplicationHandler<GraphicsContext> for App {
                     }
                 }
 
+                if is_idle && self.has_idle_work {
+                    self.idle(frame_start);
+                }
+                self.next_wakeup = self.state.window.next_wakeup(frame_start);
+
                 profiling::finish_frame!();
             }
+            WindowEvent::HoveredFile(_) => {
+                self.handle_event(AppEvent::FileHovered { is_hovered: true });
+            }
+            WindowEvent::HoveredFileCancelled => {
+                self.handle_event(AppEvent::FileHovered { is_hovered: false });
+            }
+            WindowEvent::DroppedFile(path) => {
+                self.handle_event(AppEvent::FileHovered { is_hovered: false });
+                match read_build_file(&path) {
+                    Ok(build) => {
+                        self.state.window.focus();
+                        self.open_build(build);
+                    }
+                    Err(err) => log::warn!("Unable to open dropped file: {err}"),
+                }
+            }
             WindowEvent::Resized(size) => {
                 self.state.window.size = PhysicalSize::new(size.width, size.height);
                 self.needs_reconfigure = true;
+                self.state.window.request_redraw();
+                if self.minimize_to_tray {
+                    self.state.window.hide_if_minimized();
+                }
+            }
+            // macOS doesn't resize minimized windows
+            WindowEvent::Occluded(true) if self.minimize_to_tray => {
+                self.state.window.hide_if_minimized();
             }
             WindowEvent::Focused(focused) => {
                 self.state.window.is_focused = focused;
@@ -272,27 +790,34 @@ impl ApplicationHandler<GraphicsContext> for App {
                     // Clear inputs on lost focus to avoid "stuck" keys on Wayland
                     // systems.
                     self.state.input.clear_pressed();
+                    self.state.window.release_cursor();
                 }
             }
+            WindowEvent::ThemeChanged(theme) => {
+                self.state.window.theme = Some(theme);
+                self.handle_event(AppEvent::ThemeChanged { theme });
+                self.state.window.request_redraw();
+            }
             WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
                 self.state.window.set_scale_factor(scale_factor as f32);
+                // re-layout and redraw at the new scale factor right away instead
+                // of waiting for the next input
+                self.needs_reconfigure = true;
+                self.state.window.request_redraw();
             }
             WindowEvent::KeyboardInput { event, .. } => {
                 let state = event.state;
+                let key = normalize_key(&event.logical_key, event.physical_key);
 
                 // update input state
                 self.state
                     .input
-                    .set_key_pressed(event.logical_key.clone(), state.is_pressed());
+                    .set_key_pressed(key.clone(), state.is_pressed());
 
                 // forward KeyUp/KeyDown events
                 let app_event = match state {
-                    ElementState::Pressed => AppEvent::KeyDown {
-                        key: event.logical_key.clone(),
-                    },
-                    ElementState::Released => AppEvent::KeyUp {
-                        key: event.logical_key.clone(),
-                    },
+                    ElementState::Pressed => AppEvent::KeyDown { key },
+                    ElementState::Released => AppEvent::KeyUp { key },
                 };
                 self.handle_event(app_event);
 
@@ -308,6 +833,7 @@ impl ApplicationHandler<GraphicsContext> for App {
             }
             WindowEvent::ModifiersChanged(modifiers) => {
                 self.state.input.key_modifiers = modifiers.state();
+                self.state.window.request_redraw();
             }
             WindowEvent::MouseInput { state, button, .. } => {
                 let is_double_click = self
@@ -323,10 +849,17 @@ impl ApplicationHandler<GraphicsContext> for App {
                     ElementState::Released => AppEvent::MouseUp { button },
                 };
                 self.handle_event(event);
+                // drags end when the button is released
+                if !state.is_pressed() {
+                    self.state.window.release_cursor();
+                }
             }
+            // the locked cursor doesn't move, its position is updated by `device_event`
+            WindowEvent::CursorMoved { .. } if self.state.window.is_cursor_captured() => {}
             WindowEvent::CursorMoved { position, .. } => {
                 let pos = PhysicalPoint::new(position.x as f32, position.y as f32);
                 self.state.set_mouse_pos(pos);
+                self.state.window.request_redraw();
             }
             WindowEvent::CursorEntered { .. } => {
                 self.state.window.is_hovered = true;
@@ -334,12 +867,13 @@ impl ApplicationHandler<GraphicsContext> for App {
             }
             WindowEvent::CursorLeft { .. } => {
                 self.state.window.is_hovered = false;
+                self.state.window.request_redraw();
             }
             WindowEvent::MouseWheel { delta, .. } => {
                 let delta = match delta {
                     MouseScrollDelta::LineDelta(_, y) => y,
                     MouseScrollDelta::PixelDelta(winit::dpi::PhysicalPosition { y, .. }) => {
-                        y as f32
+                        y as f32 / self.state.window.scale_factor() / PIXELS_PER_WHEEL_LINE
                     }
                 };
                 let event = AppEvent::MouseWheel { delta };
@@ -348,9 +882,86 @@ impl ApplicationHandler<GraphicsContext> for App {
             _ => {}
         }
     }
+
+    fn device_event(
+        &mut self,
+        _event_loop: &ActiveEventLoop,
+        _device_id: DeviceId,
+        event: DeviceEvent,
+    ) {
+        if let DeviceEvent::MouseMotion { delta: (x, y) } = event
+            && self.state.window.is_cursor_captured()
+        {
+            self.state
+                .move_captured_mouse(PhysicalVector::new(x as f32, y as f32));
+            self.state.window.request_redraw();
+        }
+    }
+
+    fn new_events(&mut self, _event_loop: &ActiveEventLoop, cause: StartCause) {
+        if let StartCause::ResumeTimeReached { .. } = cause {
+            self.state.window.request_redraw();
+        }
+    }
+
+    fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
+        self.current_mode.exiting();
+    }
+
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        event_loop.set_control_flow(match self.next_wakeup {
+            Some(next_wakeup) => ControlFlow::WaitUntil(next_wakeup),
+            None => ControlFlow::Wait,
+        });
+    }
+
+    fn user_event(&mut self, _event_loop: &ActiveEventLoop, event: UserEvent) {
+        match event {
+            UserEvent::Instance(message, reply) => {
+                self.state.window.focus();
+                let is_handled = match message {
+                    InstanceMessage::Focus => true,
+                    InstanceMessage::Import(url) => self.open_build(BuildSource::Url(url)),
+                };
+                let _ = reply.send(is_handled);
+            }
+            // changes are picked up by PoB in the next frame
+            UserEvent::FilesChanged
+            | UserEvent::DownloadProgress
+            | UserEvent::ProcessExited
+            | UserEvent::ClipboardChanged
+            | UserEvent::ImageLoaded => self.state.window.set_needs_redraw(),
+            // bring PoB back to the front after the user authorized it in the browser
+            UserEvent::OAuthRedirect => {
+                self.state.window.focus();
+                self.state.window.set_needs_redraw();
+            }
+            #[cfg(feature = "tray")]
+            UserEvent::Tray(action) => {
+                self.state.window.focus();
+                match action {
+                    TrayAction::Open => {}
+                    TrayAction::ImportFromClipboard => {
+                        let text = self.state.window.get_clipboard_text().unwrap_or_default();
+                        match parse_build_source(&text) {
+                            Ok(build) => {
+                                self.open_build(build);
+                            }
+                            Err(err) => log::warn!("No build in the clipboard: {err}"),
+                        }
+                    }
+                    // unsaved changes are handled like closing the window
+                    TrayAction::Quit => {
+                        self.state.should_exit = self.current_mode.can_exit(&mut self.state);
+                    }
+                }
+                self.state.window.request_redraw();
+            }
+        }
+    }
 }
 
-fn pob_font_definitions() -> FontDefinitions {
+pub fn pob_font_definitions() -> FontDefinitions {
     let mut definitions = FontDefinitions::default();
 
     definitions.font_data.insert(
@@ -412,6 +1023,27 @@ fn pob_font_definitions() -> FontDefinitions {
         ],
     );
 
+    // The bundled fonts only cover Latin scripts. Build names and notes frequently
+    // contain Cyrillic or CJK characters, so fall back to common system fonts.
+    definitions.fallback_families = [
+        "Noto Sans",
+        "Noto Sans CJK SC",
+        "Noto Sans CJK JP",
+        "Noto Sans CJK KR",
+        "Source Han Sans",
+        "DejaVu Sans",
+        "WenQuanYi Micro Hei",
+        "Segoe UI",
+        "Microsoft YaHei",
+        "Yu Gothic",
+        "Malgun Gothic",
+        "PingFang SC",
+        "Hiragino Sans",
+        "Arial Unicode MS",
+    ]
+    .map(String::from)
+    .to_vec();
+
     definitions
 }
 
@@ -0,0 +1,172 @@
+//! Builds passed on the command line with `--build` or dropped onto the window.
+//!
+//! Accepts links to supported build sites, PoB's `pob://` links, build codes and
+//! paths to files that contain either a build code or build XML. Build codes are
+//! decoded in Rust so that invalid codes are reported before PoB is started.
+
+use crate::{api::decompress, util::decode_base64};
+use std::path::{Path, PathBuf};
+
+/// Sites that PoB can import builds from
+const BUILD_SITES: [&str; 4] = ["pobb.in", "pastebin.com", "poe.ninja", "poedb.tw"];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BuildSource {
+    /// Link that PoB downloads the build from
+    Url(String),
+    /// Decoded build XML
+    Xml(String),
+    /// File that contains build XML, PoB opens it by path
+    File { path: PathBuf, xml: String },
+}
+
+impl BuildSource {
+    /// Type name exposed to Lua
+    pub fn kind(&self) -> &'static str {
+        match self {
+            BuildSource::Url(_) => "url",
+            BuildSource::Xml(_) | BuildSource::File { .. } => "xml",
+        }
+    }
+
+    pub fn value(&self) -> &str {
+        match self {
+            BuildSource::Url(value)
+            | BuildSource::Xml(value)
+            | BuildSource::File { xml: value, .. } => value,
+        }
+    }
+}
+
+/// Parses the value of `--build`. Used as clap value parser.
+pub fn parse_build_source(value: &str) -> Result<BuildSource, String> {
+    let value = value.trim();
+
+    if value.starts_with("pob:") {
+        return Ok(BuildSource::Url(value.to_owned()));
+    }
+
+    if let Some(url) = normalize_build_url(value) {
+        return url.map(BuildSource::Url);
+    }
+
+    let path = Path::new(value);
+    if path.is_file() {
+        return read_build_file(path);
+    }
+
+    decode_build_code(value).map(BuildSource::Xml)
+}
+
+/// Reads a file that contains build XML, a build code or a link to a build.
+pub fn read_build_file(path: &Path) -> Result<BuildSource, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("unable to read {}: {e}", path.display()))?;
+    let contents = contents.trim();
+    if contents.starts_with('<') {
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.to_owned());
+        return validate_build_xml(contents.to_owned()).map(|xml| BuildSource::File { path, xml });
+    }
+    if contents.starts_with("pob:") {
+        return Ok(BuildSource::Url(contents.to_owned()));
+    }
+    if let Some(url) = normalize_build_url(contents) {
+        return url.map(BuildSource::Url);
+    }
+    decode_build_code(contents).map(BuildSource::Xml)
+}
+
+/// Returns `None` if `value` isn't an http(s) link. Links are normalized to https
+/// and need to point to one of the supported build sites.
+fn normalize_build_url(value: &str) -> Option<Result<String, String>> {
+    let rest = value
+        .strip_prefix("https://")
+        .or_else(|| value.strip_prefix("http://"))?;
+
+    let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+    let host = host.to_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+
+    if !BUILD_SITES.contains(&host) {
+        return Some(Err(format!(
+            "unsupported build site {host}, expected one of: {}",
+            BUILD_SITES.join(", ")
+        )));
+    }
+
+    let path = path.trim_end_matches('/');
+    if path.is_empty() {
+        return Some(Err(format!("link to {host} is missing the build id")));
+    }
+
+    Some(Ok(format!("https://{host}/{path}")))
+}
+
+/// Decodes a build code, i.e. zlib compressed build XML encoded with URL-safe base64.
+fn decode_build_code(code: &str) -> Result<String, String> {
+    let compressed =
+        decode_base64(code).map_err(|e| format!("not a build code, link or file: {e}"))?;
+
+    let xml = decompress(&compressed).map_err(|e| format!("invalid build code: {e}"))?;
+    let xml = String::from_utf8(xml).map_err(|e| format!("invalid build code: {e}"))?;
+
+    validate_build_xml(xml)
+}
+
+fn validate_build_xml(xml: String) -> Result<String, String> {
+    if xml.contains("<PathOfBuilding") {
+        Ok(xml)
+    } else {
+        Err(String::from("not a Path of Building build"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{Engine, engine::general_purpose::URL_SAFE};
+    use flate2::{Compression, read::ZlibEncoder};
+    use std::io::Read;
+
+    #[test]
+    fn test_parse_build_source() {
+        let xml = r#"<?xml version="1.0"?><PathOfBuilding><Build/></PathOfBuilding>"#;
+        let mut compressed = Vec::new();
+        ZlibEncoder::new(xml.as_bytes(), Compression::fast())
+            .read_to_end(&mut compressed)
+            .unwrap();
+        let code = URL_SAFE.encode(&compressed);
+
+        assert_eq!(parse_build_source(&code), Ok(BuildSource::Xml(xml.into())));
+        assert_eq!(
+            parse_build_source("http://www.POBB.in/abc123/"),
+            Ok(BuildSource::Url("https://pobb.in/abc123".into()))
+        );
+        assert_eq!(
+            parse_build_source("pob://pobbin/abc123"),
+            Ok(BuildSource::Url("pob://pobbin/abc123".into()))
+        );
+        assert!(parse_build_source("https://example.com/abc123").is_err());
+        assert!(parse_build_source("https://pobb.in/").is_err());
+        assert!(parse_build_source("not a build").is_err());
+
+        // dropped files may contain a link instead of a build
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("build.txt");
+        std::fs::write(&path, "https://pobb.in/abc123\n").unwrap();
+        assert_eq!(
+            read_build_file(&path),
+            Ok(BuildSource::Url("https://pobb.in/abc123".into()))
+        );
+
+        // build files are kept by path
+        std::fs::write(&path, xml).unwrap();
+        assert_eq!(
+            read_build_file(&path),
+            Ok(BuildSource::File {
+                path: path.clone(),
+                xml: xml.into()
+            })
+        );
+    }
+}
@@ -0,0 +1,719 @@
+//! Captures of the primitives drawn in each frame, see `--capture` and `--replay`.
+//!
+//! Captures make rendering bugs reproducible without PoB's Lua code or the user's
+//! builds. They are zstd-compressed text with one record per line:
+//!
+//! ```text
+//! pob-capture 1
+//! frame <width> <height> <pixels_per_point>
+//! texture <id> <width> <height> <flags> <source>
+//! clip <min_x> <min_y> <max_x> <max_y>
+//! rect <min_x> <min_y> <max_x> <max_y> <color> [tex <id> <uv rect> <layer>]
+//! quad <4 points> <4 colors> [tex <id> <uv quad> <layer>]
+//! nine_slice <rect> <4 insets> <color> [tex <id> <uv rect> <4 uv insets> <layer>]
+//! line <width> <color> <points...>
+//! text <x> <y> <rotation> <size> <line_height> <alignment> <weight> <style> <segments> <family>
+//! segment <color> <text>
+//! ```
+//!
+//! A clip rect applies to the primitives after it. Textures are recorded before the
+//! first frame that draws them, text is recorded as the job it was laid out from.
+
+use crate::{
+    color::Srgba,
+    dpi::{LogicalPoint, LogicalRect, LogicalSize},
+    fonts::{Alignment, FontStyle, Layout, LayoutJob, LayoutSegment},
+    math::{Point, Quad, Rect},
+    renderer::{
+        primitives::{
+            ClippedPrimitive, DrawPrimitive, LinePrimitive, NineSlicePrimitive, NineSliceTexture,
+            QuadPrimitive, QuadTexture, RectPrimitive, RectTexture, TextPrimitive,
+        },
+        textures::{TextureId, TextureOptions, WrappedTextureManager},
+    },
+};
+use ahash::HashSet;
+use anyhow::{Context, anyhow, bail};
+use ordered_float::OrderedFloat;
+use parley::FontFamily;
+use std::{
+    borrow::Cow,
+    fmt::Write as _,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    str::SplitWhitespace,
+    sync::Arc,
+};
+
+const HEADER: &str = "pob-capture 1";
+
+/// Uncompressed size after which no more frames are written, so that a forgotten
+/// capture doesn't fill up the disk
+const MAX_CAPTURE_SIZE: u64 = 1 << 30;
+
+/// Writes the primitives of rendered frames to a capture file. The capture is
+/// completed when the writer is dropped.
+pub struct CaptureWriter {
+    encoder: zstd::Encoder<'static, BufWriter<File>>,
+    // textures whose metadata was already written
+    written_textures: HashSet<TextureId>,
+    line: String,
+    // uncompressed bytes written so far
+    written_size: u64,
+}
+
+impl CaptureWriter {
+    pub fn create(path: &Path) -> anyhow::Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Unable to create capture {}", path.display()))?;
+        let mut encoder = zstd::Encoder::new(BufWriter::new(file), 0)?;
+        writeln!(encoder, "{HEADER}")?;
+        Ok(Self {
+            encoder,
+            written_textures: HashSet::default(),
+            line: String::new(),
+            written_size: HEADER.len() as u64 + 1,
+        })
+    }
+
+    pub fn write_frame(
+        &mut self,
+        size: LogicalSize<f32>,
+        pixels_per_point: f32,
+        primitives: &[ClippedPrimitive],
+        texture_manager: &WrappedTextureManager,
+    ) -> anyhow::Result<()> {
+        profiling::scope!("write_capture_frame");
+
+        let out = &mut self.line;
+        out.clear();
+        let _ = writeln!(
+            out,
+            "frame {} {} {pixels_per_point}",
+            size.width, size.height
+        );
+
+        // the font atlas is created again from the text jobs when replaying
+        for primitive in primitives {
+            let id = primitive.primitive.texture_id();
+            if id == TextureId::default() || !self.written_textures.insert(id) {
+                continue;
+            }
+            if let Some(meta_data) = texture_manager.get_meta_data(id) {
+                let [width, height] = meta_data.size;
+                let flags = texture_flags(meta_data.options);
+                let source = meta_data.source_path().unwrap_or(&meta_data.name);
+                let _ = writeln!(out, "texture {id} {width} {height} {flags} {source}");
+            }
+        }
+
+        let mut clip_rect = None;
+        for primitive in primitives {
+            if clip_rect != Some(primitive.clip_rect) {
+                clip_rect = Some(primitive.clip_rect);
+                out.push_str("clip");
+                write_rect(out, &primitive.clip_rect);
+                out.push('\n');
+            }
+            write_primitive(out, &primitive.primitive);
+        }
+
+        self.written_size += out.len() as u64;
+        if self.written_size > MAX_CAPTURE_SIZE {
+            bail!("the capture exceeds {} MiB", MAX_CAPTURE_SIZE >> 20);
+        }
+        self.encoder.write_all(out.as_bytes())?;
+        Ok(())
+    }
+}
+
+impl Drop for CaptureWriter {
+    fn drop(&mut self) {
+        let result = self
+            .encoder
+            .do_finish()
+            .and_then(|()| self.encoder.get_mut().flush());
+        if let Err(err) = result {
+            log::warn!("Unable to finish capture: {err}");
+        }
+    }
+}
+
+fn texture_flags(options: TextureOptions) -> String {
+    let mut flags = Vec::new();
+    if options.wrap_mode == wgpu::AddressMode::ClampToEdge {
+        flags.push("clamp");
+    }
+    if options.magnification == wgpu::FilterMode::Nearest {
+        flags.push("nearest");
+    }
+    if options.generate_mipmaps {
+        flags.push("mipmap");
+    }
+    match flags.is_empty() {
+        true => String::from("-"),
+        false => flags.join(","),
+    }
+}
+
+fn write_point<U>(out: &mut String, point: &Point<f32, U>) {
+    let _ = write!(out, " {} {}", point.x, point.y);
+}
+
+fn write_rect<U>(out: &mut String, rect: &Rect<f32, U>) {
+    write_point(out, &rect.min);
+    write_point(out, &rect.max);
+}
+
+fn write_quad<U>(out: &mut String, quad: &Quad<f32, U>) {
+    for point in [quad.p0, quad.p1, quad.p2, quad.p3] {
+        write_point(out, &point);
+    }
+}
+
+fn write_insets(out: &mut String, insets: &[f32; 4]) {
+    for inset in insets {
+        let _ = write!(out, " {inset}");
+    }
+}
+
+fn write_color(out: &mut String, color: Srgba) {
+    let [r, g, b, a] = color.0;
+    let _ = write!(out, " {r:02x}{g:02x}{b:02x}{a:02x}");
+}
+
+fn write_primitive(out: &mut String, primitive: &DrawPrimitive) {
+    match primitive {
+        DrawPrimitive::Rect(rect) => {
+            out.push_str("rect");
+            write_rect(out, &rect.rect);
+            write_color(out, rect.color);
+            if let Some(texture) = &rect.texture {
+                let _ = write!(out, " tex {}", texture.texture_id);
+                write_rect(out, &texture.uv);
+                let _ = write!(out, " {}", texture.layer_idx);
+            }
+        }
+        DrawPrimitive::Quad(quad) => {
+            out.push_str("quad");
+            write_quad(out, &quad.quad);
+            for color in quad.colors {
+                write_color(out, color);
+            }
+            if let Some(texture) = &quad.texture {
+                let _ = write!(out, " tex {}", texture.texture_id);
+                write_quad(out, &texture.uv);
+                let _ = write!(out, " {}", texture.layer_idx);
+            }
+        }
+        DrawPrimitive::NineSlice(nine_slice) => {
+            out.push_str("nine_slice");
+            write_rect(out, &nine_slice.rect);
+            write_insets(out, &nine_slice.insets);
+            write_color(out, nine_slice.color);
+            if let Some(texture) = &nine_slice.texture {
+                let _ = write!(out, " tex {}", texture.texture_id);
+                write_rect(out, &texture.uv);
+                write_insets(out, &texture.uv_insets);
+                let _ = write!(out, " {}", texture.layer_idx);
+            }
+        }
+        DrawPrimitive::Line(line) => {
+            let _ = write!(out, "line {}", line.width);
+            write_color(out, line.color);
+            for point in &line.points {
+                write_point(out, point);
+            }
+        }
+        DrawPrimitive::Text(text) => {
+            // layouts only keep their job while capturing, see `Fonts::set_keep_jobs`
+            let Some(job) = &text.layout.job else {
+                return;
+            };
+            let alignment = match job.alignment {
+                None => "-",
+                Some(Alignment::Min) => "min",
+                Some(Alignment::Center) => "center",
+                Some(Alignment::Max) => "max",
+            };
+            let weight = match job.font_weight {
+                Some(weight) => weight.to_string(),
+                None => String::from("-"),
+            };
+            let style = match job.font_style {
+                FontStyle::Normal => "normal",
+                FontStyle::Italic => "italic",
+            };
+            let _ = write!(
+                out,
+                "text {} {} {} {} {} {alignment} {weight} {style} {} {}",
+                text.pos.x,
+                text.pos.y,
+                text.rotation,
+                job.font_size,
+                job.line_height,
+                job.segments.len(),
+                job.font_family,
+            );
+            for segment in &job.segments {
+                out.push_str("\nsegment");
+                write_color(out, segment.color);
+                out.push(' ');
+                out.push_str(&escape(&segment.text));
+            }
+        }
+    }
+    out.push('\n');
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn unescape(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            output.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => output.push('\n'),
+            Some('r') => output.push('\r'),
+            Some(other) => output.push(other),
+            None => {}
+        }
+    }
+    output
+}
+
+/// Texture drawn in a capture. Replays load it from `source` if it exists.
+#[derive(Debug)]
+pub struct CapturedTexture {
+    pub id: TextureId,
+    pub size: [usize; 2],
+    pub options: TextureOptions,
+    pub source: String,
+}
+
+pub enum CapturedPrimitive {
+    /// Rects, quads and lines
+    Primitive(ClippedPrimitive),
+    /// Text, which needs to be laid out again
+    Text {
+        clip_rect: LogicalRect<f32>,
+        pos: LogicalPoint<f32>,
+        rotation: f32,
+        job: LayoutJob<'static>,
+    },
+}
+
+impl CapturedPrimitive {
+    /// Converts to a primitive that can be drawn. `layout` lays out text and
+    /// `texture_id` maps captured texture ids to the replay's textures.
+    pub fn to_primitive(
+        &self,
+        layout: &mut impl FnMut(LayoutJob<'static>) -> Arc<Layout>,
+        texture_id: impl Fn(TextureId) -> TextureId,
+    ) -> ClippedPrimitive {
+        match self {
+            CapturedPrimitive::Primitive(primitive) => {
+                let mut primitive = primitive.clone();
+                match &mut primitive.primitive {
+                    DrawPrimitive::Rect(RectPrimitive {
+                        texture: Some(texture),
+                        ..
+                    }) => texture.texture_id = texture_id(texture.texture_id),
+                    DrawPrimitive::Quad(QuadPrimitive {
+                        texture: Some(texture),
+                        ..
+                    }) => texture.texture_id = texture_id(texture.texture_id),
+                    DrawPrimitive::NineSlice(NineSlicePrimitive {
+                        texture: Some(texture),
+                        ..
+                    }) => texture.texture_id = texture_id(texture.texture_id),
+                    _ => {}
+                }
+                primitive
+            }
+            CapturedPrimitive::Text {
+                clip_rect,
+                pos,
+                rotation,
+                job,
+            } => ClippedPrimitive {
+                clip_rect: *clip_rect,
+                primitive: DrawPrimitive::Text(
+                    TextPrimitive::new(*pos, layout(job.clone())).with_rotation(*rotation),
+                ),
+            },
+        }
+    }
+}
+
+pub struct CapturedFrame {
+    pub size: LogicalSize<f32>,
+    pub pixels_per_point: f32,
+    pub primitives: Vec<CapturedPrimitive>,
+}
+
+#[derive(Default)]
+pub struct Capture {
+    pub textures: Vec<CapturedTexture>,
+    pub frames: Vec<CapturedFrame>,
+}
+
+impl Capture {
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Unable to open capture {}", path.display()))?;
+        let mut lines = BufReader::new(zstd::Decoder::new(file)?).lines();
+
+        match lines.next() {
+            Some(Ok(header)) if header == HEADER => {}
+            _ => bail!("{} isn't a capture", path.display()),
+        }
+
+        let mut capture = Capture::default();
+        let mut clip_rect = LogicalRect::zero();
+        let mut line_number = 1;
+        while let Some(line) = lines.next() {
+            line_number += 1;
+            let line = match line {
+                Ok(line) => line,
+                // the end of captures of crashed sessions is missing
+                Err(err) => {
+                    log::warn!("Capture ends unexpectedly: {err}");
+                    break;
+                }
+            };
+            capture
+                .read_record(&line, &mut clip_rect, &mut lines)
+                .with_context(|| format!("Invalid record in line {line_number}"))?;
+        }
+        Ok(capture)
+    }
+
+    fn read_record(
+        &mut self,
+        line: &str,
+        clip_rect: &mut LogicalRect<f32>,
+        lines: &mut impl Iterator<Item = std::io::Result<String>>,
+    ) -> anyhow::Result<()> {
+        let (tag, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let mut fields = Fields(rest.split_whitespace());
+
+        if tag == "frame" {
+            self.frames.push(CapturedFrame {
+                size: LogicalSize::new(fields.f32()?, fields.f32()?),
+                pixels_per_point: fields.f32()?,
+                primitives: Vec::new(),
+            });
+            return Ok(());
+        }
+        if tag == "texture" {
+            // the source is the rest of the line and may contain spaces
+            let mut parts = rest.splitn(5, ' ');
+            let mut next = || parts.next().ok_or_else(|| anyhow!("missing field"));
+            let id = next()?.parse()?;
+            let size = [next()?.parse()?, next()?.parse()?];
+            let mut options = TextureOptions::LINEAR_REPEAT;
+            for flag in next()?.split(',') {
+                match flag {
+                    "clamp" => options.wrap_mode = wgpu::AddressMode::ClampToEdge,
+                    "nearest" => options.magnification = wgpu::FilterMode::Nearest,
+                    "mipmap" => options.generate_mipmaps = true,
+                    _ => {}
+                }
+            }
+            let source = next()?.to_owned();
+            self.textures.push(CapturedTexture {
+                id,
+                size,
+                options,
+                source,
+            });
+            return Ok(());
+        }
+
+        let frame = self
+            .frames
+            .last_mut()
+            .ok_or_else(|| anyhow!("{tag} before the first frame"))?;
+        let primitive = match tag {
+            "clip" => {
+                *clip_rect = fields.rect()?;
+                return Ok(());
+            }
+            "rect" => {
+                let rect = fields.rect()?;
+                let color = fields.color()?;
+                let texture = match fields.0.next() {
+                    Some("tex") => Some(RectTexture::new(
+                        fields.parse()?,
+                        fields.rect()?,
+                        fields.parse()?,
+                    )),
+                    _ => None,
+                };
+                DrawPrimitive::Rect(RectPrimitive::new(rect, color, texture))
+            }
+            "quad" => {
+                let quad = fields.quad()?;
+                let colors = [
+                    fields.color()?,
+                    fields.color()?,
+                    fields.color()?,
+                    fields.color()?,
+                ];
+                let texture = match fields.0.next() {
+                    Some("tex") => Some(QuadTexture::new(
+                        fields.parse()?,
+                        fields.quad()?,
+                        fields.parse()?,
+                    )),
+                    _ => None,
+                };
+                DrawPrimitive::Quad(QuadPrimitive::with_colors(quad, colors, texture))
+            }
+            "nine_slice" => {
+                let rect = fields.rect()?;
+                let insets = fields.insets()?;
+                let color = fields.color()?;
+                let texture = match fields.0.next() {
+                    Some("tex") => Some(NineSliceTexture::new(
+                        fields.parse()?,
+                        fields.rect()?,
+                        fields.insets()?,
+                        fields.parse()?,
+                    )),
+                    _ => None,
+                };
+                DrawPrimitive::NineSlice(NineSlicePrimitive::new(rect, insets, color, texture))
+            }
+            "line" => {
+                let width = fields.f32()?;
+                let color = fields.color()?;
+                let mut points = Vec::new();
+                while let Some(x) = fields.0.next() {
+                    points.push(LogicalPoint::new(x.parse()?, fields.f32()?));
+                }
+                DrawPrimitive::Line(LinePrimitive::new(points, width, color))
+            }
+            "text" => {
+                let pos = fields.point()?;
+                let rotation = fields.f32()?;
+                let font_size = fields.f32()?;
+                let line_height = fields.f32()?;
+                let alignment = match fields.str()? {
+                    "min" => Some(Alignment::Min),
+                    "center" => Some(Alignment::Center),
+                    "max" => Some(Alignment::Max),
+                    _ => None,
+                };
+                let font_weight = match fields.str()? {
+                    "-" => None,
+                    weight => Some(weight.parse()?),
+                };
+                let font_style = match fields.str()? {
+                    "italic" => FontStyle::Italic,
+                    _ => FontStyle::Normal,
+                };
+                let segment_count: usize = fields.parse()?;
+                // the family is the rest of the line and may contain spaces
+                let family = rest.splitn(10, ' ').nth(9).unwrap_or_default();
+                let font_family = match FontFamily::parse(family) {
+                    Some(FontFamily::Named(name)) => FontFamily::Named(Cow::Owned(name.into())),
+                    Some(FontFamily::Generic(family)) => FontFamily::Generic(family),
+                    None => bail!("invalid font family {family}"),
+                };
+
+                let mut job = LayoutJob {
+                    segments: Vec::new(),
+                    font_family,
+                    font_size: OrderedFloat(font_size),
+                    line_height: OrderedFloat(line_height),
+                    alignment,
+                    font_weight: font_weight.map(OrderedFloat),
+                    font_style,
+                };
+                for _ in 0..segment_count {
+                    let line = lines.next().ok_or_else(|| anyhow!("missing segment"))??;
+                    let segment = line
+                        .strip_prefix("segment ")
+                        .ok_or_else(|| anyhow!("missing segment"))?;
+                    let (color, text) = segment.split_once(' ').unwrap_or((segment, ""));
+                    job.segments.push(LayoutSegment {
+                        text: Cow::Owned(unescape(text)),
+                        color: Srgba::from_hex(color)?,
+                    });
+                }
+
+                frame.primitives.push(CapturedPrimitive::Text {
+                    clip_rect: *clip_rect,
+                    pos,
+                    rotation,
+                    job,
+                });
+                return Ok(());
+            }
+            _ => bail!("unknown record {tag}"),
+        };
+
+        frame
+            .primitives
+            .push(CapturedPrimitive::Primitive(ClippedPrimitive {
+                clip_rect: *clip_rect,
+                primitive,
+            }));
+        Ok(())
+    }
+}
+
+/// Whitespace separated fields of a record
+struct Fields<'a>(SplitWhitespace<'a>);
+
+impl<'a> Fields<'a> {
+    fn str(&mut self) -> anyhow::Result<&'a str> {
+        self.0.next().ok_or_else(|| anyhow!("missing field"))
+    }
+
+    fn parse<T: std::str::FromStr>(&mut self) -> anyhow::Result<T>
+    where
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        Ok(self.str()?.parse()?)
+    }
+
+    fn f32(&mut self) -> anyhow::Result<f32> {
+        self.parse()
+    }
+
+    fn point<U>(&mut self) -> anyhow::Result<Point<f32, U>> {
+        Ok(Point::new(self.f32()?, self.f32()?))
+    }
+
+    fn rect<U>(&mut self) -> anyhow::Result<Rect<f32, U>> {
+        Ok(Rect::new(self.point()?, self.point()?))
+    }
+
+    fn quad<U>(&mut self) -> anyhow::Result<Quad<f32, U>> {
+        Ok(Quad::new(
+            self.point()?,
+            self.point()?,
+            self.point()?,
+            self.point()?,
+        ))
+    }
+
+    fn insets(&mut self) -> anyhow::Result<[f32; 4]> {
+        Ok([self.f32()?, self.f32()?, self.f32()?, self.f32()?])
+    }
+
+    fn color(&mut self) -> anyhow::Result<Srgba> {
+        Srgba::from_hex(self.str()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dpi::{LogicalQuad, NormalizedQuad, NormalizedRect},
+        math::Size,
+    };
+
+    #[test]
+    fn test_read_written_records() {
+        let clip_rect = Rect::from_size(Size::new(800.0, 600.0));
+        let primitives = [
+            DrawPrimitive::Rect(RectPrimitive::new(
+                Rect::new(Point::new(10.0, 20.5), Point::new(30.125, 40.0)),
+                Srgba::new(255, 128, 0, 200),
+                Some(RectTexture::new(
+                    3,
+                    NormalizedRect::new(Point::new(0.0, 0.25), Point::new(1.0, 0.75)),
+                    2,
+                )),
+            )),
+            DrawPrimitive::Quad(QuadPrimitive::new(
+                LogicalQuad::from_size(Size::new(4.0, 4.0)),
+                Srgba::WHITE,
+                Some(QuadTexture::new(
+                    5,
+                    NormalizedQuad::from_size(Size::new(1.0, 1.0)),
+                    0,
+                )),
+            )),
+            DrawPrimitive::NineSlice(NineSlicePrimitive::new(
+                Rect::from_size(Size::new(100.0, 50.0)),
+                [8.0, 4.0, 8.0, 4.0],
+                Srgba::WHITE,
+                Some(NineSliceTexture::new(
+                    7,
+                    NormalizedRect::from_size(Size::new(0.5, 1.0)),
+                    [0.125, 0.25, 0.125, 0.25],
+                    1,
+                )),
+            )),
+            DrawPrimitive::Line(LinePrimitive::new(
+                vec![Point::new(1.0, 2.0), Point::new(3.0, 4.0)],
+                1.5,
+                Srgba::TRANSPARENT,
+            )),
+        ];
+
+        let mut out = String::from("frame 800 600 1.5\nclip");
+        write_rect(&mut out, &clip_rect);
+        out.push('\n');
+        for primitive in &primitives {
+            write_primitive(&mut out, primitive);
+        }
+        out.push_str(
+            "text 1 2 0 14 16 center 700 normal 2 \"Liberation Sans\"\n\
+             segment ffffffff ^7multi\\nline\n\
+             segment ff0000ff  trailing\\\\\n",
+        );
+
+        let mut capture = Capture::default();
+        let mut clip = LogicalRect::zero();
+        let mut lines = out.lines().map(|line| Ok(line.to_owned()));
+        while let Some(line) = lines.next() {
+            capture
+                .read_record(&line.unwrap(), &mut clip, &mut lines)
+                .unwrap();
+        }
+
+        let frame = &capture.frames[0];
+        assert_eq!(frame.pixels_per_point, 1.5);
+        assert_eq!(frame.primitives.len(), 5);
+        let mut reread = String::new();
+        for primitive in &frame.primitives[..4] {
+            let CapturedPrimitive::Primitive(primitive) = primitive else {
+                panic!("expected a primitive");
+            };
+            assert_eq!(primitive.clip_rect, clip_rect);
+            write_primitive(&mut reread, &primitive.primitive);
+        }
+        assert_eq!(
+            reread,
+            out.lines().skip(2).take(4).collect::<Vec<_>>().join("\n") + "\n"
+        );
+
+        let CapturedPrimitive::Text { job, .. } = &frame.primitives[4] else {
+            panic!("expected text");
+        };
+        assert_eq!(
+            job.font_family,
+            FontFamily::Named(Cow::Borrowed("Liberation Sans"))
+        );
+        assert_eq!(job.alignment, Some(Alignment::Center));
+        assert_eq!(job.segments[0].text, "^7multi\nline");
+        assert_eq!(job.segments[1].text, " trailing\\");
+        assert_eq!(job.segments[1].color, Srgba::from_rgb(255, 0, 0));
+    }
+}
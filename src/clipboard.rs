@@ -1,4 +1,13 @@
+use crate::app::{EventProxy, UserEvent};
 use raw_window_handle::RawDisplayHandle;
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
 
 /// Abstraction over clipboard crates
 ///
@@ -55,6 +64,45 @@ impl Clipboard {
 
         None
     }
+
+    /// Sets the primary selection, which is pasted with the middle mouse button
+    /// on Linux. Does nothing on other platforms.
+    pub fn set_primary_text(&mut self, _text: String) {
+        #[cfg(target_family = "unix")]
+        if let Some(clipboard) = &mut self.smithay {
+            clipboard.store_primary(_text);
+            return;
+        }
+
+        #[cfg(target_os = "linux")]
+        if let Some(clipboard) = &mut self.arboard {
+            use arboard::{LinuxClipboardKind, SetExtLinux};
+            let _ = clipboard
+                .set()
+                .clipboard(LinuxClipboardKind::Primary)
+                .text(_text);
+        }
+    }
+
+    /// Gets the primary selection. `None` on platforms without one.
+    pub fn get_primary_text(&mut self) -> Option<String> {
+        #[cfg(target_family = "unix")]
+        if let Some(clipboard) = &mut self.smithay {
+            return clipboard.load_primary().ok();
+        }
+
+        #[cfg(target_os = "linux")]
+        if let Some(clipboard) = &mut self.arboard {
+            use arboard::{GetExtLinux, LinuxClipboardKind};
+            return clipboard
+                .get()
+                .clipboard(LinuxClipboardKind::Primary)
+                .text()
+                .ok();
+        }
+
+        None
+    }
 }
 
 #[cfg(target_family = "unix")]
@@ -79,3 +127,253 @@ fn create_arboard_clipboard() -> Option<arboard::Clipboard> {
         }
     }
 }
+
+/// Interval at which the clipboard's change counter or owner change events are
+/// checked. Neither accesses the clipboard's content.
+const COUNTER_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Interval at which the clipboard's text is read and compared on Wayland
+#[cfg(not(any(windows, target_os = "macos")))]
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Source of a value that changes with the content of the clipboard.
+enum ChangeSource {
+    /// `GetClipboardSequenceNumber`
+    #[cfg(windows)]
+    SequenceNumber,
+    /// `changeCount` of the general pasteboard
+    #[cfg(target_os = "macos")]
+    ChangeCount,
+    /// Number of changes of the text read through the window's Wayland connection.
+    /// Wayland has no change events without the data-control protocol, which not
+    /// all compositors support, and only offers the clipboard to focused windows.
+    #[cfg(not(any(windows, target_os = "macos")))]
+    Wayland {
+        clipboard: smithay_clipboard::Clipboard,
+        text: Option<String>,
+        changes: u64,
+    },
+    /// Number of XFixes events for changes of the `CLIPBOARD` selection's owner
+    #[cfg(not(any(windows, target_os = "macos")))]
+    X11 {
+        connection: Box<x11rb::rust_connection::RustConnection>,
+        changes: u64,
+    },
+}
+
+impl ChangeSource {
+    /// `wayland_display` is the pointer to the window's `wl_display`, if any.
+    fn new(_wayland_display: Option<usize>) -> anyhow::Result<Self> {
+        #[cfg(windows)]
+        let source = Self::SequenceNumber;
+
+        #[cfg(target_os = "macos")]
+        let source = Self::ChangeCount;
+
+        #[cfg(not(any(windows, target_os = "macos")))]
+        let source = match _wayland_display {
+            // SAFETY: the display connection is owned by the event loop. The thread
+            // that uses the clipboard is joined in `ClipboardWatcher::stop`, which
+            // runs before the event loop exits (see `App::exiting`) or when the
+            // watcher is dropped before that.
+            Some(display) => Self::Wayland {
+                clipboard: unsafe { smithay_clipboard::Clipboard::new(display as *mut _) },
+                text: None,
+                changes: 0,
+            },
+            None => Self::X11 {
+                connection: Box::new(watch_x11_clipboard()?),
+                changes: 0,
+            },
+        };
+
+        Ok(source)
+    }
+
+    fn interval(&self) -> Duration {
+        match self {
+            #[cfg(windows)]
+            Self::SequenceNumber => COUNTER_INTERVAL,
+            #[cfg(target_os = "macos")]
+            Self::ChangeCount => COUNTER_INTERVAL,
+            #[cfg(not(any(windows, target_os = "macos")))]
+            Self::Wayland { .. } => POLL_INTERVAL,
+            #[cfg(not(any(windows, target_os = "macos")))]
+            Self::X11 { .. } => COUNTER_INTERVAL,
+        }
+    }
+
+    /// Value that differs whenever the clipboard changed.
+    fn current(&mut self) -> u64 {
+        match self {
+            #[cfg(windows)]
+            // SAFETY: has no preconditions
+            Self::SequenceNumber => unsafe {
+                windows::Win32::System::DataExchange::GetClipboardSequenceNumber() as u64
+            },
+            #[cfg(target_os = "macos")]
+            Self::ChangeCount => {
+                objc2_app_kit::NSPasteboard::generalPasteboard().changeCount() as u64
+            }
+            #[cfg(not(any(windows, target_os = "macos")))]
+            Self::Wayland {
+                clipboard,
+                text,
+                changes,
+            } => {
+                // texts of different lengths differ without comparing their content
+                let current = clipboard.load().ok();
+                if current != *text {
+                    *text = current;
+                    *changes += 1;
+                }
+                *changes
+            }
+            #[cfg(not(any(windows, target_os = "macos")))]
+            Self::X11 {
+                connection,
+                changes,
+            } => {
+                use x11rb::{connection::Connection, protocol::Event};
+                while let Ok(Some(event)) = connection.poll_for_event() {
+                    if let Event::XfixesSelectionNotify(_) = event {
+                        *changes += 1;
+                    }
+                }
+                *changes
+            }
+        }
+    }
+}
+
+/// Connects to the X server and subscribes to changes of the `CLIPBOARD`
+/// selection's owner, which happen whenever something is copied.
+#[cfg(not(any(windows, target_os = "macos")))]
+fn watch_x11_clipboard() -> anyhow::Result<x11rb::rust_connection::RustConnection> {
+    use x11rb::{
+        connection::Connection,
+        protocol::{xfixes::ConnectionExt as _, xfixes::SelectionEventMask, xproto::ConnectionExt},
+    };
+
+    let (connection, screen) = x11rb::rust_connection::RustConnection::connect(None)?;
+    connection.xfixes_query_version(5, 0)?.reply()?;
+    let root = connection.setup().roots[screen].root;
+    let clipboard = connection.intern_atom(false, b"CLIPBOARD")?.reply()?.atom;
+    connection.xfixes_select_selection_input(
+        root,
+        clipboard,
+        SelectionEventMask::SET_SELECTION_OWNER
+            | SelectionEventMask::SELECTION_WINDOW_DESTROY
+            | SelectionEventMask::SELECTION_CLIENT_CLOSE,
+    )?;
+    connection.flush()?;
+    Ok(connection)
+}
+
+/// Detects changes of the clipboard, e.g. items copied in the game, so that PoB
+/// doesn't need to read the clipboard on a timer.
+///
+/// A background thread checks the clipboard's change counter on Windows and macOS
+/// and the selection owner change events of XFixes on X11. Wayland has neither, so
+/// the text is read through the window's connection and compared instead. Wayland
+/// only offers the clipboard to the focused window, so changes made while the
+/// window is unfocused aren't seen until it's focused again. Watching starts with
+/// the first call of `sequence`.
+pub struct ClipboardWatcher {
+    sequence: Arc<AtomicU64>,
+    // set when the watcher is stopped, ends the thread
+    stop: Arc<AtomicBool>,
+    is_running: bool,
+    thread: Option<JoinHandle<()>>,
+    // sequence PoB was last notified about
+    notified_sequence: u64,
+    // wakes up the event loop, which might be idle
+    proxy: EventProxy,
+}
+
+impl ClipboardWatcher {
+    pub fn new(proxy: EventProxy) -> Self {
+        Self {
+            sequence: Arc::new(AtomicU64::new(0)),
+            stop: Arc::new(AtomicBool::new(false)),
+            is_running: false,
+            thread: None,
+            notified_sequence: 0,
+            proxy,
+        }
+    }
+
+    /// Number that is incremented whenever the content of the clipboard changes.
+    /// `display` is the window's display, whose connection is used on Wayland.
+    pub fn sequence(&mut self, display: Option<RawDisplayHandle>) -> u64 {
+        if !self.is_running {
+            self.is_running = true;
+            let wayland_display = match display {
+                Some(RawDisplayHandle::Wayland(handle)) => Some(handle.display.as_ptr() as usize),
+                _ => None,
+            };
+            self.spawn(wayland_display);
+        }
+        self.sequence.load(Ordering::Relaxed)
+    }
+
+    fn spawn(&mut self, wayland_display: Option<usize>) {
+        let sequence = Arc::clone(&self.sequence);
+        let stop = Arc::clone(&self.stop);
+        let proxy = self.proxy.clone();
+        let thread = std::thread::Builder::new().name("Clipboard watcher".to_owned());
+        let result = thread.spawn(move || {
+            let mut source = match ChangeSource::new(wayland_display) {
+                Ok(source) => source,
+                Err(err) => {
+                    log::warn!("Unable to watch clipboard: {err}");
+                    return;
+                }
+            };
+            let mut last_value = source.current();
+            loop {
+                // unparked when the watcher is stopped
+                std::thread::park_timeout(source.interval());
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                let value = source.current();
+                if value != last_value {
+                    sequence.fetch_add(1, Ordering::Relaxed);
+                    let _ = proxy.send_event(UserEvent::ClipboardChanged);
+                }
+                last_value = value;
+            }
+        });
+        match result {
+            Ok(thread) => self.thread = Some(thread),
+            Err(err) => log::warn!("Unable to watch clipboard: {err}"),
+        }
+    }
+
+    /// Stops watching and waits for the thread, which may use the window's display
+    /// connection, to exit.
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+
+    /// The current sequence if the clipboard changed since the last call.
+    pub fn take_change(&mut self) -> Option<u64> {
+        let sequence = self.sequence.load(Ordering::Relaxed);
+        if sequence == self.notified_sequence {
+            return None;
+        }
+        self.notified_sequence = sequence;
+        Some(sequence)
+    }
+}
+
+impl Drop for ClipboardWatcher {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -27,6 +27,10 @@ impl Srgba {
     pub fn from_hex<T: AsRef<str>>(hex: T) -> anyhow::Result<Self> {
         let hex = hex.as_ref();
         let hex = hex.strip_prefix("#").unwrap_or(hex);
+        // from_str_radix would also accept a sign, e.g. "+FC"
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            anyhow::bail!("Invalid hex string");
+        }
 
         match hex.len() {
             // RGB shorthand form, e.g. #FC0 -> #FFCC00
@@ -84,3 +88,49 @@ const fn f32_to_u8(c: f32) -> u8 {
 const fn u8_to_f32(c: u8) -> f32 {
     c as f32 / 255.0
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_from_hex() {
+        assert_eq!(
+            Srgba::from_hex("#FC0").unwrap(),
+            Srgba::from_rgb(255, 204, 0)
+        );
+        assert_eq!(
+            Srgba::from_hex("fc08").unwrap(),
+            Srgba::new(255, 204, 0, 136)
+        );
+        assert_eq!(
+            Srgba::from_hex("E5B428").unwrap(),
+            Srgba::from_rgb(229, 180, 40)
+        );
+        assert!(Srgba::from_hex("+FC").is_err());
+        assert!(Srgba::from_hex("#").is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn from_hex_never_panics(hex in "\\PC*") {
+            let _ = Srgba::from_hex(hex);
+        }
+
+        #[test]
+        fn from_hex_roundtrips(rgba: [u8; 4], prefix in "#?") {
+            let [r, g, b, a] = rgba;
+            let rgb = format!("{prefix}{r:02x}{g:02X}{b:02x}");
+            prop_assert_eq!(Srgba::from_hex(&rgb).unwrap(), Srgba::from_rgb(r, g, b));
+            let rgba = format!("{rgb}{a:02X}");
+            prop_assert_eq!(Srgba::from_hex(rgba).unwrap(), Srgba::new(r, g, b, a));
+        }
+
+        #[test]
+        fn from_hex_rejects_other_lengths(hex in "[0-9a-fA-F]{0,12}") {
+            prop_assume!(![3, 4, 6, 8].contains(&hex.len()));
+            prop_assert!(Srgba::from_hex(hex).is_err());
+        }
+    }
+}
@@ -0,0 +1,296 @@
+//! Searchable list of commands that is opened with Ctrl+Shift+P, or the shortcut
+//! configured as `palette_shortcut`.
+//!
+//! Lists app-level actions as well as commands registered by PoB through
+//! `RegisterCommand`. The palette is drawn on top of PoB's UI and captures all
+//! keyboard input while it is open.
+
+use crate::{
+    color::Srgba,
+    config::{self, Config},
+    dpi::{LogicalPoint, LogicalRect, LogicalSize, LogicalVector, NormalizedRect, Uv},
+    fonts::{FontStyle, Fonts, LayoutJob},
+    layers::Layers,
+    shortcut::Shortcut,
+};
+use parley::{FontFamily, GenericFamily};
+use winit::keyboard::{Key, ModifiersState, NamedKey};
+
+const WIDTH: f32 = 600.0;
+const TOP: f32 = 80.0;
+const PADDING: f32 = 8.0;
+const ROW_HEIGHT: f32 = 24.0;
+const FONT_SIZE: f32 = 16.0;
+const LINE_HEIGHT: f32 = 18.0;
+const MAX_VISIBLE_ROWS: usize = 12;
+
+/// Shortcut unless another one is configured
+pub const TOGGLE_SHORTCUT: Shortcut = Shortcut::ctrl_shift('p');
+
+const BACKGROUND_COLOR: Srgba = Srgba::new(24, 24, 24, 240);
+const SELECTION_COLOR: Srgba = Srgba::new(60, 80, 120, 255);
+const TEXT_COLOR: Srgba = Srgba::WHITE;
+const HINT_COLOR: Srgba = Srgba::new(150, 150, 150, 255);
+
+/// Actions that are handled by the app itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AppCommand {
+    OpenSettings,
+    ReloadTextures,
+    ToggleAlwaysOnTop,
+    TogglePerfOverlay,
+    ToggleConsole,
+    OpenUserFolder,
+    OpenLogFile,
+    /// Restarts the app with the profile selector
+    SwitchProfile,
+    Restart,
+}
+
+impl AppCommand {
+    const ALL: [AppCommand; 9] = [
+        AppCommand::OpenSettings,
+        AppCommand::ReloadTextures,
+        AppCommand::ToggleAlwaysOnTop,
+        AppCommand::TogglePerfOverlay,
+        AppCommand::ToggleConsole,
+        AppCommand::OpenUserFolder,
+        AppCommand::OpenLogFile,
+        AppCommand::SwitchProfile,
+        AppCommand::Restart,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            AppCommand::OpenSettings => "Settings",
+            AppCommand::ReloadTextures => "Reload textures",
+            AppCommand::ToggleAlwaysOnTop => "Toggle always on top",
+            AppCommand::TogglePerfOverlay => "Toggle performance overlay",
+            AppCommand::ToggleConsole => "Toggle console",
+            AppCommand::OpenUserFolder => "Open user folder",
+            AppCommand::OpenLogFile => "Open log file",
+            AppCommand::SwitchProfile => "Switch profile",
+            AppCommand::Restart => "Restart",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    App(AppCommand),
+    /// Command registered by PoB, identified by its name
+    Lua(String),
+}
+
+/// Result of handling a key press while the palette is open.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PaletteAction {
+    None,
+    Close,
+    Run(Command),
+}
+
+struct Entry {
+    label: String,
+    command: Command,
+}
+
+pub struct CommandPalette {
+    entries: Vec<Entry>,
+    query: String,
+    // indices into `entries` that match the query, best match first
+    matches: Vec<usize>,
+    selected: usize,
+}
+
+impl CommandPalette {
+    /// `has_profiles` lists the command to switch profiles, which is only useful if
+    /// profiles exist.
+    pub fn new(lua_commands: Vec<String>, has_profiles: bool) -> Self {
+        let app_commands = AppCommand::ALL
+            .into_iter()
+            .filter(|command| has_profiles || *command != AppCommand::SwitchProfile);
+        let app_entries = app_commands.map(|command| Entry {
+            label: command.label().to_owned(),
+            command: Command::App(command),
+        });
+        let lua_entries = lua_commands.into_iter().map(|name| Entry {
+            label: name.clone(),
+            command: Command::Lua(name),
+        });
+
+        let mut palette = Self {
+            entries: app_entries.chain(lua_entries).collect(),
+            query: String::new(),
+            matches: Vec::new(),
+            selected: 0,
+        };
+        palette.update_matches();
+        palette
+    }
+
+    /// Whether `key` is the shortcut that opens and closes the palette.
+    pub fn is_toggle_shortcut(config: &Config, key: &Key, modifiers: ModifiersState) -> bool {
+        config
+            .get_shortcut(config::PALETTE_SHORTCUT, TOGGLE_SHORTCUT)
+            .matches(key, modifiers)
+    }
+
+    pub fn handle_key(&mut self, key: &Key) -> PaletteAction {
+        match key {
+            Key::Named(NamedKey::Escape) => return PaletteAction::Close,
+            Key::Named(NamedKey::Enter) => {
+                return match self.matches.get(self.selected) {
+                    Some(&idx) => PaletteAction::Run(self.entries[idx].command.clone()),
+                    None => PaletteAction::None,
+                };
+            }
+            Key::Named(NamedKey::ArrowUp) => {
+                self.selected = self.selected.saturating_sub(1);
+            }
+            Key::Named(NamedKey::ArrowDown) => {
+                self.selected = (self.selected + 1).min(self.matches.len().saturating_sub(1));
+            }
+            Key::Named(NamedKey::Backspace) => {
+                self.query.pop();
+                self.update_matches();
+            }
+            _ => {}
+        }
+        PaletteAction::None
+    }
+
+    pub fn handle_char(&mut self, ch: char) {
+        if !ch.is_control() {
+            self.query.push(ch);
+            self.update_matches();
+        }
+    }
+
+    fn update_matches(&mut self) {
+        let mut scored: Vec<_> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, entry)| Some((match_score(&entry.label, &self.query)?, idx)))
+            .collect();
+        // stable sort keeps the original order for equal scores
+        scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        self.matches = scored.into_iter().map(|(_, idx)| idx).collect();
+        self.selected = 0;
+    }
+
+    pub fn draw(
+        &self,
+        layers: &mut Layers,
+        fonts: &mut Fonts,
+        screen_size: LogicalSize<f32>,
+        pixels_per_point: f32,
+    ) {
+        layers.set_viewport(LogicalRect::from_size(screen_size));
+        layers.set_draw_layer(i32::MAX, 0);
+
+        let visible_rows = self.matches.len().clamp(1, MAX_VISIBLE_ROWS);
+        let width = WIDTH.min(screen_size.width - 2.0 * PADDING);
+        let height = ROW_HEIGHT * (visible_rows + 1) as f32 + 2.0 * PADDING;
+        let origin = LogicalPoint::new((screen_size.width - width) / 2.0, TOP);
+
+        layers.set_draw_color(BACKGROUND_COLOR);
+        layers.draw_rect(
+            None,
+            LogicalRect::from_origin_and_size(origin, LogicalSize::new(width, height)),
+            NormalizedRect::default_uv(),
+            0,
+        );
+
+        let mut draw_row = |layers: &mut Layers, row: usize, text: &str, color: Srgba| {
+            let mut job = LayoutJob::new(
+                FontFamily::Generic(GenericFamily::SansSerif),
+                FONT_SIZE,
+                LINE_HEIGHT,
+                None,
+                None,
+                FontStyle::Normal,
+            );
+            job.append(text, color);
+            let layout = fonts.layout(job, pixels_per_point);
+            let offset = LogicalVector::new(
+                PADDING * 2.0,
+                PADDING + ROW_HEIGHT * row as f32 + (ROW_HEIGHT - LINE_HEIGHT) / 2.0,
+            );
+            layers.draw_text(origin + offset, layout, 0.0, true);
+        };
+
+        if self.query.is_empty() {
+            draw_row(layers, 0, "Type to search commands", HINT_COLOR);
+        } else {
+            draw_row(layers, 0, &format!("> {}", self.query), TEXT_COLOR);
+        }
+
+        if self.matches.is_empty() {
+            draw_row(layers, 1, "No matching commands", HINT_COLOR);
+            return;
+        }
+
+        // scroll so that the selected entry stays visible
+        let first = self.selected.saturating_sub(MAX_VISIBLE_ROWS - 1);
+        for (i, &idx) in self
+            .matches
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(MAX_VISIBLE_ROWS)
+        {
+            // the first row shows the query
+            let row = i - first + 1;
+            if i == self.selected {
+                layers.set_draw_color(SELECTION_COLOR);
+                layers.draw_rect(
+                    None,
+                    LogicalRect::from_origin_and_size(
+                        origin + LogicalVector::new(PADDING, PADDING + ROW_HEIGHT * row as f32),
+                        LogicalSize::new(width - 2.0 * PADDING, ROW_HEIGHT),
+                    ),
+                    NormalizedRect::default_uv(),
+                    0,
+                );
+            }
+            draw_row(layers, row, &self.entries[idx].label, TEXT_COLOR);
+        }
+    }
+}
+
+/// Scores how well `label` matches `query`, ignoring case. Labels that start with
+/// the query rank highest, followed by labels that contain it and finally labels
+/// that contain all characters of the query in order. `None` if there's no match.
+fn match_score(label: &str, query: &str) -> Option<u32> {
+    let label = label.to_lowercase();
+    let query = query.to_lowercase();
+
+    if label.starts_with(&query) {
+        return Some(3);
+    }
+    if label.contains(&query) {
+        return Some(2);
+    }
+
+    let mut label_chars = label.chars();
+    query
+        .chars()
+        .all(|ch| label_chars.any(|c| c == ch))
+        .then_some(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_score() {
+        assert_eq!(match_score("Reload textures", ""), Some(3));
+        assert_eq!(match_score("Reload textures", "rel"), Some(3));
+        assert_eq!(match_score("Reload textures", "TEXT"), Some(2));
+        assert_eq!(match_score("Reload textures", "rtx"), Some(1));
+        assert_eq!(match_score("Reload textures", "sr"), None);
+    }
+}
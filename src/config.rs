@@ -0,0 +1,279 @@
+//! Options of the app itself that persist between launches, e.g. the render
+//! scale. They are stored in `config.toml` next to the runtime directory and
+//! shared by both games and all profiles.
+//!
+//! Options passed on the command line take precedence over the file. Changes
+//! made in the settings overlay or with `SetRustyOption` are written back.
+
+use crate::{args::data_root, shortcut::Shortcut, util::write_atomic};
+use anyhow::{Context as _, bail};
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+pub const RENDER_SCALE: &str = "render_scale";
+pub const INTEGER_SCALING: &str = "integer_scaling";
+pub const MSAA: &str = "msaa";
+pub const HDR: &str = "hdr";
+pub const PREMULTIPLIED_ALPHA: &str = "premultiplied_alpha";
+pub const POWER_SAVING: &str = "power_saving";
+pub const VSYNC: &str = "vsync";
+pub const LINEAR_BLENDING: &str = "linear_blending";
+/// `"copy"` (default), `"link"` or `"off"`, see `shared_assets`
+pub const SHARE_RUNTIME_ASSETS: &str = "share_runtime_assets";
+/// UI scale in percent, the scale factor of the display is used if it isn't set
+pub const DPI_SCALE_OVERRIDE: &str = "dpi_scale_override";
+/// Used like `--data-dir`. Only read from the config in the default data
+/// directory, the config in the given directory is used for everything else.
+pub const DATA_DIR: &str = "data_dir";
+pub const SETTINGS_SHORTCUT: &str = "settings_shortcut";
+pub const CONSOLE_SHORTCUT: &str = "console_shortcut";
+pub const PALETTE_SHORTCUT: &str = "palette_shortcut";
+
+/// File in the script directory that stored the DPI scale override before it
+/// moved into the config
+const LEGACY_DPI_SCALE_OVERRIDE_FILE: &str = "rpob.dpi_scale_override";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OptionType {
+    Bool,
+    Integer {
+        min: i64,
+        max: i64,
+    },
+    Float {
+        min: f64,
+        max: f64,
+    },
+    Path,
+    Shortcut,
+    /// one of the given strings
+    Choice(&'static [&'static str]),
+}
+
+/// Options that may be stored, with the type of their values
+const OPTIONS: [(&str, OptionType); 14] = [
+    (RENDER_SCALE, OptionType::Float { min: 0.5, max: 2.0 }),
+    (INTEGER_SCALING, OptionType::Bool),
+    (MSAA, OptionType::Integer { min: 1, max: 16 }),
+    (HDR, OptionType::Bool),
+    (PREMULTIPLIED_ALPHA, OptionType::Bool),
+    (POWER_SAVING, OptionType::Bool),
+    (VSYNC, OptionType::Bool),
+    (LINEAR_BLENDING, OptionType::Bool),
+    (
+        SHARE_RUNTIME_ASSETS,
+        OptionType::Choice(&["link", "copy", "off"]),
+    ),
+    (
+        DPI_SCALE_OVERRIDE,
+        OptionType::Integer { min: 50, max: 400 },
+    ),
+    (DATA_DIR, OptionType::Path),
+    (SETTINGS_SHORTCUT, OptionType::Shortcut),
+    (CONSOLE_SHORTCUT, OptionType::Shortcut),
+    (PALETTE_SHORTCUT, OptionType::Shortcut),
+];
+
+pub fn config_path() -> PathBuf {
+    data_root().join("RustyPathOfBuilding").join("config.toml")
+}
+
+pub struct Config {
+    path: PathBuf,
+    table: Table,
+}
+
+impl Config {
+    /// Loads the config from `path`. A missing or invalid file results in an
+    /// empty config, unknown options and values of the wrong type are dropped.
+    pub fn load(path: PathBuf) -> Self {
+        let table = match std::fs::read_to_string(&path) {
+            Ok(content) => content.parse::<Table>().unwrap_or_else(|err| {
+                log::warn!("Ignoring invalid {}: {err}", path.display());
+                Table::new()
+            }),
+            Err(_) => Table::new(),
+        };
+        let table = table
+            .into_iter()
+            .filter(|(name, value)| match validate(name, value) {
+                Ok(()) => true,
+                Err(err) => {
+                    log::warn!("Ignoring option in {}: {err}", path.display());
+                    false
+                }
+            })
+            .collect();
+        Self { path, table }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.table.get(name)
+    }
+
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        self.get(name).and_then(Value::as_bool)
+    }
+
+    pub fn get_u32(&self, name: &str) -> Option<u32> {
+        self.get(name)
+            .and_then(Value::as_integer)
+            .and_then(|value| value.try_into().ok())
+    }
+
+    pub fn get_f32(&self, name: &str) -> Option<f32> {
+        // integers are valid floats, e.g. `render_scale = 2`
+        let value = self.get(name)?;
+        value
+            .as_float()
+            .or_else(|| value.as_integer().map(|value| value as f64))
+            .map(|value| value as f32)
+    }
+
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        self.get(name).and_then(Value::as_str)
+    }
+
+    /// The shortcut stored for `name`, or `default` if there is none.
+    pub fn get_shortcut(&self, name: &str, default: Shortcut) -> Shortcut {
+        self.get_str(name)
+            .and_then(|shortcut| shortcut.parse().ok())
+            .unwrap_or(default)
+    }
+
+    /// Moves the DPI scale override that older versions stored in `script_dir`
+    /// into the config. An override that is already in the config is kept, and so
+    /// is the file, which is only removed once its value has been migrated.
+    pub fn migrate_dpi_scale_override(&mut self, script_dir: &Path) {
+        let path = script_dir.join(LEGACY_DPI_SCALE_OVERRIDE_FILE);
+        if self.get(DPI_SCALE_OVERRIDE).is_some() {
+            return;
+        }
+        let Ok(content) = std::fs::read_to_string(&path) else {
+            return;
+        };
+        let result = match content.trim().parse::<i64>() {
+            // older versions stored 0 for no override
+            Ok(0) => Ok(()),
+            Ok(percent) => self.set(DPI_SCALE_OVERRIDE, percent),
+            Err(err) => Err(err.into()),
+        };
+        if let Err(err) = result {
+            log::warn!("Unable to migrate {}: {err}", path.display());
+            return;
+        }
+        if let Err(err) = std::fs::remove_file(&path) {
+            log::warn!("Unable to remove {}: {err}", path.display());
+        }
+    }
+
+    /// Stores the option and saves the file. Errors if the option is unknown or
+    /// the value doesn't fit it.
+    pub fn set(&mut self, name: &str, value: impl Into<Value>) -> anyhow::Result<()> {
+        let value = value.into();
+        validate(name, &value)?;
+        if self.table.get(name) == Some(&value) {
+            return Ok(());
+        }
+        self.table.insert(name.to_owned(), value);
+        self.save()
+    }
+
+    /// Removes the option and saves the file, e.g. to go back to the default.
+    pub fn remove(&mut self, name: &str) -> anyhow::Result<()> {
+        if self.table.remove(name).is_none() {
+            return Ok(());
+        }
+        self.save()
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        write_atomic(&self.path, self.table.to_string().as_bytes())
+            .with_context(|| format!("Unable to save {}", self.path.display()))
+    }
+}
+
+fn validate(name: &str, value: &Value) -> anyhow::Result<()> {
+    let Some((_, option_type)) = OPTIONS.iter().find(|(option, _)| *option == name) else {
+        bail!("Unknown option {name}");
+    };
+    let is_valid = match (option_type, value) {
+        (OptionType::Bool, Value::Boolean(_)) => true,
+        (OptionType::Integer { min, max }, Value::Integer(value)) => (*min..=*max).contains(value),
+        (OptionType::Float { min, max }, Value::Float(value)) => (*min..=*max).contains(value),
+        (OptionType::Float { min, max }, Value::Integer(value)) => {
+            (*min..=*max).contains(&(*value as f64))
+        }
+        (OptionType::Path, Value::String(value)) => !value.is_empty(),
+        (OptionType::Shortcut, Value::String(value)) => value.parse::<Shortcut>().is_ok(),
+        (OptionType::Choice(choices), Value::String(value)) => choices.contains(&value.as_str()),
+        _ => false,
+    };
+    if !is_valid {
+        bail!("Invalid value {value} for option {name}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_set_and_reload() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("config.toml");
+        std::fs::write(&path, "msaa = 4\nrender_scale = 9.0\nunknown = true\n").unwrap();
+
+        let mut config = Config::load(path.clone());
+        assert_eq!(config.get_u32(MSAA), Some(4));
+        // out of range and unknown options are dropped
+        assert_eq!(config.get_f32(RENDER_SCALE), None);
+        assert!(config.get("unknown").is_none());
+
+        config.set(RENDER_SCALE, 2).unwrap();
+        config.set(HDR, true).unwrap();
+        assert!(config.set(HDR, 1).is_err());
+        assert!(config.set("unknown", true).is_err());
+
+        let config = Config::load(path);
+        assert_eq!(config.get_f32(RENDER_SCALE), Some(2.0));
+        assert_eq!(config.get_bool(HDR), Some(true));
+        assert_eq!(config.get_u32(MSAA), Some(4));
+    }
+
+    #[test]
+    fn test_migrate_dpi_scale_override() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let legacy_path = temp_dir.path().join(LEGACY_DPI_SCALE_OVERRIDE_FILE);
+        std::fs::write(&legacy_path, "150").unwrap();
+
+        let mut config = Config::load(temp_dir.path().join("config.toml"));
+        config.migrate_dpi_scale_override(temp_dir.path());
+        assert_eq!(config.get_u32(DPI_SCALE_OVERRIDE), Some(150));
+        assert!(!legacy_path.exists());
+
+        // the override in the config wins over files of other script directories,
+        // which are kept
+        std::fs::write(&legacy_path, "200").unwrap();
+        config.migrate_dpi_scale_override(temp_dir.path());
+        assert_eq!(config.get_u32(DPI_SCALE_OVERRIDE), Some(150));
+        assert!(legacy_path.exists());
+
+        // values outside of the supported range aren't migrated
+        config.remove(DPI_SCALE_OVERRIDE).unwrap();
+        std::fs::write(&legacy_path, "20").unwrap();
+        config.migrate_dpi_scale_override(temp_dir.path());
+        assert_eq!(config.get_u32(DPI_SCALE_OVERRIDE), None);
+        assert!(legacy_path.exists());
+        assert!(config.set(DPI_SCALE_OVERRIDE, 0).is_err());
+
+        std::fs::write(&legacy_path, "0").unwrap();
+        config.migrate_dpi_scale_override(temp_dir.path());
+        assert_eq!(config.get_u32(DPI_SCALE_OVERRIDE), None);
+        assert!(!legacy_path.exists());
+    }
+}
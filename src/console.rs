@@ -0,0 +1,134 @@
+//! Output of `ConPrintf` and `ConExecute`, shown in an overlay at the bottom of
+//! the window with `Ctrl+Shift+L` (see `console_shortcut` in `config`), the
+//! `console` command or the command palette.
+//!
+//! Lets users without a terminal, e.g. when launched from a desktop entry, see
+//! PoB's log. Output is also printed to stdout and written to the log file.
+
+use crate::{
+    color::Srgba,
+    config::{self, Config},
+    dpi::{LogicalPoint, LogicalRect, LogicalSize, NormalizedRect, Uv},
+    fonts::{FontStyle, Fonts, LayoutJob},
+    layers::Layers,
+    log_file,
+    shortcut::Shortcut,
+};
+use parley::{FontFamily, GenericFamily};
+use std::collections::VecDeque;
+use winit::keyboard::{Key, ModifiersState};
+
+const FONT_SIZE: f32 = 14.0;
+const LINE_HEIGHT: f32 = 16.0;
+const PADDING: f32 = 6.0;
+/// Fraction of the window height covered by the overlay
+const HEIGHT_FRACTION: f32 = 0.4;
+const BACKGROUND_COLOR: Srgba = Srgba::new(0, 0, 0, 220);
+const TEXT_COLOR: Srgba = Srgba::new(220, 220, 220, 255);
+
+/// Older lines are dropped
+const MAX_LINES: usize = 1000;
+
+/// Shortcut unless another one is configured
+pub const TOGGLE_SHORTCUT: Shortcut = Shortcut::ctrl_shift('l');
+
+#[derive(Default)]
+pub struct Console {
+    lines: VecDeque<String>,
+    pub is_visible: bool,
+}
+
+impl Console {
+    /// Whether `key` is the shortcut that shows and hides the overlay.
+    pub fn is_toggle_shortcut(config: &Config, key: &Key, modifiers: ModifiersState) -> bool {
+        config
+            .get_shortcut(config::CONSOLE_SHORTCUT, TOGGLE_SHORTCUT)
+            .matches(key, modifiers)
+    }
+
+    pub fn toggle(&mut self) {
+        self.is_visible = !self.is_visible;
+    }
+
+    /// Prints `text` to stdout and the log file and appends its lines to the overlay.
+    pub fn print(&mut self, text: &str) {
+        println!("{text}");
+        log_file::write_console(text);
+        for line in text.lines() {
+            if self.lines.len() == MAX_LINES {
+                self.lines.pop_front();
+            }
+            self.lines.push_back(line.to_owned());
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    /// Draws the most recent lines that fit into the overlay.
+    pub fn draw(
+        &self,
+        layers: &mut Layers,
+        fonts: &mut Fonts,
+        screen_size: LogicalSize<f32>,
+        pixels_per_point: f32,
+    ) {
+        layers.set_viewport(LogicalRect::from_size(screen_size));
+        layers.set_draw_layer(i32::MAX, 0);
+
+        let height = (screen_size.height * HEIGHT_FRACTION).round();
+        let background = LogicalRect::from_origin_and_size(
+            LogicalPoint::new(0.0, screen_size.height - height),
+            LogicalSize::new(screen_size.width, height),
+        );
+        layers.set_draw_color(BACKGROUND_COLOR);
+        layers.draw_rect(None, background, NormalizedRect::default_uv(), 0);
+
+        let visible_lines = ((height - 2.0 * PADDING) / LINE_HEIGHT).max(0.0) as usize;
+        let first = self.lines.len().saturating_sub(visible_lines);
+        let text = self
+            .lines
+            .range(first..)
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let mut job = LayoutJob::new(
+            FontFamily::Generic(GenericFamily::Monospace),
+            FONT_SIZE,
+            LINE_HEIGHT,
+            None,
+            None,
+            FontStyle::Normal,
+        );
+        job.append(&text, TEXT_COLOR);
+        let layout = fonts.layout(job, pixels_per_point);
+        let line_count = self.lines.len() - first;
+        // the last line is aligned with the bottom of the overlay
+        let text_pos = LogicalPoint::new(
+            PADDING,
+            screen_size.height - PADDING - LINE_HEIGHT * line_count as f32,
+        );
+        layers.draw_text(text_pos, layout, 0.0, true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_print_keeps_recent_lines() {
+        let mut console = Console::default();
+        console.print("first\nsecond");
+        assert_eq!(console.lines, ["first", "second"]);
+
+        for i in 0..MAX_LINES {
+            console.print(&i.to_string());
+        }
+        assert_eq!(console.lines.len(), MAX_LINES);
+        assert_eq!(console.lines.front().unwrap(), "0");
+        assert_eq!(console.lines.back().unwrap(), &(MAX_LINES - 1).to_string());
+    }
+}
@@ -0,0 +1,258 @@
+//! Downloads started by PoB with `NewDownload(url, options)`, e.g. for trade
+//! queries and league data.
+//!
+//! Downloads run on a shared pool of worker threads. PoB polls the returned
+//! handle for the status, progress and data received since the last poll, which
+//! lets it draw progress bars, and can cancel it at any time. Progress wakes up
+//! the event loop, so that PoB gets a frame to redraw while the window is
+//! otherwise idle.
+//! Requests to rate limited APIs are queued by [`crate::rate_limit`].
+
+use crate::{
+    app::{EventProxy, UserEvent},
+    http, rate_limit,
+    worker_pool::{Priority, WorkerPool},
+};
+use std::{
+    io::Read,
+    sync::{
+        Arc, LazyLock, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+/// Number of downloads that run at the same time, the others wait in the queue.
+/// Downloads that wait for a rate limit occupy a worker.
+const MAX_CONCURRENT_DOWNLOADS: usize = 6;
+
+/// Downloads that take longer than this fail
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Responses larger than this fail
+const MAX_DOWNLOAD_SIZE: u64 = 256 << 20;
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Minimum time between two wake-ups of the event loop
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+// lives as long as the process, so that restarting PoB doesn't wait for the
+// downloads it cancelled
+static DOWNLOAD_POOL: LazyLock<WorkerPool> =
+    LazyLock::new(|| WorkerPool::new(MAX_CONCURRENT_DOWNLOADS));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DownloadStatus {
+    /// Waiting for the rate limits of the endpoint
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl DownloadStatus {
+    /// Whether the download ended, successfully or not
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, DownloadStatus::Queued | DownloadStatus::Running)
+    }
+
+    /// Name exposed to Lua
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DownloadStatus::Queued => "queued",
+            DownloadStatus::Running => "running",
+            DownloadStatus::Done => "done",
+            DownloadStatus::Failed => "failed",
+            DownloadStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct DownloadRequest {
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    /// Sent with a POST request if set, otherwise a GET request is made
+    pub body: Option<Vec<u8>>,
+}
+
+pub struct DownloadState {
+    pub status: DownloadStatus,
+    /// HTTP status code, once the response headers arrived
+    pub response_code: Option<u16>,
+    /// From the Content-Length header, if the server sent one
+    pub total: Option<u64>,
+    /// Bytes received so far
+    pub received: u64,
+    /// Received since the last call of `Download::take_data`
+    pub data: Vec<u8>,
+    pub error: Option<String>,
+}
+
+struct Shared {
+    state: Mutex<DownloadState>,
+    cancel: AtomicBool,
+}
+
+/// Handle to a running or finished download. Dropping it cancels the download.
+pub struct Download {
+    shared: Arc<Shared>,
+}
+
+impl Download {
+    /// Calls `f` with the current state of the download
+    pub fn with_state<R>(&self, f: impl FnOnce(&DownloadState) -> R) -> R {
+        f(&self.shared.state.lock().unwrap())
+    }
+
+    /// Data received since the last call
+    pub fn take_data(&self) -> Vec<u8> {
+        std::mem::take(&mut self.shared.state.lock().unwrap().data)
+    }
+
+    pub fn cancel(&self) {
+        self.shared.cancel.store(true, Ordering::Relaxed);
+        rate_limit::notify_cancelled();
+    }
+}
+
+impl Drop for Download {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Starts downloads and cancels the ones still running when PoB exits or restarts
+pub struct Downloads {
+    running: Vec<Arc<Shared>>,
+    // wakes up the event loop, which might be idle
+    proxy: EventProxy,
+}
+
+impl Downloads {
+    pub fn new(proxy: EventProxy) -> Self {
+        Self {
+            running: Vec::new(),
+            proxy,
+        }
+    }
+
+    pub fn start(&mut self, request: DownloadRequest) -> Download {
+        self.running
+            .retain(|shared| !shared.state.lock().unwrap().status.is_finished());
+
+        let shared = Arc::new(Shared {
+            state: Mutex::new(DownloadState {
+                status: DownloadStatus::Queued,
+                response_code: None,
+                total: None,
+                received: 0,
+                data: Vec::new(),
+                error: None,
+            }),
+            cancel: AtomicBool::new(false),
+        });
+
+        let thread_shared = Arc::clone(&shared);
+        let proxy = self.proxy.clone();
+        DOWNLOAD_POOL.execute(Priority::default(), move || {
+            let status = match download(&request, &thread_shared, &proxy) {
+                Ok(status) => status,
+                Err(err) => {
+                    log::warn!("Download of {} failed: {err:#}", request.url);
+                    thread_shared.state.lock().unwrap().error = Some(format!("{err:#}"));
+                    DownloadStatus::Failed
+                }
+            };
+            thread_shared.state.lock().unwrap().status = status;
+            let _ = proxy.send_event(UserEvent::DownloadProgress);
+        });
+
+        self.running.push(Arc::clone(&shared));
+        Download { shared }
+    }
+}
+
+impl Drop for Downloads {
+    fn drop(&mut self) {
+        for shared in &self.running {
+            shared.cancel.store(true, Ordering::Relaxed);
+        }
+        rate_limit::notify_cancelled();
+    }
+}
+
+fn download(
+    request: &DownloadRequest,
+    shared: &Shared,
+    proxy: &EventProxy,
+) -> anyhow::Result<DownloadStatus> {
+    if !rate_limit::acquire(&request.url, &shared.cancel) {
+        return Ok(DownloadStatus::Cancelled);
+    }
+    shared.state.lock().unwrap().status = DownloadStatus::Running;
+    let _ = proxy.send_event(UserEvent::DownloadProgress);
+
+    let agent = http::agent(DOWNLOAD_TIMEOUT);
+    let result = match &request.body {
+        Some(body) => {
+            let mut builder = agent.post(&request.url);
+            for (name, value) in &request.headers {
+                builder = builder.header(name, value);
+            }
+            builder
+                .config()
+                .http_status_as_error(false)
+                .timeout_recv_response(Some(RESPONSE_TIMEOUT))
+                .build()
+                .send(body.as_slice())
+        }
+        None => {
+            let mut builder = agent.get(&request.url);
+            for (name, value) in &request.headers {
+                builder = builder.header(name, value);
+            }
+            builder
+                .config()
+                .http_status_as_error(false)
+                .timeout_recv_response(Some(RESPONSE_TIMEOUT))
+                .build()
+                .call()
+        }
+    };
+    let mut response = result.map_err(http::describe_error)?;
+    rate_limit::update(&request.url, response.status(), response.headers());
+
+    {
+        let mut state = shared.state.lock().unwrap();
+        state.response_code = Some(response.status().as_u16());
+        state.total = response.body().content_length();
+    }
+
+    let mut reader = response
+        .body_mut()
+        .with_config()
+        .limit(MAX_DOWNLOAD_SIZE)
+        .reader();
+    let mut chunk = vec![0; CHUNK_SIZE];
+    let mut last_progress = Instant::now();
+    loop {
+        if shared.cancel.load(Ordering::Relaxed) {
+            return Ok(DownloadStatus::Cancelled);
+        }
+        let len = reader.read(&mut chunk)?;
+        if len == 0 {
+            return Ok(DownloadStatus::Done);
+        }
+
+        let mut state = shared.state.lock().unwrap();
+        state.received += len as u64;
+        state.data.extend_from_slice(&chunk[..len]);
+        drop(state);
+
+        if last_progress.elapsed() >= PROGRESS_INTERVAL {
+            last_progress = Instant::now();
+            let _ = proxy.send_event(UserEvent::DownloadProgress);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! Watches files and directories chosen by PoB, e.g. the builds folder, so that
+//! changes made by other programs like sync clients or git show up without a
+//! restart. Changes are passed to PoB's optional `OnFileChanged(path)` callback.
+
+use crate::app::{EventProxy, UserEvent};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::mpsc::{Receiver, Sender, channel},
+};
+
+pub struct FileWatcher {
+    // created when the first path is watched
+    watcher: Option<RecommendedWatcher>,
+    sender: Sender<notify::Result<Event>>,
+    receiver: Receiver<notify::Result<Event>>,
+    // wakes up the event loop, which might be idle
+    proxy: EventProxy,
+}
+
+impl FileWatcher {
+    pub fn new(proxy: EventProxy) -> Self {
+        let (sender, receiver) = channel();
+        Self {
+            watcher: None,
+            sender,
+            receiver,
+            proxy,
+        }
+    }
+
+    pub fn watch(&mut self, path: &Path, recursive: bool) -> notify::Result<()> {
+        let watcher = match self.watcher {
+            Some(ref mut watcher) => watcher,
+            None => {
+                let sender = self.sender.clone();
+                let proxy = self.proxy.clone();
+                let watcher = notify::recommended_watcher(move |event| {
+                    // reading files doesn't change them, don't wake up for it
+                    if is_change(&event) {
+                        let _ = sender.send(event);
+                        let _ = proxy.send_event(UserEvent::FilesChanged);
+                    }
+                })?;
+                self.watcher.insert(watcher)
+            }
+        };
+
+        let mode = if recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        watcher.watch(path, mode)
+    }
+
+    pub fn unwatch(&mut self, path: &Path) -> notify::Result<()> {
+        match self.watcher {
+            Some(ref mut watcher) => watcher.unwatch(path),
+            None => Err(notify::Error::watch_not_found()),
+        }
+    }
+
+    /// Paths that were created, modified or removed since the last call. Each
+    /// path is only reported once, even if it changed several times.
+    pub fn take_changes(&mut self) -> Vec<PathBuf> {
+        let mut changed = BTreeSet::new();
+        for event in self.receiver.try_iter() {
+            match event {
+                Ok(event) => changed.extend(event.paths),
+                Err(err) => log::warn!("Unable to watch files: {err}"),
+            }
+        }
+        changed.into_iter().collect()
+    }
+}
+
+/// Errors are passed on, so that they get logged.
+fn is_change(event: &notify::Result<Event>) -> bool {
+    match event {
+        Ok(event) => matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+        ),
+        Err(_) => true,
+    }
+}
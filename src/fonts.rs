@@ -2,10 +2,13 @@ use crate::{
     color::Srgba,
     dpi::{LogicalPoint, LogicalVector},
     fonts::{
-        atlas::FontAtlas, glyph_key::SubpixelBin, layout::LayoutRow, rasterizer::GlyphRasterizer,
+        atlas::FontAtlas,
+        glyph_key::SubpixelBin,
+        layout::{LayoutLine, LayoutRow},
+        rasterizer::GlyphRasterizer,
     },
     renderer::image::ImageDelta,
-    util::calculate_hash,
+    util::{calculate_hash, stable_hash},
 };
 use ahash::HashMap;
 use ordered_float::OrderedFloat;
@@ -13,10 +16,10 @@ use parley::{
     FontContext, FontFamily, FontStack, FontWeight, GenericFamily, LayoutContext, StyleProperty,
     TextStyle, fontique::Blob,
 };
-use std::sync::Arc;
+use std::{borrow::Cow, ops::Range, sync::Arc};
 
 pub use atlas::FontAtlasSize;
-pub use layout::{Alignment, FontStyle, Layout, LayoutJob};
+pub use layout::{Alignment, FontStyle, Layout, LayoutJob, LayoutSegment};
 
 mod atlas;
 mod glyph_key;
@@ -47,6 +50,9 @@ impl AsRef<[u8]> for FontData {
 pub struct FontDefinitions {
     pub font_data: HashMap<String, Arc<FontData>>,
     pub generic_families: HashMap<GenericFamily, Vec<String>>,
+    /// Families that are tried, in order, for characters missing from the requested
+    /// font. Can name bundled or system fonts. Families that can't be found are ignored.
+    pub fallback_families: Vec<String>,
 }
 
 pub struct Fonts {
@@ -55,7 +61,15 @@ pub struct Fonts {
     layout_context: LayoutContext<Srgba>,
     atlas: FontAtlas,
     glyph_rasterizer: GlyphRasterizer,
-    layout_cache: LayoutCache,
+    layout_cache: LayoutCache<Layout>,
+    // shaped lines by their segments and style, see `LayoutLine`
+    line_cache: LayoutCache<parley::Layout<Srgba>>,
+    // scale factor the cached glyphs were mostly rasterized at
+    pixels_per_point: f32,
+    // fallback families that were found in the font collection
+    fallback_families: Vec<FontFamily<'static>>,
+    // layouts keep the job they were created from, see `set_keep_jobs`
+    keep_jobs: bool,
 }
 
 impl Fonts {
@@ -64,9 +78,13 @@ impl Fonts {
             definitions,
             font_context: FontContext::new(),
             layout_context: LayoutContext::new(),
-            atlas: FontAtlas::new(1024),
+            atlas: FontAtlas::new(1024, FontAtlas::DEFAULT_MAX_ALLOCATION_FRACTION),
             glyph_rasterizer: GlyphRasterizer::new(),
             layout_cache: LayoutCache::default(),
+            line_cache: LayoutCache::default(),
+            pixels_per_point: 1.0,
+            fallback_families: Vec::new(),
+            keep_jobs: false,
         };
 
         fonts.register_fonts();
@@ -92,6 +110,32 @@ impl Fonts {
                 .collection
                 .set_generic_families(*generic_family, family_ids.into_iter());
         }
+
+        for family_name in &self.definitions.fallback_families {
+            if self
+                .font_context
+                .collection
+                .family_id(family_name)
+                .is_some()
+            {
+                self.fallback_families
+                    .push(FontFamily::Named(Cow::Owned(family_name.clone())));
+            } else {
+                log::debug!("Fallback font family '{family_name}' not found");
+            }
+        }
+    }
+
+    /// Font stack consisting of the given family followed by the fallback families.
+    fn font_stack<'a>(&self, font_family: FontFamily<'a>) -> FontStack<'a> {
+        if self.fallback_families.is_empty() {
+            return FontStack::Single(font_family);
+        }
+
+        let mut families = Vec::with_capacity(self.fallback_families.len() + 1);
+        families.push(font_family);
+        families.extend(self.fallback_families.iter().cloned());
+        FontStack::List(Cow::Owned(families))
     }
 
     /// Needs to be called at beginning of each frame.
@@ -101,6 +145,7 @@ impl Fonts {
             self.clear_atlas();
         }
         self.layout_cache.flush();
+        self.line_cache.flush();
     }
 
     /// Gets changes to the font atlas texture since last call.
@@ -108,6 +153,30 @@ impl Fonts {
         self.atlas.take_delta()
     }
 
+    /// Sets the scale factor text is rendered at, e.g. after the window moved to a
+    /// monitor with a different DPI. Glyphs and layouts of the previous scale factor
+    /// are dropped so that they don't fill up the atlas.
+    pub fn set_pixels_per_point(&mut self, pixels_per_point: f32) {
+        if self.pixels_per_point == pixels_per_point {
+            return;
+        }
+        self.pixels_per_point = pixels_per_point;
+        self.clear_atlas();
+        self.preload_common_characters(14.0);
+        self.preload_common_characters(16.0);
+    }
+
+    /// Keeps the job of each layout, e.g. while capturing frames. Cached layouts
+    /// without their job are created again when they're used.
+    pub fn set_keep_jobs(&mut self, keep_jobs: bool) {
+        self.keep_jobs = keep_jobs;
+    }
+
+    /// Uploads the whole font atlas again with the next delta.
+    pub fn invalidate_font_atlas(&mut self) {
+        self.atlas.invalidate();
+    }
+
     pub fn preload_common_characters(&mut self, font_size: f32) {
         const ASCII_PRINTABLE_START: u8 = 32;
         const ASCII_PRINTABLE_END: u8 = 126;
@@ -153,7 +222,7 @@ impl Fonts {
         profiling::scope!("preload_text");
 
         let style = TextStyle {
-            font_stack: FontStack::Single(font_family),
+            font_stack: self.font_stack(font_family),
             font_weight: font_weight.unwrap_or(FontWeight::NORMAL),
             font_style,
             font_size,
@@ -179,7 +248,7 @@ impl Fonts {
                             &mut self.atlas,
                             &run,
                             LogicalVector::new(horizontal_offset, 0.0),
-                            1.0,
+                            self.pixels_per_point,
                         )
                         .for_each(|_| {});
                 }
@@ -194,13 +263,17 @@ impl Fonts {
     pub fn layout(&mut self, job: LayoutJob, pixels_per_point: f32) -> Arc<Layout> {
         let hash = calculate_hash(&(&job, OrderedFloat(pixels_per_point)));
 
-        if let Some(cached_layout) = self.layout_cache.get(hash) {
+        if let Some(cached_layout) = self.layout_cache.get(hash)
+            && (cached_layout.job.is_some() || !self.keep_jobs)
+        {
             return cached_layout;
         }
 
+        // unlike the cache key, the job hash ends up in the canonical form of primitives
+        let job_hash = stable_hash(&(&job, OrderedFloat(pixels_per_point)));
         let default_style = TextStyle::default();
         let style = TextStyle {
-            font_stack: parley::FontStack::Single(job.font_family),
+            font_stack: self.font_stack(job.font_family.clone()),
             font_size: job.font_size.into(),
             line_height: parley::LineHeight::Absolute(job.line_height.into()),
             font_weight: job
@@ -211,76 +284,114 @@ impl Fonts {
             ..default_style
         };
 
-        let mut builder =
-            self.layout_context
-                .tree_builder(&mut self.font_context, 1.0, false, &style);
-
-        for segment in job.segments {
-            let brush_style = StyleProperty::Brush(segment.color);
-            builder.push_style_modification_span(&[brush_style]);
-            builder.push_text(segment.text);
-            builder.pop_style_span();
-        }
-
-        let (mut parley_layout, _) = builder.build();
-        parley_layout.break_all_lines(None);
-
-        // extra offset applied to each glyph to get position relative to layout origin
-        let mut glyph_offset = LogicalVector::new(0.0, 0.0);
-        if let Some(alignment) = job.alignment {
-            let alignment = match alignment {
-                Alignment::Min => parley::Alignment::Start,
-                Alignment::Center => {
-                    glyph_offset.x += -parley_layout.full_width() * 0.5;
-                    parley::Alignment::Center
-                }
-                Alignment::Max => {
-                    glyph_offset.x += -parley_layout.full_width();
-                    parley::Alignment::End
-                }
-            };
-            parley_layout.align(None, alignment, parley::AlignmentOptions::default());
-        }
+        // each line is shaped on its own, reusing lines shaped for other layouts
+        let lines: Vec<(Range<usize>, Arc<parley::Layout<Srgba>>)> = job
+            .lines()
+            .into_iter()
+            .map(|(text_index, segments)| {
+                let text_len: usize = segments.iter().map(|s| s.text.len()).sum();
+                let text_range = text_index..text_index + text_len;
+                let line_job = LayoutJob {
+                    segments,
+                    font_family: job.font_family.clone(),
+                    font_size: job.font_size,
+                    line_height: job.line_height,
+                    alignment: None,
+                    font_weight: job.font_weight,
+                    font_style: job.font_style,
+                };
+                let line_hash = calculate_hash(&line_job);
+                let parley_layout = match self.line_cache.get(line_hash) {
+                    Some(parley_layout) => parley_layout,
+                    None => {
+                        let parley_layout = Arc::new(self.shape_line(&line_job, &style));
+                        self.line_cache
+                            .insert(line_hash, Arc::clone(&parley_layout));
+                        parley_layout
+                    }
+                };
+                (text_range, parley_layout)
+            })
+            .collect();
+        let links = job.links();
+
+        let width = lines
+            .iter()
+            .map(|(_, parley_layout)| parley_layout.full_width())
+            .fold(0.0, f32::max);
+        let baseline = lines
+            .first()
+            .and_then(|(_, parley_layout)| parley_layout.lines().next())
+            .map_or(0.0, |line| line.metrics().baseline);
+        // lines are aligned relative to the layout origin
+        let align_factor = match job.alignment {
+            None | Some(Alignment::Min) => 0.0,
+            Some(Alignment::Center) => 0.5,
+            Some(Alignment::Max) => 1.0,
+        };
 
+        let mut layout_lines = Vec::with_capacity(lines.len());
         let mut layout_rows = Vec::new();
         let mut num_of_vertices = 0;
         let mut num_of_indices = 0;
 
-        for line in parley_layout.lines() {
-            let mut layout_row = LayoutRow::default();
+        for (line_idx, (text_range, parley_layout)) in lines.into_iter().enumerate() {
+            let line_width = parley_layout.full_width();
+            // extra offset applied to each glyph to get position relative to layout origin
+            let glyph_offset = LogicalVector::new(
+                -line_width * align_factor,
+                line_idx as f32 * job.line_height.0,
+            );
 
-            for item in line.items() {
-                let parley::PositionedLayoutItem::GlyphRun(run) = item else {
-                    continue;
-                };
+            for line in parley_layout.lines() {
+                let mut layout_row = LayoutRow::default();
 
-                for rasterized_glyph in self.glyph_rasterizer.rasterize_glyph_run(
-                    &mut self.atlas,
-                    &run,
-                    glyph_offset,
-                    pixels_per_point,
-                ) {
-                    let Some(glyph) = rasterized_glyph else {
+                for item in line.items() {
+                    let parley::PositionedLayoutItem::GlyphRun(run) = item else {
                         continue;
                     };
 
-                    layout_row.glyphs.push(glyph);
-                    num_of_vertices += 4;
-                    num_of_indices += 6;
+                    for rasterized_glyph in self.glyph_rasterizer.rasterize_glyph_run(
+                        &mut self.atlas,
+                        &run,
+                        glyph_offset,
+                        pixels_per_point,
+                    ) {
+                        let Some(glyph) = rasterized_glyph else {
+                            continue;
+                        };
+
+                        layout_row.glyphs.push(glyph);
+                        num_of_vertices += 4;
+                        num_of_indices += 6;
+                    }
                 }
-            }
 
-            if !layout_row.glyphs.is_empty() {
-                layout_rows.push(layout_row);
+                if !layout_row.glyphs.is_empty() {
+                    layout_rows.push(layout_row);
+                }
             }
+
+            layout_lines.push(LayoutLine {
+                x_offset: glyph_offset.x,
+                parley_layout,
+                text_range,
+            });
         }
 
         let layout = Arc::new(Layout {
-            job_hash: hash,
-            parley_layout,
+            job_hash,
+            font_size: job.font_size.0,
+            line_height: job.line_height.0,
+            job: self.keep_jobs.then(|| job.into_owned()),
+            lines: layout_lines,
+            links,
+            width,
+            baseline,
             rows: layout_rows,
             num_of_vertices,
             num_of_indices,
+            subpixel_text: self.glyph_rasterizer.subpixel(),
         });
 
         self.layout_cache.insert(hash, Arc::clone(&layout));
@@ -288,6 +399,37 @@ impl Fonts {
         layout
     }
 
+    /// Shapes a job that consists of a single line of text.
+    fn shape_line(
+        &mut self,
+        job: &LayoutJob,
+        style: &TextStyle<'_, Srgba>,
+    ) -> parley::Layout<Srgba> {
+        let mut builder =
+            self.layout_context
+                .tree_builder(&mut self.font_context, 1.0, false, style);
+
+        for segment in &job.segments {
+            let brush_style = StyleProperty::Brush(segment.color);
+            builder.push_style_modification_span(&[brush_style]);
+            builder.push_text(&segment.text);
+            builder.pop_style_span();
+        }
+
+        let (mut parley_layout, _) = builder.build();
+        parley_layout.break_all_lines(None);
+        parley_layout
+    }
+
+    /// Enables subpixel (LCD) text rendering.
+    /// Requires a renderer that supports dual-source blending.
+    pub fn set_subpixel_text(&mut self, subpixel_text: bool) {
+        if self.glyph_rasterizer.subpixel() != subpixel_text {
+            self.clear_atlas();
+            self.glyph_rasterizer.set_subpixel(subpixel_text);
+        }
+    }
+
     /// Clear atlas and invalidate caches depend on atlas state
     fn clear_atlas(&mut self) {
         self.atlas.clear();
@@ -313,19 +455,28 @@ impl Fonts {
     }
 }
 
-struct CachedLayout {
+struct CachedLayout<T> {
     generation: u32,
-    layout: Arc<Layout>,
+    layout: Arc<T>,
 }
 
-#[derive(Default)]
-struct LayoutCache {
+/// Layouts by hash, kept as long as they are used every frame
+struct LayoutCache<T> {
     current_generation: u32,
-    cache: nohash_hasher::IntMap<u64, CachedLayout>,
+    cache: nohash_hasher::IntMap<u64, CachedLayout<T>>,
+}
+
+impl<T> Default for LayoutCache<T> {
+    fn default() -> Self {
+        Self {
+            current_generation: 0,
+            cache: Default::default(),
+        }
+    }
 }
 
-impl LayoutCache {
-    fn get(&mut self, hash: u64) -> Option<Arc<Layout>> {
+impl<T> LayoutCache<T> {
+    fn get(&mut self, hash: u64) -> Option<Arc<T>> {
         match self.cache.entry(hash) {
             std::collections::hash_map::Entry::Occupied(entry) => {
                 let cached = entry.into_mut();
@@ -336,7 +487,7 @@ impl LayoutCache {
         }
     }
 
-    fn insert(&mut self, hash: u64, layout: Arc<Layout>) {
+    fn insert(&mut self, hash: u64, layout: Arc<T>) {
         self.cache.insert(
             hash,
             CachedLayout {
@@ -16,6 +16,8 @@ pub type FontAtlasRect = Rect<u32, FontAtlasSpace>;
 pub struct FontAtlas {
     // max width/height of atlas texture
     max_texture_side: u32,
+    // allocations wider or taller than this fraction of `max_texture_side` are rejected
+    max_allocation_fraction: f32,
     image: RgbaImage,
     // position of next allocation
     cursor: FontAtlasPoint,
@@ -25,21 +27,29 @@ pub struct FontAtlas {
     dirty: bool,
     // atlas has overflowed and needs to be recreated
     overflowed: bool,
+    // incremented when the atlas is cleared, which moves all glyphs
+    generation: u64,
 }
 
 impl FontAtlas {
-    pub fn new(max_texture_side: u32) -> Self {
+    pub const DEFAULT_MAX_ALLOCATION_FRACTION: f32 = 0.25;
+
+    /// `max_allocation_fraction` limits the width/height of a single allocation to a
+    /// fraction of `max_texture_side`.
+    pub fn new(max_texture_side: u32, max_allocation_fraction: f32) -> Self {
         // start out with maximum width and let height grow as needed
         let width = max_texture_side;
         let initial_height = 256;
 
         let mut atlas = Self {
             max_texture_side,
+            max_allocation_fraction: max_allocation_fraction.clamp(0.0, 1.0),
             image: RgbaImage::new(width, initial_height),
             cursor: FontAtlasPoint::zero(),
             current_row_height: 0,
             dirty: false,
             overflowed: false,
+            generation: 0,
         };
 
         atlas.initialize();
@@ -50,15 +60,29 @@ impl FontAtlas {
         // Puts white pixel at (0, 0).
         // NOTE: Rendering a solid color shape is done by setting the texture to
         // the font atlas and sampling the white pixel at (0, 0).
-        let mut sub_image = self.allocate(FontAtlasSize::new(1, 1));
+        let mut sub_image = self
+            .allocate(FontAtlasSize::new(1, 1))
+            .expect("1x1 allocation always fits");
         sub_image.put_pixel(0, 0, Srgba::WHITE.into());
     }
 
     // TODO: use an actual bin packing algorithm for tighter packing
     /// Returns a mutable view into the atlas of given size.
-    pub fn allocate(&mut self, size: FontAtlasSize) -> SubImage<&mut RgbaImage> {
+    /// Returns `None` if the allocation is too large for the atlas.
+    pub fn allocate(&mut self, size: FontAtlasSize) -> Option<SubImage<&mut RgbaImage>> {
         const PADDING: u32 = 1;
 
+        let max_allocation_side =
+            ((self.max_texture_side as f32 * self.max_allocation_fraction) as u32).max(1);
+        if size.width > max_allocation_side || size.height > max_allocation_side {
+            log::warn!(
+                "rejected font atlas allocation of {}x{}, exceeds {max_allocation_side}x{max_allocation_side}",
+                size.width,
+                size.height
+            );
+            return None;
+        }
+
         if self.cursor.x + size.width > self.image.width() {
             self.cursor.x = 0;
             self.cursor.y += self.current_row_height + PADDING;
@@ -88,7 +112,7 @@ impl FontAtlas {
 
         self.dirty = true;
 
-        self.image.sub_image(pos.x, pos.y, size.width, size.height)
+        Some(self.image.sub_image(pos.x, pos.y, size.width, size.height))
     }
 
     pub fn take_delta(&mut self) -> Option<ImageDelta> {
@@ -103,6 +127,11 @@ impl FontAtlas {
         }
     }
 
+    /// Marks the whole atlas for upload, e.g. after the GPU texture was lost.
+    pub fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+
     pub fn capacity(&self) -> f32 {
         if self.overflowed {
             1.0
@@ -117,9 +146,15 @@ impl FontAtlas {
         self.current_row_height = 0;
         self.dirty = false;
         self.overflowed = false;
+        self.generation += 1;
         self.initialize();
     }
 
+    /// Changes whenever previously allocated glyphs are no longer valid.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
     pub fn image(&self) -> &RgbaImage {
         &self.image
     }
@@ -1,6 +1,33 @@
-use crate::{color::Srgba, dpi::LogicalPoint, fonts::rasterizer::RasterizedGlyph};
+use crate::{
+    color::Srgba,
+    dpi::{LogicalPoint, LogicalRect},
+    fonts::rasterizer::RasterizedGlyph,
+};
 use ordered_float::OrderedFloat;
-use parley::FontFamily;
+use parley::{Affinity, Cluster, Cursor, FontFamily, Selection};
+use regex::Regex;
+use std::{
+    borrow::Cow,
+    ops::Range,
+    sync::{Arc, LazyLock},
+};
+
+/// Web URLs, e.g. in changelogs and notes
+static LINK_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"https?://[^\s<>"']+"#).unwrap());
+
+/// Byte ranges of web URLs in `text`. Trailing punctuation isn't part of a link.
+pub fn find_links(text: &str) -> Vec<Range<usize>> {
+    LINK_REGEX
+        .find_iter(text)
+        .map(|link| {
+            let trimmed = link
+                .as_str()
+                .trim_end_matches(['.', ',', ';', ':', '!', '?', ')', ']']);
+            link.start()..link.start() + trimmed.len()
+        })
+        .collect()
+}
 
 #[derive(Copy, Clone, Default, Debug, Hash, PartialEq)]
 pub enum Alignment {
@@ -28,7 +55,7 @@ impl From<FontStyle> for parley::FontStyle {
 
 #[derive(Clone, Debug, Hash)]
 pub struct LayoutSegment<'s> {
-    pub text: &'s str,
+    pub text: Cow<'s, str>,
     pub color: Srgba,
 }
 
@@ -64,7 +91,82 @@ impl<'s> LayoutJob<'s> {
     }
 
     pub fn append(&mut self, text: &'s str, color: Srgba) {
-        self.segments.push(LayoutSegment { text, color });
+        self.segments.push(LayoutSegment {
+            text: Cow::Borrowed(text),
+            color,
+        });
+    }
+
+    /// Segments of each line of text and the text index at which the line starts.
+    /// Lines are separated by `\n`, which isn't part of any line.
+    pub fn lines(&self) -> Vec<(usize, Vec<LayoutSegment<'_>>)> {
+        let mut lines = vec![(0, Vec::new())];
+        let mut text_index = 0;
+        for segment in &self.segments {
+            for (i, text) in segment.text.split('\n').enumerate() {
+                if i > 0 {
+                    lines.push((text_index, Vec::new()));
+                }
+                if !text.is_empty() {
+                    let line = &mut lines.last_mut().unwrap().1;
+                    line.push(LayoutSegment {
+                        text: Cow::Borrowed(text),
+                        color: segment.color,
+                    });
+                }
+                text_index += text.len() + 1;
+            }
+            // no separator follows the last piece of the segment
+            text_index -= 1;
+        }
+        lines
+    }
+
+    /// Links in the text with the color of the segment they start in
+    pub fn links(&self) -> Vec<LayoutLink> {
+        let text = self.text();
+        find_links(&text)
+            .into_iter()
+            .map(|range| LayoutLink {
+                url: text[range.clone()].to_owned(),
+                color: self.color_at(range.start),
+                range,
+            })
+            .collect()
+    }
+
+    /// Color of the segment containing the text index
+    fn color_at(&self, index: usize) -> Srgba {
+        let mut end = 0;
+        for segment in &self.segments {
+            end += segment.text.len();
+            if index < end {
+                return segment.color;
+            }
+        }
+        self.segments.last().map_or(Srgba::WHITE, |s| s.color)
+    }
+
+    /// Text of all segments
+    pub fn text(&self) -> String {
+        self.segments
+            .iter()
+            .map(|segment| segment.text.as_ref())
+            .collect()
+    }
+
+    pub fn into_owned(self) -> LayoutJob<'static> {
+        LayoutJob {
+            segments: self
+                .segments
+                .into_iter()
+                .map(|segment| LayoutSegment {
+                    text: Cow::Owned(segment.text.into_owned()),
+                    color: segment.color,
+                })
+                .collect(),
+            ..self
+        }
     }
 }
 
@@ -87,33 +189,259 @@ impl std::hash::Hash for LayoutJob<'_> {
     }
 }
 
+/// Web URL in the text of a layout, see [`find_links`]
+pub struct LayoutLink {
+    pub range: Range<usize>,
+    pub url: String,
+    pub color: Srgba,
+}
+
 #[derive(Default)]
 pub struct LayoutRow {
     pub glyphs: Vec<RasterizedGlyph>,
 }
 
+/// Line of text that was shaped on its own, so that the shaping can be reused by
+/// other layouts containing the same line, e.g. tooltips that differ in one number.
+pub struct LayoutLine {
+    pub parley_layout: Arc<parley::Layout<Srgba>>,
+    // horizontal offset of the line's glyphs relative to the layout origin
+    pub x_offset: f32,
+    // range of the line in the job's text
+    pub text_range: Range<usize>,
+}
+
 pub struct Layout {
     pub job_hash: u64,
-    pub parley_layout: parley::Layout<Srgba>,
+    /// Job the layout was created from, only kept while captures or SVG exports
+    /// need it, see `Fonts::set_keep_jobs`
+    pub job: Option<LayoutJob<'static>>,
+    pub font_size: f32,
+    pub line_height: f32,
+    pub lines: Vec<LayoutLine>,
+    pub links: Vec<LayoutLink>,
+    pub width: f32,
+    /// Distance from the top of the layout to the baseline of the first line
+    pub baseline: f32,
     pub rows: Vec<LayoutRow>,
     pub num_of_vertices: usize,
     pub num_of_indices: usize,
+    // glyphs were rasterized as RGB subpixel masks
+    pub subpixel_text: bool,
 }
 
 impl Layout {
     pub fn width(&self) -> f32 {
-        self.parley_layout.full_width()
+        self.width
+    }
+
+    pub fn height(&self) -> f32 {
+        self.lines.len() as f32 * self.line_height
     }
 
-    /// Returns text index at cursor position
+    /// Returns text index at cursor position, which is relative to the layout origin
     pub fn cursor_index(&self, cursor: LogicalPoint<f32>) -> usize {
-        let cursor = parley::Cursor::from_point(&self.parley_layout, cursor.x, cursor.y);
-        cursor.index()
+        let line_height = self.line_height;
+        let line_idx = match line_height > 0.0 {
+            true => (cursor.y / line_height).max(0.0) as usize,
+            false => 0,
+        };
+        let line_idx = line_idx.min(self.lines.len() - 1);
+        let line = &self.lines[line_idx];
+        let cursor = parley::Cursor::from_point(
+            &line.parley_layout,
+            cursor.x - line.x_offset,
+            cursor.y - line_idx as f32 * line_height,
+        );
+        line.text_range.start + cursor.index()
+    }
+
+    /// Text index of the character under `cursor`, which is relative to the layout
+    /// origin. `None` if the cursor isn't over any text.
+    pub fn hit_index(&self, cursor: LogicalPoint<f32>) -> Option<usize> {
+        let line_height = self.line_height;
+        if line_height <= 0.0 || cursor.y < 0.0 {
+            return None;
+        }
+        let line_idx = (cursor.y / line_height) as usize;
+        let line = self.lines.get(line_idx)?;
+        let x = cursor.x - line.x_offset;
+        if x < 0.0 || x > line.parley_layout.full_width() {
+            return None;
+        }
+        let y = cursor.y - line_idx as f32 * line_height;
+        let (cluster, _) = Cluster::from_point(&line.parley_layout, x, y)?;
+        Some(line.text_range.start + cluster.text_range().start)
+    }
+
+    /// URL of the link under `cursor`, which is relative to the layout origin.
+    pub fn link_at(&self, cursor: LogicalPoint<f32>) -> Option<String> {
+        let index = self.hit_index(cursor)?;
+        self.links
+            .iter()
+            .find(|link| link.range.contains(&index))
+            .map(|link| link.url.clone())
+    }
+
+    /// Rects covering the text in `range` relative to the layout origin, one per
+    /// line. Rects span the height of the line.
+    pub fn range_rects(&self, range: Range<usize>) -> Vec<LogicalRect<f32>> {
+        let mut rects = Vec::new();
+        for (line_idx, line) in self.lines.iter().enumerate() {
+            let start = range.start.max(line.text_range.start);
+            let end = range.end.min(line.text_range.end);
+            if start >= end {
+                continue;
+            }
+            let layout = &line.parley_layout;
+            let selection = Selection::new(
+                Cursor::from_byte_index(
+                    layout,
+                    start - line.text_range.start,
+                    Affinity::Downstream,
+                ),
+                Cursor::from_byte_index(layout, end - line.text_range.start, Affinity::Upstream),
+            );
+            let y = line_idx as f32 * self.line_height;
+            for (rect, _) in selection.geometry(layout) {
+                rects.push(LogicalRect::new(
+                    LogicalPoint::new(rect.x0 as f32 + line.x_offset, y),
+                    LogicalPoint::new(rect.x1 as f32 + line.x_offset, y + self.line_height),
+                ));
+            }
+        }
+        rects
+    }
+
+    /// Range of text selected by dragging from `start` to `end`, which are in the
+    /// same coordinates as for `cursor_index`.
+    pub fn range_between(&self, start: LogicalPoint<f32>, end: LogicalPoint<f32>) -> Range<usize> {
+        let start = self.cursor_index(start);
+        let end = self.cursor_index(end);
+        start.min(end)..start.max(end)
+    }
+
+    /// Rects below each link relative to the layout origin, in the link's color.
+    pub fn link_underlines(&self) -> Vec<(LogicalRect<f32>, Srgba)> {
+        let thickness = (self.font_size * 0.075).max(1.0);
+        let mut underlines = Vec::new();
+        for link in &self.links {
+            for rect in self.range_rects(link.range.clone()) {
+                let y = rect.min.y + self.baseline + thickness;
+                let rect = LogicalRect::new(
+                    LogicalPoint::new(rect.min.x, y),
+                    LogicalPoint::new(rect.max.x, y + thickness),
+                );
+                underlines.push((rect, link.color));
+            }
+        }
+        underlines
     }
 }
 
 impl std::hash::Hash for Layout {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.job_hash.hash(state);
+        self.subpixel_text.hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parley::GenericFamily;
+
+    #[test]
+    fn test_lines_split_segments() {
+        let mut job = LayoutJob::new(
+            FontFamily::Generic(GenericFamily::Monospace),
+            14.0,
+            16.0,
+            None,
+            None,
+            FontStyle::Normal,
+        );
+        job.append("Life: ", Srgba::WHITE);
+        job.append("100\nMana: 5", Srgba::from_rgb(255, 0, 0));
+        job.append("0\n", Srgba::WHITE);
+
+        let lines = job.lines();
+        let texts: Vec<Vec<&str>> = lines
+            .iter()
+            .map(|(_, segments)| segments.iter().map(|s| s.text.as_ref()).collect())
+            .collect();
+        let indices: Vec<usize> = lines.iter().map(|(idx, _)| *idx).collect();
+        let text: String = job.segments.iter().map(|s| s.text.as_ref()).collect();
+        assert_eq!(texts, [vec!["Life: ", "100"], vec!["Mana: 5", "0"], vec![]]);
+        assert_eq!(indices, [0, 10, text.len()]);
+        assert!(text[10..].starts_with("Mana: 50"));
+    }
+
+    #[test]
+    fn test_range_rects_and_range_between() {
+        let mut fonts = crate::fonts::Fonts::new(crate::app::pob_font_definitions());
+        let mut job = LayoutJob::new(
+            FontFamily::Named(Cow::Borrowed("Liberation Sans")),
+            14.0,
+            16.0,
+            None,
+            None,
+            FontStyle::Normal,
+        );
+        job.append("abc\ndef", Srgba::WHITE);
+        let layout = fonts.layout(job, 1.0);
+
+        // "bc" on the first line and "d" on the second
+        let rects = layout.range_rects(1..5);
+        assert_eq!(rects.len(), 2);
+        assert!(rects[0].min.x > 0.0 && rects[0].min.y == 0.0);
+        assert!(rects[1].min.x == 0.0 && rects[1].min.y == 16.0);
+        assert!(rects.iter().all(|rect| rect.height() == 16.0));
+
+        let end = LogicalPoint::new(rects[1].max.x, 20.0);
+        let start = LogicalPoint::new(rects[0].min.x, 4.0);
+        assert_eq!(layout.range_between(end, start), 1..5);
+    }
+
+    #[test]
+    fn test_links_of_aligned_layout() {
+        let mut fonts = crate::fonts::Fonts::new(crate::app::pob_font_definitions());
+        let mut job = LayoutJob::new(
+            FontFamily::Named(Cow::Borrowed("Liberation Sans")),
+            14.0,
+            16.0,
+            Some(Alignment::Center),
+            None,
+            FontStyle::Normal,
+        );
+        job.append("See ", Srgba::WHITE);
+        job.append("https://pobb.in/abc", Srgba::from_rgb(255, 0, 0));
+        let layout = fonts.layout(job, 1.0);
+
+        assert_eq!(layout.links.len(), 1);
+        let link = &layout.links[0];
+        assert_eq!(link.url, "https://pobb.in/abc");
+        assert_eq!(link.color, Srgba::from_rgb(255, 0, 0));
+
+        // hit testing and cursor positions use the same offset of the centered line
+        let rect = layout.range_rects(link.range.clone())[0];
+        assert!(rect.min.x < 0.0);
+        let center = LogicalPoint::new((rect.min.x + rect.max.x) / 2.0, 8.0);
+        assert_eq!(
+            layout.link_at(center).as_deref(),
+            Some("https://pobb.in/abc")
+        );
+        let start = LogicalPoint::new(rect.min.x + 0.1, 8.0);
+        assert_eq!(layout.cursor_index(start), link.range.start);
+    }
+
+    #[test]
+    fn test_find_links() {
+        let text = "See https://pobb.in/abc. Or (http://example.com/a?b=1), not ftp://x";
+        let links: Vec<&str> = find_links(text)
+            .into_iter()
+            .map(|link| &text[link])
+            .collect();
+        assert_eq!(links, ["https://pobb.in/abc", "http://example.com/a?b=1"]);
     }
 }
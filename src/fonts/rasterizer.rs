@@ -97,6 +97,8 @@ pub struct GlyphRasterizer {
     cached_glyphs: HashMap<GlyphKey, Option<CachedGlyph>>,
     // scratch image buffer used to write bitmap data into
     scratch: swash::scale::image::Image,
+    // rasterize outlines into RGB subpixel masks instead of alpha masks
+    subpixel: bool,
 }
 
 impl GlyphRasterizer {
@@ -108,6 +110,7 @@ impl GlyphRasterizer {
             next_style_id: 0,
             cached_glyphs: Default::default(),
             scratch: Default::default(),
+            subpixel: false,
         }
     }
 
@@ -118,6 +121,16 @@ impl GlyphRasterizer {
         self.cached_glyphs.clear();
     }
 
+    pub fn subpixel(&self) -> bool {
+        self.subpixel
+    }
+
+    /// Enables subpixel (LCD) rasterization.
+    /// Cached glyphs need to be cleared together with the atlas when this changes.
+    pub fn set_subpixel(&mut self, subpixel: bool) {
+        self.subpixel = subpixel;
+    }
+
     /// Gets a swash::FontRef from FontData
     fn get_font_ref<'a>(&mut self, font: &'a FontData) -> swash::FontRef<'a> {
         let font_blob_id = font.data.id();
@@ -192,6 +205,12 @@ impl GlyphRasterizer {
             .hint(true)
             .build();
 
+        let format = if self.subpixel {
+            zeno::Format::Subpixel
+        } else {
+            zeno::Format::Alpha
+        };
+
         let image = &mut self.scratch;
         let cached_glyphs = &mut self.cached_glyphs;
         glyph_run.positioned_glyphs().map(move |mut glyph| {
@@ -214,7 +233,7 @@ impl GlyphRasterizer {
                 swash::scale::Source::ColorBitmap(swash::scale::StrikeWith::BestFit),
                 swash::scale::Source::Outline,
             ])
-            .format(zeno::Format::Alpha)
+            .format(format)
             .transform(skew.map(|skew| {
                 zeno::Transform::skew(zeno::Angle::from_degrees(skew), zeno::Angle::ZERO)
             }))
@@ -226,7 +245,11 @@ impl GlyphRasterizer {
                 return None;
             };
 
-            let atlas_region = write_to_atlas(image, atlas);
+            // oversized glyphs are skipped instead of filling up the atlas
+            let Some(atlas_region) = write_to_atlas(image, atlas) else {
+                cached_glyphs.insert(glyph_key, None);
+                return None;
+            };
 
             let cached_glyph = CachedGlyph {
                 uv: atlas_region,
@@ -244,9 +267,14 @@ impl GlyphRasterizer {
     }
 }
 
-/// Writes rasterized glyph to atlas and returns region it wrote into
-fn write_to_atlas(image: &swash::scale::image::Image, atlas: &mut FontAtlas) -> FontAtlasRect {
-    let mut atlas_region = atlas.allocate(Size::new(image.placement.width, image.placement.height));
+/// Writes rasterized glyph to atlas and returns region it wrote into.
+/// Returns `None` if the glyph is too large for the atlas.
+fn write_to_atlas(
+    image: &swash::scale::image::Image,
+    atlas: &mut FontAtlas,
+) -> Option<FontAtlasRect> {
+    let mut atlas_region =
+        atlas.allocate(Size::new(image.placement.width, image.placement.height))?;
 
     match image.content {
         swash::scale::image::Content::Mask => {
@@ -262,11 +290,25 @@ fn write_to_atlas(image: &swash::scale::image::Image, atlas: &mut FontAtlas) ->
                 }
             }
         }
+        swash::scale::image::Content::SubpixelMask => {
+            let mut i = 0;
+            for y in 0..image.placement.height {
+                for x in 0..image.placement.width {
+                    let (r, g, b) = (image.data[i], image.data[i + 1], image.data[i + 2]);
+                    // Per-channel coverage goes into RGB. Alpha holds the maximum
+                    // coverage so that the glyph box still has a sensible alpha mask.
+                    let a = r.max(g).max(b);
+                    // SAFETY: allocated atlas region and swash image have the same size
+                    unsafe { atlas_region.unsafe_put_pixel(x, y, Srgba::new(r, g, b, a).into()) };
+                    i += 4;
+                }
+            }
+        }
         _ => unreachable!(),
     };
 
-    FontAtlasRect::from_origin_and_size(
+    Some(FontAtlasRect::from_origin_and_size(
         Point::new(atlas_region.offsets().0, atlas_region.offsets().1),
         Size::new(image.placement.width, image.placement.height),
-    )
+    ))
 }
@@ -1,11 +1,202 @@
 use crate::{
-    dpi::PhysicalSize,
-    renderer::{Renderer, mesh::ClippedMesh, textures::TexturesDelta},
+    dpi::{ConvertToLogical, LogicalRect, PhysicalPoint, PhysicalSize},
+    renderer::{
+        Renderer,
+        mesh::{ClippedMesh, Mesh},
+        textures::TexturesDelta,
+    },
+};
+use image::RgbaImage;
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
 };
-use std::sync::Arc;
 use wgpu::{Texture, TextureFormat, TextureView};
 use winit::window::Window;
 
+#[cfg(test)]
+pub mod offscreen;
+
+/// Number of consecutive failures to acquire a lost/outdated surface texture after
+/// which the context should be recreated.
+const MAX_SURFACE_ERRORS: u32 = 3;
+
+/// Options for creating a [`GraphicsContext`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GraphicsOptions {
+    /// Number of samples per pixel for MSAA, 1 disables it
+    pub msaa_sample_count: u32,
+    /// Prefer HDR/wide-gamut surface formats
+    pub hdr: bool,
+    /// Brightness of white in nits on HDR surfaces
+    pub hdr_white_level: f32,
+    /// Wait for the display's vertical blank before presenting a frame. Without
+    /// it, frames are presented immediately, which may tear.
+    pub vsync: bool,
+    /// Blend in linear space instead of sRGB space like PoB does. Requested by PoB
+    /// with `RenderInit("COLOR_MANAGED")` or enabled by the user with
+    /// `--linear-blending`
+    pub linear_blending: bool,
+}
+
+/// Optional features of a graphics context, reported to PoB by `RenderInit`
+#[derive(Clone, Copy, Debug)]
+pub struct GraphicsCapabilities {
+    /// See [`GraphicsOptions::linear_blending`]
+    pub linear_blending: bool,
+    /// Largest width and height of a texture, limits the size of `CaptureRegion` images
+    pub max_texture_size: u32,
+}
+
+impl Default for GraphicsCapabilities {
+    fn default() -> Self {
+        Self {
+            linear_blending: false,
+            // supported by every device
+            max_texture_size: wgpu::Limits::downlevel_defaults().max_texture_dimension_2d,
+        }
+    }
+}
+
+/// Resolution at which the UI is rendered relative to the surface. The rendered UI
+/// is scaled to the surface size during blit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderScale {
+    pub factor: f32,
+    /// Upscale with nearest-neighbor instead of linear filtering
+    pub nearest: bool,
+}
+
+impl RenderScale {
+    pub const NATIVE: Self = Self {
+        factor: 1.0,
+        nearest: false,
+    };
+
+    /// Pixel-perfect upscaling by a whole number
+    pub fn integer_upscale(factor: u32) -> Self {
+        Self {
+            factor: 1.0 / factor.max(1) as f32,
+            nearest: true,
+        }
+    }
+}
+
+/// Measures how long the GPU takes for the main render pass with timestamp queries.
+///
+/// Results are read back asynchronously and become available a frame or two
+/// later. Frames rendered while a readback is pending aren't measured.
+struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    // nanoseconds per timestamp tick
+    period: f32,
+    // timestamps were copied into the readback buffer and it's being mapped
+    is_pending: bool,
+    // set by the map callback
+    is_mapped: Arc<AtomicBool>,
+    last_duration: Option<Duration>,
+}
+
+impl GpuTimer {
+    const QUERY_COUNT: u32 = 2;
+    const BUFFER_SIZE: u64 = Self::QUERY_COUNT as u64 * wgpu::QUERY_SIZE as u64;
+
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("GPU timer queries"),
+            ty: wgpu::QueryType::Timestamp,
+            count: Self::QUERY_COUNT,
+        });
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU timer resolve buffer"),
+            size: Self::BUFFER_SIZE,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU timer readback buffer"),
+            size: Self::BUFFER_SIZE,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            period: queue.get_timestamp_period(),
+            is_pending: false,
+            is_mapped: Arc::new(AtomicBool::new(false)),
+            last_duration: None,
+        }
+    }
+
+    /// Reads back the result of the previous measurement if it's ready.
+    fn poll(&mut self, device: &wgpu::Device) {
+        if !self.is_pending {
+            return;
+        }
+        let _ = device.poll(wgpu::PollType::Poll);
+        if !self.is_mapped.swap(false, Ordering::Relaxed) {
+            return;
+        }
+
+        {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            let timestamps: Vec<u64> = data
+                .chunks_exact(8)
+                .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+                .collect();
+            let ticks = timestamps[1].saturating_sub(timestamps[0]);
+            self.last_duration = Some(Duration::from_nanos(
+                (ticks as f64 * self.period as f64) as u64,
+            ));
+        }
+        self.readback_buffer.unmap();
+        self.is_pending = false;
+    }
+
+    /// Timestamp writes for the render pass, `None` while a readback is pending.
+    fn timestamp_writes(&self) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+        (!self.is_pending).then_some(wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(0),
+            end_of_pass_write_index: Some(1),
+        })
+    }
+
+    fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.resolve_query_set(
+            &self.query_set,
+            0..Self::QUERY_COUNT,
+            &self.resolve_buffer,
+            0,
+        );
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            Self::BUFFER_SIZE,
+        );
+    }
+
+    /// Needs to be called after the commands of [`Self::resolve`] were submitted.
+    fn map(&mut self) {
+        let is_mapped = Arc::clone(&self.is_mapped);
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                is_mapped.store(result.is_ok(), Ordering::Relaxed);
+            });
+        self.is_pending = true;
+    }
+}
+
 pub enum RenderJob {
     Render {
         meshes: Vec<ClippedMesh>,
@@ -23,12 +214,33 @@ pub struct GraphicsContext {
     renderer: Renderer,
     blit_texture: wgpu::Texture,
     blit_texture_view: wgpu::TextureView,
+    // view of the blit texture that is rendered to. Has the sRGB variant of the
+    // texture's format with linear blending, so that blending happens in linear space
+    blit_render_view: wgpu::TextureView,
+    render_format: wgpu::TextureFormat,
+    capabilities: GraphicsCapabilities,
+    // multisampled render target that gets resolved into the blit texture
+    msaa_texture_view: Option<wgpu::TextureView>,
+    sample_count: u32,
     texture_blitter: wgpu::util::TextureBlitter,
+    // used instead of `texture_blitter` when upscaling by an integer factor
+    nearest_texture_blitter: wgpu::util::TextureBlitter,
+    render_scale: RenderScale,
+    // options the context was created with, the context is recreated when they change
+    options: GraphicsOptions,
+    // set by the device lost callback, e.g. after a driver reset
+    device_lost: Arc<AtomicBool>,
+    // consecutive lost/outdated errors when acquiring the surface texture
+    surface_error_count: u32,
+    // `None` if the adapter doesn't support timestamp queries
+    gpu_timer: Option<GpuTimer>,
+    /// Measure the GPU time of the main render pass
+    pub measure_gpu_time: bool,
     pub window: Arc<Window>,
 }
 
 impl GraphicsContext {
-    pub async fn new(window: Arc<Window>) -> anyhow::Result<Self> {
+    pub async fn new(window: Arc<Window>, options: GraphicsOptions) -> anyhow::Result<Self> {
         let size = window.inner_size();
 
         let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
@@ -46,45 +258,17 @@ impl GraphicsContext {
             })
             .await?;
 
-        let required_features = wgpu::Features::TEXTURE_COMPRESSION_BC;
-        let required_limits = wgpu::Limits {
-            max_texture_array_layers: 512,
-            ..Default::default()
-        };
-
-        if !adapter.features().contains(required_features) {
-            anyhow::bail!(
-                "Unsupported features were requested: {}",
-                required_features - adapter.features()
-            );
-        }
-
-        let mut failed_limit = Vec::new();
-
-        required_limits.check_limits_with_fail_fn(
-            &adapter.limits(),
-            false,
-            |name, requested, allowed| {
-                failed_limit.push((name, requested, allowed));
-            },
-        );
-
-        if let Some((name, requested, allowed)) = failed_limit.pop() {
-            anyhow::bail!(
-                "Requested limit '{name}' value {requested} is better than allowed {allowed}!"
-            )
-        }
+        let (device, queue) = request_device(&adapter).await?;
 
-        let (device, queue) = adapter
-            .request_device(&wgpu::DeviceDescriptor {
-                label: None,
-                required_features,
-                required_limits,
-                memory_hints: Default::default(),
-                trace: wgpu::Trace::Off,
-                experimental_features: Default::default(),
-            })
-            .await?;
+        let device_lost = Arc::new(AtomicBool::new(false));
+        let device_lost_flag = Arc::clone(&device_lost);
+        device.set_device_lost_callback(move |reason, message| {
+            // `Destroyed` is expected when the device is dropped
+            if reason == wgpu::DeviceLostReason::Unknown {
+                log::error!("GPU device lost: {message}");
+                device_lost_flag.store(true, Ordering::Relaxed);
+            }
+        });
 
         let surface_caps = surface.get_capabilities(&adapter);
 
@@ -92,31 +276,112 @@ impl GraphicsContext {
         // To get a similar visual outcome, we need to do the same.
         // Select a non-sRGB format so that no automatic linear -> sRGB conversion
         // is performed.
-        let surface_format = surface_caps
+        let sdr_surface_format = surface_caps
             .formats
             .iter()
             .find(|f| !f.is_srgb() && f.required_features().is_empty())
             .copied()
             .unwrap_or(surface_caps.formats[0]);
 
+        // Rgba16Float surfaces are extended-range linear (scRGB). 10-bit surfaces are
+        // still sRGB encoded but have more precision, which reduces banding.
+        let hdr_surface_format = options
+            .hdr
+            .then(|| {
+                [
+                    wgpu::TextureFormat::Rgba16Float,
+                    wgpu::TextureFormat::Rgb10a2Unorm,
+                ]
+                .into_iter()
+                .find(|format| surface_caps.formats.contains(format))
+            })
+            .flatten();
+        if options.hdr && hdr_surface_format.is_none() {
+            log::warn!("No HDR surface format available, falling back to SDR output");
+        }
+
+        let surface_format = hdr_surface_format.unwrap_or(sdr_surface_format);
+        let hdr_white_level =
+            (surface_format == wgpu::TextureFormat::Rgba16Float).then_some(options.hdr_white_level);
+
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
             width: size.width,
             height: size.height,
-            //present_mode: surface_caps.present_modes[0],
-            present_mode: wgpu::PresentMode::Fifo,
+            present_mode: present_mode(options.vsync, &surface_caps.present_modes),
             alpha_mode: surface_caps.alpha_modes[0],
             view_formats: vec![],
             desired_maximum_frame_latency: 2,
         };
 
-        let (blit_texture, blit_texture_view) =
-            create_blit_texture(&device, config.width, config.height, config.format);
+        let supports_view_formats = adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::VIEW_FORMATS);
+
+        // linear HDR output is always blended in linear space
+        let srgb_format = config.format.add_srgb_suffix();
+        let capabilities = GraphicsCapabilities {
+            linear_blending: hdr_white_level.is_some()
+                || (supports_view_formats && srgb_format.is_srgb()),
+            max_texture_size: device.limits().max_texture_dimension_2d,
+        };
+        let render_format =
+            if options.linear_blending && capabilities.linear_blending && srgb_format.is_srgb() {
+                srgb_format
+            } else {
+                config.format
+            };
+        // scRGB's reference white is 80 nits
+        let linear_white_scale = match hdr_white_level {
+            Some(white_level) => Some(white_level / 80.0),
+            None => (render_format != config.format).then_some(1.0),
+        };
+
+        let format_features = adapter.get_texture_format_features(render_format);
+        let sample_count = options.msaa_sample_count;
+        let sample_count = if format_features.flags.sample_count_supported(sample_count) {
+            sample_count
+        } else {
+            log::warn!("{sample_count}x MSAA isn't supported, disabling MSAA");
+            1
+        };
+
+        let (blit_texture, blit_texture_view, blit_render_view) = create_blit_texture(
+            &device,
+            config.width,
+            config.height,
+            config.format,
+            render_format,
+        );
+        let msaa_texture_view = create_msaa_texture_view(
+            &device,
+            config.width,
+            config.height,
+            render_format,
+            sample_count,
+        );
 
         let texture_blitter = wgpu::util::TextureBlitter::new(&device, config.format);
+        let nearest_texture_blitter =
+            wgpu::util::TextureBlitterBuilder::new(&device, config.format)
+                .sample_type(wgpu::FilterMode::Nearest)
+                .build();
+
+        let renderer = Renderer::new(
+            &device,
+            render_format,
+            None,
+            sample_count,
+            linear_white_scale,
+            supports_view_formats,
+        );
 
-        let renderer = Renderer::new(&device, config.format, None);
+        let gpu_timer = device
+            .features()
+            .contains(wgpu::Features::TIMESTAMP_QUERY)
+            .then(|| GpuTimer::new(&device, &queue));
 
         Ok(Self {
             surface,
@@ -127,11 +392,59 @@ impl GraphicsContext {
             renderer,
             blit_texture,
             blit_texture_view,
+            blit_render_view,
+            render_format,
+            capabilities,
+            msaa_texture_view,
+            sample_count,
             texture_blitter,
+            nearest_texture_blitter,
+            render_scale: RenderScale::NATIVE,
+            options,
+            device_lost,
+            surface_error_count: 0,
+            gpu_timer,
+            measure_gpu_time: false,
             window,
         })
     }
 
+    pub fn supports_subpixel_text(&self) -> bool {
+        self.renderer.supports_subpixel_text()
+    }
+
+    /// Whether the device was lost or the surface keeps failing even after being
+    /// reconfigured. The context needs to be recreated in that case.
+    pub fn needs_recovery(&self) -> bool {
+        self.device_lost.load(Ordering::Relaxed) || self.surface_error_count >= MAX_SURFACE_ERRORS
+    }
+
+    /// GPU time of the main render pass of a recent frame. `None` if it isn't
+    /// measured or timestamp queries aren't supported.
+    pub fn gpu_time(&self) -> Option<Duration> {
+        self.gpu_timer
+            .as_ref()
+            .and_then(|timer| timer.last_duration)
+    }
+
+    pub fn options(&self) -> GraphicsOptions {
+        self.options
+    }
+
+    pub fn capabilities(&self) -> GraphicsCapabilities {
+        self.capabilities
+    }
+
+    pub fn render_scale(&self) -> RenderScale {
+        self.render_scale
+    }
+
+    /// Sets the resolution of the rendered UI relative to the surface.
+    /// Takes effect on the next [`Self::resize`].
+    pub fn set_render_scale(&mut self, render_scale: RenderScale) {
+        self.render_scale = render_scale;
+    }
+
     pub fn resize(&mut self, width: u32, height: u32) {
         if width > 0 && height > 0 {
             self.config.width = width;
@@ -139,8 +452,29 @@ impl GraphicsContext {
             self.surface.configure(&self.device, &self.config);
             self.is_surface_configured = true;
 
-            (self.blit_texture, self.blit_texture_view) =
-                create_blit_texture(&self.device, width, height, self.config.format);
+            // NOTE: integer upscaling is only pixel-perfect if the surface size is a
+            // multiple of the upscale factor. Otherwise, the last row/column gets stretched.
+            let factor = self.render_scale.factor;
+            let blit_width = ((width as f32 * factor).round() as u32).max(1);
+            let blit_height = ((height as f32 * factor).round() as u32).max(1);
+            (
+                self.blit_texture,
+                self.blit_texture_view,
+                self.blit_render_view,
+            ) = create_blit_texture(
+                &self.device,
+                blit_width,
+                blit_height,
+                self.config.format,
+                self.render_format,
+            );
+            self.msaa_texture_view = create_msaa_texture_view(
+                &self.device,
+                blit_width,
+                blit_height,
+                self.render_format,
+                self.sample_count,
+            );
         }
     }
 
@@ -152,10 +486,24 @@ impl GraphicsContext {
         profiling::scope!("render");
 
         if !self.is_surface_configured {
+            self.renderer.discard_uploaded_meshes();
             return Ok(());
         }
 
-        let output = self.surface.get_current_texture()?;
+        let output = match self.surface.get_current_texture() {
+            Ok(output) => {
+                self.surface_error_count = 0;
+                output
+            }
+            Err(err) => {
+                // the next frame may not contain the changes of this one
+                self.renderer.discard_uploaded_meshes();
+                if matches!(err, wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) {
+                    self.surface_error_count += 1;
+                }
+                return Err(err);
+            }
+        };
         let suboptimal = output.suboptimal;
 
         let surface_view = output
@@ -168,6 +516,15 @@ impl GraphicsContext {
                 label: Some("Render Encoder"),
             });
 
+        let mut gpu_timer = match &mut self.gpu_timer {
+            Some(timer) if self.measure_gpu_time => {
+                timer.poll(&self.device);
+                Some(timer)
+            }
+            _ => None,
+        };
+        let mut is_timed = false;
+
         // If render_job is [`RenderJob::Skip`], skip rendering and just
         // blit the texture of the previous frame onto the surface texture.
         if let RenderJob::Render {
@@ -175,7 +532,8 @@ impl GraphicsContext {
             textures_delta,
         } = render_job
         {
-            let screen_size = PhysicalSize::new(self.config.width, self.config.height);
+            let screen_size =
+                PhysicalSize::new(self.blit_texture.width(), self.blit_texture.height());
 
             // upload new textures
             self.renderer
@@ -190,10 +548,26 @@ impl GraphicsContext {
                 scale_factor,
             );
 
+            // with MSAA, render into the multisampled texture and resolve into the
+            // blit texture. The multisampled contents aren't needed afterwards.
+            let (view, resolve_target, store) = match &self.msaa_texture_view {
+                Some(msaa_texture_view) => (
+                    msaa_texture_view,
+                    Some(&self.blit_render_view),
+                    wgpu::StoreOp::Discard,
+                ),
+                None => (&self.blit_render_view, None, wgpu::StoreOp::Store),
+            };
+
+            let timestamp_writes = gpu_timer
+                .as_ref()
+                .and_then(|timer| timer.timestamp_writes());
+            is_timed = timestamp_writes.is_some();
+
             let rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &self.blit_texture_view,
-                    resolve_target: None,
+                    view,
+                    resolve_target,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color {
                             r: 0.0,
@@ -201,12 +575,12 @@ impl GraphicsContext {
                             b: 0.0,
                             a: 1.0,
                         }),
-                        store: wgpu::StoreOp::Store,
+                        store,
                     },
                     depth_slice: None,
                 })],
                 depth_stencil_attachment: None,
-                timestamp_writes: None,
+                timestamp_writes,
                 label: Some("main render pass"),
                 occlusion_query_set: None,
             });
@@ -219,11 +593,22 @@ impl GraphicsContext {
             );
 
             self.renderer.free_textures(&textures_delta);
+
+            if let Some(timer) = &gpu_timer
+                && is_timed
+            {
+                timer.resolve(&mut encoder);
+            }
         }
 
         {
             profiling::scope!("blit");
-            self.texture_blitter.copy(
+            let texture_blitter = if self.render_scale.nearest {
+                &self.nearest_texture_blitter
+            } else {
+                &self.texture_blitter
+            };
+            texture_blitter.copy(
                 &self.device,
                 &mut encoder,
                 &self.blit_texture_view,
@@ -233,6 +618,12 @@ impl GraphicsContext {
 
         self.queue.submit(std::iter::once(encoder.finish()));
 
+        if let Some(timer) = &mut gpu_timer
+            && is_timed
+        {
+            timer.map();
+        }
+
         self.window.pre_present_notify();
         output.present();
 
@@ -242,14 +633,268 @@ impl GraphicsContext {
             Ok(())
         }
     }
+
+    /// Renders `meshes` of the last rendered frame within `rect` into a texture of
+    /// `size` and reads it back. `rect` is in the logical coordinates of the frame
+    /// and may extend beyond the window. Clipping at the edges of the window is
+    /// ignored, so that anything drawn beyond them is captured too.
+    pub fn capture_region(
+        &mut self,
+        meshes: &[ClippedMesh],
+        rect: LogicalRect<f32>,
+        size: PhysicalSize<u32>,
+        pixels_per_point: f32,
+    ) -> anyhow::Result<RgbaImage> {
+        let format = self.config.format;
+        let is_bgra = match format {
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => false,
+            TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => true,
+            format => anyhow::bail!("Frames with format {format:?} can't be captured, e.g. HDR"),
+        };
+        let max_size = self.device.limits().max_texture_dimension_2d;
+        if size.is_empty() || size.width > max_size || size.height > max_size {
+            anyhow::bail!("The image size needs to be between 1 and {max_size} pixels");
+        }
+
+        let window_rect = LogicalRect::from_size(
+            PhysicalSize::new(self.blit_texture.width(), self.blit_texture.height())
+                .to_logical::<f32, _>(pixels_per_point),
+        );
+        let offset = -rect.min.to_vector();
+        let meshes: Vec<_> = meshes
+            .iter()
+            .map(|clipped| {
+                let mut mesh = Mesh::clone(&clipped.mesh);
+                for vertex in &mut mesh.vertices {
+                    vertex.pos += offset;
+                }
+                let clip_rect = if clipped.clip_rect.contains_box(&window_rect) {
+                    LogicalRect::from_size(rect.size())
+                } else {
+                    clipped.clip_rect.translate(offset)
+                };
+                ClippedMesh {
+                    clip_rect,
+                    mesh: Arc::new(mesh),
+                    // the buffers of the frame are overwritten
+                    id: None,
+                    is_dirty: true,
+                }
+            })
+            .collect();
+
+        let (texture, _, render_view) = create_blit_texture(
+            &self.device,
+            size.width,
+            size.height,
+            format,
+            self.render_format,
+        );
+        let msaa_texture_view = create_msaa_texture_view(
+            &self.device,
+            size.width,
+            size.height,
+            self.render_format,
+            self.sample_count,
+        );
+        // `CaptureRegion` only accepts sizes with the aspect ratio of `rect`
+        let capture_pixels_per_point = size.width as f32 / rect.width();
+        self.renderer.update_buffers(
+            &self.device,
+            &self.queue,
+            &meshes,
+            size,
+            capture_pixels_per_point,
+        );
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Capture Encoder"),
+            });
+        let (view, resolve_target, store) = match &msaa_texture_view {
+            Some(msaa_texture_view) => (
+                msaa_texture_view,
+                Some(&render_view),
+                wgpu::StoreOp::Discard,
+            ),
+            None => (&render_view, None, wgpu::StoreOp::Store),
+        };
+        let rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            label: Some("capture render pass"),
+            occlusion_query_set: None,
+        });
+        self.renderer.render(
+            &mut rpass.forget_lifetime(),
+            &meshes,
+            size,
+            capture_pixels_per_point,
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+        // the next frame needs to upload its meshes again
+        self.renderer.discard_uploaded_meshes();
+
+        let mut image = read_texture(
+            &self.device,
+            &self.queue,
+            &texture,
+            PhysicalPoint::zero(),
+            size,
+        )?;
+        if is_bgra {
+            for pixel in image.pixels_mut() {
+                pixel.0.swap(0, 2);
+            }
+        }
+        Ok(image)
+    }
 }
 
+/// Copies the pixels of `texture` within `size` at `origin` into an image. The
+/// texture needs 4 bytes per pixel and `COPY_SRC` usage. Blocks until the copy is
+/// done.
+fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &Texture,
+    origin: PhysicalPoint<u32>,
+    size: PhysicalSize<u32>,
+) -> anyhow::Result<RgbaImage> {
+    // rows of buffer copies need to be aligned
+    let bytes_per_row = (size.width * 4).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback Buffer"),
+        size: (bytes_per_row * size.height) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        wgpu::TexelCopyTextureInfo {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d {
+                x: origin.x,
+                y: origin.y,
+                z: 0,
+            },
+            aspect: wgpu::TextureAspect::All,
+        },
+        wgpu::TexelCopyBufferInfo {
+            buffer: &readback_buffer,
+            layout: wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(bytes_per_row),
+                rows_per_image: None,
+            },
+        },
+        wgpu::Extent3d {
+            width: size.width,
+            height: size.height,
+            depth_or_array_layers: 1,
+        },
+    );
+    queue.submit(std::iter::once(encoder.finish()));
+
+    readback_buffer
+        .slice(..)
+        .map_async(wgpu::MapMode::Read, |_| {});
+    device.poll(wgpu::PollType::wait_indefinitely())?;
+
+    let data = readback_buffer.slice(..).get_mapped_range();
+    let mut image = RgbaImage::new(size.width, size.height);
+    for (row, pixels) in data
+        .chunks_exact(bytes_per_row as usize)
+        .zip(image.chunks_exact_mut(size.width as usize * 4))
+    {
+        pixels.copy_from_slice(&row[..pixels.len()]);
+    }
+    Ok(image)
+}
+
+/// Requests a device with the features and limits the renderer needs.
+async fn request_device(adapter: &wgpu::Adapter) -> anyhow::Result<(wgpu::Device, wgpu::Queue)> {
+    let required_features = wgpu::Features::TEXTURE_COMPRESSION_BC;
+    let required_limits = wgpu::Limits {
+        max_texture_array_layers: 512,
+        ..Default::default()
+    };
+
+    if !adapter.features().contains(required_features) {
+        anyhow::bail!(
+            "Unsupported features were requested: {}",
+            required_features - adapter.features()
+        );
+    }
+
+    let mut failed_limit = Vec::new();
+
+    required_limits.check_limits_with_fail_fn(
+        &adapter.limits(),
+        false,
+        |name, requested, allowed| {
+            failed_limit.push((name, requested, allowed));
+        },
+    );
+
+    if let Some((name, requested, allowed)) = failed_limit.pop() {
+        anyhow::bail!(
+            "Requested limit '{name}' value {requested} is better than allowed {allowed}!"
+        )
+    }
+
+    let device_and_queue = adapter
+        .request_device(&wgpu::DeviceDescriptor {
+            label: None,
+            // dual-source blending is optional and only needed for subpixel text,
+            // timestamp queries are only needed for the performance overlay
+            required_features: required_features
+                | (adapter.features()
+                    & (wgpu::Features::DUAL_SOURCE_BLENDING | wgpu::Features::TIMESTAMP_QUERY)),
+            required_limits,
+            memory_hints: Default::default(),
+            trace: wgpu::Trace::Off,
+            experimental_features: Default::default(),
+        })
+        .await?;
+    Ok(device_and_queue)
+}
+
+/// Present mode for the surface. Without vsync, mailbox is preferred because it
+/// doesn't tear.
+fn present_mode(vsync: bool, supported: &[wgpu::PresentMode]) -> wgpu::PresentMode {
+    if vsync {
+        return wgpu::PresentMode::Fifo;
+    }
+    [wgpu::PresentMode::Mailbox, wgpu::PresentMode::Immediate]
+        .into_iter()
+        .find(|mode| supported.contains(mode))
+        .unwrap_or(wgpu::PresentMode::Fifo)
+}
+
+/// Returns the blit texture, a view of it for sampling and a view with
+/// `render_format` for rendering.
 fn create_blit_texture(
     device: &wgpu::Device,
     width: u32,
     height: u32,
     format: TextureFormat,
-) -> (Texture, TextureView) {
+    render_format: TextureFormat,
+) -> (Texture, TextureView, TextureView) {
     let blit_texture = device.create_texture(&wgpu::TextureDescriptor {
         label: Some("Blit Texture"),
         size: wgpu::Extent3d {
@@ -261,11 +906,51 @@ fn create_blit_texture(
         sample_count: 1,
         dimension: wgpu::TextureDimension::D2,
         format,
-        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
-        view_formats: &[],
+        // copied from by `GraphicsContext::capture_region`
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+            | wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_SRC,
+        view_formats: if render_format != format {
+            std::slice::from_ref(&render_format)
+        } else {
+            &[]
+        },
     });
 
     let blit_texture_view = blit_texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let blit_render_view = blit_texture.create_view(&wgpu::TextureViewDescriptor {
+        format: Some(render_format),
+        ..Default::default()
+    });
+
+    (blit_texture, blit_texture_view, blit_render_view)
+}
+
+fn create_msaa_texture_view(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+    format: TextureFormat,
+    sample_count: u32,
+) -> Option<TextureView> {
+    if sample_count <= 1 {
+        return None;
+    }
+
+    let msaa_texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("MSAA Texture"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
 
-    (blit_texture, blit_texture_view)
+    Some(msaa_texture.create_view(&wgpu::TextureViewDescriptor::default()))
 }
@@ -0,0 +1,252 @@
+//! Renders frames into a texture without a window for the visual regression tests.
+//!
+//! Scenes are drawn into [`Layers`] like PoB does, tessellated and rendered with
+//! the regular [`Renderer`]. The result is compared against golden images in
+//! `tests/golden`. Set `UPDATE_GOLDEN=1` to record new golden images or all of
+//! them again after an intended change.
+//!
+//! Tests are skipped if no GPU adapter is available or the device lacks features
+//! the renderer requires, e.g. BC texture compression.
+
+use crate::{
+    app::pob_font_definitions,
+    dpi::{LogicalSize, PhysicalPoint, PhysicalSize},
+    fonts::Fonts,
+    gfx::{read_texture, request_device},
+    layers::Layers,
+    renderer::{
+        Renderer, mesh::ClippedMesh, tessellator::Tessellator, textures::WrappedTextureManager,
+    },
+};
+use image::RgbaImage;
+use std::path::PathBuf;
+
+/// Largest difference of a color channel that is still considered equal.
+/// Rasterization and filtering differ slightly between GPUs.
+const CHANNEL_TOLERANCE: u8 = 8;
+
+/// Fraction of pixels that may differ by more than [`CHANNEL_TOLERANCE`]
+const DIFFERING_PIXELS_TOLERANCE: f64 = 0.001;
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+pub struct OffscreenContext {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    renderer: Renderer,
+    texture: wgpu::Texture,
+}
+
+impl OffscreenContext {
+    /// Returns `None` if no adapter is available, e.g. on machines without a GPU.
+    pub async fn new(size: PhysicalSize<u32>) -> anyhow::Result<Option<Self>> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::PRIMARY,
+            ..Default::default()
+        });
+        let Ok(adapter) = instance
+            .request_adapter(&wgpu::RequestAdapterOptions::default())
+            .await
+        else {
+            return Ok(None);
+        };
+
+        let (device, queue) = request_device(&adapter).await?;
+        let supports_view_formats = adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::VIEW_FORMATS);
+        // PoB blends in sRGB space, so the non-sRGB format is used like for surfaces
+        let renderer = Renderer::new(&device, FORMAT, None, 1, None, supports_view_formats);
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen Texture"),
+            size: wgpu::Extent3d {
+                width: size.width,
+                height: size.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        Ok(Some(Self {
+            device,
+            queue,
+            renderer,
+            texture,
+        }))
+    }
+
+    /// Renders `meshes` on a black background and reads back the result.
+    pub fn render(
+        &mut self,
+        meshes: &[ClippedMesh],
+        texture_manager: &WrappedTextureManager,
+        pixels_per_point: f32,
+    ) -> anyhow::Result<RgbaImage> {
+        let screen_size = PhysicalSize::new(self.texture.width(), self.texture.height());
+        let textures_delta = texture_manager.take_delta();
+        self.renderer
+            .update_textures(&self.device, &self.queue, &textures_delta);
+        self.renderer.update_buffers(
+            &self.device,
+            &self.queue,
+            meshes,
+            screen_size,
+            pixels_per_point,
+        );
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Offscreen Encoder"),
+            });
+
+        let view = self
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            label: Some("offscreen render pass"),
+            ..Default::default()
+        });
+        self.renderer.render(
+            &mut rpass.forget_lifetime(),
+            meshes,
+            screen_size,
+            pixels_per_point,
+        );
+        self.renderer.free_textures(&textures_delta);
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        read_texture(
+            &self.device,
+            &self.queue,
+            &self.texture,
+            PhysicalPoint::origin(),
+            screen_size,
+        )
+    }
+}
+
+/// Renders a scene drawn by `draw` at a scale of 1 with the app's fonts.
+/// Returns `None` if no adapter is available.
+pub fn render_scene(
+    size: LogicalSize<u32>,
+    draw: impl FnOnce(&mut Layers, &mut Fonts),
+) -> Option<RgbaImage> {
+    render_scaled_scene(size, 1.0, draw)
+}
+
+/// Renders the scene like a window with the given scale factor does. `draw` needs
+/// to lay out text with the same `pixels_per_point`.
+pub fn render_scaled_scene(
+    size: LogicalSize<u32>,
+    pixels_per_point: f32,
+    draw: impl FnOnce(&mut Layers, &mut Fonts),
+) -> Option<RgbaImage> {
+    let physical_size = PhysicalSize::new(
+        (size.width as f32 * pixels_per_point).round() as u32,
+        (size.height as f32 * pixels_per_point).round() as u32,
+    );
+    let mut context = match pollster::block_on(OffscreenContext::new(physical_size)) {
+        Ok(Some(context)) => context,
+        Ok(None) => {
+            eprintln!("No GPU adapter available, skipping visual test");
+            return None;
+        }
+        Err(err) => {
+            eprintln!("Unable to create a device, skipping visual test: {err:#}");
+            return None;
+        }
+    };
+
+    let mut layers = Layers::default();
+    layers.set_viewport_from_size(size);
+    let mut fonts = Fonts::new(pob_font_definitions());
+    fonts.set_pixels_per_point(pixels_per_point);
+    fonts.begin_frame();
+    draw(&mut layers, &mut fonts);
+
+    let texture_manager = WrappedTextureManager::new();
+    if let Some(font_image_delta) = fonts.font_atlas_delta() {
+        texture_manager.update_font_texture(font_image_delta);
+    }
+    let groups = layers.groups();
+    let meshes = Tessellator::default().convert_clipped_primitives(
+        layers.consume_layers(),
+        &groups,
+        fonts.font_atlas().size(),
+        fonts.font_atlas().generation(),
+        pixels_per_point,
+    );
+
+    Some(
+        context
+            .render(&meshes, &texture_manager, pixels_per_point)
+            .expect("scene should be rendered"),
+    )
+}
+
+/// Panics if `image` differs from the golden image `name` by more than the
+/// tolerance or the golden image is missing. The differing image is saved next to
+/// the golden image.
+pub fn assert_matches_golden(name: &str, image: &RgbaImage) {
+    let golden_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let golden_path = golden_dir.join(format!("{name}.png"));
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(&golden_dir).unwrap();
+        image.save(&golden_path).unwrap();
+        eprintln!("Recorded golden image {}", golden_path.display());
+        return;
+    }
+
+    if !golden_path.exists() {
+        let actual_path = golden_dir.join(format!("{name}.actual.png"));
+        image.save(&actual_path).unwrap();
+        panic!(
+            "golden image {} is missing, run with UPDATE_GOLDEN=1 to record it",
+            golden_path.display()
+        );
+    }
+
+    let golden = image::open(&golden_path).unwrap().into_rgba8();
+    assert_eq!(
+        golden.dimensions(),
+        image.dimensions(),
+        "size of {name} changed"
+    );
+
+    let differing_pixels = golden
+        .pixels()
+        .zip(image.pixels())
+        .filter(|(a, b)| {
+            a.0.iter()
+                .zip(b.0)
+                .any(|(a, b)| a.abs_diff(b) > CHANNEL_TOLERANCE)
+        })
+        .count();
+    let max_differing_pixels = (golden.len() / 4) as f64 * DIFFERING_PIXELS_TOLERANCE;
+    if differing_pixels as f64 > max_differing_pixels {
+        let actual_path = golden_dir.join(format!("{name}.actual.png"));
+        image.save(&actual_path).unwrap();
+        panic!(
+            "{differing_pixels} pixels of {name} differ from the golden image, see {}",
+            actual_path.display()
+        );
+    }
+}
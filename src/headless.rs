@@ -0,0 +1,142 @@
+//! Headless mode that calculates the stats of a build without a window or GPU.
+//!
+//! PoB ships `HeadlessWrapper.lua`, which replaces all rendering and window
+//! functions with no-ops and exposes `loadBuildFromXML`. After loading the build,
+//! the requested stats are read from the main output of the calcs tab and printed
+//! to stdout as a JSON object.
+
+use crate::{
+    integrity::{IntegrityMode, restrict_native_modules},
+    lua::LuaInstance,
+    util::change_working_directory,
+};
+use mlua::{Lua, Table, Value, Variadic};
+use std::{fmt::Write, fs, path::Path};
+
+/// Stats that are printed if none are specified
+pub const DEFAULT_STATS: [&str; 6] = [
+    "CombinedDPS",
+    "TotalDPS",
+    "TotalEHP",
+    "Life",
+    "EnergyShield",
+    "Mana",
+];
+
+pub fn run(
+    script_dir: &Path,
+    build_path: &Path,
+    stats: &[String],
+    integrity_mode: IntegrityMode,
+) -> anyhow::Result<()> {
+    let wrapper_path = script_dir.join("HeadlessWrapper.lua");
+    if !wrapper_path.exists() {
+        anyhow::bail!(
+            "{} not found. Start the app once to download PoB",
+            wrapper_path.display()
+        );
+    }
+    let build_xml = fs::read_to_string(build_path)?;
+
+    // SAFETY: use `unsafe_new` to allow loading of C modules
+    let lua = unsafe { Lua::unsafe_new() };
+    LuaInstance::register_package_paths(&lua, &script_dir.to_path_buf())?;
+    restrict_native_modules(&lua, script_dir, integrity_mode)?;
+
+    // stdout is reserved for the result
+    lua.globals().set(
+        "print",
+        lua.create_function(|_, args: Variadic<Value>| {
+            let line: Vec<_> = args
+                .iter()
+                .map(|value| value.to_string().unwrap_or_default())
+                .collect();
+            log::info!(target: "pob", "{}", line.join("\t"));
+            Ok(())
+        })?,
+    )?;
+    lua.globals().set("arg", lua.create_table()?)?;
+
+    change_working_directory(script_dir)?;
+    lua.load(wrapper_path.as_path()).exec()?;
+
+    let build_name = build_path
+        .file_stem()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let load_build: mlua::Function = lua.globals().get("loadBuildFromXML")?;
+    load_build.call::<()>((build_xml, build_name))?;
+
+    let build: Table = lua.globals().get("build")?;
+    let calcs_tab: Table = build.get("calcsTab")?;
+    let main_output: Table = calcs_tab.get("mainOutput")?;
+
+    let mut json = String::from("{");
+    for (i, stat) in stats.iter().enumerate() {
+        if i > 0 {
+            json.push_str(", ");
+        }
+        write_json_string(&mut json, stat);
+        json.push_str(": ");
+        write_json_value(&mut json, &main_output.get::<Value>(stat.as_str())?);
+    }
+    json.push('}');
+    println!("{json}");
+
+    Ok(())
+}
+
+fn write_json_value(json: &mut String, value: &Value) {
+    match value {
+        Value::Boolean(value) => {
+            let _ = write!(json, "{value}");
+        }
+        Value::Integer(value) => {
+            let _ = write!(json, "{value}");
+        }
+        // JSON has no representation for infinity and NaN
+        Value::Number(value) if value.is_finite() => {
+            let _ = write!(json, "{value}");
+        }
+        Value::String(value) => write_json_string(json, &value.to_string_lossy()),
+        _ => json.push_str("null"),
+    }
+}
+
+fn write_json_string(json: &mut String, value: &str) {
+    json.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            ch if ch.is_control() => {
+                let _ = write!(json, "\\u{:04x}", ch as u32);
+            }
+            ch => json.push(ch),
+        }
+    }
+    json.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_json_value() {
+        let lua = Lua::new();
+        let mut json = String::new();
+        write_json_value(&mut json, &Value::Number(1234.5));
+        json.push(' ');
+        write_json_value(&mut json, &Value::Number(f64::INFINITY));
+        json.push(' ');
+        write_json_value(
+            &mut json,
+            &Value::String(lua.create_string("a\"b\n").unwrap()),
+        );
+        assert_eq!(json, r#"1234.5 null "a\"b\n""#);
+    }
+}
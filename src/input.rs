@@ -5,7 +5,7 @@ use ahash::{HashMap, HashSet};
 use std::time::{Duration, Instant};
 use winit::{
     event::MouseButton,
-    keyboard::{Key, ModifiersState, NamedKey, SmolStr},
+    keyboard::{Key, KeyCode, ModifiersState, NamedKey, PhysicalKey, SmolStr},
 };
 
 /// Current state of various keyboard and mouse inputs for the application.
@@ -88,46 +88,11 @@ impl InputState {
 
 /// Attempts to convert the provided string `s` to a [winit::keyboard::Key].
 /// Returns [None] if no matching Key found.
+///
+/// Single characters are matched against keys normalized by [`normalize_key`],
+/// i.e. letters are lowercase.
 pub fn str_as_key(s: &str) -> Option<Key> {
     Some(match s.to_uppercase().as_str() {
-        "A" => Key::Character(SmolStr::new_static("A")),
-        "B" => Key::Character(SmolStr::new_static("B")),
-        "C" => Key::Character(SmolStr::new_static("C")),
-        "D" => Key::Character(SmolStr::new_static("D")),
-        "E" => Key::Character(SmolStr::new_static("E")),
-        "F" => Key::Character(SmolStr::new_static("F")),
-        "G" => Key::Character(SmolStr::new_static("G")),
-        "H" => Key::Character(SmolStr::new_static("H")),
-        "I" => Key::Character(SmolStr::new_static("I")),
-        "J" => Key::Character(SmolStr::new_static("J")),
-        "K" => Key::Character(SmolStr::new_static("K")),
-        "L" => Key::Character(SmolStr::new_static("L")),
-        "M" => Key::Character(SmolStr::new_static("M")),
-        "N" => Key::Character(SmolStr::new_static("N")),
-        "O" => Key::Character(SmolStr::new_static("O")),
-        "P" => Key::Character(SmolStr::new_static("P")),
-        "Q" => Key::Character(SmolStr::new_static("Q")),
-        "R" => Key::Character(SmolStr::new_static("R")),
-        "S" => Key::Character(SmolStr::new_static("S")),
-        "T" => Key::Character(SmolStr::new_static("T")),
-        "U" => Key::Character(SmolStr::new_static("U")),
-        "V" => Key::Character(SmolStr::new_static("V")),
-        "W" => Key::Character(SmolStr::new_static("W")),
-        "X" => Key::Character(SmolStr::new_static("X")),
-        "Y" => Key::Character(SmolStr::new_static("Y")),
-        "Z" => Key::Character(SmolStr::new_static("Z")),
-
-        "0" => Key::Character(SmolStr::new_static("0")),
-        "1" => Key::Character(SmolStr::new_static("1")),
-        "2" => Key::Character(SmolStr::new_static("2")),
-        "3" => Key::Character(SmolStr::new_static("3")),
-        "4" => Key::Character(SmolStr::new_static("4")),
-        "5" => Key::Character(SmolStr::new_static("5")),
-        "6" => Key::Character(SmolStr::new_static("6")),
-        "7" => Key::Character(SmolStr::new_static("7")),
-        "8" => Key::Character(SmolStr::new_static("8")),
-        "9" => Key::Character(SmolStr::new_static("9")),
-
         "SHIFT" => Key::Named(NamedKey::Shift),
         "CTRL" => Key::Named(NamedKey::Control),
         "ALT" => Key::Named(NamedKey::Alt),
@@ -165,6 +130,96 @@ pub fn str_as_key(s: &str) -> Option<Key> {
         "NUMLOCK" => Key::Named(NamedKey::NumLock),
         "SCROLL" => Key::Named(NamedKey::ScrollLock),
 
+        // PoB names the `=` key after its shifted character
+        "+" => Key::Character(SmolStr::new_static("=")),
+        _ if s.len() == 1 && s.is_ascii() => Key::Character(SmolStr::new(s.to_ascii_lowercase())),
+
+        _ => return None,
+    })
+}
+
+/// Makes the key that PoB sees independent of the modifiers and keyboard layout.
+///
+/// Letters are lowercase even while shift is held, so that shortcuts like
+/// `Ctrl+Shift+Z` work. Other characters, e.g. digits and symbols that change with
+/// shift or characters of Cyrillic or Greek layouts, and dead keys are replaced
+/// with the character of the physical key on a US layout, so that `Shift+1` is
+/// still `1` and shortcuts like `Ctrl+C` work on every layout. Letters of Latin
+/// layouts are kept, so that `Ctrl+Z` follows the label on AZERTY keyboards.
+pub fn normalize_key(logical_key: &Key, physical_key: PhysicalKey) -> Key {
+    let from_physical_key = || match physical_key {
+        PhysicalKey::Code(code) => keycode_as_str(code).and_then(str_as_key),
+        PhysicalKey::Unidentified(_) => None,
+    };
+    match logical_key {
+        Key::Character(ch) if ch.len() == 1 && ch.as_bytes()[0].is_ascii_alphabetic() => {
+            Key::Character(SmolStr::new(ch.to_ascii_lowercase()))
+        }
+        Key::Character(_) | Key::Dead(_) | Key::Unidentified(_) => {
+            from_physical_key().unwrap_or_else(|| logical_key.clone())
+        }
+        key => key.clone(),
+    }
+}
+
+/// Name of the key at the position of `code` on a US layout, as sent to PoB.
+/// Numpad digits and operators are named like their counterparts on the main
+/// keyboard.
+pub fn keycode_as_str(code: KeyCode) -> Option<&'static str> {
+    Some(match code {
+        KeyCode::KeyA => "a",
+        KeyCode::KeyB => "b",
+        KeyCode::KeyC => "c",
+        KeyCode::KeyD => "d",
+        KeyCode::KeyE => "e",
+        KeyCode::KeyF => "f",
+        KeyCode::KeyG => "g",
+        KeyCode::KeyH => "h",
+        KeyCode::KeyI => "i",
+        KeyCode::KeyJ => "j",
+        KeyCode::KeyK => "k",
+        KeyCode::KeyL => "l",
+        KeyCode::KeyM => "m",
+        KeyCode::KeyN => "n",
+        KeyCode::KeyO => "o",
+        KeyCode::KeyP => "p",
+        KeyCode::KeyQ => "q",
+        KeyCode::KeyR => "r",
+        KeyCode::KeyS => "s",
+        KeyCode::KeyT => "t",
+        KeyCode::KeyU => "u",
+        KeyCode::KeyV => "v",
+        KeyCode::KeyW => "w",
+        KeyCode::KeyX => "x",
+        KeyCode::KeyY => "y",
+        KeyCode::KeyZ => "z",
+
+        KeyCode::Digit0 | KeyCode::Numpad0 => "0",
+        KeyCode::Digit1 | KeyCode::Numpad1 => "1",
+        KeyCode::Digit2 | KeyCode::Numpad2 => "2",
+        KeyCode::Digit3 | KeyCode::Numpad3 => "3",
+        KeyCode::Digit4 | KeyCode::Numpad4 => "4",
+        KeyCode::Digit5 | KeyCode::Numpad5 => "5",
+        KeyCode::Digit6 | KeyCode::Numpad6 => "6",
+        KeyCode::Digit7 | KeyCode::Numpad7 => "7",
+        KeyCode::Digit8 | KeyCode::Numpad8 => "8",
+        KeyCode::Digit9 | KeyCode::Numpad9 => "9",
+
+        KeyCode::Minus | KeyCode::NumpadSubtract => "-",
+        KeyCode::Equal => "+",
+        KeyCode::NumpadAdd => "+",
+        KeyCode::NumpadMultiply => "*",
+        KeyCode::Slash | KeyCode::NumpadDivide => "/",
+        KeyCode::Period | KeyCode::NumpadDecimal => ".",
+        KeyCode::Comma | KeyCode::NumpadComma => ",",
+        KeyCode::BracketLeft => "[",
+        KeyCode::BracketRight => "]",
+        KeyCode::Semicolon => ";",
+        KeyCode::Quote => "'",
+        KeyCode::Backquote => "`",
+        KeyCode::Backslash | KeyCode::IntlBackslash => "\\",
+        KeyCode::NumpadEnter => "RETURN",
+
         _ => return None,
     })
 }
@@ -252,3 +307,66 @@ pub fn mousebutton_as_str(button: MouseButton) -> Option<SmolStr> {
         _ => return None,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_round_trip() {
+        // names PoB uses for the keys of a US layout
+        for (code, name) in [
+            (KeyCode::KeyA, "a"),
+            (KeyCode::KeyZ, "z"),
+            (KeyCode::Digit0, "0"),
+            (KeyCode::Digit9, "9"),
+            (KeyCode::Equal, "+"),
+            (KeyCode::Minus, "-"),
+            (KeyCode::BracketLeft, "["),
+            (KeyCode::BracketRight, "]"),
+            (KeyCode::Semicolon, ";"),
+            (KeyCode::Quote, "'"),
+            (KeyCode::Backslash, "\\"),
+            (KeyCode::Slash, "/"),
+            (KeyCode::Backquote, "`"),
+        ] {
+            assert_eq!(keycode_as_str(code), Some(name));
+            let key = str_as_key(name).unwrap();
+            assert_eq!(key_as_str(key).as_deref(), Some(name));
+        }
+        for name in [
+            "SHIFT", "CTRL", "RETURN", "BACK", "PAGEDOWN", "F12", "NUMLOCK",
+        ] {
+            let key = str_as_key(name).unwrap();
+            assert_eq!(key_as_str(key).as_deref(), Some(name));
+        }
+        assert_eq!(keycode_as_str(KeyCode::Numpad7), Some("7"));
+
+        // shift and non-latin layouts don't change the key PoB sees
+        let physical_key = PhysicalKey::Code(KeyCode::KeyC);
+        let cyrillic = Key::Character(SmolStr::new("с"));
+        assert_eq!(
+            normalize_key(&cyrillic, physical_key),
+            str_as_key("c").unwrap()
+        );
+        let shifted = Key::Character(SmolStr::new("C"));
+        assert_eq!(
+            normalize_key(&shifted, physical_key),
+            str_as_key("C").unwrap()
+        );
+        for (code, shifted, name) in [(KeyCode::Equal, "+", "+"), (KeyCode::Digit1, "!", "1")] {
+            let key = normalize_key(
+                &Key::Character(SmolStr::new(shifted)),
+                PhysicalKey::Code(code),
+            );
+            assert_eq!(key, str_as_key(name).unwrap());
+            assert_eq!(key_as_str(key).as_deref(), Some(name));
+        }
+        // keys without a US layout name keep their character
+        let intl = Key::Character(SmolStr::new("#"));
+        assert_eq!(
+            normalize_key(&intl, PhysicalKey::Code(KeyCode::IntlRo)),
+            intl
+        );
+    }
+}
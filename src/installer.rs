@@ -1,36 +1,68 @@
 use crate::{
     app::AppState,
-    args::Game,
+    args::{Args, Game, runtime_dir},
     color::Srgba,
-    dpi::{LogicalPoint, LogicalRect},
+    config::{Config, config_path},
+    dpi::{LogicalPoint, LogicalRect, LogicalSize},
     fonts::{Alignment, FontStyle, LayoutJob},
+    http,
+    integrity::{self, IntegrityMode},
+    lua::LuaInstance,
     mode::{AppEvent, ModeFrameOutput, ModeTransition},
-    renderer::primitives::{ClippedPrimitive, DrawPrimitive, TextPrimitive},
+    renderer::primitives::{ClippedPrimitive, DrawPrimitive, RectPrimitive, TextPrimitive},
+    shared_assets::{self, Sharing, shared_assets_dir},
     util::replace_in_matching_lines,
 };
+use anyhow::Context as _;
 use flate2::read::GzDecoder;
 use parley::{FontFamily, GenericFamily};
 use regex::Regex;
 use std::{
+    cell::Cell,
     fs::{self},
-    io::copy,
-    path::{Path, PathBuf},
-    sync::mpsc::{self, Receiver, TryRecvError},
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+    rc::Rc,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, TryRecvError},
+    },
     thread,
 };
 use std::{
     sync::LazyLock,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use ureq::{Agent, http::Response};
+use ureq::http::Response;
+use winit::event::MouseButton;
 
 const REPO_NAME: &str = "meehl/rusty-pob-manifest";
+/// Native modules required by PoB, as passed to `require`
+const NATIVE_MODULES: [&str; 4] = ["lcurl.safe", "lua-utf8", "socket", "lzip"];
+/// Release of the manifest repo whose native module bundles are installed. The
+/// SHA-256 checksums of its bundles are listed in `{tag}.sha256` on the repo's main
+/// branch, in the format of `sha256sum`.
+const RUNTIME_RELEASE_TAG: &str = "runtime-v1";
+/// Directory in the script directory in which PoB stores builds and settings
+const USER_DATA_DIR: &str = "userdata";
+/// Limit for files downloaded into memory
+const MAX_DOWNLOAD_SIZE: u64 = 64 << 20;
 static VERSION_RE: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r"^(\d+)\.(\d+)\.(\d+)$").unwrap());
 
-enum DownloadProgress {
-    Percentage(f32), // percentage of total size (between 0 and 1)
-    TotalBytes(u64), // amount of bytes downloaded
+const CANCEL_BUTTON_SIZE: LogicalSize<f32> = LogicalSize::new(140.0, 36.0);
+const BUTTON_COLOR: Srgba = Srgba::new(60, 60, 60, 255);
+const BUTTON_HOVER_COLOR: Srgba = Srgba::new(90, 90, 90, 255);
+const DETAIL_COLOR: Srgba = Srgba::new(170, 170, 170, 255);
+
+struct DownloadProgress {
+    /// Bytes of the archive received so far
+    received: u64,
+    /// Size of the archive, if the server sent it
+    total: Option<u64>,
+    /// File that is being extracted, relative to the archive root
+    current_file: String,
 }
 
 enum Progress {
@@ -46,6 +78,56 @@ enum CurrentProgress {
     Download(DownloadProgress),
 }
 
+/// Where PoB's assets are downloaded from, set with `--pob-repo`, `--pob-branch`
+/// and `--pob-version`. Only used for new installations.
+#[derive(Clone, Debug, Default)]
+pub struct PoBSource {
+    /// GitHub repository in `owner/name` form. Defaults to the official repository.
+    pub repo: Option<String>,
+    pub reference: PoBReference,
+}
+
+#[derive(Clone, Debug, Default)]
+pub enum PoBReference {
+    /// Highest release that is compatible with this version of Rusty PoB
+    #[default]
+    Compatible,
+    /// Release with the given version, without compatibility check
+    Release(String),
+    /// Latest commit of a branch, without compatibility check
+    Branch(String),
+}
+
+impl PoBSource {
+    pub fn from_args(args: &Args) -> Self {
+        let reference = match (&args.pob_branch, &args.pob_version) {
+            (Some(branch), _) => PoBReference::Branch(branch.clone()),
+            (None, Some(version)) => PoBReference::Release(version.clone()),
+            (None, None) => PoBReference::Compatible,
+        };
+        Self {
+            repo: args.pob_repo.clone(),
+            reference,
+        }
+    }
+
+    fn repo(&self, game: Game) -> &str {
+        match (&self.repo, game) {
+            (Some(repo), _) => repo,
+            (None, Game::Poe1) => "PathOfBuildingCommunity/PathOfBuilding",
+            (None, Game::Poe2) => "PathOfBuildingCommunity/PathOfBuilding-PoE2",
+        }
+    }
+
+    /// Branch written to manifest.xml
+    fn branch(&self) -> &str {
+        match &self.reference {
+            PoBReference::Branch(branch) => branch,
+            _ => "master",
+        }
+    }
+}
+
 /// Execution mode in which PoB's assets are downloaded if they don't exist yet.
 ///
 /// Immediately transitions into PoB mode if assets already exist. Otherwise,
@@ -53,15 +135,28 @@ enum CurrentProgress {
 pub struct InstallMode {
     progress_rx: Option<Receiver<Progress>>,
     current_progress: CurrentProgress,
+    // set when the first download progress is received, used for speed and ETA
+    download_start: Option<Instant>,
+    cancel: Arc<AtomicBool>,
 }
 
 impl InstallMode {
-    pub fn new(game: Game) -> Self {
+    pub fn new(game: Game, integrity_mode: IntegrityMode, source: PoBSource) -> Self {
         let script_dir = game.script_dir();
         let (progress_tx, progress_rx) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
 
+        let thread_cancel = Arc::clone(&cancel);
         thread::spawn(move || {
-            if let Err(err) = install(script_dir.as_path(), game, &progress_tx) {
+            let result = install(
+                script_dir.as_path(),
+                game,
+                integrity_mode,
+                &source,
+                &progress_tx,
+                &thread_cancel,
+            );
+            if let Err(err) = result {
                 progress_tx.send(Progress::Error(err)).unwrap();
                 return;
             }
@@ -71,6 +166,22 @@ impl InstallMode {
         Self {
             progress_rx: Some(progress_rx),
             current_progress: CurrentProgress::Starting,
+            download_start: None,
+            cancel,
+        }
+    }
+
+    /// Transitions into PoB mode without installing anything, e.g. for a custom
+    /// script directory. PoB is launched by the first update, after the graphics
+    /// context has been created, so that `RenderInit` reports its capabilities.
+    pub fn skip() -> Self {
+        let (progress_tx, progress_rx) = mpsc::channel();
+        progress_tx.send(Progress::Complete).unwrap();
+        Self {
+            progress_rx: Some(progress_rx),
+            current_progress: CurrentProgress::Starting,
+            download_start: None,
+            cancel: Arc::new(AtomicBool::new(false)),
         }
     }
 
@@ -79,16 +190,18 @@ impl InstallMode {
 
         Ok(ModeFrameOutput {
             primitives,
+            groups: Vec::new(),
             can_elide: false,
             should_continue: true,
         })
     }
 
-    pub fn update(&mut self, _app_state: &mut AppState) -> anyhow::Result<Option<ModeTransition>> {
+    pub fn update(&mut self, app_state: &mut AppState) -> anyhow::Result<Option<ModeTransition>> {
         if let Some(progress_rx) = &self.progress_rx {
             loop {
                 match progress_rx.try_recv() {
                     Ok(Progress::Download(progress)) => {
+                        self.download_start.get_or_insert_with(Instant::now);
                         self.current_progress = CurrentProgress::Download(progress);
                     }
                     Ok(Progress::Status(msg)) => {
@@ -97,6 +210,11 @@ impl InstallMode {
                     Ok(Progress::Complete) => {
                         return Ok(Some(ModeTransition::PoB));
                     }
+                    Ok(Progress::Error(_)) if self.cancel.load(Ordering::Relaxed) => {
+                        log::info!("Installation cancelled");
+                        app_state.should_exit = true;
+                        return Ok(None);
+                    }
                     Ok(Progress::Error(err)) => {
                         return Err(anyhow::anyhow!("Download failed: {}", err));
                     }
@@ -115,62 +233,249 @@ impl InstallMode {
 
     pub fn handle_event(
         &mut self,
-        _app_state: &mut AppState,
-        _event: AppEvent,
+        app_state: &mut AppState,
+        event: AppEvent,
     ) -> anyhow::Result<()> {
+        if let AppEvent::MouseDown {
+            button: MouseButton::Left,
+            ..
+        } = event
+        {
+            let screen_size = app_state.window.logical_size().cast::<f32>();
+            if Self::cancel_button_rect(screen_size).contains(app_state.input.mouse_pos()) {
+                // takes effect once the installer thread reaches the next check
+                self.cancel.store(true, Ordering::Relaxed);
+            }
+        }
         Ok(())
     }
 
+    fn cancel_button_rect(screen_size: LogicalSize<f32>) -> LogicalRect<f32> {
+        let origin = LogicalPoint::new(
+            (screen_size.width - CANCEL_BUTTON_SIZE.width) / 2.0,
+            screen_size.height / 2.0 + 90.0,
+        );
+        LogicalRect::from_origin_and_size(origin, CANCEL_BUTTON_SIZE)
+    }
+
     fn draw_current_progress(
         &self,
         app_state: &mut AppState,
     ) -> Box<dyn Iterator<Item = ClippedPrimitive>> {
-        let mut job = LayoutJob::new(
-            FontFamily::Generic(GenericFamily::SansSerif),
-            32.0,
-            34.0,
-            Some(Alignment::Center),
-            Some(700.0),
-            FontStyle::Normal,
-        );
-
         let progress_text = match &self.current_progress {
             CurrentProgress::Starting => String::from("Starting download..."),
             CurrentProgress::Status(msg) => msg.clone(),
-            CurrentProgress::Download(progress) => match progress {
-                DownloadProgress::Percentage(progress) => {
-                    let percent = (progress * 100.0) as u32;
-                    format!("Downloading assets... ({})", percent)
-                }
-                DownloadProgress::TotalBytes(total_bytes) => {
-                    format!("Downloading assets... ({})", format_bytes(*total_bytes))
+            CurrentProgress::Download(progress) => match progress.total {
+                Some(total) => {
+                    let percent = (progress.received as f32 / total as f32 * 100.0) as u32;
+                    format!("Downloading assets... ({}%)", percent.min(100))
                 }
+                None => format!(
+                    "Downloading assets... ({})",
+                    format_bytes(progress.received)
+                ),
             },
         };
-        job.append(&progress_text, Srgba::WHITE);
 
-        let layout = app_state.fonts.layout(job, app_state.window.scale_factor());
+        // current file and transfer speed below the progress
+        let mut details = Vec::new();
+        if let CurrentProgress::Download(progress) = &self.current_progress {
+            details.push(progress.current_file.clone());
+            let elapsed = self.download_start.map(|start| start.elapsed());
+            if let Some(elapsed) = elapsed.filter(|elapsed| elapsed.as_secs_f32() >= 0.5) {
+                let speed = progress.received as f32 / elapsed.as_secs_f32();
+                let mut transfer = format!("{}/s", format_bytes(speed as u64));
+                if let Some(total) = progress.total.filter(|_| speed > 0.0) {
+                    let remaining = total.saturating_sub(progress.received) as f32 / speed;
+                    transfer.push_str(&format!(", {} remaining", format_eta(remaining as u64)));
+                }
+                details.push(transfer);
+            }
+        }
 
-        // center text vertically and horizontally
         let screen_size = app_state.window.logical_size().cast::<f32>();
-        let pos = LogicalPoint::new(screen_size.width / 2.0, screen_size.height / 2.0);
+        let clip_rect = LogicalRect::from_size(screen_size);
+        let center_x = screen_size.width / 2.0;
+        let mut primitives = Vec::new();
+
+        // button is drawn first so that its label ends up on top
+        let button_rect = Self::cancel_button_rect(screen_size);
+        let is_hovered = button_rect.contains(app_state.input.mouse_pos());
+        let button_color = if is_hovered {
+            BUTTON_HOVER_COLOR
+        } else {
+            BUTTON_COLOR
+        };
+        primitives.push(ClippedPrimitive {
+            clip_rect,
+            primitive: DrawPrimitive::Rect(RectPrimitive::new(button_rect, button_color, None)),
+        });
 
-        let primitive = TextPrimitive::new(pos, layout);
+        let mut push_text = |app_state: &mut AppState, text: &str, y: f32, size: f32, color| {
+            let mut job = LayoutJob::new(
+                FontFamily::Generic(GenericFamily::SansSerif),
+                size,
+                size + 2.0,
+                Some(Alignment::Center),
+                Some(700.0),
+                FontStyle::Normal,
+            );
+            job.append(text, color);
+            let layout = app_state
+                .fonts
+                .layout(job, app_state.window.pixels_per_point());
+            primitives.push(ClippedPrimitive {
+                clip_rect,
+                primitive: DrawPrimitive::Text(TextPrimitive::new(
+                    LogicalPoint::new(center_x, y),
+                    layout,
+                )),
+            });
+        };
+
+        // center text vertically and horizontally
+        let mut y = screen_size.height / 2.0 - 34.0;
+        push_text(app_state, &progress_text, y, 32.0, Srgba::WHITE);
+        y += 44.0;
+        for detail in &details {
+            push_text(app_state, detail, y, 18.0, DETAIL_COLOR);
+            y += 22.0;
+        }
 
-        let clipped_primitive = ClippedPrimitive {
-            clip_rect: LogicalRect::from_size(app_state.window.logical_size().cast()),
-            primitive: DrawPrimitive::Text(primitive),
+        let button_text = match self.cancel.load(Ordering::Relaxed) {
+            true => "Cancelling...",
+            false => "Cancel",
         };
+        push_text(
+            app_state,
+            button_text,
+            button_rect.min.y + 8.0,
+            18.0,
+            Srgba::WHITE,
+        );
 
-        let primitives = vec![clipped_primitive];
         Box::new(primitives.into_iter())
     }
 }
 
+/// Reports installed files that don't match manifest.xml and reinstalls PoB. A
+/// failed update can leave files that match an updated manifest but don't work,
+/// so PoB is reinstalled even if verification passes. Runs without a window and
+/// prints its progress.
+pub fn repair(game: Game, integrity_mode: IntegrityMode, source: PoBSource) -> anyhow::Result<()> {
+    let target_dir = game.script_dir();
+    let version_file_path = target_dir.join("rpob.version");
+
+    if !version_file_path.exists() || !target_dir.join("Launch.lua").exists() {
+        println!("PoB isn't fully installed");
+    } else {
+        match integrity::verify_installed_files(&target_dir) {
+            Ok(failures) if failures.is_empty() => println!("All files match manifest.xml"),
+            Ok(failures) => {
+                for failure in &failures {
+                    println!("{}: {}", failure.path.display(), failure.reason);
+                }
+                println!("{} files failed verification", failures.len());
+            }
+            Err(err) => println!("Unable to verify files: {err}"),
+        }
+    }
+    println!("Reinstalling PoB to {}", target_dir.display());
+
+    // files left behind by PoB's updater are replaced by the reinstall as well
+    let update_dir = target_dir.join("Update");
+    if update_dir.exists() {
+        fs::remove_dir_all(&update_dir)?;
+    }
+    if version_file_path.exists() {
+        fs::remove_file(&version_file_path)?;
+    }
+
+    let (progress_tx, progress_rx) = mpsc::channel();
+    let installer = thread::spawn(move || {
+        let cancel = AtomicBool::new(false);
+        install(
+            &target_dir,
+            game,
+            integrity_mode,
+            &source,
+            &progress_tx,
+            &cancel,
+        )
+    });
+    for progress in progress_rx {
+        if let Progress::Status(msg) = progress {
+            println!("{msg}");
+        }
+    }
+    installer
+        .join()
+        .map_err(|_| anyhow::anyhow!("Installer thread panicked"))??;
+
+    println!("Repair complete");
+    Ok(())
+}
+
+/// Removes the installed PoB files. Builds and settings in `userdata` are kept
+/// unless `purge` is set. Asks for confirmation unless `assume_yes` is set.
+pub fn uninstall(game: Game, purge: bool, assume_yes: bool) -> anyhow::Result<()> {
+    let target_dir = game.script_dir();
+    if !target_dir.exists() {
+        println!("Nothing to remove, {} doesn't exist", target_dir.display());
+        return Ok(());
+    }
+
+    let question = match purge {
+        true => format!(
+            "Remove {} including builds and settings?",
+            target_dir.display()
+        ),
+        false => format!(
+            "Remove PoB from {}? Builds and settings in userdata are kept.",
+            target_dir.display()
+        ),
+    };
+    if !assume_yes && !confirm(&question)? {
+        println!("Aborted");
+        return Ok(());
+    }
+
+    if purge {
+        fs::remove_dir_all(&target_dir)?;
+        println!("Removed {}", target_dir.display());
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(&target_dir)? {
+        let entry = entry?;
+        if entry.file_name() == USER_DATA_DIR {
+            continue;
+        }
+        match entry.file_type()?.is_dir() {
+            true => fs::remove_dir_all(entry.path())?,
+            false => fs::remove_file(entry.path())?,
+        }
+    }
+    println!("Removed PoB from {}", target_dir.display());
+    Ok(())
+}
+
+/// Asks a yes/no question on the terminal. Defaults to no.
+fn confirm(question: &str) -> anyhow::Result<bool> {
+    print!("{question} [y/N] ");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
 fn install<P: AsRef<Path>>(
     target_dir: P,
     game: Game,
+    integrity_mode: IntegrityMode,
+    source: &PoBSource,
     progress_tx: &mpsc::Sender<Progress>,
+    cancel: &AtomicBool,
 ) -> anyhow::Result<()> {
     // Skip installation if version file exists
     let current_version = env!("CARGO_PKG_VERSION");
@@ -183,29 +488,72 @@ fn install<P: AsRef<Path>>(
             fs::write(&version_file_path, current_version).unwrap();
         }
 
+        if source.repo.is_some() || !matches!(source.reference, PoBReference::Compatible) {
+            log::warn!("PoB is already installed, ignoring the requested PoB source");
+        }
+
+        // files replaced by PoB's updater are shared again
+        share_runtime_assets(target_dir.as_ref());
+
         return Ok(());
     }
 
-    progress_tx.send(Progress::Status("Fetching compatibility info...".into()))?;
-    log::info!("Fetching compatibility info...");
-    let compatibility_info = fetch_compatibility_info(game)?;
-
-    progress_tx.send(Progress::Status("Resolving PoB version...".into()))?;
-    log::info!("Resolving supported PoB version...");
-    let needed_pob_version = highest_supported_pob_version(&compatibility_info, current_version)
-        .ok_or_else(|| anyhow::anyhow!("Unable to determine supported PoB version"))?;
-    log::info!("Using PoB version: {needed_pob_version}");
+    let archive = match &source.reference {
+        PoBReference::Compatible => {
+            progress_tx.send(Progress::Status("Fetching compatibility info...".into()))?;
+            log::info!("Fetching compatibility info...");
+            let compatibility_info = fetch_compatibility_info(game)?;
+
+            progress_tx.send(Progress::Status("Resolving PoB version...".into()))?;
+            log::info!("Resolving supported PoB version...");
+            let needed_pob_version =
+                highest_supported_pob_version(&compatibility_info, current_version)
+                    .ok_or_else(|| anyhow::anyhow!("Unable to determine supported PoB version"))?;
+            log::info!("Using PoB version: {needed_pob_version}");
+            format!("tags/v{needed_pob_version}")
+        }
+        PoBReference::Release(version) => {
+            log::info!("Using PoB version: {version} (compatibility not checked)");
+            format!("tags/v{version}")
+        }
+        PoBReference::Branch(branch) => {
+            log::info!("Using PoB branch: {branch} (compatibility not checked)");
+            format!("heads/{branch}")
+        }
+    };
 
+    check_cancelled(cancel)?;
     progress_tx.send(Progress::Status("Downloading assets...".into()))?;
-    download_path_of_building(&target_dir, game, needed_pob_version, progress_tx)?;
+    download_path_of_building(
+        &target_dir,
+        source.repo(game),
+        &archive,
+        progress_tx,
+        cancel,
+    )?;
+    check_cancelled(cancel)?;
+
+    progress_tx.send(Progress::Status("Verifying files...".into()))?;
+    log::info!("Verifying files...");
+    let failures = integrity::verify_installed_files(target_dir.as_ref())?;
+    integrity::report_failures(&failures, integrity_mode)?;
 
+    check_cancelled(cancel)?;
     progress_tx.send(Progress::Status("Patching UpdateCheck...".into()))?;
     log::info!("Patching UpdateCheck...");
-    replace_updatecheck(&target_dir)?;
+    replace_updatecheck(&target_dir, integrity_mode)?;
 
+    progress_tx.send(Progress::Status("Checking native modules...".into()))?;
+    log::info!("Checking native modules...");
+    if let Err(err) = install_native_modules(target_dir.as_ref(), integrity_mode) {
+        log::warn!("Unable to install native modules: {err}. Features like trade may not work");
+    }
+
+    check_cancelled(cancel)?;
     progress_tx.send(Progress::Status("Finalizing installation...".into()))?;
     log::info!("Finalizing installation...");
-    set_branch_and_platform(&target_dir)?;
+    set_branch_and_platform(&target_dir, source.branch())?;
+    share_runtime_assets(target_dir.as_ref());
 
     fs::write(&version_file_path, env!("CARGO_PKG_VERSION")).unwrap();
     log::info!("Installation complete.");
@@ -213,6 +561,22 @@ fn install<P: AsRef<Path>>(
     Ok(())
 }
 
+/// Shares the runtime assets of the install in `target_dir` with other installs,
+/// unless turned off with the `share_runtime_assets` option.
+fn share_runtime_assets(target_dir: &Path) {
+    let Some(sharing) = Sharing::from_config(&Config::load(config_path())) else {
+        return;
+    };
+    let shared_dir = shared_assets_dir();
+    let result = shared_assets::share(target_dir, &shared_dir, sharing)
+        .and_then(|shared| shared_assets::prune(&shared_dir).map(|_| shared));
+    match result {
+        Ok(0) => {}
+        Ok(shared) => log::info!("Shared {shared} runtime assets of {}", target_dir.display()),
+        Err(err) => log::warn!("Unable to share runtime assets: {err}"),
+    }
+}
+
 #[derive(Debug)]
 struct VersionReq {
     pob_ver: String,
@@ -279,22 +643,20 @@ fn highest_supported_pob_version<'a>(
     highest_pob_version
 }
 
-/// Downloads specified version of Path of Building
+/// Downloads Path of Building from `repo`. `archive` is the git reference of the
+/// archive, e.g. `tags/v2.55.0` or `heads/dev`.
 fn download_path_of_building<P: AsRef<Path>>(
     target_dir: P,
-    game: Game,
-    pob_version: &str,
+    repo: &str,
+    archive: &str,
     progress_tx: &mpsc::Sender<Progress>,
+    cancel: &AtomicBool,
 ) -> anyhow::Result<()> {
-    log::info!("Downloading Path of Building assets...");
+    log::info!("Downloading Path of Building assets from {repo}...");
 
-    let repo = match game {
-        Game::Poe1 => "PathOfBuildingCommunity/PathOfBuilding",
-        Game::Poe2 => "PathOfBuildingCommunity/PathOfBuilding-PoE2",
-    };
     let url = format!(
-        "https://github.com/{}/archive/refs/tags/v{}.tar.gz",
-        repo, pob_version
+        "https://github.com/{}/archive/refs/{}.tar.gz",
+        repo, archive
     );
 
     let mut response = http_get_with_backoff(&url)?;
@@ -303,46 +665,25 @@ fn download_path_of_building<P: AsRef<Path>>(
         .get("Content-Length")
         .and_then(|s| s.to_str().ok()?.parse::<u64>().ok());
 
-    let body_reader = response.body_mut().as_reader();
+    let received = Rc::new(Cell::new(0));
+    let body_reader = CountingReader {
+        inner: CancellableReader {
+            inner: response.body_mut().as_reader(),
+            cancel,
+        },
+        count: Rc::clone(&received),
+    };
     let mut archive = tar::Archive::new(GzDecoder::new(body_reader));
-    let mut downloaded = 0u64;
 
     for file in archive.entries()? {
+        check_cancelled(cancel)?;
         let mut file = file?;
         let file_path = file.path()?;
         let components: Vec<_> = file_path.components().collect();
+        let target_path = extracted_name(&components).map(|name| target_dir.as_ref().join(name));
 
-        let target_path = match components.len() {
-            0..=1 => None,
-            // put these into target_dir/
-            2 => {
-                let filename = components[1].as_os_str();
-                if filename == "manifest.xml"
-                    || filename == "help.txt"
-                    || filename == "changelog.txt"
-                    || filename == "LICENSE.md"
-                {
-                    Some(target_dir.as_ref().join(filename))
-                } else {
-                    None
-                }
-            }
-            // put lua runtime files into target_dir/lua/
-            3.. => {
-                if components[1].as_os_str() == "src"
-                    || (components[1].as_os_str() == "runtime"
-                        && components[2].as_os_str() == "lua")
-                {
-                    Some(
-                        target_dir
-                            .as_ref()
-                            .join(components[2..].iter().collect::<PathBuf>()),
-                    )
-                } else {
-                    None
-                }
-            }
-        };
+        let current_file = components.iter().skip(1).collect::<PathBuf>();
+        let current_file = current_file.to_string_lossy().into_owned();
 
         // create needed directories and extract
         if let Some(target_path) = target_path {
@@ -352,28 +693,93 @@ fn download_path_of_building<P: AsRef<Path>>(
             file.unpack(&target_path)?;
         }
 
-        downloaded += file.size();
+        progress_tx.send(Progress::Download(DownloadProgress {
+            received: received.get(),
+            total: total_size,
+            current_file,
+        }))?;
+    }
 
-        if let Some(total) = total_size {
-            let progress = downloaded as f32 / total as f32;
-            progress_tx.send(Progress::Download(DownloadProgress::Percentage(progress)))?;
-        } else {
-            progress_tx.send(Progress::Download(DownloadProgress::TotalBytes(downloaded)))?;
+    Ok(())
+}
+
+/// Counts the bytes read from the response body, i.e. before decompression.
+struct CountingReader<R> {
+    inner: R,
+    count: Rc<Cell<u64>>,
+}
+
+impl<R: io::Read> io::Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count.set(self.count.get() + read as u64);
+        Ok(read)
+    }
+}
+
+/// Fails reads once the installation is cancelled, so that decompressing and
+/// writing a large file stops as well.
+struct CancellableReader<'a, R> {
+    inner: R,
+    cancel: &'a AtomicBool,
+}
+
+impl<R: io::Read> io::Read for CancellableReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.cancel.load(Ordering::Relaxed) {
+            return Err(io::Error::other("Installation cancelled"));
+        }
+        self.inner.read(buf)
+    }
+}
+
+/// Path relative to the script directory that the file at `components` of a
+/// PoB archive is extracted to, or `None` if it isn't needed. The first component
+/// is the archive's root directory.
+fn extracted_name(components: &[Component]) -> Option<String> {
+    if !components
+        .iter()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+    let names: Vec<_> = components
+        .iter()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect();
+    match names.as_slice() {
+        // put these into target_dir/
+        [_, name]
+            if ["manifest.xml", "help.txt", "changelog.txt", "LICENSE.md"]
+                .contains(&name.as_ref()) =>
+        {
+            Some(name.to_string())
         }
+        // put lua runtime files into target_dir/lua/
+        [_, dir, rest @ ..] if dir == "src" && !rest.is_empty() => Some(rest.join("/")),
+        [_, dir, lua, rest @ ..] if dir == "runtime" && lua == "lua" && !rest.is_empty() => {
+            Some(format!("lua/{}", rest.join("/")))
+        }
+        _ => None,
     }
+}
 
+fn check_cancelled(cancel: &AtomicBool) -> anyhow::Result<()> {
+    if cancel.load(Ordering::Relaxed) {
+        anyhow::bail!("Installation cancelled");
+    }
     Ok(())
 }
 
 /// Replaces UpdateCheck with rusty-path-of-building's modified version
-fn replace_updatecheck<P: AsRef<Path>>(target_dir: P) -> anyhow::Result<()> {
-    download_file(
-        &format!(
-            "https://raw.githubusercontent.com/{REPO_NAME}/main/{}",
-            "UpdateCheck.lua"
-        ),
-        target_dir.as_ref().join("UpdateCheck.lua"),
-    )?;
+fn replace_updatecheck<P: AsRef<Path>>(
+    target_dir: P,
+    integrity_mode: IntegrityMode,
+) -> anyhow::Result<()> {
+    let updatecheck = download_file_contents(&format!(
+        "https://raw.githubusercontent.com/{REPO_NAME}/main/{}",
+        "UpdateCheck.lua"
+    ))?;
 
     // Replace original checksum with checksum of modified update script
     let new_checksum = download_file_contents(&format!(
@@ -388,6 +794,22 @@ fn replace_updatecheck<P: AsRef<Path>>(target_dir: P) -> anyhow::Result<()> {
         .next()
         .ok_or_else(|| anyhow::anyhow!("Invalid checksum file"))?;
 
+    // only write the script if it matches the checksum published alongside it
+    let updatecheck_path = target_dir.as_ref().join("UpdateCheck.lua");
+    let actual_checksum = integrity::sha1_hex(updatecheck.as_bytes());
+    if !actual_checksum.eq_ignore_ascii_case(new_checksum) {
+        integrity::report_failures(
+            &[integrity::IntegrityFailure {
+                path: updatecheck_path.clone(),
+                reason: format!(
+                    "checksum mismatch (expected {new_checksum}, got {actual_checksum})"
+                ),
+            }],
+            integrity_mode,
+        )?;
+    }
+    fs::write(&updatecheck_path, &updatecheck)?;
+
     let filename = target_dir.as_ref().join("manifest.xml");
     let manifest = fs::read_to_string(&filename)?;
 
@@ -403,8 +825,103 @@ fn replace_updatecheck<P: AsRef<Path>>(target_dir: P) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Downloads the native modules for this platform from a release of the manifest
+/// repo if any of the ones PoB requires can't be loaded, e.g. because the distro
+/// doesn't package them. The bundle must match the checksum listed for it in the
+/// manifest repo. Modules are placed in [`runtime_dir`], since native modules are
+/// never loaded from the script directory.
+fn install_native_modules(script_dir: &Path, integrity_mode: IntegrityMode) -> anyhow::Result<()> {
+    let missing = unloadable_native_modules(script_dir, integrity_mode)?;
+    if missing.is_empty() {
+        return Ok(());
+    }
+    for (name, err) in &missing {
+        log::info!("Unable to load native module '{name}': {err}");
+    }
+
+    let platform = format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH);
+    let bundle_name = format!("{platform}.tar.gz");
+    let checksums = download_file_contents(&format!(
+        "https://raw.githubusercontent.com/{REPO_NAME}/main/{RUNTIME_RELEASE_TAG}.sha256"
+    ))?;
+    let Some(expected_checksum) = bundle_checksum(&checksums, &bundle_name) else {
+        anyhow::bail!("No prebuilt native modules are available for {platform}");
+    };
+    let bundle_url = format!(
+        "https://github.com/{REPO_NAME}/releases/download/{RUNTIME_RELEASE_TAG}/{bundle_name}"
+    );
+    log::info!("Downloading native modules for {platform}...");
+    let bundle = download_file_bytes(&bundle_url)?;
+
+    let target_dir = runtime_dir();
+    unpack_runtime_bundle(&bundle, expected_checksum, &target_dir)
+        .with_context(|| format!("Unable to install {bundle_url}"))?;
+
+    let missing = unloadable_native_modules(script_dir, integrity_mode)?;
+    if let Some((name, err)) = missing.first() {
+        anyhow::bail!("'{name}' can't be loaded after installing the runtime bundle: {err}");
+    }
+    log::info!("Installed native modules to {target_dir:?}");
+
+    Ok(())
+}
+
+/// Returns the checksum of `bundle_name` from a checksum file in the format of
+/// `sha256sum`, i.e. a checksum and a file name per line.
+fn bundle_checksum<'a>(checksums: &'a str, bundle_name: &str) -> Option<&'a str> {
+    checksums.lines().find_map(|line| {
+        let (checksum, name) = line.split_once(char::is_whitespace)?;
+        // `sha256sum` marks files read in binary mode with '*'
+        let name = name.trim_start().trim_start_matches('*').trim_end();
+        (name == bundle_name).then_some(checksum)
+    })
+}
+
+/// Unpacks the gzipped tarball `bundle` into `target_dir` if it matches
+/// `expected_checksum`.
+fn unpack_runtime_bundle(
+    bundle: &[u8],
+    expected_checksum: &str,
+    target_dir: &Path,
+) -> anyhow::Result<()> {
+    // native code is never installed unverified, whatever the integrity mode
+    let actual_checksum = integrity::sha256_hex(bundle);
+    if !actual_checksum.eq_ignore_ascii_case(expected_checksum) {
+        anyhow::bail!("Checksum mismatch (expected {expected_checksum}, got {actual_checksum})");
+    }
+
+    fs::create_dir_all(target_dir)?;
+    let mut archive = tar::Archive::new(GzDecoder::new(bundle));
+    for file in archive.entries()? {
+        // `unpack_in` skips paths that would end up outside of `target_dir`
+        file?.unpack_in(target_dir)?;
+    }
+    Ok(())
+}
+
+/// Returns the native modules that fail to load together with the error. Uses a
+/// throwaway Lua state with the same search paths and restrictions as PoB's.
+fn unloadable_native_modules(
+    script_dir: &Path,
+    integrity_mode: IntegrityMode,
+) -> mlua::Result<Vec<(&'static str, String)>> {
+    // SAFETY: use `unsafe_new` to allow loading of C modules
+    let lua = unsafe { mlua::Lua::unsafe_new() };
+    LuaInstance::register_package_paths(&lua, &script_dir.to_path_buf())?;
+    integrity::restrict_native_modules(&lua, script_dir, integrity_mode)?;
+
+    let require: mlua::Function = lua.globals().get("require")?;
+    Ok(NATIVE_MODULES
+        .into_iter()
+        .filter_map(|name| {
+            let err = require.call::<mlua::Value>(name).err()?;
+            Some((name, err.to_string()))
+        })
+        .collect())
+}
+
 /// Sets branch and platform in manifest.xml
-fn set_branch_and_platform<P: AsRef<Path>>(target_dir: P) -> anyhow::Result<()> {
+fn set_branch_and_platform<P: AsRef<Path>>(target_dir: P, branch: &str) -> anyhow::Result<()> {
     let filename = target_dir.as_ref().join("manifest.xml");
     let manifest = fs::read_to_string(&filename)?;
 
@@ -413,7 +930,7 @@ fn set_branch_and_platform<P: AsRef<Path>>(target_dir: P) -> anyhow::Result<()>
     #[cfg(target_os = "windows")]
     let platform = "win32";
 
-    let new_version = format!(r#"<Version branch="master" platform="{}""#, platform);
+    let new_version = format!(r#"<Version branch="{}" platform="{}""#, branch, platform);
 
     let version_regex = Regex::new(r"<Version").unwrap();
     let new_manifest = version_regex.replace(&manifest, new_version);
@@ -423,29 +940,33 @@ fn set_branch_and_platform<P: AsRef<Path>>(target_dir: P) -> anyhow::Result<()>
     Ok(())
 }
 
-/// Downloads file and saves it to given path
-fn download_file<P: AsRef<Path>>(url: &str, file_path: P) -> anyhow::Result<()> {
+/// Downloads file and returns contents as string
+fn download_file_contents(url: &str) -> anyhow::Result<String> {
     let mut response = http_get_with_backoff(url)?;
 
     if response.status().is_success() {
         let body = response.body_mut();
-        let mut file = fs::File::create(file_path)?;
-        copy(&mut body.as_reader(), &mut file)?;
-        Ok(())
+        Ok(body.read_to_string()?)
     } else {
         anyhow::bail!("Unable to download: {}", url);
     }
 }
 
-/// Downloads file and returns contents as string
-fn download_file_contents(url: &str) -> anyhow::Result<String> {
+/// Downloads file and returns its contents
+fn download_file_bytes(url: &str) -> anyhow::Result<Vec<u8>> {
     let mut response = http_get_with_backoff(url)?;
+    Ok(response
+        .body_mut()
+        .with_config()
+        .limit(MAX_DOWNLOAD_SIZE)
+        .read_to_vec()?)
+}
 
-    if response.status().is_success() {
-        let body = response.body_mut();
-        Ok(body.read_to_string()?)
-    } else {
-        anyhow::bail!("Unable to download: {}", url);
+/// Formats a remaining time, e.g. `1m 05s`
+fn format_eta(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{secs}s"),
+        _ => format!("{}m {:02}s", secs / 60, secs % 60),
     }
 }
 
@@ -511,11 +1032,7 @@ fn http_get_with_backoff(url: &str) -> anyhow::Result<Response<ureq::Body>> {
     let mut attempt = 0;
     let mut backoff_secs: u64 = 2;
 
-    let config = Agent::config_builder()
-        .timeout_global(Some(Duration::from_secs(10)))
-        .build();
-
-    let agent: Agent = config.into();
+    let agent = http::agent(Duration::from_secs(10));
 
     loop {
         attempt += 1;
@@ -534,7 +1051,7 @@ fn http_get_with_backoff(url: &str) -> anyhow::Result<Response<ureq::Body>> {
                     MAX_ATTEMPTS
                 );
                 if attempt >= MAX_ATTEMPTS {
-                    return Err(anyhow::Error::new(err));
+                    return Err(http::describe_error(err));
                 }
                 thread::sleep(Duration::from_secs(backoff_secs));
                 backoff_secs = (backoff_secs * 2).min(MAX_BACKOFF_SECS);
@@ -599,6 +1116,48 @@ fn is_higher_version(v1: &str, v2: &str) -> anyhow::Result<bool> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use flate2::{Compression, write::GzEncoder};
+
+    fn runtime_bundle() -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        let contents = b"-- lua-utf8";
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "lua/lua-utf8.lua", &contents[..])
+            .unwrap();
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    #[test]
+    fn test_install_runtime_bundle() {
+        let bundle = runtime_bundle();
+        let checksums = format!(
+            "{}  windows-x86_64.tar.gz\n{} *linux-x86_64.tar.gz\n",
+            "0".repeat(64),
+            integrity::sha256_hex(&bundle)
+        );
+        let checksum = bundle_checksum(&checksums, "linux-x86_64.tar.gz").unwrap();
+        assert_eq!(bundle_checksum(&checksums, "macos-aarch64.tar.gz"), None);
+
+        let target_dir = tempfile::tempdir().unwrap();
+        unpack_runtime_bundle(&bundle, checksum, target_dir.path()).unwrap();
+        assert_eq!(
+            fs::read_to_string(target_dir.path().join("lua/lua-utf8.lua")).unwrap(),
+            "-- lua-utf8"
+        );
+    }
+
+    #[test]
+    fn test_refuse_runtime_bundle_with_checksum_mismatch() {
+        let bundle = runtime_bundle();
+        let target_dir = tempfile::tempdir().unwrap();
+        let result = unpack_runtime_bundle(&bundle, &"0".repeat(64), target_dir.path());
+        assert!(result.is_err());
+        assert!(!target_dir.path().join("lua").exists());
+    }
 
     #[test]
     fn test_major_version() {
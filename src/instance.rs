@@ -0,0 +1,254 @@
+//! Single-instance handling.
+//!
+//! The first instance listens on a local socket in its data directory. Later
+//! instances send their import link (or a request to raise the window) to it and
+//! exit instead of opening a second window. On Unix, a Unix domain socket is used.
+//! On Windows, a localhost TCP socket whose port is written to the data directory
+//! together with a token of the session, which other instances have to send first,
+//! so that other local processes can't send messages.
+
+use crate::app::UserEvent;
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    path::Path,
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+use winit::event_loop::EventLoopProxy;
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+/// Time the running instance has to hand an imported build to PoB
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Messages longer than this are rejected by the running instance
+const MAX_MESSAGE_LENGTH: u64 = 64 * 1024;
+
+/// Request sent to the running instance.
+#[derive(Debug, PartialEq, Eq)]
+pub enum InstanceMessage {
+    /// Raise the window
+    Focus,
+    /// Raise the window and import the build behind the link
+    Import(String),
+}
+
+impl InstanceMessage {
+    fn encode(&self) -> String {
+        match self {
+            InstanceMessage::Focus => String::from("focus"),
+            InstanceMessage::Import(url) => format!("import {url}"),
+        }
+    }
+
+    fn decode(message: &str) -> Option<Self> {
+        match message.split_once(' ') {
+            Some(("import", url)) if !url.is_empty() => Some(Self::Import(url.to_owned())),
+            None if message == "focus" => Some(Self::Focus),
+            _ => None,
+        }
+    }
+}
+
+/// Sends `message` to an already running instance. Returns whether it was handled,
+/// or `None` if there is no running instance.
+pub fn send_to_running_instance(data_dir: &Path, message: &InstanceMessage) -> Option<bool> {
+    let send = || -> std::io::Result<bool> {
+        let mut stream = platform::connect(data_dir)?;
+        writeln!(stream, "{}", message.encode())?;
+        // the running instance answers once PoB was handed the message
+        stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+        let mut reply = String::new();
+        BufReader::new(stream).read_line(&mut reply)?;
+        Ok(reply.trim() == "ok")
+    };
+
+    // a socket might be left over from an instance that crashed
+    send().ok()
+}
+
+/// Accepts messages from later instances and passes them to the event loop.
+pub fn listen(data_dir: &Path, proxy: EventLoopProxy<UserEvent>) -> anyhow::Result<()> {
+    std::fs::create_dir_all(data_dir)?;
+    let listener = platform::bind(data_dir)?;
+
+    thread::spawn(move || {
+        loop {
+            let mut stream = match platform::accept(&listener) {
+                Ok(stream) => stream,
+                Err(err) => {
+                    log::warn!("Unable to accept connection from another instance: {err}");
+                    continue;
+                }
+            };
+
+            let mut line = String::new();
+            let read = BufReader::new((&mut stream).take(MAX_MESSAGE_LENGTH)).read_line(&mut line);
+            let Some(message) = read.ok().and_then(|_| InstanceMessage::decode(line.trim())) else {
+                log::warn!("Ignoring invalid message from another instance");
+                continue;
+            };
+
+            log::info!("Received message from another instance: {message:?}");
+            let (reply_sender, reply_receiver) = mpsc::channel();
+            if proxy
+                .send_event(UserEvent::Instance(message, reply_sender))
+                .is_err()
+            {
+                // event loop was closed
+                break;
+            }
+            // leave the other instance time to read the reply
+            let is_handled = reply_receiver
+                .recv_timeout(REPLY_TIMEOUT - TIMEOUT)
+                .unwrap_or(false);
+            let _ = writeln!(stream, "{}", if is_handled { "ok" } else { "failed" });
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(unix)]
+mod platform {
+    use super::TIMEOUT;
+    use std::{
+        io,
+        os::unix::net::{UnixListener, UnixStream},
+        path::Path,
+    };
+
+    const SOCKET_FILE: &str = "rpob.sock";
+
+    pub fn connect(data_dir: &Path) -> io::Result<UnixStream> {
+        let stream = UnixStream::connect(data_dir.join(SOCKET_FILE))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        Ok(stream)
+    }
+
+    pub fn bind(data_dir: &Path) -> io::Result<UnixListener> {
+        let socket_path = data_dir.join(SOCKET_FILE);
+        // another instance might have started in the meantime, so the socket is
+        // only removed if nothing listens on it anymore
+        match UnixStream::connect(&socket_path) {
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    "another instance is listening",
+                ));
+            }
+            Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+                std::fs::remove_file(&socket_path)?;
+            }
+            Err(_) => {}
+        }
+        UnixListener::bind(socket_path)
+    }
+
+    pub fn accept(listener: &UnixListener) -> io::Result<UnixStream> {
+        let (stream, _) = listener.accept()?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        Ok(stream)
+    }
+}
+
+#[cfg(not(unix))]
+mod platform {
+    use super::TIMEOUT;
+    use ring::rand::{SecureRandom, SystemRandom};
+    use std::{
+        io::{self, Read, Write},
+        net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+        path::Path,
+    };
+
+    /// Contains the port and the token of the running instance
+    const PORT_FILE: &str = "rpob.instance";
+    /// Length of the hex encoded token
+    const TOKEN_LENGTH: usize = 32;
+
+    pub struct Listener {
+        listener: TcpListener,
+        token: String,
+    }
+
+    pub fn connect(data_dir: &Path) -> io::Result<TcpStream> {
+        let contents = std::fs::read_to_string(data_dir.join(PORT_FILE))?;
+        let (port, token) = contents
+            .trim()
+            .split_once(' ')
+            .ok_or_else(|| io::Error::other("invalid port file"))?;
+        let port = port.parse::<u16>().map_err(io::Error::other)?;
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        writeln!(stream, "{token}")?;
+        Ok(stream)
+    }
+
+    pub fn bind(data_dir: &Path) -> io::Result<Listener> {
+        let mut token = [0; TOKEN_LENGTH / 2];
+        SystemRandom::new()
+            .fill(&mut token)
+            .map_err(|_| io::Error::other("unable to generate a token"))?;
+        let token = format!("{:032x}", u128::from_le_bytes(token));
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let port = listener.local_addr()?.port();
+        std::fs::write(data_dir.join(PORT_FILE), format!("{port} {token}"))?;
+        Ok(Listener { listener, token })
+    }
+
+    /// Accepts a connection and checks the token it sends first.
+    pub fn accept(listener: &Listener) -> io::Result<TcpStream> {
+        let (mut stream, _) = listener.listener.accept()?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+
+        // read byte by byte, so that the message after it stays in the stream
+        let mut token = Vec::with_capacity(TOKEN_LENGTH);
+        let mut byte = [0];
+        while token.len() <= TOKEN_LENGTH {
+            stream.read_exact(&mut byte)?;
+            if byte[0] == b'\n' {
+                break;
+            }
+            token.push(byte[0]);
+        }
+        if token != listener.token.as_bytes() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "invalid token",
+            ));
+        }
+        Ok(stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instance_message_roundtrip() {
+        for message in [
+            InstanceMessage::Focus,
+            InstanceMessage::Import(String::from("pob://pobbin/abc123")),
+        ] {
+            assert_eq!(InstanceMessage::decode(&message.encode()), Some(message));
+        }
+        assert_eq!(InstanceMessage::decode("import "), None);
+        assert_eq!(InstanceMessage::decode("exit"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_bind_keeps_live_socket() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let listener = platform::bind(temp_dir.path()).unwrap();
+        let err = platform::bind(temp_dir.path()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AddrInUse);
+
+        // the socket file is left behind, like after a crash
+        drop(listener);
+        assert!(platform::bind(temp_dir.path()).is_ok());
+    }
+}
@@ -0,0 +1,316 @@
+//! Checks for the Lua code that is downloaded and executed at runtime.
+//!
+//! Downloaded files are compared with the SHA-1 checksums listed in PoB's
+//! `manifest.xml` (and the checksum published next to the patched `UpdateCheck.lua`
+//! in the manifest repo). The checksums come from the same source as the files, so
+//! this only detects corrupted or incomplete downloads, not tampering. Native Lua
+//! modules are only loaded if they are on an allowlist and don't reside in the
+//! script directory, which is writable by anything that can modify downloaded files.
+
+use crate::util::to_hex;
+use clap::ValueEnum;
+use mlua::{Function, Lua, MultiValue, Result as LuaResult, Table, Value};
+use regex::Regex;
+use ring::digest::{SHA1_FOR_LEGACY_USE_ONLY, SHA256, digest};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+/// Native modules required by PoB, identified by the first component of their name
+const NATIVE_MODULE_ALLOWLIST: [&str; 5] = ["lcurl", "lua-utf8", "socket", "mime", "lzip"];
+
+/// File extensions of native libraries that must not be part of the downloaded files
+const NATIVE_LIBRARY_EXTENSIONS: [&str; 3] = ["so", "dll", "dylib"];
+
+static MANIFEST_FILE_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<File\s[^>]*>").unwrap());
+static NAME_ATTR_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"\bname="([^"]+)""#).unwrap());
+static SHA1_ATTR_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"\bsha1="([0-9A-Fa-f]+)""#).unwrap());
+static PART_ATTR_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"\bpart="([^"]+)""#).unwrap());
+
+/// How integrity violations are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum IntegrityMode {
+    /// Abort the installation and refuse to load modules that fail verification
+    #[value(name = "enforce")]
+    Enforce,
+    /// Log a warning for every violation but continue anyway
+    #[value(name = "warn")]
+    Warn,
+}
+
+/// A file that failed verification.
+#[derive(Debug)]
+pub struct IntegrityFailure {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+/// Returns the lowercase hex encoded SHA-1 checksum of `data`.
+pub fn sha1_hex(data: &[u8]) -> String {
+    to_hex(digest(&SHA1_FOR_LEGACY_USE_ONLY, data).as_ref())
+}
+
+/// Returns the lowercase hex encoded SHA-256 checksum of `data`.
+pub fn sha256_hex(data: &[u8]) -> String {
+    to_hex(digest(&SHA256, data).as_ref())
+}
+
+/// A file with a checksum in PoB's `manifest.xml`.
+#[derive(Debug, PartialEq)]
+pub struct ManifestFile {
+    /// Path relative to the script directory
+    pub name: String,
+    /// Part of PoB the file belongs to, e.g. `program` or `runtime`
+    pub part: String,
+    /// Lowercase hex encoded SHA-1 checksum
+    pub sha1: String,
+}
+
+/// Extracts the files with a checksum from the contents of PoB's `manifest.xml`.
+pub fn parse_manifest_files(manifest: &str) -> Vec<ManifestFile> {
+    MANIFEST_FILE_RE
+        .find_iter(manifest)
+        .filter_map(|file| {
+            let file = file.as_str();
+            Some(ManifestFile {
+                name: NAME_ATTR_RE.captures(file)?[1].to_owned(),
+                part: PART_ATTR_RE
+                    .captures(file)
+                    .map_or("default".to_owned(), |part| part[1].to_owned()),
+                sha1: SHA1_ATTR_RE.captures(file)?[1].to_lowercase(),
+            })
+        })
+        .collect()
+}
+
+/// Extracts `(file name, sha1)` pairs from the contents of PoB's `manifest.xml`.
+fn parse_manifest_checksums(manifest: &str) -> Vec<(String, String)> {
+    parse_manifest_files(manifest)
+        .into_iter()
+        .map(|file| (file.name, file.sha1))
+        .collect()
+}
+
+/// Compares the files in `target_dir` with the checksums in its `manifest.xml`,
+/// which was downloaded along with them. This catches corrupted downloads and files
+/// changed after the installation, but not a manifest that was changed as well.
+///
+/// Files listed in the manifest that weren't extracted (e.g. Windows binaries) are
+/// skipped. Native libraries are never expected and always reported.
+pub fn verify_installed_files(target_dir: &Path) -> anyhow::Result<Vec<IntegrityFailure>> {
+    let manifest = fs::read_to_string(target_dir.join("manifest.xml"))?;
+    let checksums = parse_manifest_checksums(&manifest);
+    if checksums.is_empty() {
+        anyhow::bail!("manifest.xml doesn't contain any checksums");
+    }
+
+    let mut failures = Vec::new();
+    let mut verified = 0;
+    for (name, expected) in checksums {
+        let path = target_dir.join(&name);
+        let Ok(contents) = fs::read(&path) else {
+            continue;
+        };
+        let actual = sha1_hex(&contents);
+        if actual != expected {
+            failures.push(IntegrityFailure {
+                path,
+                reason: format!("checksum mismatch (expected {expected}, got {actual})"),
+            });
+        }
+        verified += 1;
+    }
+    log::info!("Verified {verified} files against manifest.xml");
+
+    find_native_libraries(target_dir, &mut failures)?;
+
+    Ok(failures)
+}
+
+fn find_native_libraries(dir: &Path, failures: &mut Vec<IntegrityFailure>) -> anyhow::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_native_libraries(&path, failures)?;
+        } else if is_native_library(&path) {
+            failures.push(IntegrityFailure {
+                path,
+                reason: String::from("unexpected native library"),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn is_native_library(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| NATIVE_LIBRARY_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Logs integrity failures. Returns an error in enforce mode if there are any.
+pub fn report_failures(failures: &[IntegrityFailure], mode: IntegrityMode) -> anyhow::Result<()> {
+    for failure in failures {
+        log::warn!(
+            "Verification failed for {:?}: {}",
+            failure.path,
+            failure.reason
+        );
+    }
+
+    match (failures.len(), mode) {
+        (0, _) => Ok(()),
+        (count, IntegrityMode::Enforce) => Err(anyhow::anyhow!(
+            "{count} files failed verification. Run with `--integrity warn` to ignore"
+        )),
+        (count, IntegrityMode::Warn) => {
+            log::warn!("Continuing despite {count} files that failed verification");
+            Ok(())
+        }
+    }
+}
+
+/// Whether a native module may be loaded, based on the first component of its name.
+fn is_allowed_native_module(name: &str) -> bool {
+    let root = name.split('.').next().unwrap_or(name);
+    NATIVE_MODULE_ALLOWLIST.contains(&root)
+}
+
+/// Wraps Lua's C module loaders so that only allowlisted modules outside of the
+/// script directory are loaded.
+pub fn restrict_native_modules(lua: &Lua, script_dir: &Path, mode: IntegrityMode) -> LuaResult<()> {
+    let package: Table = lua.globals().get("package")?;
+    let loaders: Table = package.get("loaders")?;
+    let script_dir = script_dir.canonicalize().unwrap_or(script_dir.to_owned());
+
+    // index 3 loads `name` from `package.cpath`, index 4 loads the root of `name`
+    for (idx, is_root_loader) in [(3, false), (4, true)] {
+        let loader: Function = loaders.get(idx)?;
+        let script_dir = script_dir.clone();
+
+        let wrapper = lua.create_function(move |lua, (name, rest): (String, MultiValue)| {
+            let lookup_name = match is_root_loader {
+                true => name.split('.').next().unwrap_or(&name).to_owned(),
+                false => name.clone(),
+            };
+
+            if let Some(reason) = native_module_violation(lua, &name, &lookup_name, &script_dir) {
+                match mode {
+                    IntegrityMode::Enforce => {
+                        log::warn!("Refusing to load native module '{name}': {reason}");
+                        return Ok(MultiValue::from_vec(vec![Value::String(
+                            lua.create_string(format!("\n\tnative module '{name}' {reason}"))?,
+                        )]));
+                    }
+                    IntegrityMode::Warn => {
+                        log::warn!("Loading native module '{name}' although it {reason}");
+                    }
+                }
+            }
+
+            loader.call::<MultiValue>((name, rest))
+        })?;
+        loaders.set(idx, wrapper)?;
+    }
+
+    Ok(())
+}
+
+/// Returns why loading the native module `name` is not allowed, if it isn't.
+///
+/// A module whose location can't be determined counts as a violation, so that the
+/// restriction doesn't fail open.
+fn native_module_violation(
+    lua: &Lua,
+    name: &str,
+    lookup_name: &str,
+    script_dir: &Path,
+) -> Option<String> {
+    if !is_allowed_native_module(name) {
+        return Some(String::from("is not on the allowlist"));
+    }
+
+    match resolve_native_module(lua, lookup_name) {
+        // the loader won't find the module either and reports that itself
+        Ok(None) => None,
+        Ok(Some(resolved)) => resolved
+            .starts_with(script_dir)
+            .then(|| format!("is located in the script directory ({resolved:?})")),
+        Err(err) => Some(format!("could not be located ({err})")),
+    }
+}
+
+/// Resolves the library the C loader is going to open for `lookup_name`.
+fn resolve_native_module(lua: &Lua, lookup_name: &str) -> anyhow::Result<Option<PathBuf>> {
+    let package: Table = lua.globals().get("package")?;
+    let searchpath: Function = package.get("searchpath")?;
+    let cpath: String = package.get("cpath")?;
+    let resolved: Option<String> = searchpath.call((lookup_name, cpath))?;
+    resolved
+        .map(|resolved| Ok(PathBuf::from(resolved).canonicalize()?))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_manifest_checksums() {
+        let manifest = r#"<PoBVersion>
+    <Version number="2.59.2" />
+    <Source part="program" url="https://example.com/{branch}/src/" />
+    <File name="Launch.lua" part="program" sha1="0A1B2C" />
+    <File sha1="abcdef" name="lua/xml.lua" part="runtime" />
+    <File name="Missing.lua" part="program" />
+</PoBVersion>"#;
+        assert_eq!(
+            parse_manifest_checksums(manifest),
+            vec![
+                ("Launch.lua".to_owned(), "0a1b2c".to_owned()),
+                ("lua/xml.lua".to_owned(), "abcdef".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_native_module_allowlist() {
+        assert!(is_allowed_native_module("lcurl.safe"));
+        assert!(is_allowed_native_module("lua-utf8"));
+        assert!(is_allowed_native_module("socket.core"));
+        assert!(!is_allowed_native_module("lcurlx"));
+        assert!(!is_allowed_native_module("evil.socket"));
+        assert_eq!(sha1_hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_native_module_lookup_failure_is_violation() {
+        let lua = Lua::new();
+        let script_dir = std::env::temp_dir();
+        let package: Table = lua.globals().get("package").unwrap();
+
+        // not found in `package.cpath`, left to the loader
+        package.set("cpath", "").unwrap();
+        assert_eq!(
+            native_module_violation(&lua, "lcurl", "lcurl", &script_dir),
+            None
+        );
+
+        let failing = lua
+            .create_function(|_, ()| Err::<(), _>(mlua::Error::runtime("lookup failed")))
+            .unwrap();
+        package.set("searchpath", failing).unwrap();
+        let violation = native_module_violation(&lua, "lcurl", "lcurl", &script_dir);
+        assert!(violation.is_some_and(|reason| reason.starts_with("could not be located")));
+
+        package.set("searchpath", Value::Nil).unwrap();
+        assert!(native_module_violation(&lua, "lcurl", "lcurl", &script_dir).is_some());
+    }
+}
@@ -5,13 +5,14 @@ use crate::{
     dpi::{LogicalPoint, LogicalQuad, LogicalRect, LogicalSize, NormalizedQuad, NormalizedRect},
     fonts::Layout,
     renderer::{
+        canonical,
         primitives::{
-            ClippedPrimitive, DrawPrimitive, QuadPrimitive, QuadTexture, RectPrimitive,
-            RectTexture, TextPrimitive,
+            ClippedPrimitive, DrawPrimitive, LinePrimitive, NineSlicePrimitive, NineSliceTexture,
+            PrimitiveGroup, QuadPrimitive, QuadTexture, RectPrimitive, RectTexture, TextPrimitive,
         },
         textures::TextureId,
     },
-    util::calculate_hash,
+    util::{calculate_hash, stable_hash},
 };
 
 /// Holds the draw primitives for each layer.
@@ -25,6 +26,12 @@ pub struct Layers {
     current_layer: (i32, i32),
     viewport: LogicalRect<f32>,
     current_draw_color: Srgba,
+    // links in drawn text are underlined, see `set_underline_links`
+    underline_links: bool,
+    // content hash of each layer as of the last call to `groups`
+    layer_hashes: BTreeMap<(i32, i32), u64>,
+    // primitives of the last consumed frame, layers that are equal keep their hash
+    previous_layers: BTreeMap<(i32, i32), Vec<ClippedPrimitive>>,
 }
 
 impl Layers {
@@ -32,14 +39,44 @@ impl Layers {
         self.current_layer = (0, 0);
         self.layers.clear();
         self.current_draw_color = Srgba::TRANSPARENT;
+        self.underline_links = false;
     }
 
-    /// Consume primitives and return an iterator over them in drawing order.
+    /// Consume primitives and return an iterator over them in drawing order. A
+    /// copy is kept to find the layers that are unchanged in the next frame.
     pub fn consume_layers(&mut self) -> Box<dyn Iterator<Item = ClippedPrimitive>> {
         let layers = std::mem::take(&mut self.layers);
+        self.previous_layers = layers.clone();
         Box::new(layers.into_values().flatten())
     }
 
+    /// One group per layer in drawing order. A layer is dirty if its primitives
+    /// changed since the last call. The tessellator reuses the meshes of layers
+    /// whose hash didn't change.
+    ///
+    /// Only layers that differ from the last frame are hashed, so an unchanged
+    /// frame is compared without hashing any primitives.
+    pub fn groups(&mut self) -> Vec<PrimitiveGroup> {
+        let previous_hashes = std::mem::take(&mut self.layer_hashes);
+        self.layers
+            .iter()
+            .map(|(key, primitives)| {
+                let previous_hash = previous_hashes.get(key).copied();
+                let hash = match previous_hash {
+                    Some(hash) if self.previous_layers.get(key) == Some(primitives) => hash,
+                    _ => stable_hash(primitives),
+                };
+                self.layer_hashes.insert(*key, hash);
+                PrimitiveGroup {
+                    id: calculate_hash(key),
+                    len: primitives.len(),
+                    hash,
+                    is_dirty: previous_hash != Some(hash),
+                }
+            })
+            .collect()
+    }
+
     pub fn set_viewport(&mut self, viewport: LogicalRect<f32>) {
         self.viewport = viewport;
     }
@@ -60,6 +97,12 @@ impl Layers {
         self.current_draw_color = color;
     }
 
+    /// Underlines web URLs in text drawn afterwards in the color of the URL.
+    /// Rotated text isn't underlined.
+    pub fn set_underline_links(&mut self, underline_links: bool) {
+        self.underline_links = underline_links;
+    }
+
     pub fn get_draw_color(&self) -> Srgba {
         self.current_draw_color
     }
@@ -88,14 +131,69 @@ impl Layers {
         self.add_quad(primitive);
     }
 
+    /// Draws a quad with per-corner colors instead of the current draw color.
+    pub fn draw_gradient_quad(
+        &mut self,
+        texture_id: Option<TextureId>,
+        quad: LogicalQuad<f32>,
+        uv: NormalizedQuad,
+        colors: [Srgba; 4],
+        layer_idx: u32,
+    ) {
+        let texture = texture_id.map(|id| QuadTexture::new(id, uv, layer_idx));
+        let primitive = QuadPrimitive::with_colors(quad, colors, texture);
+        self.add_quad(primitive);
+    }
+
+    /// Draws a rect whose border of `insets` doesn't stretch, see
+    /// [`NineSlicePrimitive`]. `uv_insets` is the border within `uv`.
+    pub fn draw_nine_slice(
+        &mut self,
+        texture_id: Option<TextureId>,
+        rect: LogicalRect<f32>,
+        insets: [f32; 4],
+        uv: NormalizedRect,
+        uv_insets: [f32; 4],
+        layer_idx: u32,
+    ) {
+        let texture = texture_id.map(|id| NineSliceTexture::new(id, uv, uv_insets, layer_idx));
+        let mut primitive = NineSlicePrimitive::new(rect, insets, self.current_draw_color, texture);
+        primitive.translate(self.viewport.min.to_vector());
+
+        self.push(ClippedPrimitive {
+            clip_rect: self.viewport,
+            primitive: DrawPrimitive::NineSlice(primitive),
+        });
+    }
+
+    /// Draws a polyline with round caps and joins in the current draw color.
+    pub fn draw_line(&mut self, points: Vec<LogicalPoint<f32>>, width: f32) {
+        let primitive = LinePrimitive::new(points, width, self.current_draw_color);
+        self.add_line(primitive);
+    }
+
     pub fn draw_text(
         &mut self,
         position: LogicalPoint<f32>,
         layout: std::sync::Arc<Layout>,
+        rotation: f32,
         is_absolute_position: bool,
     ) {
-        let primitive = TextPrimitive::new(position, layout);
+        let underlines = match self.underline_links && rotation == 0.0 {
+            true => layout.link_underlines(),
+            false => Vec::new(),
+        };
+        let primitive = TextPrimitive::new(position, layout).with_rotation(rotation);
         self.add_text(primitive, is_absolute_position);
+
+        for (rect, color) in underlines {
+            let mut rect = rect.translate(position.to_vector());
+            // rects are always placed relative to the viewport
+            if is_absolute_position {
+                rect = rect.translate(-self.viewport.min.to_vector());
+            }
+            self.add_rect(RectPrimitive::new(rect, color, None));
+        }
     }
 
     pub fn add_rect(&mut self, mut rect: RectPrimitive) {
@@ -120,6 +218,17 @@ impl Layers {
         self.push(clipped_primitive);
     }
 
+    pub fn add_line(&mut self, mut line: LinePrimitive) {
+        line.translate(self.viewport.min.to_vector());
+
+        let clipped_primitive = ClippedPrimitive {
+            clip_rect: self.viewport,
+            primitive: DrawPrimitive::Line(line),
+        };
+
+        self.push(clipped_primitive);
+    }
+
     pub fn add_text(&mut self, mut text: TextPrimitive, is_absolute_position: bool) {
         if !is_absolute_position {
             text.translate(self.viewport.min.to_vector());
@@ -141,13 +250,14 @@ impl Layers {
             .push(clipped_primitive);
     }
 
+    /// Hash of all primitives as of the last call to [`Self::groups`]. Combines the
+    /// layer hashes, so it's cheap even for many primitives.
     pub fn get_hash(&self) -> u64 {
-        calculate_hash(self)
+        stable_hash(&self.layer_hashes)
     }
-}
 
-impl std::hash::Hash for Layers {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.layers.hash(state);
+    /// Canonical text form of all primitives in drawing order.
+    pub fn dump(&self) -> String {
+        canonical::dump(self.layers.values().flatten())
     }
 }
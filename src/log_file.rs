@@ -0,0 +1,182 @@
+//! Log file in the data directory that mirrors console output and log records.
+//!
+//! Output to stdout and stderr is lost when the app is launched from a desktop
+//! environment, so users can attach this file to issues instead. The log of the
+//! previous sessions is kept in numbered files, e.g. `rusty-path-of-building.1.log`.
+
+use log::{LevelFilter, Log, Metadata, Record};
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::Instant,
+};
+
+const FILE_NAME: &str = "rusty-path-of-building";
+/// The log is rotated once it grows larger
+const MAX_FILE_SIZE: u64 = 4 << 20;
+/// Number of rotated files that are kept
+const MAX_ROTATED_FILES: usize = 3;
+
+static LOG_FILE: OnceLock<Mutex<LogFile>> = OnceLock::new();
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    start: Instant,
+}
+
+impl LogFile {
+    fn open(dir: &Path) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{FILE_NAME}.log"));
+        // every session starts with a new file
+        rotate(&path)?;
+        Ok(Self {
+            file: File::create(&path)?,
+            path,
+            size: 0,
+            start: Instant::now(),
+        })
+    }
+
+    fn write(&mut self, prefix: &str, text: &str) -> io::Result<()> {
+        if self.size > MAX_FILE_SIZE {
+            rotate(&self.path)?;
+            self.file = File::create(&self.path)?;
+            self.size = 0;
+        }
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let mut entry = String::new();
+        for line in text.lines() {
+            let _ = writeln!(entry, "[{elapsed:>10.3}] {prefix}{line}");
+        }
+        self.file.write_all(entry.as_bytes())?;
+        self.size += entry.len() as u64;
+        Ok(())
+    }
+}
+
+/// Renames `name.log` to `name.1.log`, `name.1.log` to `name.2.log` and so on.
+/// The oldest file is overwritten.
+fn rotate(path: &Path) -> io::Result<()> {
+    let rotated = |idx: usize| path.with_extension(format!("{idx}.log"));
+    for idx in (1..MAX_ROTATED_FILES).rev() {
+        if rotated(idx).exists() {
+            std::fs::rename(rotated(idx), rotated(idx + 1))?;
+        }
+    }
+    if path.exists() {
+        std::fs::rename(path, rotated(1))?;
+    }
+    Ok(())
+}
+
+/// Writes `text` to the log file, if it was opened.
+fn write(prefix: &str, text: &str) {
+    if let Some(log_file) = LOG_FILE.get()
+        && let Ok(mut log_file) = log_file.lock()
+    {
+        // there's nowhere left to report the error to
+        let _ = log_file.write(prefix, text);
+    }
+}
+
+/// Writes output of `ConPrintf` and similar functions to the log file.
+pub fn write_console(text: &str) {
+    write("", text);
+}
+
+/// Path of the log file, if it could be opened.
+pub fn path() -> Option<PathBuf> {
+    let log_file = LOG_FILE.get()?.lock().ok()?;
+    Some(log_file.path.clone())
+}
+
+/// Logs an error that ends the app, pointing to the log file for bug reports.
+pub fn log_fatal_error(err: &anyhow::Error) {
+    log::error!("{err:#}");
+    if let Some(path) = path() {
+        log::error!("Please attach {} when reporting this error", path.display());
+    }
+}
+
+/// Forwards log records to `env_logger` and writes warnings and errors, as well
+/// as info records of the app, to the log file.
+struct Logger {
+    env_logger: env_logger::Logger,
+}
+
+impl Logger {
+    fn is_logged_to_file(metadata: &Metadata) -> bool {
+        let level = match metadata.target().starts_with(env!("CARGO_CRATE_NAME")) {
+            true => LevelFilter::Info,
+            false => LevelFilter::Warn,
+        };
+        metadata.level() <= level
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.env_logger.enabled(metadata) || Self::is_logged_to_file(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.env_logger.matches(record) {
+            self.env_logger.log(record);
+        }
+        if Self::is_logged_to_file(record.metadata()) {
+            let prefix = format!("{} {}: ", record.level(), record.target());
+            write(&prefix, &record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {
+        self.env_logger.flush();
+    }
+}
+
+/// Installs the logger. Records are printed according to `RUST_LOG` and written
+/// to the log file once it's opened.
+pub fn init_logger() {
+    let env_logger = env_logger::Builder::from_default_env().build();
+    log::set_max_level(env_logger.filter().max(LevelFilter::Info));
+    let _ = log::set_boxed_logger(Box::new(Logger { env_logger }));
+}
+
+/// Opens the log file in `dir`, rotating the logs of previous sessions.
+pub fn open(dir: &Path) {
+    match LogFile::open(dir) {
+        Ok(log_file) => {
+            let _ = LOG_FILE.set(Mutex::new(log_file));
+        }
+        Err(err) => log::warn!("Unable to open log file in {}: {err}", dir.display()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotate() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let path = dir.join("test.log");
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).ok();
+
+        for session in 0..=MAX_ROTATED_FILES {
+            rotate(&path).unwrap();
+            std::fs::write(&path, session.to_string()).unwrap();
+        }
+
+        assert_eq!(read("test.log").as_deref(), Some("3"));
+        assert_eq!(read("test.1.log").as_deref(), Some("2"));
+        assert_eq!(read("test.3.log").as_deref(), Some("0"));
+        assert_eq!(read("test.4.log"), None);
+    }
+}
@@ -1,24 +1,67 @@
+//! Runs PoB's application code and forwards app events to its callbacks.
+//!
+//! Besides the callbacks that PoB's Launch.lua defines (`OnInit`, `OnFrame`,
+//! `OnKeyDown`, ...), PoB can opt in to the following optional callbacks. They
+//! are looked up like the others, on the main object or through `SetCallback`,
+//! and skipped if PoB doesn't define them, so the features behind them do
+//! nothing until PoB adopts them. `tests/fixtures/Launch.lua` implements all of
+//! them and serves as reference.
+//!
+//! - `OnIdle(budget_ms)`: background work while the app is idle, returns `true`
+//!   if there is more work to do
+//! - `OnImageLoadFailed(path, error)`: an image failed to load
+//! - `OnFileChanged(path)`: a path watched with `WatchPath` changed
+//! - `OnOAuthRedirect(code, state, error)`: the redirect of `StartOAuthListener`
+//!   arrived
+//! - `OnProcessExited(id, code, error)`: a process of `SpawnProcess` exited
+//! - `OnClipboardChanged(sequence)`: the clipboard changed after
+//!   `GetClipboardSequence` was called
+//! - `OnThemeChanged(theme)`: the system switched to `"DARK"` or `"LIGHT"`
+
 use crate::{
-    api::{self, get_callback},
+    api::{self, get_callback, get_command, get_command_names},
+    api_trace::ApiTrace,
     app::AppState,
-    args::Args,
+    args::{Game, launch_args, runtime_dir},
+    build_source::BuildSource,
+    clipboard::ClipboardWatcher,
+    config::Config,
+    console::Console,
+    download::Downloads,
+    file_watcher::FileWatcher,
     fonts::Fonts,
+    gfx::{GraphicsCapabilities, GraphicsOptions},
+    http,
     input::InputState,
+    integrity::{IntegrityMode, restrict_native_modules},
     layers::Layers,
+    oauth::{OAuthListener, OAuthRedirect},
+    perf_overlay::PerfOverlay,
     pob::PoBState,
-    renderer::textures::WrappedTextureManager,
+    process::{ProcessExit, ProcessSpawner},
+    renderer::textures::{TextureId, WrappedTextureManager},
     subscript::{NativeMultiValue, SubscriptManager, SubscriptResult, register_subscript_globals},
     util::change_working_directory,
-    window::WindowState,
+    window::{WindowState, theme_name},
 };
-use clap::Parser;
-use mlua::{Function, Lua, Result as LuaResult, Table, ThreadStatus};
+use mlua::{FromLuaMulti, Function, IntoLuaMulti, Lua, Result as LuaResult, Table, ThreadStatus};
 use std::{
     cell::{Cell, RefCell},
     path::PathBuf,
     rc::Rc,
+    time::Duration,
 };
-use winit::keyboard::SmolStr;
+use winit::{keyboard::SmolStr, window::Theme};
+
+/// File name pattern of native modules in `package.cpath`
+#[cfg(target_os = "windows")]
+const NATIVE_MODULE_PATTERN: &str = "?.dll";
+#[cfg(not(target_os = "windows"))]
+const NATIVE_MODULE_PATTERN: &str = "?.so";
+
+/// Build codes passed with `--build` are written to this file in the data
+/// directory, so that PoB can open them like a build file
+const LAUNCH_BUILD_FILE: &str = "launch-build.xml";
 
 macro_rules! ctx_accessor {
     ($field:ident: & $ty:ty) => {
@@ -54,6 +97,16 @@ pub struct Context {
     needs_restart: Cell<*mut bool>,
     should_exit: Cell<*mut bool>,
     is_dpi_aware: Cell<*mut bool>,
+    file_watcher: Cell<*mut FileWatcher>,
+    oauth_listener: Cell<*mut OAuthListener>,
+    downloads: Cell<*mut Downloads>,
+    process_spawner: Cell<*mut ProcessSpawner>,
+    clipboard_watcher: Cell<*mut ClipboardWatcher>,
+    graphics_options: Cell<*mut GraphicsOptions>,
+    config: Cell<*mut Config>,
+    graphics_capabilities: Cell<*const GraphicsCapabilities>,
+    console: Cell<*mut Console>,
+    perf_overlay: Cell<*mut PerfOverlay>,
 }
 
 impl Context {
@@ -69,6 +122,16 @@ impl Context {
             needs_restart: Cell::new(std::ptr::null_mut()),
             should_exit: Cell::new(std::ptr::null_mut()),
             is_dpi_aware: Cell::new(std::ptr::null_mut()),
+            file_watcher: Cell::new(std::ptr::null_mut()),
+            oauth_listener: Cell::new(std::ptr::null_mut()),
+            downloads: Cell::new(std::ptr::null_mut()),
+            process_spawner: Cell::new(std::ptr::null_mut()),
+            clipboard_watcher: Cell::new(std::ptr::null_mut()),
+            graphics_options: Cell::new(std::ptr::null_mut()),
+            config: Cell::new(std::ptr::null_mut()),
+            graphics_capabilities: Cell::new(std::ptr::null()),
+            console: Cell::new(std::ptr::null_mut()),
+            perf_overlay: Cell::new(std::ptr::null_mut()),
         }))
     }
 
@@ -84,6 +147,17 @@ impl Context {
         self.needs_restart.set(&mut ctx.pob.needs_restart);
         self.should_exit.set(&mut ctx.app.should_exit);
         self.is_dpi_aware.set(&mut ctx.pob.is_dpi_aware);
+        self.file_watcher.set(&mut ctx.pob.file_watcher);
+        self.oauth_listener.set(&mut ctx.pob.oauth_listener);
+        self.downloads.set(&mut ctx.pob.downloads);
+        self.process_spawner.set(&mut ctx.pob.process_spawner);
+        self.clipboard_watcher.set(&mut ctx.pob.clipboard_watcher);
+        self.graphics_options.set(&mut ctx.app.graphics_options);
+        self.config.set(&mut ctx.app.config);
+        self.graphics_capabilities
+            .set(&ctx.app.graphics_capabilities);
+        self.console.set(&mut ctx.pob.console);
+        self.perf_overlay.set(&mut ctx.app.perf_overlay);
     }
 
     pub fn clear(&self) {
@@ -97,6 +171,16 @@ impl Context {
         self.needs_restart.set(std::ptr::null_mut());
         self.should_exit.set(std::ptr::null_mut());
         self.is_dpi_aware.set(std::ptr::null_mut());
+        self.file_watcher.set(std::ptr::null_mut());
+        self.oauth_listener.set(std::ptr::null_mut());
+        self.downloads.set(std::ptr::null_mut());
+        self.process_spawner.set(std::ptr::null_mut());
+        self.clipboard_watcher.set(std::ptr::null_mut());
+        self.graphics_options.set(std::ptr::null_mut());
+        self.config.set(std::ptr::null_mut());
+        self.graphics_capabilities.set(std::ptr::null());
+        self.console.set(std::ptr::null_mut());
+        self.perf_overlay.set(std::ptr::null_mut());
     }
 
     ctx_accessor!(window: &mut WindowState);
@@ -109,6 +193,16 @@ impl Context {
     ctx_accessor!(needs_restart: &mut bool);
     ctx_accessor!(should_exit: &mut bool);
     ctx_accessor!(is_dpi_aware: &mut bool);
+    ctx_accessor!(file_watcher: &mut FileWatcher);
+    ctx_accessor!(oauth_listener: &mut OAuthListener);
+    ctx_accessor!(downloads: &mut Downloads);
+    ctx_accessor!(process_spawner: &mut ProcessSpawner);
+    ctx_accessor!(clipboard_watcher: &mut ClipboardWatcher);
+    ctx_accessor!(graphics_options: &mut GraphicsOptions);
+    ctx_accessor!(config: &mut Config);
+    ctx_accessor!(graphics_capabilities: &GraphicsCapabilities);
+    ctx_accessor!(console: &mut Console);
+    ctx_accessor!(perf_overlay: &mut PerfOverlay);
 }
 
 pub enum PoBEvent {
@@ -126,6 +220,17 @@ pub enum PoBEvent {
         id: u64,
         error: String,
     },
+    ImageLoaded(TextureId),
+    ImageLoadFailed {
+        id: Option<TextureId>,
+        image_path: String,
+        error: String,
+    },
+    FileChanged(String),
+    OAuthRedirect(OAuthRedirect),
+    ProcessExited(ProcessExit),
+    ClipboardChanged(u64),
+    ThemeChanged(Theme),
 }
 
 impl std::fmt::Display for PoBEvent {
@@ -139,6 +244,13 @@ impl std::fmt::Display for PoBEvent {
             PoBEvent::Char(_) => write!(f, "Char"),
             PoBEvent::SubFinished { .. } => write!(f, "SubFinished"),
             PoBEvent::SubError { .. } => write!(f, "SubError"),
+            PoBEvent::ImageLoaded(_) => write!(f, "ImageLoaded"),
+            PoBEvent::ImageLoadFailed { .. } => write!(f, "ImageLoadFailed"),
+            PoBEvent::FileChanged(_) => write!(f, "FileChanged"),
+            PoBEvent::OAuthRedirect(_) => write!(f, "OAuthRedirect"),
+            PoBEvent::ProcessExited(_) => write!(f, "ProcessExited"),
+            PoBEvent::ClipboardChanged(_) => write!(f, "ClipboardChanged"),
+            PoBEvent::ThemeChanged(_) => write!(f, "ThemeChanged"),
         }
     }
 }
@@ -161,38 +273,78 @@ impl<'a> PoBContext<'a> {
 pub struct LuaInstance {
     lua: Lua,
     subscript_manager: Rc<RefCell<SubscriptManager>>,
+    // kept across restarts, see `--trace-lua-api`
+    api_trace: Option<Rc<ApiTrace>>,
+    game: Game,
 }
 
 impl LuaInstance {
-    pub fn new(script_dir: &PathBuf) -> anyhow::Result<Self> {
-        let subscript_manager = Rc::new(RefCell::new(SubscriptManager::new(script_dir.to_owned())));
-
-        let lua = Self::create_lua_state(script_dir)?;
+    pub fn new(
+        script_dir: &PathBuf,
+        integrity_mode: IntegrityMode,
+        max_subscripts: usize,
+        game: Game,
+    ) -> anyhow::Result<Self> {
+        let subscript_manager = Rc::new(RefCell::new(SubscriptManager::new(
+            script_dir.to_owned(),
+            integrity_mode,
+            max_subscripts,
+        )));
+
+        let build = launch_args().and_then(|args| {
+            (args.build.clone()).or_else(|| args.import_url.clone().map(BuildSource::Url))
+        });
+        let lua = Self::create_lua_state(script_dir, integrity_mode, game, build.as_ref())?;
         register_subscript_globals(&lua, &subscript_manager)?;
 
+        let api_trace = launch_args()
+            .is_some_and(|args| args.trace_lua_api)
+            .then(|| Rc::new(ApiTrace::default()));
+        if let Some(api_trace) = &api_trace {
+            api_trace.wrap_globals(&lua)?;
+        }
+
         Ok(Self {
             lua,
             subscript_manager,
+            api_trace,
+            game,
         })
     }
 
-    fn create_lua_state(script_dir: &PathBuf) -> LuaResult<Lua> {
+    fn create_lua_state(
+        script_dir: &PathBuf,
+        integrity_mode: IntegrityMode,
+        game: Game,
+        build: Option<&BuildSource>,
+    ) -> LuaResult<Lua> {
         // SAFETY: use `unsafe_new` to allow loading of C modules
         let lua = unsafe { Lua::unsafe_new() };
 
-        // expose import url to lua
-        let args = Args::parse();
-        let args_table = lua.create_sequence_from(std::iter::once(args.import_url))?;
+        // PoB's Main opens the link or build file in `arg[1]`
+        let launch_arg = build
+            .map(|build| build_launch_arg(build, game))
+            .transpose()
+            .map_err(mlua::Error::external)?;
+        let args_table = lua.create_sequence_from(launch_arg)?;
+        // `arg.build` describes the build in `arg[1]`
+        if let Some(build) = build {
+            args_table.set("build", build_table(&lua, build)?)?;
+        }
+        // lets PoB skip update checks and other requests that would fail. Launching
+        // doesn't wait for the check, PoB can call `IsOnline()` if it's still running
+        args_table.set("offline", http::online_status() == Some(false))?;
         lua.globals().set("arg", args_table)?;
 
         Self::register_package_paths(&lua, script_dir)?;
+        restrict_native_modules(&lua, script_dir, integrity_mode)?;
 
         // register context
         let ctx = Context::new();
         lua.set_app_data(ctx);
 
         // register callbacks
-        api::register_globals(&lua)?;
+        api::register_globals(&lua, game)?;
 
         Ok(lua)
     }
@@ -210,9 +362,21 @@ impl LuaInstance {
         Ok(())
     }
 
-    pub fn restart(&mut self, ctx: &mut PoBContext) -> LuaResult<()> {
-        self.lua = Self::create_lua_state(&ctx.app.script_dir)?;
+    /// Restarts PoB, which opens `build` on startup like a build passed with
+    /// `--build`.
+    pub fn restart(&mut self, ctx: &mut PoBContext, build: Option<&BuildSource>) -> LuaResult<()> {
+        // subscripts of the old state would report to a state that doesn't know them
+        self.subscript_manager.borrow_mut().abort_all();
+        self.lua = Self::create_lua_state(
+            &ctx.app.script_dir,
+            ctx.app.integrity_mode,
+            self.game,
+            build,
+        )?;
         register_subscript_globals(&self.lua, &self.subscript_manager)?;
+        if let Some(api_trace) = &self.api_trace {
+            api_trace.wrap_globals(&self.lua)?;
+        }
         self.launch(ctx)?;
         Ok(())
     }
@@ -276,6 +440,47 @@ impl LuaInstance {
         can_exit
     }
 
+    /// Lets PoB do background work while the app is idle. `budget` is the time PoB
+    /// should spend before returning. Returns whether PoB has more work to do.
+    pub fn idle(&self, budget: Duration, pob_ctx: &mut PoBContext) -> LuaResult<bool> {
+        profiling::scope!("idle");
+
+        let ctx = self.lua.app_data_ref::<&'static Context>().unwrap();
+        ctx.set(pob_ctx);
+
+        let has_more_work = call_optional_callback::<Option<bool>>(
+            &self.lua,
+            "OnIdle",
+            budget.as_secs_f64() * 1000.0,
+        )
+        .map(|has_more_work| has_more_work.flatten().unwrap_or_default());
+
+        ctx.clear();
+        has_more_work
+    }
+
+    /// Groups the recorded Lua API calls into frames, see `--trace-lua-api`.
+    pub fn end_trace_frame(&self) {
+        if let Some(api_trace) = &self.api_trace {
+            api_trace.end_frame();
+        }
+    }
+
+    /// Names of commands registered with `RegisterCommand`.
+    pub fn command_names(&self) -> Vec<String> {
+        get_command_names(&self.lua).unwrap_or_default()
+    }
+
+    pub fn run_command(&self, name: &str, pob_ctx: &mut PoBContext) -> LuaResult<()> {
+        profiling::scope!("run_command", name);
+
+        let ctx = self.lua.app_data_ref::<&'static Context>().unwrap();
+        ctx.set(pob_ctx);
+        let result = get_command(&self.lua, name).and_then(|command| command.call::<()>(()));
+        ctx.clear();
+        result
+    }
+
     pub fn handle_event(&self, event: PoBEvent, pob_ctx: &mut PoBContext) -> LuaResult<()> {
         profiling::scope!("handle_event", format!("{}", event));
 
@@ -283,6 +488,8 @@ impl LuaInstance {
         let ctx = self.lua.app_data_ref::<&'static Context>().unwrap();
         ctx.set(pob_ctx);
 
+        let is_frame = matches!(event, PoBEvent::Frame);
+
         // Call event handler in PoB application code
         let handler_result = match event {
             PoBEvent::Init => get_callback(&self.lua, "OnInit")?.call::<()>(()),
@@ -299,11 +506,61 @@ impl LuaInstance {
             PoBEvent::SubError { id, error } => {
                 get_callback(&self.lua, "OnSubError")?.call::<()>((id, error))
             }
+            PoBEvent::ImageLoaded(id) => api::call_load_callback(&self.lua, id, None),
+            PoBEvent::ImageLoadFailed {
+                id,
+                image_path,
+                error,
+            } => id
+                .map_or(Ok(()), |id| {
+                    api::call_load_callback(&self.lua, id, Some(error.clone()))
+                })
+                .and_then(|()| {
+                    call_optional_callback::<()>(
+                        &self.lua,
+                        "OnImageLoadFailed",
+                        (image_path, error),
+                    )
+                })
+                .map(drop),
+            PoBEvent::FileChanged(path) => {
+                call_optional_callback::<()>(&self.lua, "OnFileChanged", path).map(drop)
+            }
+            PoBEvent::OAuthRedirect(redirect) => call_optional_callback::<()>(
+                &self.lua,
+                "OnOAuthRedirect",
+                (redirect.code, redirect.state, redirect.error),
+            )
+            .map(|called| {
+                if called.is_none() {
+                    log::warn!("Ignoring OAuth redirect: PoB doesn't define OnOAuthRedirect");
+                }
+            }),
+            PoBEvent::ProcessExited(exit) => call_optional_callback::<()>(
+                &self.lua,
+                "OnProcessExited",
+                (exit.id, exit.code, exit.error),
+            )
+            .map(drop),
+            PoBEvent::ClipboardChanged(sequence) => {
+                call_optional_callback::<()>(&self.lua, "OnClipboardChanged", sequence).map(drop)
+            }
+            PoBEvent::ThemeChanged(theme) => {
+                call_optional_callback::<()>(&self.lua, "OnThemeChanged", theme_name(theme))
+                    .map(drop)
+            }
         };
 
         // "Unplug" references from context
         ctx.clear();
 
+        // subscripts waiting on the main instance would otherwise never return
+        if is_frame && let Err(err) = &handler_result {
+            self.subscript_manager
+                .borrow_mut()
+                .cancel_blocking_calls(&format!("PoB frame failed: {err}"));
+        }
+
         handler_result
     }
 
@@ -315,12 +572,71 @@ impl LuaInstance {
         package_path.push_str(script_dir.join("lua/?.lua").to_str().unwrap());
         package_path.push(';');
         package_path.push_str(script_dir.join("lua/?/init.lua").to_str().unwrap());
+
+        // native modules downloaded by the installer, used if they aren't installed
+        // system-wide
+        let runtime_dir = runtime_dir();
+        package_path.push(';');
+        package_path.push_str(runtime_dir.join("?.lua").to_str().unwrap());
+        package_path.push(';');
+        package_path.push_str(runtime_dir.join("?/init.lua").to_str().unwrap());
         package.set("path", package_path)?;
 
+        let mut package_cpath: String = package.get("cpath")?;
+        package_cpath.push(';');
+        package_cpath.push_str(runtime_dir.join(NATIVE_MODULE_PATTERN).to_str().unwrap());
+        package.set("cpath", package_cpath)?;
+
         Ok(())
     }
 }
 
+impl Drop for LuaInstance {
+    fn drop(&mut self) {
+        if let Some(api_trace) = &self.api_trace {
+            eprint!("{}", api_trace.summary());
+        }
+    }
+}
+
+/// Calls one of the optional callbacks listed in the module documentation.
+/// Returns `None` if PoB doesn't define it.
+fn call_optional_callback<R: FromLuaMulti>(
+    lua: &Lua,
+    name: &str,
+    args: impl IntoLuaMulti,
+) -> LuaResult<Option<R>> {
+    match get_callback(lua, name) {
+        Ok(callback) => callback.call(args).map(Some),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Returns the value of `arg[1]` for a build passed with `--build` or opened while
+/// running. Decoded build codes are written to a file in the data directory first.
+fn build_launch_arg(build: &BuildSource, game: Game) -> std::io::Result<String> {
+    let path = match build {
+        BuildSource::Url(url) => return Ok(url.clone()),
+        BuildSource::File { path, .. } => path.clone(),
+        BuildSource::Xml(xml) => {
+            let data_dir = game.data_dir();
+            std::fs::create_dir_all(&data_dir)?;
+            let path = data_dir.join(LAUNCH_BUILD_FILE);
+            std::fs::write(&path, xml)?;
+            path
+        }
+    };
+    Ok(path.to_string_lossy().into_owned())
+}
+
+/// Describes a build passed to PoB, e.g. `{ type = "xml", value = "<PathOfBuilding>..." }`
+fn build_table(lua: &Lua, build: &BuildSource) -> LuaResult<Table> {
+    let table = lua.create_table()?;
+    table.set("type", build.kind())?;
+    table.set("value", build.value())?;
+    Ok(table)
+}
+
 impl std::ops::Deref for LuaInstance {
     type Target = Lua;
     fn deref(&self) -> &Self::Target {
@@ -1,33 +1,84 @@
-use crate::{app::App, args::Args};
+use crate::{
+    app::App,
+    args::{Args, set_data_dir_override, set_launch_args, set_profile},
+    build_source::BuildSource,
+    config::{Config, config_path},
+    installer::PoBSource,
+    instance::InstanceMessage,
+};
 use clap::Parser;
 use std::path::{Path, PathBuf};
 use winit::event_loop::EventLoop;
 
 mod api;
+mod api_trace;
 mod app;
 mod args;
+mod build_source;
+mod capture;
 mod clipboard;
 mod color;
+mod command_palette;
+mod config;
+mod console;
+mod download;
 mod dpi;
+mod file_watcher;
 mod fonts;
 mod gfx;
+mod headless;
+mod http;
 mod input;
 mod installer;
+mod instance;
+mod integrity;
 mod layers;
+mod log_file;
 mod lua;
 mod math;
 mod mode;
+mod oauth;
+mod perf_overlay;
 mod pob;
+mod process;
+mod profile_select;
+mod protocol;
+mod rate_limit;
 mod renderer;
+mod replay;
+mod settings;
+mod shared_assets;
+mod shortcut;
 mod subscript;
+mod svg;
+mod taskbar;
+#[cfg(feature = "tray")]
+mod tray;
 mod util;
 mod window;
 mod worker_pool;
 
 fn main() -> anyhow::Result<()> {
     profiling::register_thread!("Main Thread");
-    env_logger::init();
+    log_file::init_logger();
 
+    let args = set_launch_args(Args::parse());
+    if let Some(data_dir) = &args.data_dir {
+        set_data_dir_override(data_dir)?;
+    } else if let Some(data_dir) = Config::load(config_path()).get_str(config::DATA_DIR) {
+        set_data_dir_override(Path::new(data_dir))?;
+    }
+    if let Some(profile) = &args.profile {
+        set_profile(profile)?;
+    }
+    http::configure(
+        args.proxy.as_deref(),
+        args.ca_certs.as_deref(),
+        &args.connectivity_url,
+    )?;
+
+    // started after `http::configure`, which modifies the environment and has to
+    // run before any other thread exists
     #[cfg(feature = "profile-with-puffin")]
     let _puffin_server = {
         let server_addr = format!("127.0.0.1:{}", puffin_http::DEFAULT_PORT);
@@ -37,12 +88,76 @@ fn main() -> anyhow::Result<()> {
         server
     };
 
-    let args = Args::parse();
     let script_dir = find_nearby_launch_script();
 
-    let mut app = App::new(args.game, script_dir)?;
+    if let Some(build_path) = &args.headless {
+        let stats = match args.stats.is_empty() {
+            true => headless::DEFAULT_STATS.map(String::from).to_vec(),
+            false => args.stats.clone(),
+        };
+        let script_dir = script_dir.unwrap_or_else(|| args.game.script_dir());
+        return headless::run(&script_dir, build_path, &stats, args.integrity);
+    }
+
+    if let Some(svg_path) = &args.export_svg {
+        let capture_path = args
+            .replay
+            .as_ref()
+            .expect("--export-svg requires --replay");
+        let script_dir = script_dir.unwrap_or_else(|| args.game.script_dir());
+        return svg::export_capture(capture_path, svg_path, &script_dir);
+    }
+
+    if args.register_protocol {
+        return protocol::register_protocol_handler(args.game);
+    }
+
+    if args.repair {
+        return installer::repair(args.game, args.integrity, PoBSource::from_args(args));
+    }
+
+    if args.uninstall {
+        return installer::uninstall(args.game, args.purge, args.yes);
+    }
+
+    // hand over to the running instance instead of opening a second window
+    let data_dir = args.game.data_dir();
+    let mut new_instance = args.new_instance || args.replay.is_some();
+    let message = match (&args.build, &args.import_url) {
+        (Some(BuildSource::Url(url)), _) | (None, Some(url)) => {
+            Some(InstanceMessage::Import(url.clone()))
+        }
+        // only links are forwarded, decoded builds open in a new window
+        (Some(BuildSource::Xml(_) | BuildSource::File { .. }), _) => None,
+        (None, None) => Some(InstanceMessage::Focus),
+    };
+    if !new_instance && let Some(message) = message {
+        match instance::send_to_running_instance(&data_dir, &message) {
+            Some(true) => {
+                log::info!("Passed {message:?} to the running instance");
+                return Ok(());
+            }
+            // e.g. the open build has unsaved changes, the running instance keeps listening
+            Some(false) => {
+                log::info!("The running instance didn't handle {message:?}, opening a new window");
+                new_instance = true;
+            }
+            None => {}
+        }
+    }
+
+    // opened after handing over, which would rotate the running instance's log
+    log_file::open(&data_dir.join("logs"));
+    // checked while the window opens, usually done before Launch.lua runs
+    std::thread::spawn(http::is_online);
 
     let event_loop = EventLoop::with_user_event().build()?;
+    if !new_instance && let Err(err) = instance::listen(&data_dir, event_loop.create_proxy()) {
+        log::warn!("Unable to listen for other instances: {err}");
+    }
+
+    let mut app = App::new(args, script_dir, event_loop.create_proxy())
+        .inspect_err(log_file::log_fatal_error)?;
     event_loop.run_app(&mut app)?;
 
     Ok(())
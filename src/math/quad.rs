@@ -1,4 +1,4 @@
-use crate::math::{Point, Size, Vector};
+use crate::math::{Corners, Point, Rect, Size, Vector};
 use bytemuck::{Pod, Zeroable};
 use euclid::num::Zero;
 use std::fmt;
@@ -101,6 +101,22 @@ where
     }
 }
 
+impl<T, U> From<Rect<T, U>> for Quad<T, U>
+where
+    T: Copy,
+{
+    /// Creates a `Quad` from the corners of a `Rect`, in clockwise order starting at the top left.
+    #[inline]
+    fn from(rect: Rect<T, U>) -> Self {
+        Quad::new(
+            rect.top_left(),
+            rect.top_right(),
+            rect.bottom_right(),
+            rect.bottom_left(),
+        )
+    }
+}
+
 impl<T, U> Quad<T, U>
 where
     T: Zero,
@@ -1,7 +1,14 @@
 use crate::{
-    app::AppState, installer::InstallMode, pob::PoBMode, renderer::primitives::ClippedPrimitive,
+    app::AppState,
+    build_source::BuildSource,
+    installer::InstallMode,
+    pob::PoBMode,
+    profile_select::ProfileSelectMode,
+    renderer::primitives::{ClippedPrimitive, PrimitiveGroup},
+    replay::ReplayMode,
 };
-use winit::{event::MouseButton, keyboard::Key};
+use std::time::Duration;
+use winit::{event::MouseButton, keyboard::Key, window::Theme};
 
 pub enum AppEvent {
     KeyDown {
@@ -18,21 +25,37 @@ pub enum AppEvent {
         button: MouseButton,
     },
     MouseWheel {
+        /// In wheel notches, fractional for trackpads and high-resolution wheels
         delta: f32,
     },
     CharacterInput {
         ch: char,
     },
+    /// A file is dragged over the window, or no longer is
+    FileHovered {
+        is_hovered: bool,
+    },
+    /// The system switched between dark and light mode
+    ThemeChanged {
+        theme: Theme,
+    },
     Exit,
 }
 
 /// Represents the transition to another mode
 pub enum ModeTransition {
+    /// Install assets of the chosen profile, `None` being the default profile
+    Install {
+        profile: Option<String>,
+    },
     PoB,
 }
 
 pub struct ModeFrameOutput {
     pub primitives: Box<dyn Iterator<Item = ClippedPrimitive>>,
+    /// Splits `primitives` into groups. Primitives after the last group are
+    /// treated as changed every frame.
+    pub groups: Vec<PrimitiveGroup>,
     pub can_elide: bool,
     /// Indicates that this should be redrawn again next frame even if user is not interacting with
     /// window
@@ -40,35 +63,73 @@ pub struct ModeFrameOutput {
 }
 
 pub enum AppMode {
+    ProfileSelect(ProfileSelectMode),
     Install(InstallMode),
-    PoB(PoBMode),
+    PoB(Box<PoBMode>),
+    Replay(ReplayMode),
 }
 
 impl AppMode {
     pub fn frame(&mut self, state: &mut AppState) -> anyhow::Result<ModeFrameOutput> {
         match self {
+            AppMode::ProfileSelect(mode) => mode.frame(state),
             AppMode::Install(mode) => mode.frame(state),
             AppMode::PoB(mode) => mode.frame(state),
+            AppMode::Replay(mode) => mode.frame(state),
         }
     }
 
     pub fn update(&mut self, state: &mut AppState) -> anyhow::Result<Option<ModeTransition>> {
         match self {
+            AppMode::ProfileSelect(mode) => mode.update(state),
             AppMode::Install(mode) => mode.update(state),
             AppMode::PoB(mode) => mode.update(state),
+            AppMode::Replay(mode) => mode.update(state),
         }
     }
 
     pub fn handle_event(&mut self, state: &mut AppState, event: AppEvent) -> anyhow::Result<()> {
         match self {
+            AppMode::ProfileSelect(mode) => mode.handle_event(state, event),
             AppMode::Install(mode) => mode.handle_event(state, event),
             AppMode::PoB(mode) => mode.handle_event(state, event),
+            AppMode::Replay(mode) => mode.handle_event(state, event),
+        }
+    }
+
+    /// Opens a build that was opened while the app is running, e.g. a dropped file
+    /// or a link forwarded by another instance. Returns whether PoB was handed the
+    /// build.
+    pub fn open_build(&mut self, state: &mut AppState, build: BuildSource) -> bool {
+        match self {
+            AppMode::ProfileSelect(_) | AppMode::Install(_) | AppMode::Replay(_) => {
+                log::warn!("Unable to open a build before PoB is running");
+                false
+            }
+            AppMode::PoB(mode) => mode.open_build(state, build),
+        }
+    }
+
+    /// Runs background work while the app is idle. Returns whether there is more
+    /// work to do.
+    pub fn idle(&mut self, state: &mut AppState, budget: Duration) -> anyhow::Result<bool> {
+        match self {
+            AppMode::ProfileSelect(_) | AppMode::Install(_) | AppMode::Replay(_) => Ok(false),
+            AppMode::PoB(mode) => mode.idle(state, budget),
+        }
+    }
+
+    /// Stops background threads that use the window's display connection, which
+    /// is closed together with the event loop.
+    pub fn exiting(&mut self) {
+        if let AppMode::PoB(mode) = self {
+            mode.exiting();
         }
     }
 
     pub fn can_exit(&mut self, state: &mut AppState) -> bool {
         match self {
-            AppMode::Install(_) => true,
+            AppMode::ProfileSelect(_) | AppMode::Install(_) | AppMode::Replay(_) => true,
             AppMode::PoB(mode) => mode.can_exit(state),
         }
     }
@@ -0,0 +1,250 @@
+//! Localhost listener that captures the redirect at the end of an OAuth
+//! authorization flow, e.g. to import characters and stashes from a GGG account.
+//!
+//! PoB starts it with `StartOAuthListener(port)` before opening the authorization
+//! page in the browser. The first request that carries a `code` or `error` query
+//! parameter is answered with a page that tells the user to return to PoB, passed
+//! to PoB's optional `OnOAuthRedirect(code, state, error)` callback and ends the
+//! listener. Listeners that don't receive a redirect stop after a timeout.
+
+use crate::{
+    app::{EventProxy, UserEvent},
+    util::percent_decode,
+};
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, Sender, channel},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Listeners stop if the user doesn't finish the authorization within this time
+const LISTEN_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+/// Longer request lines are rejected
+const MAX_REQUEST_LINE_LENGTH: u64 = 8 * 1024;
+
+const SUCCESS_PAGE: &str = "<!DOCTYPE html><html><body><h3>Authorization complete</h3>\
+    <p>You can close this tab and return to Path of Building.</p></body></html>";
+const ERROR_PAGE: &str = "<!DOCTYPE html><html><body><h3>Authorization failed</h3>\
+    <p>Return to Path of Building for details.</p></body></html>";
+
+/// Query parameters of the redirect
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct OAuthRedirect {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+}
+
+impl OAuthRedirect {
+    /// Parses the target of the request line, e.g. `/callback?code=abc&state=xyz`.
+    /// Returns `None` for requests that aren't redirects, like the browser
+    /// requesting `/favicon.ico`.
+    fn parse(target: &str) -> Option<Self> {
+        let (_, query) = target.split_once('?')?;
+        let mut redirect = Self::default();
+        for pair in query.split('&') {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = form_decode(value);
+            match form_decode(key).as_str() {
+                "code" => redirect.code = Some(value),
+                "state" => redirect.state = Some(value),
+                "error" => redirect.error = Some(value),
+                _ => {}
+            }
+        }
+        (redirect.code.is_some() || redirect.error.is_some()).then_some(redirect)
+    }
+}
+
+/// Decodes a key or value of a query in the `application/x-www-form-urlencoded`
+/// format, in which `+` is a space.
+fn form_decode(text: &str) -> String {
+    let text = text.replace('+', " ");
+    String::from_utf8_lossy(&percent_decode(text.as_bytes())).into_owned()
+}
+
+pub struct OAuthListener {
+    // set while a listener thread is running
+    stop: Option<Arc<AtomicBool>>,
+    thread: Option<JoinHandle<()>>,
+    sender: Sender<OAuthRedirect>,
+    receiver: Receiver<OAuthRedirect>,
+    // wakes up the event loop, which might be idle
+    proxy: EventProxy,
+}
+
+impl OAuthListener {
+    pub fn new(proxy: EventProxy) -> Self {
+        let (sender, receiver) = channel();
+        Self {
+            stop: None,
+            thread: None,
+            sender,
+            receiver,
+            proxy,
+        }
+    }
+
+    /// Listens on `port` on localhost, 0 picks a free port. Replaces a running
+    /// listener. Returns the port, which is part of the redirect URI.
+    ///
+    /// The socket bound here is the one that receives the redirect, so no other
+    /// process can take the port in between.
+    pub fn start(&mut self, port: u16) -> io::Result<u16> {
+        // the running listener has to release its port first, PoB might reuse it
+        self.stop();
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        listener.set_nonblocking(true)?;
+        let port = listener.local_addr()?.port();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+        let sender = self.sender.clone();
+        let proxy = self.proxy.clone();
+        let thread = thread::Builder::new()
+            .name(String::from("OAuth Listener"))
+            .spawn(move || {
+                match listen(&listener, &thread_stop) {
+                    Ok(Some(redirect)) => {
+                        let _ = sender.send(redirect);
+                        let _ = proxy.send_event(UserEvent::OAuthRedirect);
+                    }
+                    Ok(None) => log::info!("OAuth listener on port {port} stopped"),
+                    Err(err) => log::warn!("OAuth listener on port {port} failed: {err}"),
+                }
+                thread_stop.store(true, Ordering::Relaxed);
+            })?;
+        self.stop = Some(stop);
+        self.thread = Some(thread);
+
+        log::info!("Listening for OAuth redirects on port {port}");
+        Ok(port)
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            stop.store(true, Ordering::Relaxed);
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+
+    pub fn is_listening(&self) -> bool {
+        self.stop
+            .as_ref()
+            .is_some_and(|stop| !stop.load(Ordering::Relaxed))
+    }
+
+    /// Redirects received since the last call
+    pub fn take_redirects(&mut self) -> Vec<OAuthRedirect> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+impl Drop for OAuthListener {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Accepts connections until a redirect arrives, `stop` is set or the listener
+/// times out.
+fn listen(listener: &TcpListener, stop: &AtomicBool) -> io::Result<Option<OAuthRedirect>> {
+    let deadline = Instant::now() + LISTEN_TIMEOUT;
+    while !stop.load(Ordering::Relaxed) && Instant::now() < deadline {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        match handle_connection(stream) {
+            Ok(Some(redirect)) => return Ok(Some(redirect)),
+            Ok(None) => {}
+            Err(err) => log::warn!("Invalid request to OAuth listener: {err}"),
+        }
+    }
+    Ok(None)
+}
+
+fn handle_connection(mut stream: TcpStream) -> io::Result<Option<OAuthRedirect>> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+    let mut request_line = String::new();
+    BufReader::new((&mut stream).take(MAX_REQUEST_LINE_LENGTH)).read_line(&mut request_line)?;
+    let target = match request_line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["GET", target, _] => target,
+        _ => return Err(io::Error::other("expected a GET request")),
+    };
+
+    let redirect = OAuthRedirect::parse(target);
+    let (status, body) = match &redirect {
+        Some(OAuthRedirect { error: None, .. }) => ("200 OK", SUCCESS_PAGE),
+        Some(_) => ("200 OK", ERROR_PAGE),
+        None => ("404 Not Found", ""),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/html; charset=utf-8\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    Ok(redirect)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_redirect() {
+        assert_eq!(
+            OAuthRedirect::parse("/callback?code=a%2Bb&state=xyz&scope=account"),
+            Some(OAuthRedirect {
+                code: Some(String::from("a+b")),
+                state: Some(String::from("xyz")),
+                error: None,
+            })
+        );
+        assert_eq!(
+            OAuthRedirect::parse("/?error=access_denied"),
+            Some(OAuthRedirect {
+                error: Some(String::from("access_denied")),
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            OAuthRedirect::parse("/?%65rror=invalid_scope&error+description=x&state=a+b%2B"),
+            Some(OAuthRedirect {
+                state: Some(String::from("a b+")),
+                error: Some(String::from("invalid_scope")),
+                ..Default::default()
+            })
+        );
+        assert_eq!(OAuthRedirect::parse("/favicon.ico"), None);
+        assert_eq!(OAuthRedirect::parse("/?state=xyz"), None);
+    }
+
+    #[test]
+    fn test_restart_on_same_port() {
+        let mut listener = OAuthListener::new(EventProxy::disconnected());
+        let port = listener.start(0).unwrap();
+        assert_eq!(listener.start(port).unwrap(), port);
+        assert!(listener.is_listening());
+        listener.stop();
+        assert!(!listener.is_listening());
+    }
+}
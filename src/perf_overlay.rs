@@ -0,0 +1,155 @@
+//! Overlay with frame timings, shown with `--perf-overlay`, `Ctrl+Shift+F` or the
+//! command palette. Helps users report performance problems.
+//!
+//! CPU time covers the whole frame up to the submission of the GPU work, of which
+//! PoB's `OnFrame` and the tessellation of the draw primitives are shown
+//! separately. GPU time is measured with timestamp queries and is missing if the
+//! adapter doesn't support them. Texture memory is estimated from the uploaded
+//! images, see `--texture-budget`.
+
+use crate::{
+    color::Srgba,
+    dpi::{LogicalPoint, LogicalRect, LogicalSize},
+    fonts::{FontStyle, Fonts, LayoutJob},
+    renderer::{
+        primitives::{ClippedPrimitive, DrawPrimitive, RectPrimitive, TextPrimitive},
+        textures::TextureMemory,
+    },
+};
+use parley::{FontFamily, GenericFamily};
+use std::{fmt::Write, time::Duration};
+use winit::keyboard::{Key, ModifiersState};
+
+const FONT_SIZE: f32 = 14.0;
+const LINE_HEIGHT: f32 = 16.0;
+const PADDING: f32 = 6.0;
+const WIDTH: f32 = 190.0;
+const BACKGROUND_COLOR: Srgba = Srgba::new(0, 0, 0, 200);
+
+/// Weight of the latest sample in the displayed averages
+const SMOOTHING: f64 = 0.1;
+
+#[derive(Clone, Copy)]
+pub enum Timing {
+    /// Time between the start of two frames
+    Interval,
+    /// CPU time of a frame
+    Cpu,
+    /// PoB's `OnFrame`
+    Lua,
+    Tessellation,
+    Gpu,
+}
+
+#[derive(Default)]
+pub struct PerfOverlay {
+    pub is_visible: bool,
+    // exponential moving averages in milliseconds, indexed by `Timing`
+    averages: [Option<f64>; 5],
+    /// GPU memory used by textures as of the last rendered frame
+    pub texture_memory: TextureMemory,
+}
+
+impl PerfOverlay {
+    pub fn new(is_visible: bool) -> Self {
+        Self {
+            is_visible,
+            ..Default::default()
+        }
+    }
+
+    /// Whether `key` is the shortcut that shows and hides the overlay.
+    pub fn is_toggle_shortcut(key: &Key, modifiers: ModifiersState) -> bool {
+        matches!(key, Key::Character(ch) if ch.eq_ignore_ascii_case("f"))
+            && modifiers.shift_key()
+            && (modifiers.control_key() || modifiers.super_key())
+    }
+
+    pub fn toggle(&mut self) {
+        self.is_visible = !self.is_visible;
+        // averages are stale, since frames were elided while the overlay was hidden
+        self.averages = Default::default();
+    }
+
+    pub fn record(&mut self, timing: Timing, duration: Duration) {
+        if !self.is_visible {
+            return;
+        }
+        let millis = duration.as_secs_f64() * 1000.0;
+        let average = &mut self.averages[timing as usize];
+        *average = Some(match *average {
+            Some(average) => average + (millis - average) * SMOOTHING,
+            None => millis,
+        });
+    }
+
+    fn average(&self, timing: Timing) -> Option<f64> {
+        self.averages[timing as usize]
+    }
+
+    /// Draw primitives of the overlay in the top right corner of the screen.
+    pub fn primitives(
+        &self,
+        fonts: &mut Fonts,
+        screen_size: LogicalSize<f32>,
+        pixels_per_point: f32,
+    ) -> Vec<ClippedPrimitive> {
+        let mut text = String::new();
+        if let Some(interval) = self.average(Timing::Interval) {
+            let _ = writeln!(text, "FPS    {:>8.1}", 1000.0 / interval.max(0.001));
+        }
+        for (label, timing) in [
+            ("CPU", Timing::Cpu),
+            ("Lua", Timing::Lua),
+            ("Tess", Timing::Tessellation),
+            ("GPU", Timing::Gpu),
+        ] {
+            let _ = match self.average(timing) {
+                Some(millis) => writeln!(text, "{label:<6} {millis:>6.2} ms"),
+                None => writeln!(text, "{label:<6} {:>9}", "n/a"),
+            };
+        }
+        let memory = self.texture_memory;
+        let _ = writeln!(
+            text,
+            "Tex    {:>6.1} MB\n{:>6} ({} evicted)",
+            memory.bytes as f64 / (1 << 20) as f64,
+            memory.texture_count,
+            memory.evicted_count,
+        );
+        let line_count = text.lines().count();
+
+        let mut job = LayoutJob::new(
+            FontFamily::Generic(GenericFamily::Monospace),
+            FONT_SIZE,
+            LINE_HEIGHT,
+            None,
+            None,
+            FontStyle::Normal,
+        );
+        job.append(text.trim_end(), Srgba::WHITE);
+        let layout = fonts.layout(job, pixels_per_point);
+
+        let clip_rect = LogicalRect::from_size(screen_size);
+        let background = LogicalRect::from_origin_and_size(
+            LogicalPoint::new(screen_size.width - WIDTH, 0.0),
+            LogicalSize::new(WIDTH, LINE_HEIGHT * line_count as f32 + 2.0 * PADDING),
+        );
+        let text_pos = LogicalPoint::new(background.min.x + PADDING, background.min.y + PADDING);
+
+        vec![
+            ClippedPrimitive {
+                clip_rect,
+                primitive: DrawPrimitive::Rect(RectPrimitive::new(
+                    background,
+                    BACKGROUND_COLOR,
+                    None,
+                )),
+            },
+            ClippedPrimitive {
+                clip_rect,
+                primitive: DrawPrimitive::Text(TextPrimitive::new(text_pos, layout)),
+            },
+        ]
+    }
+}
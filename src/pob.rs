@@ -1,18 +1,52 @@
 use crate::{
     app::AppState,
-    dpi::{LogicalRect, LogicalSize},
+    args::{Game, launch_args},
+    build_source::BuildSource,
+    clipboard::ClipboardWatcher,
+    color::Srgba,
+    command_palette::{AppCommand, Command, CommandPalette, PaletteAction},
+    console::Console,
+    download::Downloads,
+    dpi::{LogicalPoint, LogicalRect, LogicalSize, NormalizedRect, Uv},
+    file_watcher::FileWatcher,
+    fonts::{Alignment, FontStyle, LayoutJob},
     input::{key_as_str, mousebutton_as_str},
     layers::Layers,
+    log_file,
     lua::{LuaInstance, PoBContext, PoBEvent},
     mode::{AppEvent, ModeFrameOutput, ModeTransition},
+    oauth::OAuthListener,
+    perf_overlay::{PerfOverlay, Timing},
+    process::ProcessSpawner,
+    settings::{SettingsAction, SettingsOverlay},
 };
-use std::path::PathBuf;
+use parley::{FontFamily, GenericFamily};
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+#[cfg(test)]
+mod integration_tests;
+
+const DROP_HINT_BACKGROUND_COLOR: Srgba = Srgba::new(0, 0, 0, 160);
+
+/// Limits the wheel events sent for a single fast flick on a trackpad.
+const MAX_WHEEL_NOTCHES_PER_EVENT: usize = 10;
 
 pub struct PoBState {
     pub layers: Layers,
     pub current_working_dir: PathBuf,
     pub needs_restart: bool,
+    /// Opened in `arg[1]` on the next restart, see `PoBMode::open_build`
+    pub pending_build: Option<BuildSource>,
     pub is_dpi_aware: bool,
+    pub file_watcher: FileWatcher,
+    pub oauth_listener: OAuthListener,
+    pub downloads: Downloads,
+    pub process_spawner: ProcessSpawner,
+    pub clipboard_watcher: ClipboardWatcher,
+    pub console: Console,
 }
 
 /// Execution mode in which PoB's application code is run.
@@ -23,18 +57,41 @@ pub struct PoBMode {
     lua_instance: LuaInstance,
     state: PoBState,
     previous_layers_hash: u64,
+    // open command palette, captures keyboard input
+    command_palette: Option<CommandPalette>,
+    // open settings overlay, captures keyboard and mouse input
+    settings: Option<SettingsOverlay>,
+    // a file is dragged over the window
+    is_file_hovered: bool,
+    // fraction of a wheel notch that wasn't forwarded yet
+    scroll_remainder: f32,
 }
 
 impl PoBMode {
-    pub fn new(app_state: &mut AppState) -> anyhow::Result<Self> {
+    pub fn new(app_state: &mut AppState, game: Game) -> anyhow::Result<Self> {
         let mut state = PoBState {
             layers: Layers::default(),
             current_working_dir: PathBuf::default(),
             needs_restart: false,
+            pending_build: None,
             is_dpi_aware: false,
+            file_watcher: FileWatcher::new(app_state.event_proxy.clone()),
+            oauth_listener: OAuthListener::new(app_state.event_proxy.clone()),
+            downloads: Downloads::new(app_state.event_proxy.clone()),
+            process_spawner: ProcessSpawner::new(
+                app_state.event_proxy.clone(),
+                launch_args().and_then(|args| args.editor.clone()),
+            ),
+            clipboard_watcher: ClipboardWatcher::new(app_state.event_proxy.clone()),
+            console: Console::default(),
         };
 
-        let lua_instance = LuaInstance::new(&app_state.script_dir)?;
+        let lua_instance = LuaInstance::new(
+            &app_state.script_dir,
+            app_state.integrity_mode,
+            app_state.max_subscripts,
+            game,
+        )?;
 
         let mut pob_ctx = PoBContext::new(app_state, &mut state);
         lua_instance.launch(&mut pob_ctx)?;
@@ -44,6 +101,10 @@ impl PoBMode {
             lua_instance,
             state,
             previous_layers_hash: Default::default(),
+            command_palette: None,
+            settings: None,
+            is_file_hovered: false,
+            scroll_remainder: 0.0,
         })
     }
 
@@ -59,21 +120,102 @@ impl PoBMode {
         // handle subscripts
         self.lua_instance.handle_subscripts(&mut ctx);
 
+        // notify PoB about finished async image loads and images that failed to load
+        for id in ctx.app.texture_manager.take_finished_loads() {
+            self.lua_instance
+                .handle_event(PoBEvent::ImageLoaded(id), &mut ctx)?;
+        }
+        for failure in ctx.app.texture_manager.take_load_failures() {
+            let pob_event = PoBEvent::ImageLoadFailed {
+                id: failure.id,
+                image_path: failure.image_path,
+                error: failure.error,
+            };
+            self.lua_instance.handle_event(pob_event, &mut ctx)?;
+        }
+
+        // notify PoB about changes to watched paths
+        for path in ctx.pob.file_watcher.take_changes() {
+            let path = path.to_string_lossy().into_owned();
+            self.lua_instance
+                .handle_event(PoBEvent::FileChanged(path), &mut ctx)?;
+        }
+
+        // pass the authorization code of finished OAuth flows to PoB
+        for redirect in ctx.pob.oauth_listener.take_redirects() {
+            self.lua_instance
+                .handle_event(PoBEvent::OAuthRedirect(redirect), &mut ctx)?;
+        }
+
+        // report processes started with `SpawnProcess` that have exited
+        for exit in ctx.pob.process_spawner.take_exits() {
+            self.lua_instance
+                .handle_event(PoBEvent::ProcessExited(exit), &mut ctx)?;
+        }
+
+        // let PoB react to copied items without reading the clipboard every frame
+        if let Some(sequence) = ctx.pob.clipboard_watcher.take_change() {
+            self.lua_instance
+                .handle_event(PoBEvent::ClipboardChanged(sequence), &mut ctx)?;
+        }
+
         // run PoB's draw code.
         // this will "fill up" up the layers with draw primitives
+        let lua_start = Instant::now();
         self.lua_instance.handle_event(PoBEvent::Frame, &mut ctx)?;
+        self.lua_instance.end_trace_frame();
+        ctx.app
+            .perf_overlay
+            .record(Timing::Lua, lua_start.elapsed());
+
+        if self.state.console.is_visible {
+            self.state.console.draw(
+                &mut self.state.layers,
+                &mut app_state.fonts,
+                app_state.window.logical_size().cast(),
+                app_state.window.pixels_per_point(),
+            );
+        }
+
+        if let Some(command_palette) = &self.command_palette {
+            command_palette.draw(
+                &mut self.state.layers,
+                &mut app_state.fonts,
+                app_state.window.logical_size().cast(),
+                app_state.window.pixels_per_point(),
+            );
+        }
+
+        if let Some(settings) = &self.settings {
+            settings.draw(&mut self.state.layers, app_state);
+        }
+
+        if self.is_file_hovered {
+            draw_drop_hint(&mut self.state.layers, app_state);
+        }
 
         // check if draw prmitives are identical to primitives from last frame
+        let groups = self.state.layers.groups();
         let layers_hash = self.state.layers.get_hash();
         let identical = layers_hash == self.previous_layers_hash;
         self.previous_layers_hash = layers_hash;
 
+        // dump changed frames with `RUST_LOG=frame_dump=trace`
+        if !identical && log::log_enabled!(target: "frame_dump", log::Level::Trace) {
+            log::trace!(
+                target: "frame_dump",
+                "frame {layers_hash:016x}\n{}",
+                self.state.layers.dump()
+            );
+        }
+
         let has_active_subscript = self.lua_instance.has_running_subscripts();
         let has_active_coroutine = self.lua_instance.has_active_coroutine();
         let should_continue = has_active_subscript || has_active_coroutine;
 
         Ok(ModeFrameOutput {
             primitives: self.state.layers.consume_layers(),
+            groups,
             can_elide: identical,
             should_continue,
         })
@@ -82,7 +224,8 @@ impl PoBMode {
     pub fn update(&mut self, app_state: &mut AppState) -> anyhow::Result<Option<ModeTransition>> {
         if self.state.needs_restart {
             let mut ctx = PoBContext::new(app_state, &mut self.state);
-            self.lua_instance.restart(&mut ctx)?;
+            let build = ctx.pob.pending_build.take();
+            self.lua_instance.restart(&mut ctx, build.as_ref())?;
             self.lua_instance.handle_event(PoBEvent::Init, &mut ctx)?;
             self.state.needs_restart = false;
         }
@@ -94,6 +237,133 @@ impl PoBMode {
         app_state: &mut AppState,
         event: AppEvent,
     ) -> anyhow::Result<()> {
+        if let AppEvent::FileHovered { is_hovered } = event {
+            self.is_file_hovered = is_hovered;
+            return Ok(());
+        }
+
+        // theme changes aren't blocked by open overlays
+        let AppEvent::ThemeChanged { theme } = event else {
+            return self.handle_input_event(app_state, event);
+        };
+        let mut ctx = PoBContext::new(app_state, &mut self.state);
+        self.lua_instance
+            .handle_event(PoBEvent::ThemeChanged(theme), &mut ctx)?;
+        Ok(())
+    }
+
+    /// Restarts PoB with `build` in `arg[1]`, which PoB's Main imports on startup
+    /// like a build passed with `--build`. Unsaved changes are handled like when
+    /// the window is closed, nothing is opened if PoB keeps running.
+    pub fn open_build(&mut self, app_state: &mut AppState, build: BuildSource) -> bool {
+        if !self.can_exit(app_state) {
+            log::warn!("Not opening the build, the current build has unsaved changes");
+            return false;
+        }
+        self.state.pending_build = Some(build);
+        self.state.needs_restart = true;
+        true
+    }
+
+    fn handle_input_event(
+        &mut self,
+        app_state: &mut AppState,
+        event: AppEvent,
+    ) -> anyhow::Result<()> {
+        // the key that is rebound may be one of the shortcuts below
+        if let Some(settings) = &mut self.settings
+            && settings.is_recording()
+            && let AppEvent::KeyDown { key } = &event
+        {
+            settings.handle_key(app_state, key);
+            return Ok(());
+        }
+
+        if let AppEvent::KeyDown { key } = &event
+            && CommandPalette::is_toggle_shortcut(
+                &app_state.config,
+                key,
+                app_state.input.key_modifiers,
+            )
+        {
+            self.command_palette = match self.command_palette {
+                Some(_) => None,
+                None => Some(CommandPalette::new(
+                    self.lua_instance.command_names(),
+                    launch_args().is_some_and(|args| !args.game.profiles().is_empty()),
+                )),
+            };
+            self.settings = None;
+            return Ok(());
+        }
+
+        if let AppEvent::KeyDown { key } = &event
+            && PerfOverlay::is_toggle_shortcut(key, app_state.input.key_modifiers)
+        {
+            app_state.perf_overlay.toggle();
+            return Ok(());
+        }
+
+        if let AppEvent::KeyDown { key } = &event
+            && Console::is_toggle_shortcut(&app_state.config, key, app_state.input.key_modifiers)
+        {
+            self.state.console.toggle();
+            return Ok(());
+        }
+
+        if let AppEvent::KeyDown { key } = &event
+            && SettingsOverlay::is_toggle_shortcut(
+                &app_state.config,
+                key,
+                app_state.input.key_modifiers,
+            )
+        {
+            self.settings = match self.settings {
+                Some(_) => None,
+                None => Some(SettingsOverlay::default()),
+            };
+            self.command_palette = None;
+            return Ok(());
+        }
+
+        if let Some(settings) = &mut self.settings {
+            let action = match event {
+                AppEvent::KeyDown { key } => settings.handle_key(app_state, &key),
+                AppEvent::MouseDown { .. } => settings.handle_click(app_state),
+                AppEvent::Exit => {
+                    self.settings = None;
+                    return self.handle_event(app_state, event);
+                }
+                _ => SettingsAction::None,
+            };
+            if action == SettingsAction::Close {
+                self.settings = None;
+            }
+            return Ok(());
+        }
+
+        if let Some(command_palette) = &mut self.command_palette {
+            match event {
+                AppEvent::KeyDown { key } => match command_palette.handle_key(&key) {
+                    PaletteAction::None => {}
+                    PaletteAction::Close => self.command_palette = None,
+                    PaletteAction::Run(command) => {
+                        self.command_palette = None;
+                        self.run_command(app_state, command)?;
+                    }
+                },
+                AppEvent::CharacterInput { ch } => command_palette.handle_char(ch),
+                // clicking anywhere closes the palette
+                AppEvent::MouseDown { .. } => self.command_palette = None,
+                AppEvent::Exit => {
+                    self.command_palette = None;
+                    return self.handle_event(app_state, event);
+                }
+                _ => {}
+            }
+            return Ok(());
+        }
+
         let mut ctx = PoBContext::new(app_state, &mut self.state);
 
         match event {
@@ -125,16 +395,26 @@ impl PoBMode {
                 }
             }
             AppEvent::MouseWheel { delta } => {
-                if delta > 0.0 {
-                    self.lua_instance
-                        .handle_event(PoBEvent::KeyDown("WHEELUP".into(), false), &mut ctx)?;
-                    self.lua_instance
-                        .handle_event(PoBEvent::KeyUp("WHEELUP".into()), &mut ctx)?;
-                } else if delta < 0.0 {
+                // PoB only knows whole notches. Small deltas from trackpads are
+                // accumulated, large ones are forwarded as several notches.
+                if delta.signum() != self.scroll_remainder.signum() {
+                    self.scroll_remainder = 0.0;
+                }
+                self.scroll_remainder += delta;
+                let notches = self.scroll_remainder.trunc();
+                self.scroll_remainder -= notches;
+
+                let key = if notches > 0.0 {
+                    "WHEELUP"
+                } else {
+                    "WHEELDOWN"
+                };
+                let count = (notches.abs() as usize).min(MAX_WHEEL_NOTCHES_PER_EVENT);
+                for _ in 0..count {
                     self.lua_instance
-                        .handle_event(PoBEvent::KeyDown("WHEELDOWN".into(), false), &mut ctx)?;
+                        .handle_event(PoBEvent::KeyDown(key.into(), false), &mut ctx)?;
                     self.lua_instance
-                        .handle_event(PoBEvent::KeyUp("WHEELDOWN".into()), &mut ctx)?;
+                        .handle_event(PoBEvent::KeyUp(key.into()), &mut ctx)?;
                 }
             }
             AppEvent::CharacterInput { ch } => {
@@ -143,10 +423,64 @@ impl PoBMode {
                     .handle_event(PoBEvent::Char(ch), &mut ctx)?;
             }
             AppEvent::Exit => self.lua_instance.handle_event(PoBEvent::Exit, &mut ctx)?,
+            // handled in `handle_event`
+            AppEvent::FileHovered { .. } | AppEvent::ThemeChanged { .. } => {}
+        }
+        Ok(())
+    }
+
+    pub fn idle(&mut self, app_state: &mut AppState, budget: Duration) -> anyhow::Result<bool> {
+        let mut ctx = PoBContext::new(app_state, &mut self.state);
+        Ok(self.lua_instance.idle(budget, &mut ctx)?)
+    }
+
+    fn run_command(&mut self, app_state: &mut AppState, command: Command) -> anyhow::Result<()> {
+        match command {
+            Command::App(AppCommand::OpenSettings) => {
+                self.settings = Some(SettingsOverlay::default());
+            }
+            Command::App(AppCommand::ReloadTextures) => {
+                app_state.fonts.invalidate_font_atlas();
+                app_state.texture_manager.reload_textures();
+            }
+            Command::App(AppCommand::ToggleAlwaysOnTop) => {
+                app_state.window.toggle_always_on_top();
+            }
+            Command::App(AppCommand::TogglePerfOverlay) => app_state.perf_overlay.toggle(),
+            Command::App(AppCommand::ToggleConsole) => self.state.console.toggle(),
+            Command::App(AppCommand::OpenUserFolder) => {
+                let user_path = app_state.script_dir.join("userdata");
+                if let Err(err) = open::that(&user_path) {
+                    log::error!("Unable to open {}: {err}", user_path.display());
+                }
+            }
+            Command::App(AppCommand::OpenLogFile) => match log_file::path() {
+                Some(path) => {
+                    if let Err(err) = open::that(&path) {
+                        log::error!("Unable to open {}: {err}", path.display());
+                    }
+                }
+                None => log::warn!("No log file is open"),
+            },
+            Command::App(AppCommand::SwitchProfile) => {
+                if self.can_exit(app_state) {
+                    relaunch_with_profile_select()?;
+                    app_state.should_exit = true;
+                }
+            }
+            Command::App(AppCommand::Restart) => self.state.needs_restart = true,
+            Command::Lua(name) => {
+                let mut ctx = PoBContext::new(app_state, &mut self.state);
+                self.lua_instance.run_command(&name, &mut ctx)?;
+            }
         }
         Ok(())
     }
 
+    pub fn exiting(&mut self) {
+        self.state.clipboard_watcher.stop();
+    }
+
     pub fn can_exit(&mut self, app_state: &mut AppState) -> bool {
         let mut ctx = PoBContext::new(app_state, &mut self.state);
         self.lua_instance.can_exit(&mut ctx)
@@ -158,3 +492,55 @@ impl PoBMode {
             .set_viewport(LogicalRect::from_size(size).cast());
     }
 }
+
+/// Starts a new instance with the arguments of this one, except for the profile
+/// and the build to open, so that it shows the profile selector.
+fn relaunch_with_profile_select() -> std::io::Result<()> {
+    let mut args = std::env::args_os().skip(1);
+    let mut kept_args = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--profile" | "--build") => {
+                args.next();
+            }
+            Some(arg)
+                if arg.starts_with("--profile=")
+                    || arg.starts_with("--build=")
+                    || arg.starts_with("pob:")
+                    || arg == "--new-instance" => {}
+            _ => kept_args.push(arg),
+        }
+    }
+    std::process::Command::new(std::env::current_exe()?)
+        .args(kept_args)
+        // the new instance would otherwise hand over to this one
+        .arg("--new-instance")
+        .spawn()?;
+    Ok(())
+}
+
+/// Dims PoB's UI while a file is dragged over the window.
+fn draw_drop_hint(layers: &mut Layers, app_state: &mut AppState) {
+    let screen_size = app_state.window.logical_size().cast::<f32>();
+    let screen_rect = LogicalRect::from_size(screen_size);
+    layers.set_viewport(screen_rect);
+    layers.set_draw_layer(i32::MAX, 0);
+
+    layers.set_draw_color(DROP_HINT_BACKGROUND_COLOR);
+    layers.draw_rect(None, screen_rect, NormalizedRect::default_uv(), 0);
+
+    let mut job = LayoutJob::new(
+        FontFamily::Generic(GenericFamily::SansSerif),
+        32.0,
+        34.0,
+        Some(Alignment::Center),
+        None,
+        FontStyle::Normal,
+    );
+    job.append("Drop to open build", Srgba::WHITE);
+    let layout = app_state
+        .fonts
+        .layout(job, app_state.window.pixels_per_point());
+    let pos = LogicalPoint::new(screen_size.width / 2.0, screen_size.height / 2.0 - 17.0);
+    layers.draw_text(pos, layout, 0.0, true);
+}
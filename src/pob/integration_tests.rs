@@ -0,0 +1,70 @@
+//! Boots the Lua runtime without a window, GPU or event loop and runs a stand-in
+//! for PoB's Launch.lua from `tests/fixtures`. It checks the globals PoB relies on
+//! and calls into the API the way PoB does, so that regressions like a missing
+//! global are caught before release.
+
+use super::*;
+use crate::{args::set_data_dir_override, oauth::OAuthRedirect, process::ProcessExit};
+use tempfile::TempDir;
+use winit::{keyboard::Key, window::Theme};
+
+fn mock_pob_dir() -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    let fixture = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/Launch.lua");
+    std::fs::copy(fixture, dir.path().join("Launch.lua")).unwrap();
+    dir
+}
+
+#[test]
+fn test_mock_pob_runs_headless() {
+    let temp_dir = mock_pob_dir();
+    let script_dir = temp_dir.path().to_path_buf();
+    // keeps the storage database out of the user's data directory
+    set_data_dir_override(&script_dir.join("data")).unwrap();
+    let mut app_state = AppState::headless(script_dir);
+
+    let mut mode = PoBMode::new(&mut app_state, Game::Poe1).unwrap();
+    let output = mode.frame(&mut app_state).unwrap();
+    assert!(output.primitives.count() > 0);
+
+    let key = Key::Character("a".into());
+    mode.handle_event(&mut app_state, AppEvent::KeyDown { key: key.clone() })
+        .unwrap();
+    mode.handle_event(&mut app_state, AppEvent::CharacterInput { ch: 'a' })
+        .unwrap();
+    mode.handle_event(&mut app_state, AppEvent::KeyUp { key })
+        .unwrap();
+    mode.frame(&mut app_state).unwrap();
+
+    // the fixture checks on exit that all optional callbacks were called
+    assert!(!mode.idle(&mut app_state, Duration::from_millis(5)).unwrap());
+    mode.handle_event(
+        &mut app_state,
+        AppEvent::ThemeChanged { theme: Theme::Dark },
+    )
+    .unwrap();
+    let mut ctx = PoBContext::new(&mut app_state, &mut mode.state);
+    for event in [
+        PoBEvent::ImageLoadFailed {
+            id: None,
+            image_path: String::from("missing.png"),
+            error: String::from("not found"),
+        },
+        PoBEvent::FileChanged(String::from("Builds/build.xml")),
+        PoBEvent::OAuthRedirect(OAuthRedirect {
+            code: Some(String::from("code")),
+            state: Some(String::from("state")),
+            error: None,
+        }),
+        PoBEvent::ProcessExited(ProcessExit {
+            id: 1,
+            code: Some(0),
+            error: None,
+        }),
+        PoBEvent::ClipboardChanged(1),
+    ] {
+        mode.lua_instance.handle_event(event, &mut ctx).unwrap();
+    }
+
+    mode.handle_event(&mut app_state, AppEvent::Exit).unwrap();
+}
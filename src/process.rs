@@ -0,0 +1,188 @@
+//! Programs PoB can start with `SpawnProcess(action, path)`, e.g. to show the
+//! builds folder in the file manager. PoB can't run arbitrary commands, only the
+//! actions of `ProcessAction`. The exit status is passed to PoB's optional
+//! `OnProcessExited(id, code, error)` callback.
+
+use crate::app::{EventProxy, UserEvent};
+use anyhow::{Context as _, anyhow, bail};
+use std::{
+    io,
+    path::Path,
+    process::{Command, Stdio},
+    str::FromStr,
+    sync::mpsc::{Receiver, Sender, channel},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProcessAction {
+    /// Show a directory in the file manager
+    OpenFolder,
+    /// Open a file in the editor configured with `--editor`
+    EditFile,
+}
+
+impl FromStr for ProcessAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "OPEN_FOLDER" => Ok(ProcessAction::OpenFolder),
+            "EDIT_FILE" => Ok(ProcessAction::EditFile),
+            _ => Err(anyhow!("Unknown action {s}")),
+        }
+    }
+}
+
+/// A process started by PoB has exited or couldn't be waited on.
+#[derive(Debug)]
+pub struct ProcessExit {
+    pub id: u64,
+    // `None` if the process was terminated by a signal
+    pub code: Option<i32>,
+    pub error: Option<String>,
+}
+
+pub struct ProcessSpawner {
+    // command line of the external editor, see `--editor`
+    editor: Option<String>,
+    next_id: u64,
+    sender: Sender<ProcessExit>,
+    receiver: Receiver<ProcessExit>,
+    // wakes up the event loop, which might be idle
+    proxy: EventProxy,
+}
+
+impl ProcessSpawner {
+    pub fn new(proxy: EventProxy, editor: Option<String>) -> Self {
+        let (sender, receiver) = channel();
+        Self {
+            editor,
+            next_id: 0,
+            sender,
+            receiver,
+            proxy,
+        }
+    }
+
+    /// Starts the program for `action` and returns an id that identifies it in
+    /// `take_exits`. Errors if `path` doesn't fit the action or nothing could be started.
+    pub fn spawn(&mut self, action: ProcessAction, path: &Path) -> anyhow::Result<u64> {
+        let mut last_error = None;
+        let mut child = None;
+        // try the candidates in order, e.g. `xdg-open` and `gio` on Linux
+        for mut command in commands(action, path, self.editor.as_deref())? {
+            match command.stdin(Stdio::null()).spawn() {
+                Ok(spawned) => {
+                    child = Some(spawned);
+                    break;
+                }
+                Err(err) => last_error = Some(err),
+            }
+        }
+        let mut child = child.ok_or_else(|| match last_error {
+            Some(err) if err.kind() != io::ErrorKind::NotFound => anyhow!(err),
+            _ => anyhow!("No program found to handle {}", path.display()),
+        })?;
+
+        let id = self.next_id;
+        self.next_id += 1;
+        log::info!("Started process {id} ({action:?} {})", path.display());
+
+        let sender = self.sender.clone();
+        let proxy = self.proxy.clone();
+        std::thread::Builder::new()
+            .name(format!("Process {id}"))
+            .spawn(move || {
+                let exit = match child.wait() {
+                    Ok(status) => ProcessExit {
+                        id,
+                        code: status.code(),
+                        error: None,
+                    },
+                    Err(err) => ProcessExit {
+                        id,
+                        code: None,
+                        error: Some(err.to_string()),
+                    },
+                };
+                let _ = sender.send(exit);
+                let _ = proxy.send_event(UserEvent::ProcessExited);
+            })?;
+        Ok(id)
+    }
+
+    /// Processes that exited since the last call.
+    pub fn take_exits(&mut self) -> Vec<ProcessExit> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+/// Commands that perform `action`, in the order in which they are tried.
+fn commands(
+    action: ProcessAction,
+    path: &Path,
+    editor: Option<&str>,
+) -> anyhow::Result<Vec<Command>> {
+    match action {
+        ProcessAction::OpenFolder => {
+            if !path.is_dir() {
+                bail!("{} is not a directory", path.display());
+            }
+            Ok(open::commands(path))
+        }
+        ProcessAction::EditFile => {
+            if !path.is_file() {
+                bail!("{} is not a file", path.display());
+            }
+            let editor = editor.context("No editor configured, see --editor")?;
+            let parts = shlex::split(editor)
+                .with_context(|| format!("Unable to parse the editor command {editor}"))?;
+            let mut parts = parts.into_iter();
+            let program = parts.next().context("No editor configured, see --editor")?;
+            let mut command = Command::new(program);
+            command.args(parts).arg(path);
+            Ok(vec![command])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands_check_path_and_editor() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let file = dir.join("build.xml");
+        std::fs::write(&file, "").unwrap();
+
+        assert!(commands(ProcessAction::OpenFolder, dir, None).is_ok());
+        assert!(commands(ProcessAction::OpenFolder, &file, None).is_err());
+        assert!(commands(ProcessAction::EditFile, &file, None).is_err());
+        assert!(commands(ProcessAction::EditFile, dir, Some("editor")).is_err());
+
+        let quoted = commands(
+            ProcessAction::EditFile,
+            &file,
+            Some("'/opt/My Editor/editor' --profile \"Path of Building\""),
+        )
+        .unwrap();
+        let args: Vec<_> = quoted[0].get_args().collect();
+        assert_eq!(quoted[0].get_program(), "/opt/My Editor/editor");
+        assert_eq!(
+            args,
+            [
+                "--profile".as_ref(),
+                "Path of Building".as_ref(),
+                file.as_os_str()
+            ]
+        );
+        assert!(commands(ProcessAction::EditFile, &file, Some("\"unterminated")).is_err());
+
+        let commands = commands(ProcessAction::EditFile, &file, Some("code --wait")).unwrap();
+        let args: Vec<_> = commands[0].get_args().collect();
+        assert_eq!(commands[0].get_program(), "code");
+        assert_eq!(args, ["--wait".as_ref(), file.as_os_str()]);
+    }
+}
@@ -0,0 +1,238 @@
+//! Picker for the profiles created with `--profile`, shown on startup.
+
+use crate::{
+    app::AppState,
+    args::Game,
+    color::Srgba,
+    dpi::{LogicalPoint, LogicalRect, LogicalSize},
+    fonts::{Alignment, FontStyle, LayoutJob},
+    mode::{AppEvent, ModeFrameOutput, ModeTransition},
+    renderer::primitives::{ClippedPrimitive, DrawPrimitive, RectPrimitive, TextPrimitive},
+};
+use parley::{FontFamily, GenericFamily};
+use winit::{
+    event::MouseButton,
+    keyboard::{Key, NamedKey},
+};
+
+const WIDTH: f32 = 400.0;
+const ROW_HEIGHT: f32 = 40.0;
+const FONT_SIZE: f32 = 24.0;
+const LINE_HEIGHT: f32 = 26.0;
+
+const SELECTION_COLOR: Srgba = Srgba::new(60, 80, 120, 255);
+const HINT_COLOR: Srgba = Srgba::new(150, 150, 150, 255);
+
+/// Execution mode that lets the user pick a profile on startup.
+///
+/// Only used if profiles exist and none was passed with `--profile`. Transitions
+/// into install mode for the chosen profile.
+pub struct ProfileSelectMode {
+    // `None` is the default profile
+    profiles: Vec<Option<String>>,
+    selected: usize,
+    chosen: bool,
+}
+
+impl ProfileSelectMode {
+    pub fn new(game: Game) -> Self {
+        let profiles = std::iter::once(None)
+            .chain(game.profiles().into_iter().map(Some))
+            .collect();
+        Self {
+            profiles,
+            selected: 0,
+            chosen: false,
+        }
+    }
+
+    pub fn frame(&mut self, app_state: &mut AppState) -> anyhow::Result<ModeFrameOutput> {
+        let screen_size = app_state.window.logical_size().cast::<f32>();
+        let clip_rect = LogicalRect::from_size(screen_size);
+        let mut primitives = Vec::new();
+
+        let mut push_text = |app_state: &mut AppState, row: isize, text: &str, color: Srgba| {
+            let mut job = LayoutJob::new(
+                FontFamily::Generic(GenericFamily::SansSerif),
+                FONT_SIZE,
+                LINE_HEIGHT,
+                Some(Alignment::Center),
+                None,
+                FontStyle::Normal,
+            );
+            job.append(text, color);
+            let layout = app_state
+                .fonts
+                .layout(job, app_state.window.pixels_per_point());
+            let rect = self.row_rect(screen_size, row);
+            let pos = LogicalPoint::new(
+                rect.center().x,
+                rect.min.y + (ROW_HEIGHT - LINE_HEIGHT) / 2.0,
+            );
+            primitives.push(ClippedPrimitive {
+                clip_rect,
+                primitive: DrawPrimitive::Text(TextPrimitive::new(pos, layout)),
+            });
+        };
+
+        push_text(app_state, -1, "Select a profile", Srgba::WHITE);
+        for (row, profile) in self.profiles.iter().enumerate() {
+            let name = profile.as_deref().unwrap_or("Default");
+            push_text(app_state, row as isize, name, Srgba::WHITE);
+        }
+        push_text(
+            app_state,
+            self.profiles.len() as isize,
+            "Arrow keys and Enter or click to select",
+            HINT_COLOR,
+        );
+
+        // selection is drawn below the text
+        let selection = RectPrimitive::new(
+            self.row_rect(screen_size, self.selected as isize),
+            SELECTION_COLOR,
+            None,
+        );
+        primitives.insert(
+            0,
+            ClippedPrimitive {
+                clip_rect,
+                primitive: DrawPrimitive::Rect(selection),
+            },
+        );
+
+        Ok(ModeFrameOutput {
+            primitives: Box::new(primitives.into_iter()),
+            groups: Vec::new(),
+            can_elide: false,
+            should_continue: false,
+        })
+    }
+
+    pub fn update(&mut self, _app_state: &mut AppState) -> anyhow::Result<Option<ModeTransition>> {
+        if !self.chosen {
+            return Ok(None);
+        }
+        let profile = self.profiles[self.selected].clone();
+        Ok(Some(ModeTransition::Install { profile }))
+    }
+
+    pub fn handle_event(
+        &mut self,
+        app_state: &mut AppState,
+        event: AppEvent,
+    ) -> anyhow::Result<()> {
+        match event {
+            AppEvent::KeyDown { key } => match key {
+                Key::Named(NamedKey::ArrowUp) => {
+                    self.selected = self.selected.saturating_sub(1);
+                }
+                Key::Named(NamedKey::ArrowDown) => {
+                    self.selected = (self.selected + 1).min(self.profiles.len() - 1);
+                }
+                Key::Named(NamedKey::Enter) => self.chosen = true,
+                _ => {}
+            },
+            AppEvent::MouseDown {
+                button: MouseButton::Left,
+                ..
+            } => {
+                let screen_size = app_state.window.logical_size().cast::<f32>();
+                let mouse_pos = app_state.input.mouse_pos();
+                let row = (0..self.profiles.len())
+                    .find(|&row| self.row_rect(screen_size, row as isize).contains(mouse_pos));
+                if let Some(row) = row {
+                    self.selected = row;
+                    self.chosen = true;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Rows are centered on screen. Row -1 holds the title.
+    fn row_rect(&self, screen_size: LogicalSize<f32>, row: isize) -> LogicalRect<f32> {
+        // title, profiles and hint
+        let total_height = ROW_HEIGHT * (self.profiles.len() + 2) as f32;
+        let top = (screen_size.height - total_height) / 2.0 + ROW_HEIGHT * (row + 1) as f32;
+        LogicalRect::from_origin_and_size(
+            LogicalPoint::new((screen_size.width - WIDTH) / 2.0, top),
+            LogicalSize::new(WIDTH, ROW_HEIGHT),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mode() -> ProfileSelectMode {
+        ProfileSelectMode {
+            profiles: vec![None, Some(String::from("beta"))],
+            selected: 0,
+            chosen: false,
+        }
+    }
+
+    fn chosen_profile(mode: &mut ProfileSelectMode, app_state: &mut AppState) -> Option<String> {
+        match mode.update(app_state).unwrap() {
+            Some(ModeTransition::Install { profile }) => profile,
+            _ => panic!("expected a profile to be chosen"),
+        }
+    }
+
+    #[test]
+    fn test_keys_select_profile() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut app_state = AppState::headless(temp_dir.path().to_owned());
+        let mut mode = mode();
+        let press = |mode: &mut ProfileSelectMode, app_state: &mut AppState, key| {
+            let event = AppEvent::KeyDown {
+                key: Key::Named(key),
+            };
+            mode.handle_event(app_state, event).unwrap();
+        };
+
+        press(&mut mode, &mut app_state, NamedKey::ArrowUp);
+        assert_eq!(mode.selected, 0);
+        press(&mut mode, &mut app_state, NamedKey::ArrowDown);
+        press(&mut mode, &mut app_state, NamedKey::ArrowDown);
+        assert_eq!(mode.selected, 1);
+        assert!(mode.update(&mut app_state).unwrap().is_none());
+
+        press(&mut mode, &mut app_state, NamedKey::Enter);
+        assert_eq!(
+            chosen_profile(&mut mode, &mut app_state).as_deref(),
+            Some("beta")
+        );
+    }
+
+    #[test]
+    fn test_click_selects_profile() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut app_state = AppState::headless(temp_dir.path().to_owned());
+        let mut mode = mode();
+        mode.selected = 1;
+
+        let screen_size = app_state.window.logical_size().cast::<f32>();
+        let click = |mode: &mut ProfileSelectMode, app_state: &mut AppState| {
+            let event = AppEvent::MouseDown {
+                button: MouseButton::Left,
+                is_double_click: false,
+            };
+            mode.handle_event(app_state, event).unwrap();
+        };
+
+        // the title isn't a profile
+        let title = mode.row_rect(screen_size, -1).center();
+        app_state.input.set_mouse_pos(title);
+        click(&mut mode, &mut app_state);
+        assert!(!mode.chosen);
+
+        let default = mode.row_rect(screen_size, 0).center();
+        app_state.input.set_mouse_pos(default);
+        click(&mut mode, &mut app_state);
+        assert_eq!(chosen_profile(&mut mode, &mut app_state), None);
+    }
+}
@@ -0,0 +1,114 @@
+//! Handling of `pob://` and `pob2://` links, e.g. from "Open in Path of Building"
+//! buttons on build sites.
+//!
+//! `--register-protocol` registers the app as handler for the link scheme of the
+//! given game. Links opened while the app is already running are forwarded to the
+//! running instance, see [`crate::instance`].
+
+use crate::args::Game;
+use clap::ValueEnum;
+use std::path::Path;
+
+impl Game {
+    /// Scheme of the links handled by the PoB version of this game
+    pub fn link_scheme(&self) -> &'static str {
+        match self {
+            Game::Poe1 => "pob",
+            Game::Poe2 => "pob2",
+        }
+    }
+}
+
+/// Registers the app as handler for the link scheme of `game`.
+pub fn register_protocol_handler(game: Game) -> anyhow::Result<()> {
+    let exe_path = std::env::current_exe()?;
+    let scheme = game.link_scheme();
+    let game_arg = game
+        .to_possible_value()
+        .map(|value| value.get_name().to_owned())
+        .unwrap_or_default();
+
+    register(scheme, &exe_path, &game_arg)?;
+    println!(
+        "Registered {} as handler for {scheme}:// links",
+        exe_path.display()
+    );
+    Ok(())
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn register(scheme: &str, exe_path: &Path, game_arg: &str) -> anyhow::Result<()> {
+    use directories::BaseDirs;
+    use std::{fs, process::Command};
+
+    let desktop_file_name = format!("rusty-path-of-building-{scheme}.desktop");
+    let applications_dir = BaseDirs::new()
+        .ok_or_else(|| anyhow::anyhow!("Unable to determine data directory"))?
+        .data_dir()
+        .join("applications");
+    fs::create_dir_all(&applications_dir)?;
+
+    let desktop_entry = format!(
+        "[Desktop Entry]\n\
+         Type=Application\n\
+         Name=Rusty Path of Building ({scheme}:// links)\n\
+         Exec=\"{}\" {game_arg} %u\n\
+         NoDisplay=true\n\
+         MimeType=x-scheme-handler/{scheme};\n",
+        exe_path.display()
+    );
+    fs::write(applications_dir.join(&desktop_file_name), desktop_entry)?;
+
+    let status = Command::new("xdg-mime")
+        .args(["default", &desktop_file_name])
+        .arg(format!("x-scheme-handler/{scheme}"))
+        .status();
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => anyhow::bail!("xdg-mime failed with {status}"),
+        Err(err) => anyhow::bail!("Unable to run xdg-mime: {err}"),
+    }
+
+    // not required on every desktop, so failure is only logged
+    if let Err(err) = Command::new("update-desktop-database")
+        .arg(&applications_dir)
+        .status()
+    {
+        log::warn!("Unable to run update-desktop-database: {err}");
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+fn register(scheme: &str, exe_path: &Path, game_arg: &str) -> anyhow::Result<()> {
+    use std::process::Command;
+
+    let key = format!(r"HKCU\Software\Classes\{scheme}");
+    let command = format!(r#""{}" {game_arg} "%1""#, exe_path.display());
+    let entries = [
+        (key.clone(), None, format!("URL:{scheme} link")),
+        (key.clone(), Some("URL Protocol"), String::new()),
+        (format!(r"{key}\shell\open\command"), None, command),
+    ];
+
+    for (key, value_name, data) in entries {
+        let mut reg = Command::new("reg");
+        reg.args(["add", &key]);
+        match value_name {
+            Some(value_name) => reg.args(["/v", value_name]),
+            None => reg.arg("/ve"),
+        };
+        let status = reg.args(["/d", &data, "/f"]).status()?;
+        if !status.success() {
+            anyhow::bail!("Unable to write registry key {key}");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(any(all(unix, not(target_os = "macos")), target_os = "windows")))]
+fn register(_scheme: &str, _exe_path: &Path, _game_arg: &str) -> anyhow::Result<()> {
+    anyhow::bail!("Registering a protocol handler isn't supported on this platform")
+}
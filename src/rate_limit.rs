@@ -0,0 +1,458 @@
+//! Paces requests to APIs that announce their rate limits, like the official
+//! trade API, so that PoB doesn't get temporarily banned for sending too many.
+//!
+//! Responses carry the policy of their endpoint in `X-Rate-Limit-Policy`, its
+//! rules (e.g. `Ip`, `Account`) in `X-Rate-Limit-Rules`, the limits of each rule
+//! as `hits:period:restriction` triples in `X-Rate-Limit-<rule>` and the current
+//! state in `X-Rate-Limit-<rule>-State`. Requests to an endpoint whose policy is
+//! known are queued until they fit into every rule and served in the order they
+//! arrived. Endpoints that share a policy share the queue.
+//!
+//! Both `NewDownload` and the lcurl requests of subscripts, which PoB uses for
+//! trade searches, go through the queue, see [`pace_lcurl`].
+
+use mlua::{Function, Lua, MultiValue, Result as LuaResult, Table, Value};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        Condvar, LazyLock, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use ureq::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+
+static RATE_LIMITER: LazyLock<Mutex<RateLimiter>> =
+    LazyLock::new(|| Mutex::new(RateLimiter::default()));
+/// Wakes up queued requests when the front of a queue changes, limits are updated
+/// or a request is cancelled
+static QUEUE_CHANGED: Condvar = Condvar::new();
+
+/// Used if a 429 response doesn't say how long to wait
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Debug, PartialEq)]
+struct Rule {
+    max_hits: usize,
+    period: Duration,
+}
+
+#[derive(Default)]
+struct Policy {
+    rules: Vec<Rule>,
+    // send times of the requests within the longest period
+    hits: VecDeque<Instant>,
+    restricted_until: Option<Instant>,
+    // tickets of the requests waiting for their turn, in arrival order
+    queue: VecDeque<u64>,
+}
+
+impl Policy {
+    fn wait_time(&self, now: Instant) -> Duration {
+        let mut wait = self
+            .restricted_until
+            .map_or(Duration::ZERO, |until| until.saturating_duration_since(now));
+        for rule in &self.rules {
+            // hits are ordered, the oldest hit inside the period has to expire first
+            let in_period: Vec<_> = self
+                .hits
+                .iter()
+                .filter(|&&hit| now.saturating_duration_since(hit) < rule.period)
+                .collect();
+            if rule.max_hits > 0 && in_period.len() >= rule.max_hits {
+                let expires = *in_period[in_period.len() - rule.max_hits] + rule.period;
+                wait = wait.max(expires.saturating_duration_since(now));
+            }
+        }
+        wait
+    }
+
+    fn record_hit(&mut self, now: Instant) {
+        self.hits.push_back(now);
+        let longest_period = self.rules.iter().map(|rule| rule.period).max();
+        let longest_period = longest_period.unwrap_or_default();
+        while self
+            .hits
+            .front()
+            .is_some_and(|&hit| now.saturating_duration_since(hit) >= longest_period)
+        {
+            self.hits.pop_front();
+        }
+    }
+}
+
+/// Queue state of a policy, exposed to Lua
+pub struct PolicyStatus {
+    pub name: String,
+    pub queued: usize,
+    pub wait_time: Duration,
+}
+
+#[derive(Default)]
+struct RateLimiter {
+    // endpoint (see `endpoint_key`) to policy name
+    endpoints: HashMap<String, String>,
+    policies: HashMap<String, Policy>,
+    next_ticket: u64,
+}
+
+impl RateLimiter {
+    fn policy_mut(&mut self, endpoint: &str) -> Option<&mut Policy> {
+        let name = self.endpoints.get(endpoint)?;
+        self.policies.get_mut(name)
+    }
+
+    fn update(&mut self, endpoint: &str, status: StatusCode, headers: &HeaderMap, now: Instant) {
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+        if let Some(name) = header("x-rate-limit-policy") {
+            self.endpoints.insert(endpoint.to_owned(), name.to_owned());
+            let policy = self.policies.entry(name.to_owned()).or_default();
+
+            let mut rules = Vec::new();
+            let rule_names = header("x-rate-limit-rules").unwrap_or_default();
+            for rule_name in rule_names.split(',').map(str::trim) {
+                let limits = header(&format!("x-rate-limit-{rule_name}")).unwrap_or_default();
+                let state = header(&format!("x-rate-limit-{rule_name}-state")).unwrap_or_default();
+                for (limit, state) in parse_triples(limits).zip(parse_triples(state)) {
+                    let (max_hits, period, _) = limit;
+                    let (hits, _, restricted) = state;
+                    let period = Duration::from_secs(period);
+                    rules.push(Rule {
+                        max_hits: max_hits as usize,
+                        period,
+                    });
+
+                    // other programs may use the same limits, e.g. the trade site
+                    let known_hits = policy
+                        .hits
+                        .iter()
+                        .filter(|&&hit| now.saturating_duration_since(hit) < period)
+                        .count();
+                    for _ in known_hits..hits as usize {
+                        policy.hits.push_back(now);
+                    }
+                    if restricted > 0 {
+                        restrict(policy, now + Duration::from_secs(restricted));
+                    }
+                }
+            }
+            if !rules.is_empty() {
+                policy.rules = rules;
+            }
+            policy.hits.make_contiguous().sort();
+        }
+
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = header("retry-after")
+                .and_then(|value| value.parse().ok())
+                .map_or(DEFAULT_RETRY_AFTER, Duration::from_secs);
+            log::warn!("Rate limited by {endpoint}, waiting {retry_after:?}");
+            if let Some(policy) = self.policy_mut(endpoint) {
+                restrict(policy, now + retry_after);
+            }
+        }
+    }
+}
+
+fn restrict(policy: &mut Policy, until: Instant) {
+    policy.restricted_until = policy.restricted_until.max(Some(until));
+}
+
+/// Parses `hits:period:restriction` triples separated by commas
+fn parse_triples(value: &str) -> impl Iterator<Item = (u64, u64, u64)> + '_ {
+    value.split(',').filter_map(|triple| {
+        let mut parts = triple.trim().split(':').map(|part| part.parse().ok());
+        Some((parts.next()??, parts.next()??, parts.next()??))
+    })
+}
+
+/// Requests to the same endpoint, e.g. `www.pathofexile.com/api/trade/search`,
+/// share a policy. The league or item ids that follow are ignored.
+fn endpoint_key(url: &str) -> String {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let rest = rest.split(['?', '#']).next().unwrap_or_default();
+    rest.split('/').take(4).collect::<Vec<_>>().join("/")
+}
+
+/// Blocks until it's the request's turn in the queue of its endpoint and it fits
+/// into the limits, then counts it. Returns false if `cancel` is set while waiting,
+/// which has to be followed by [`notify_cancelled`].
+pub fn acquire(url: &str, cancel: &AtomicBool) -> bool {
+    let endpoint = endpoint_key(url);
+    let mut limiter = RATE_LIMITER.lock().unwrap();
+    // the endpoint may move to another policy while waiting, the ticket stays put
+    let Some(name) = limiter.endpoints.get(&endpoint).cloned() else {
+        // limits are unknown until the first response
+        return true;
+    };
+    let ticket = limiter.next_ticket;
+    limiter.next_ticket += 1;
+    let policy = limiter.policies.entry(name.clone()).or_default();
+    policy.queue.push_back(ticket);
+
+    loop {
+        let policy = limiter.policies.entry(name.clone()).or_default();
+        if cancel.load(Ordering::Relaxed) {
+            policy.queue.retain(|&queued| queued != ticket);
+            QUEUE_CHANGED.notify_all();
+            return false;
+        }
+
+        limiter = if policy.queue.front() == Some(&ticket) {
+            let now = Instant::now();
+            let wait_time = policy.wait_time(now);
+            if wait_time.is_zero() {
+                policy.queue.pop_front();
+                policy.record_hit(now);
+                QUEUE_CHANGED.notify_all();
+                return true;
+            }
+            QUEUE_CHANGED.wait_timeout(limiter, wait_time).unwrap().0
+        } else {
+            QUEUE_CHANGED.wait(limiter).unwrap()
+        };
+    }
+}
+
+/// Wakes up queued requests so that cancelled ones leave their queue
+pub fn notify_cancelled() {
+    // taking the lock ensures that waiters either see the flag or get woken up
+    drop(RATE_LIMITER.lock().unwrap());
+    QUEUE_CHANGED.notify_all();
+}
+
+/// Updates the limits of the endpoint of `url` from the response headers
+pub fn update(url: &str, status: StatusCode, headers: &HeaderMap) {
+    let endpoint = endpoint_key(url);
+    RATE_LIMITER
+        .lock()
+        .unwrap()
+        .update(&endpoint, status, headers, Instant::now());
+    QUEUE_CHANGED.notify_all();
+}
+
+/// Patches lcurl when a subscript loads it, so that `perform` on easy handles
+/// waits in the queue and updates the limits from the response headers. PoB sends
+/// its trade requests this way. Handles also trust the certificates passed with
+/// `--ca-certs`. Has to be called after the module loaders are set up, see
+/// `restrict_native_modules`.
+///
+/// NOTE: Only used for subscripts, waiting in the main instance would block the UI.
+pub fn pace_lcurl(lua: &Lua) -> LuaResult<()> {
+    // subscripts can't be cancelled
+    static NOT_CANCELLED: AtomicBool = AtomicBool::new(false);
+
+    let acquire = lua.create_function(|_, url: String| Ok(acquire(&url, &NOT_CANCELLED)))?;
+    let update = lua.create_function(|_, (url, code, headers): (String, u16, String)| {
+        if let Ok(status) = StatusCode::from_u16(code) {
+            update(&url, status, &parse_header_lines(&headers));
+        }
+        Ok(())
+    })?;
+    let cainfo = crate::http::ca_certs().map(|path| path.to_string_lossy().into_owned());
+    let patch: Function = lua
+        .load(include_str!("rate_limit/lcurl.lua"))
+        .set_name("=rate_limit/lcurl.lua")
+        .call((acquire, update, cainfo))?;
+
+    let package: Table = lua.globals().get("package")?;
+    let loaders: Table = package.get("loaders")?;
+    // index 3 loads `name` from `package.cpath`, index 4 loads the root of `name`
+    for idx in [3, 4] {
+        let loader: Function = loaders.get(idx)?;
+        let patch = patch.clone();
+        let wrapper = lua.create_function(move |lua, (name, rest): (String, MultiValue)| {
+            let mut values = loader.call::<MultiValue>((name.clone(), rest))?;
+            if name.split('.').next() != Some("lcurl") {
+                return Ok(values);
+            }
+            let Some(Value::Function(open)) = values.pop_front() else {
+                return Ok(values);
+            };
+            // patch the module once it's opened by `require`
+            let patch = patch.clone();
+            let open = lua.create_function(move |_, args: MultiValue| {
+                let module = open.call::<MultiValue>(args)?;
+                if let Some(Value::Table(curl)) = module.front() {
+                    patch.call::<()>(curl)?;
+                }
+                Ok(module)
+            })?;
+            values.push_front(Value::Function(open));
+            Ok(values)
+        })?;
+        loaders.set(idx, wrapper)?;
+    }
+
+    Ok(())
+}
+
+/// Parses the header lines of lcurl's header callback. Only the headers of the
+/// last response are kept if there were redirects.
+fn parse_header_lines(text: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for line in text.lines() {
+        if line.starts_with("HTTP/") {
+            headers.clear();
+        } else if let Some((name, value)) = line.split_once(':')
+            && let Ok(name) = HeaderName::from_bytes(name.trim().as_bytes())
+            && let Ok(value) = HeaderValue::from_str(value.trim())
+        {
+            headers.insert(name, value);
+        }
+    }
+    headers
+}
+
+/// Queue state of all known policies, sorted by name
+pub fn status() -> Vec<PolicyStatus> {
+    let limiter = RATE_LIMITER.lock().unwrap();
+    let now = Instant::now();
+    let mut status: Vec<_> = limiter
+        .policies
+        .iter()
+        .map(|(name, policy)| PolicyStatus {
+            name: name.clone(),
+            queued: policy.queue.len(),
+            wait_time: policy.wait_time(now),
+        })
+        .collect();
+    status.sort_by(|a, b| a.name.cmp(&b.name));
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit_headers() {
+        let mut headers = HeaderMap::new();
+        for (name, value) in [
+            ("X-Rate-Limit-Policy", "trade-search-request-limit"),
+            ("X-Rate-Limit-Rules", "Ip"),
+            ("X-Rate-Limit-Ip", "2:10:60,5:300:1800"),
+            ("X-Rate-Limit-Ip-State", "1:10:0,1:300:0"),
+        ] {
+            headers.insert(name, HeaderValue::from_static(value));
+        }
+
+        let endpoint = endpoint_key("https://www.pathofexile.com/api/trade/search/Standard?q=1");
+        assert_eq!(endpoint, "www.pathofexile.com/api/trade/search");
+
+        let now = Instant::now();
+        let mut limiter = RateLimiter::default();
+        limiter.update(&endpoint, StatusCode::OK, &headers, now);
+        let policy = limiter.policy_mut(&endpoint).unwrap();
+        assert_eq!(
+            policy.rules,
+            [
+                Rule {
+                    max_hits: 2,
+                    period: Duration::from_secs(10)
+                },
+                Rule {
+                    max_hits: 5,
+                    period: Duration::from_secs(300)
+                },
+            ]
+        );
+
+        // the hit reported by the server leaves room for one more request
+        assert_eq!(policy.wait_time(now), Duration::ZERO);
+        policy.record_hit(now);
+        assert_eq!(policy.wait_time(now), Duration::from_secs(10));
+        assert_eq!(
+            policy.wait_time(now + Duration::from_secs(4)),
+            Duration::from_secs(6)
+        );
+
+        limiter.update(
+            &endpoint,
+            StatusCode::TOO_MANY_REQUESTS,
+            &HeaderMap::new(),
+            now,
+        );
+        let policy = limiter.policy_mut(&endpoint).unwrap();
+        assert_eq!(policy.wait_time(now), DEFAULT_RETRY_AFTER);
+    }
+
+    #[test]
+    fn test_parse_header_lines() {
+        let headers = parse_header_lines(
+            "HTTP/1.1 302 Found\r\nLocation: /trade\r\n\r\n\
+             HTTP/2 200\r\nX-Rate-Limit-Policy: trade-fetch-request-limit\r\n\r\n",
+        );
+        assert_eq!(headers.len(), 1);
+        assert_eq!(
+            headers["x-rate-limit-policy"],
+            HeaderValue::from_static("trade-fetch-request-limit")
+        );
+    }
+
+    #[test]
+    fn test_pace_lcurl() {
+        let lua = Lua::new();
+        let acquire = lua
+            .create_function(|lua, url: String| lua.globals().set("acquired", url))
+            .unwrap();
+        let update = lua
+            .create_function(|lua, (url, code, headers): (String, u16, String)| {
+                lua.globals()
+                    .set("updated", format!("{url} {code} {headers}"))
+            })
+            .unwrap();
+        let patch: Function = lua
+            .load(include_str!("rate_limit/lcurl.lua"))
+            .call((acquire, update, "/etc/proxy-ca.pem"))
+            .unwrap();
+
+        // easy handles like lcurl's, which call the header callback on `perform`
+        let curl: Table = lua
+            .load(
+                r#"
+                local methods = {}
+                methods.__index = methods
+                function methods:setopt_url(url) end
+                function methods:setopt_headerfunction(f) self.header = f end
+                function methods:setopt_cainfo(path) self.cainfo = path end
+                function methods:perform()
+                    self.header("HTTP/2 200\r\n")
+                    self.header("X-Rate-Limit-Policy: trade\r\n")
+                    return self
+                end
+                function methods:getinfo_response_code() return 200 end
+                function methods:close() end
+                return { easy = function() return setmetatable({}, methods) end }
+                "#,
+            )
+            .eval()
+            .unwrap();
+        patch.call::<()>(&curl).unwrap();
+        patch.call::<()>(&curl).unwrap();
+
+        lua.globals().set("curl", curl).unwrap();
+        let lines: String = lua
+            .load(
+                r#"
+                local lines = ""
+                local easy = curl.easy()
+                easy:setopt_url("https://www.pathofexile.com/api/trade/fetch/1")
+                easy:setopt_headerfunction(function(line) lines = lines .. line end)
+                assert(easy:perform() == easy)
+                assert(easy.cainfo == "/etc/proxy-ca.pem")
+                return lines
+                "#,
+            )
+            .eval()
+            .unwrap();
+        let url = "https://www.pathofexile.com/api/trade/fetch/1";
+        assert_eq!(lines, "HTTP/2 200\r\nX-Rate-Limit-Policy: trade\r\n");
+        assert_eq!(lua.globals().get::<String>("acquired").unwrap(), url);
+        assert_eq!(
+            lua.globals().get::<String>("updated").unwrap(),
+            format!("{url} 200 {lines}")
+        );
+    }
+}
@@ -0,0 +1,104 @@
+-- Paces requests made with lcurl's easy handles, see `pace_lcurl` in rate_limit.rs.
+-- Called with the limiter's `acquire(url)` and `update(url, code, headers)` and
+-- the CA certificates passed with `--ca-certs`, if any. Returns the function that
+-- patches a loaded lcurl module.
+local acquire, update, cainfo = ...
+local getmetatable = debug and debug.getmetatable or getmetatable
+
+local function pack(...)
+	return { n = select("#", ...), ... }
+end
+
+-- keyed by handle, module or metatable, so that nothing is kept alive or patched twice
+local patched = setmetatable({}, { __mode = "k" })
+local urls = setmetatable({}, { __mode = "k" })
+local header_callbacks = setmetatable({}, { __mode = "k" })
+
+local function patch_handles(curl, methods)
+	local setopt_url = methods.setopt_url
+	methods.setopt_url = function(easy, url, ...)
+		urls[easy] = url
+		return setopt_url(easy, url, ...)
+	end
+
+	local setopt_headerfunction = methods.setopt_headerfunction
+	methods.setopt_headerfunction = function(easy, ...)
+		header_callbacks[easy] = pack(...)
+		return setopt_headerfunction(easy, ...)
+	end
+
+	local setopt = methods.setopt
+	methods.setopt = function(easy, opt, ...)
+		if type(opt) == "table" then
+			urls[easy] = opt.url or opt[curl.OPT_URL] or urls[easy]
+		elseif opt == curl.OPT_URL then
+			urls[easy] = ...
+		elseif opt == curl.OPT_HEADERFUNCTION then
+			header_callbacks[easy] = pack(...)
+		end
+		return setopt(easy, opt, ...)
+	end
+
+	local setopt_cainfo = methods.setopt_cainfo
+	local perform = methods.perform
+	methods.perform = function(easy, ...)
+		-- libcurl doesn't read the certificates to trust from the environment
+		if cainfo then
+			setopt_cainfo(easy, cainfo)
+		end
+		local url = urls[easy]
+		if not url then
+			return perform(easy, ...)
+		end
+		acquire(url)
+
+		-- collect the response headers and pass them on to the handle's own callback
+		local headers = {}
+		local callback = header_callbacks[easy]
+		setopt_headerfunction(easy, function(line)
+			headers[#headers + 1] = line
+			if not callback or callback[1] == nil then
+				return true
+			elseif callback.n >= 2 then
+				return callback[1](callback[2], line)
+			elseif type(callback[1]) ~= "function" then
+				return callback[1]:header(line)
+			end
+			return callback[1](line)
+		end)
+
+		local results = pack(perform(easy, ...))
+		local ok, code = pcall(methods.getinfo_response_code, easy)
+		if ok and code then
+			update(url, code, table.concat(headers))
+		end
+		return unpack(results, 1, results.n)
+	end
+end
+
+return function(curl)
+	if patched[curl] then
+		return
+	end
+	patched[curl] = true
+
+	local easy = curl.easy
+	curl.easy = function(opts, ...)
+		local handle, err = easy(opts, ...)
+		if handle and type(opts) == "table" then
+			urls[handle] = opts.url or opts[curl.OPT_URL]
+		end
+		return handle, err
+	end
+
+	local probe = easy()
+	local mt = probe and getmetatable(probe)
+	if probe then
+		probe:close()
+	end
+	if not mt or patched[mt] then
+		return
+	end
+	patched[mt] = true
+	patch_handles(curl, type(mt.__index) == "table" and mt.__index or mt)
+end
@@ -3,7 +3,8 @@ use crate::{
     math::Point,
     renderer::{
         image::ImageData,
-        mesh::{ClippedMesh, Vertex},
+        mesh::{ClippedMesh, MeshId, Vertex},
+        mipmap::MipmapGenerator,
         textures::{TextureId, TextureOptions, TexturesDelta},
     },
 };
@@ -11,17 +12,27 @@ use ahash::HashMap;
 use std::{borrow::Cow, num::NonZeroU64, ops::Range};
 use wgpu::util::DeviceExt;
 
+mod bc7;
+pub mod canonical;
 pub mod image;
 pub mod mesh;
 mod mipmap;
 pub mod primitives;
 pub mod tessellator;
 pub mod textures;
+#[cfg(test)]
+mod visual_tests;
+
+pub use bc7::TextureCompression;
 
 #[derive(Debug)]
 struct Texture {
     texture: wgpu::Texture,
     bind_group: wgpu::BindGroup,
+    // options of the sampler in the bind group
+    options: TextureOptions,
+    // drawn with premultiplied alpha blending
+    premultiplied_alpha: bool,
 }
 
 #[repr(C)]
@@ -32,6 +43,11 @@ struct Globals {
 
 pub struct Renderer {
     pipeline: wgpu::RenderPipeline,
+    // blends each color channel separately for subpixel text.
+    // `None` if the device doesn't support dual-source blending.
+    subpixel_pipeline: Option<wgpu::RenderPipeline>,
+    // for textures with premultiplied alpha
+    premultiplied_pipeline: wgpu::RenderPipeline,
 
     index_buffer: SlicedBuffer,
     vertex_buffer: SlicedBuffer,
@@ -43,6 +59,11 @@ pub struct Renderer {
 
     textures: HashMap<TextureId, Texture>,
     samplers: HashMap<TextureOptions, wgpu::Sampler>,
+    // `None` if the device can't create views with a different format, in which
+    // case mipmaps are generated on the CPU
+    mipmap_generator: Option<MipmapGenerator>,
+    // mesh in each slice of the index and vertex buffers
+    uploaded_meshes: Vec<Option<MeshId>>,
 }
 
 impl Renderer {
@@ -50,7 +71,16 @@ impl Renderer {
         device: &wgpu::Device,
         output_color_format: wgpu::TextureFormat,
         output_depth_format: Option<wgpu::TextureFormat>,
+        sample_count: u32,
+        linear_white_scale: Option<f32>,
+        supports_view_formats: bool,
     ) -> Self {
+        let output = PipelineOutput {
+            depth_format: output_depth_format,
+            sample_count,
+            linear_white_scale,
+        };
+
         let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("main_shader_module"),
             source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("renderer/main.wgsl"))),
@@ -121,60 +151,70 @@ impl Renderer {
             push_constant_ranges: &[],
         });
 
-        let depth_stencil = output_depth_format.map(|format| wgpu::DepthStencilState {
-            format,
-            depth_write_enabled: false,
-            depth_compare: wgpu::CompareFunction::Always,
-            stencil: wgpu::StencilState::default(),
-            bias: wgpu::DepthBiasState::default(),
-        });
-
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("render_pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                entry_point: Some("vs_main"),
-                module: &shader_module,
-                buffers: &[wgpu::VertexBufferLayout {
-                    // 4x f32, 2x u32 -> 6 * 4 bytes
-                    array_stride: 6 * 4,
-                    step_mode: wgpu::VertexStepMode::Vertex,
-                    // 0: vec2 position
-                    // 1: vec2 texture coordinates
-                    // 2: uint color
-                    // 3: uint layer_idx
-                    attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Uint32, 3 => Uint32],
-                }],
-                compilation_options: wgpu::PipelineCompilationOptions::default()
-            },
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                unclipped_depth: false,
-                conservative: false,
-                cull_mode: None,
-                front_face: wgpu::FrontFace::default(),
-                polygon_mode: wgpu::PolygonMode::default(),
-                strip_index_format: None,
+        let pipeline = create_render_pipeline(
+            device,
+            "render_pipeline",
+            &pipeline_layout,
+            &shader_module,
+            "fs_main",
+            wgpu::ColorTargetState {
+                format: output_color_format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
             },
-            depth_stencil,
-            multisample: wgpu::MultisampleState {
-                alpha_to_coverage_enabled: false,
-                count: 1,
-                mask: !0,
+            &output,
+        );
+
+        let premultiplied_pipeline = create_render_pipeline(
+            device,
+            "premultiplied_render_pipeline",
+            &pipeline_layout,
+            &shader_module,
+            "fs_premultiplied",
+            wgpu::ColorTargetState {
+                format: output_color_format,
+                blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
             },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader_module,
-                entry_point: Some("fs_main"),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: output_color_format,
-                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: wgpu::PipelineCompilationOptions::default()
-            }),
-            multiview: None,
-            cache: None,
-        });
+            &output,
+        );
+
+        let subpixel_pipeline = device
+            .features()
+            .contains(wgpu::Features::DUAL_SOURCE_BLENDING)
+            .then(|| {
+                let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some("subpixel_shader_module"),
+                    source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(concat!(
+                        "enable dual_source_blending;\n",
+                        include_str!("renderer/main.wgsl"),
+                        include_str!("renderer/subpixel.wgsl")
+                    ))),
+                });
+
+                let blend_component = wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::Src1,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrc1,
+                    operation: wgpu::BlendOperation::Add,
+                };
+
+                create_render_pipeline(
+                    device,
+                    "subpixel_render_pipeline",
+                    &pipeline_layout,
+                    &shader_module,
+                    "fs_subpixel",
+                    wgpu::ColorTargetState {
+                        format: output_color_format,
+                        blend: Some(wgpu::BlendState {
+                            color: blend_component,
+                            alpha: blend_component,
+                        }),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    },
+                    &output,
+                )
+            });
 
         let vertex_buffer = SlicedBuffer::new(
             device,
@@ -192,6 +232,8 @@ impl Renderer {
 
         Self {
             pipeline,
+            subpixel_pipeline,
+            premultiplied_pipeline,
             vertex_buffer,
             index_buffer,
             globals_buffer: uniform_buffer,
@@ -202,9 +244,16 @@ impl Renderer {
             texture_bind_group_layout,
             textures: HashMap::default(),
             samplers: HashMap::default(),
+            mipmap_generator: supports_view_formats.then(|| MipmapGenerator::new(device)),
+            uploaded_meshes: Vec::new(),
         }
     }
 
+    /// Whether subpixel text can be rendered with per-channel blending.
+    pub fn supports_subpixel_text(&self) -> bool {
+        self.subpixel_pipeline.is_some()
+    }
+
     pub fn render(
         &self,
         render_pass: &mut wgpu::RenderPass<'static>,
@@ -228,8 +277,12 @@ impl Renderer {
         );
         render_pass.set_pipeline(&self.pipeline);
         render_pass.set_bind_group(0, &self.globals_bind_group, &[]);
+        let mut current_pipeline = &self.pipeline;
 
-        for ClippedMesh { clip_rect, mesh } in paint_jobs {
+        for ClippedMesh {
+            clip_rect, mesh, ..
+        } in paint_jobs
+        {
             let phys_clip_rect = clip_rect.to_physical::<f32, _>(pixels_per_point).round();
             let scissor = phys_clip_rect
                 // NOTE: can't cast to u32 directly because negative values cause a panic
@@ -253,7 +306,22 @@ impl Renderer {
             let index_buffer_slice = index_buffer_slices.next().unwrap();
             let vertex_buffer_slice = vertex_buffer_slices.next().unwrap();
 
-            if let Some(Texture { bind_group, .. }) = self.textures.get(&mesh.texture_id) {
+            if let Some(Texture {
+                bind_group,
+                premultiplied_alpha,
+                ..
+            }) = self.textures.get(&mesh.texture_id)
+            {
+                let pipeline = match &self.subpixel_pipeline {
+                    Some(subpixel_pipeline) if mesh.subpixel_text => subpixel_pipeline,
+                    _ if *premultiplied_alpha => &self.premultiplied_pipeline,
+                    _ => &self.pipeline,
+                };
+                if !std::ptr::eq(pipeline, current_pipeline) {
+                    render_pass.set_pipeline(pipeline);
+                    current_pipeline = pipeline;
+                }
+
                 render_pass.set_bind_group(1, bind_group, &[]);
                 render_pass.set_index_buffer(
                     self.index_buffer
@@ -294,7 +362,9 @@ impl Renderer {
                 array_layers,
                 mipmap_count,
                 data_order,
+                premultiplied_alpha,
                 ref bytes,
+                ..
             } = image_delta.image;
 
             let size = wgpu::Extent3d {
@@ -314,6 +384,48 @@ impl Renderer {
                 mipmap_count.get()
             };
 
+            let gpu_mipmaps =
+                gen_mipmaps && self.mipmap_generator.is_some() && MipmapGenerator::supports(format);
+            let mut usage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST;
+            if gpu_mipmaps {
+                usage |= wgpu::TextureUsages::RENDER_ATTACHMENT;
+            }
+
+            // contents changed, e.g. the font atlas, but the texture can be reused
+            let existing = self.textures.get_mut(id).filter(|existing| {
+                let texture = &existing.texture;
+                texture.size() == size
+                    && texture.format() == format
+                    && texture.mip_level_count() == mip_level_count
+                    && texture.usage() == usage
+                    && existing.options == image_delta.options
+            });
+            if let Some(existing) = existing {
+                write_texture_data(
+                    queue,
+                    &existing.texture,
+                    data_order.into(),
+                    bytes,
+                    gen_mipmaps,
+                );
+                existing.premultiplied_alpha = premultiplied_alpha;
+                let texture = existing.texture.clone();
+                self.generate_mipmaps(
+                    device,
+                    queue,
+                    &texture,
+                    &image_delta.image,
+                    gen_mipmaps,
+                    gpu_mipmaps,
+                );
+                continue;
+            }
+
+            // the sRGB view is only rendered to by the mipmap generator, and views
+            // with another format aren't supported everywhere
+            let srgb_view_format = [format.add_srgb_suffix()];
+            let view_formats: &[_] = if gpu_mipmaps { &srgb_view_format } else { &[] };
+
             let label_str = format!("texture_{id:?}");
             let label = Some(label_str.as_str());
 
@@ -327,17 +439,22 @@ impl Renderer {
                     sample_count: 1,
                     dimension: wgpu::TextureDimension::D2,
                     format,
-                    usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-                    view_formats: &[format.add_srgb_suffix()],
+                    usage,
+                    view_formats,
                 },
                 data_order.into(),
                 bytes,
                 gen_mipmaps,
             );
 
-            if gen_mipmaps {
-                mipmap::generate_mipmap_chain(queue, &texture, bytes);
-            }
+            self.generate_mipmaps(
+                device,
+                queue,
+                &texture,
+                &image_delta.image,
+                gen_mipmaps,
+                gpu_mipmaps,
+            );
 
             let texture_view = texture.create_view(&wgpu::TextureViewDescriptor {
                 dimension: Some(wgpu::TextureViewDimension::D2Array),
@@ -369,11 +486,31 @@ impl Renderer {
                 Texture {
                     texture,
                     bind_group,
+                    options: image_delta.options,
+                    premultiplied_alpha,
                 },
             );
         }
     }
 
+    /// Generates the mip levels after level 0 if `gen_mipmaps` is set, on the GPU
+    /// if `gpu_mipmaps` is set.
+    fn generate_mipmaps(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+        image: &ImageData,
+        gen_mipmaps: bool,
+        gpu_mipmaps: bool,
+    ) {
+        if let Some(generator) = self.mipmap_generator.as_mut().filter(|_| gpu_mipmaps) {
+            generator.generate(device, queue, texture, image.premultiplied_alpha);
+        } else if gen_mipmaps {
+            mipmap::generate_mipmap_chain(queue, texture, &image.bytes, image.premultiplied_alpha);
+        }
+    }
+
     pub fn free_textures(&mut self, textures_delta: &TexturesDelta) {
         profiling::scope!("free_textures");
 
@@ -422,42 +559,152 @@ impl Renderer {
 
         // update index and vertex buffers
         if index_count > 0 && vertex_count > 0 {
-            self.index_buffer.slices.clear();
-            self.vertex_buffer.slices.clear();
-
-            let mut staging_index_buffer = self.index_buffer.create_staging_buffer(
-                device,
-                queue,
-                NonZeroU64::new(index_count as u64).expect("index_count > 0"),
-            );
-            let mut staging_vertex_buffer = self.vertex_buffer.create_staging_buffer(
-                device,
-                queue,
-                NonZeroU64::new(vertex_count as u64).expect("vertex_count > 0"),
-            );
-
+            let mut index_slices = Vec::with_capacity(paint_jobs.len());
+            let mut vertex_slices = Vec::with_capacity(paint_jobs.len());
             let mut index_offset = 0;
             let mut vertex_offset = 0;
             for ClippedMesh { mesh, .. } in paint_jobs {
-                {
-                    let size = mesh.indices.len() * std::mem::size_of::<u32>();
-                    let slice = index_offset..(index_offset + size);
-                    staging_index_buffer[slice.clone()]
-                        .copy_from_slice(bytemuck::cast_slice(&mesh.indices));
-                    self.index_buffer.slices.push(slice);
-                    index_offset += size;
-                }
-                {
-                    let size = mesh.vertices.len() * std::mem::size_of::<Vertex>();
-                    let slice = vertex_offset..(vertex_offset + size);
-                    staging_vertex_buffer[slice.clone()]
-                        .copy_from_slice(bytemuck::cast_slice(&mesh.vertices));
-                    self.vertex_buffer.slices.push(slice);
-                    vertex_offset += size;
+                let size = mesh.indices.len() * std::mem::size_of::<u32>();
+                index_slices.push(index_offset..(index_offset + size));
+                index_offset += size;
+
+                let size = mesh.vertices.len() * std::mem::size_of::<Vertex>();
+                vertex_slices.push(vertex_offset..(vertex_offset + size));
+                vertex_offset += size;
+            }
+
+            // growing a buffer loses its contents
+            let is_reallocated = self.index_buffer.reserve(device, index_count as u64)
+                | self.vertex_buffer.reserve(device, vertex_count as u64);
+
+            // meshes that are already in the buffers at the same place
+            let is_uploaded: Vec<bool> = paint_jobs
+                .iter()
+                .enumerate()
+                .map(|(i, clipped_mesh)| {
+                    !is_reallocated
+                        && !clipped_mesh.is_dirty
+                        && clipped_mesh.id.is_some()
+                        && self.uploaded_meshes.get(i) == Some(&clipped_mesh.id)
+                        && self.index_buffer.slices.get(i) == Some(&index_slices[i])
+                        && self.vertex_buffer.slices.get(i) == Some(&vertex_slices[i])
+                })
+                .collect();
+
+            // write runs of changed meshes at once
+            let mut start = 0;
+            while start < paint_jobs.len() {
+                if is_uploaded[start] {
+                    start += 1;
+                    continue;
                 }
+                let end = (start..paint_jobs.len())
+                    .find(|&i| is_uploaded[i])
+                    .unwrap_or(paint_jobs.len());
+                let meshes = &paint_jobs[start..end];
+                self.index_buffer.write(
+                    queue,
+                    &index_slices[start..end],
+                    meshes.iter().map(|m| bytemuck::cast_slice(&m.mesh.indices)),
+                );
+                self.vertex_buffer.write(
+                    queue,
+                    &vertex_slices[start..end],
+                    meshes
+                        .iter()
+                        .map(|m| bytemuck::cast_slice(&m.mesh.vertices)),
+                );
+                start = end;
             }
+
+            self.index_buffer.slices = index_slices;
+            self.vertex_buffer.slices = vertex_slices;
+            self.uploaded_meshes = paint_jobs.iter().map(|m| m.id).collect();
         }
     }
+
+    /// Uploads all meshes in the next [`Self::update_buffers`], e.g. after a frame
+    /// was dropped whose meshes were never uploaded.
+    pub fn discard_uploaded_meshes(&mut self) {
+        self.uploaded_meshes.clear();
+    }
+}
+
+/// Properties of the render targets shared by all pipelines
+struct PipelineOutput {
+    depth_format: Option<wgpu::TextureFormat>,
+    sample_count: u32,
+    // `Some` if the output is an extended-range linear (scRGB) surface
+    linear_white_scale: Option<f32>,
+}
+
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    pipeline_layout: &wgpu::PipelineLayout,
+    shader_module: &wgpu::ShaderModule,
+    fragment_entry_point: &str,
+    color_target: wgpu::ColorTargetState,
+    output: &PipelineOutput,
+) -> wgpu::RenderPipeline {
+    let depth_stencil = output.depth_format.map(|format| wgpu::DepthStencilState {
+        format,
+        depth_write_enabled: false,
+        depth_compare: wgpu::CompareFunction::Always,
+        stencil: wgpu::StencilState::default(),
+        bias: wgpu::DepthBiasState::default(),
+    });
+
+    let constants = match output.linear_white_scale {
+        Some(white_scale) => vec![("linear_output", 1.0), ("white_scale", white_scale as f64)],
+        None => Vec::new(),
+    };
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(pipeline_layout),
+        vertex: wgpu::VertexState {
+            entry_point: Some("vs_main"),
+            module: shader_module,
+            buffers: &[wgpu::VertexBufferLayout {
+                // 4x f32, 2x u32 -> 6 * 4 bytes
+                array_stride: 6 * 4,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                // 0: vec2 position
+                // 1: vec2 texture coordinates
+                // 2: uint color
+                // 3: uint layer_idx
+                attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Uint32, 3 => Uint32],
+            }],
+            compilation_options: wgpu::PipelineCompilationOptions::default()
+        },
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            unclipped_depth: false,
+            conservative: false,
+            cull_mode: None,
+            front_face: wgpu::FrontFace::default(),
+            polygon_mode: wgpu::PolygonMode::default(),
+            strip_index_format: None,
+        },
+        depth_stencil,
+        multisample: wgpu::MultisampleState {
+            alpha_to_coverage_enabled: false,
+            count: output.sample_count,
+            mask: !0,
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader_module,
+            entry_point: Some(fragment_entry_point),
+            targets: &[Some(color_target)],
+            compilation_options: wgpu::PipelineCompilationOptions {
+                constants: &constants,
+                ..Default::default()
+            }
+        }),
+        multiview: None,
+        cache: None,
+    })
 }
 
 struct SlicedBuffer {
@@ -493,30 +740,46 @@ impl SlicedBuffer {
         }
     }
 
-    fn create_staging_buffer(
-        &mut self,
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        required_capacity: NonZeroU64,
-    ) -> wgpu::QueueWriteBufferView {
-        let required_size = required_capacity.checked_mul(self.stride).unwrap();
-
-        // resize buffer to required size
-        if self.size < required_size {
-            self.buffer = device.create_buffer(&wgpu::BufferDescriptor {
-                label: None,
-                usage: self.usage | wgpu::BufferUsages::COPY_DST,
-                size: required_size.get(),
-                mapped_at_creation: false,
-            });
-            self.size = required_size;
+    /// Grows the buffer to hold `required_capacity` elements. Returns true if the
+    /// buffer was recreated, which discards its contents.
+    fn reserve(&mut self, device: &wgpu::Device, required_capacity: u64) -> bool {
+        let required_size = required_capacity * self.stride.get();
+        if self.size.get() >= required_size {
+            return false;
         }
 
-        let staging_buffer = queue.write_buffer_with(&self.buffer, 0, required_size);
-        let Some(staging_buffer) = staging_buffer else {
+        self.buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            usage: self.usage | wgpu::BufferUsages::COPY_DST,
+            size: required_size,
+            mapped_at_creation: false,
+        });
+        self.size = wgpu::BufferSize::new(required_size).unwrap();
+        true
+    }
+
+    /// Writes `data` into consecutive `slices` of the buffer.
+    fn write<'a>(
+        &self,
+        queue: &wgpu::Queue,
+        slices: &[Range<usize>],
+        data: impl Iterator<Item = &'a [u8]>,
+    ) {
+        let (Some(first), Some(last)) = (slices.first(), slices.last()) else {
+            return;
+        };
+        let Some(size) = wgpu::BufferSize::new((last.end - first.start) as u64) else {
+            return;
+        };
+        let Some(mut staging_buffer) =
+            queue.write_buffer_with(&self.buffer, first.start as u64, size)
+        else {
             panic!("Failed to create staging buffer!");
         };
-        staging_buffer
+        for (slice, data) in slices.iter().zip(data) {
+            staging_buffer[(slice.start - first.start)..(slice.end - first.start)]
+                .copy_from_slice(data);
+        }
     }
 }
 
@@ -555,23 +818,41 @@ fn create_texture_with_data(
     let mut desc = desc.to_owned();
     desc.usage |= wgpu::TextureUsages::COPY_DST;
     let texture = device.create_texture(&desc);
+    write_texture_data(queue, &texture, order, data, skip_mipmaps);
+    texture
+}
+
+/// Uploads data for all layers and mip levels of `texture`.
+/// Doesn't upload any data for mip level > 0 if skip_mipmaps is true.
+fn write_texture_data(
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    order: wgpu::wgt::TextureDataOrder,
+    data: &[u8],
+    skip_mipmaps: bool,
+) {
+    let format = texture.format();
+    let dimension = texture.dimension();
 
     // Will return None only if it's a combined depth-stencil format
     // If so, default to 4, validation will fail later anyway since the depth or stencil
     // aspect needs to be written to individually
-    let block_size = desc.format.block_copy_size(None).unwrap_or(4);
-    let (block_width, block_height) = desc.format.block_dimensions();
-    let layer_iterations = desc.array_layer_count();
+    let block_size = format.block_copy_size(None).unwrap_or(4);
+    let (block_width, block_height) = format.block_dimensions();
+    let layer_iterations = match dimension {
+        wgpu::wgt::TextureDimension::D3 => 1,
+        _ => texture.depth_or_array_layers(),
+    };
 
     let outer_iteration;
     let inner_iteration;
     match order {
         wgpu::wgt::TextureDataOrder::LayerMajor => {
             outer_iteration = layer_iterations;
-            inner_iteration = desc.mip_level_count;
+            inner_iteration = texture.mip_level_count();
         }
         wgpu::wgt::TextureDataOrder::MipMajor => {
-            outer_iteration = desc.mip_level_count;
+            outer_iteration = texture.mip_level_count();
             inner_iteration = layer_iterations;
         }
     }
@@ -584,16 +865,16 @@ fn create_texture_with_data(
                 wgpu::wgt::TextureDataOrder::MipMajor => (inner, outer),
             };
 
-            let mut mip_size = desc.mip_level_size(mip).unwrap();
+            let mut mip_size = texture.size().mip_level_size(mip, dimension);
             // copying layers separately
-            if desc.dimension != wgpu::wgt::TextureDimension::D3 {
+            if dimension != wgpu::wgt::TextureDimension::D3 {
                 mip_size.depth_or_array_layers = 1;
             }
 
             // When uploading mips of compressed textures and the mip is supposed to be
             // a size that isn't a multiple of the block size, the mip needs to be uploaded
             // as its "physical size" which is the size rounded up to the nearest block size.
-            let mip_physical = mip_size.physical_size(desc.format);
+            let mip_physical = mip_size.physical_size(format);
 
             // All these calculations are performed on the physical size as that's the
             // data that exists in the buffer.
@@ -608,7 +889,7 @@ fn create_texture_with_data(
             if mip == 0 || !skip_mipmaps {
                 queue.write_texture(
                     wgpu::TexelCopyTextureInfo {
-                        texture: &texture,
+                        texture,
                         mip_level: mip,
                         origin: wgpu::Origin3d {
                             x: 0,
@@ -630,6 +911,4 @@ fn create_texture_with_data(
             binary_offset = end_offset;
         }
     }
-
-    texture
 }
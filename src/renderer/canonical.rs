@@ -0,0 +1,283 @@
+//! Canonical, platform-independent representation of draw primitives.
+//!
+//! Both the frame hash used for eliding redundant frames and textual frame dumps are
+//! derived from this representation. Floats are quantized to fixed-point integers so
+//! that insignificant differences in floating point results don't affect the output.
+
+use crate::{
+    color::Srgba,
+    math::{Point, Quad, Rect},
+    renderer::primitives::{
+        ClippedPrimitive, DrawPrimitive, LinePrimitive, NineSlicePrimitive, NineSliceTexture,
+        QuadPrimitive, QuadTexture, RectPrimitive, RectTexture, TextPrimitive,
+    },
+};
+use std::{fmt::Write, hash::Hasher};
+
+/// Number of quantization steps per unit
+const QUANTIZATION_STEPS: f32 = 256.0;
+
+#[inline]
+fn quantize(value: f32) -> i64 {
+    // NaN is mapped to 0 by the cast
+    (value * QUANTIZATION_STEPS).round() as i64
+}
+
+/// Receives the canonical representation of a primitive.
+pub trait CanonicalWriter {
+    /// Marks the start of a primitive or one of its parts
+    fn write_tag(&mut self, tag: &'static str);
+    fn write_int(&mut self, value: i64);
+
+    #[inline]
+    fn write_f32(&mut self, value: f32) {
+        self.write_int(quantize(value));
+    }
+}
+
+pub trait Canonical {
+    fn write_canonical<W: CanonicalWriter>(&self, writer: &mut W);
+}
+
+/// Feeds the canonical representation into a [`Hasher`].
+pub struct HashWriter<'a, H: Hasher>(pub &'a mut H);
+
+impl<H: Hasher> CanonicalWriter for HashWriter<'_, H> {
+    #[inline]
+    fn write_tag(&mut self, tag: &'static str) {
+        self.0.write(tag.as_bytes());
+    }
+
+    #[inline]
+    fn write_int(&mut self, value: i64) {
+        self.0.write_i64(value);
+    }
+}
+
+/// Writes the canonical representation as text. One line per primitive.
+#[derive(Default)]
+pub struct TextWriter {
+    pub output: String,
+}
+
+impl CanonicalWriter for TextWriter {
+    fn write_tag(&mut self, tag: &'static str) {
+        if !self.output.is_empty() && !self.output.ends_with('\n') {
+            self.output.push(' ');
+        }
+        self.output.push_str(tag);
+    }
+
+    fn write_int(&mut self, value: i64) {
+        let _ = write!(self.output, " {value}");
+    }
+
+    fn write_f32(&mut self, value: f32) {
+        // print the quantized value so that the text matches what gets hashed
+        let _ = write!(
+            self.output,
+            " {}",
+            quantize(value) as f64 / QUANTIZATION_STEPS as f64
+        );
+    }
+}
+
+/// Dumps primitives in their canonical text form, e.g. for comparing frames.
+pub fn dump<'a>(primitives: impl IntoIterator<Item = &'a ClippedPrimitive>) -> String {
+    let mut writer = TextWriter::default();
+    for primitive in primitives {
+        primitive.write_canonical(&mut writer);
+        writer.output.push('\n');
+    }
+    writer.output
+}
+
+fn write_point<W: CanonicalWriter, U>(point: &Point<f32, U>, writer: &mut W) {
+    writer.write_f32(point.x);
+    writer.write_f32(point.y);
+}
+
+fn write_rect<W: CanonicalWriter, U>(rect: &Rect<f32, U>, writer: &mut W) {
+    write_point(&rect.min, writer);
+    write_point(&rect.max, writer);
+}
+
+fn write_quad<W: CanonicalWriter, U>(quad: &Quad<f32, U>, writer: &mut W) {
+    write_point(&quad.p0, writer);
+    write_point(&quad.p1, writer);
+    write_point(&quad.p2, writer);
+    write_point(&quad.p3, writer);
+}
+
+fn write_color<W: CanonicalWriter>(color: &Srgba, writer: &mut W) {
+    writer.write_int(u32::from_be_bytes(color.0) as i64);
+}
+
+impl Canonical for ClippedPrimitive {
+    fn write_canonical<W: CanonicalWriter>(&self, writer: &mut W) {
+        writer.write_tag("clip");
+        write_rect(&self.clip_rect, writer);
+        self.primitive.write_canonical(writer);
+    }
+}
+
+impl Canonical for DrawPrimitive {
+    fn write_canonical<W: CanonicalWriter>(&self, writer: &mut W) {
+        match self {
+            DrawPrimitive::Rect(rect_primitive) => rect_primitive.write_canonical(writer),
+            DrawPrimitive::Quad(quad_primitive) => quad_primitive.write_canonical(writer),
+            DrawPrimitive::NineSlice(nine_slice_primitive) => {
+                nine_slice_primitive.write_canonical(writer)
+            }
+            DrawPrimitive::Text(text_primitive) => text_primitive.write_canonical(writer),
+            DrawPrimitive::Line(line_primitive) => line_primitive.write_canonical(writer),
+        }
+    }
+}
+
+impl Canonical for RectPrimitive {
+    fn write_canonical<W: CanonicalWriter>(&self, writer: &mut W) {
+        writer.write_tag("rect");
+        write_rect(&self.rect, writer);
+        write_color(&self.color, writer);
+        if let Some(texture) = &self.texture {
+            texture.write_canonical(writer);
+        }
+    }
+}
+
+impl Canonical for RectTexture {
+    fn write_canonical<W: CanonicalWriter>(&self, writer: &mut W) {
+        writer.write_tag("tex");
+        writer.write_int(self.texture_id as i64);
+        write_rect(&self.uv, writer);
+        writer.write_int(self.layer_idx as i64);
+    }
+}
+
+impl Canonical for QuadPrimitive {
+    fn write_canonical<W: CanonicalWriter>(&self, writer: &mut W) {
+        writer.write_tag("quad");
+        write_quad(&self.quad, writer);
+        for color in &self.colors {
+            write_color(color, writer);
+        }
+        if let Some(texture) = &self.texture {
+            texture.write_canonical(writer);
+        }
+    }
+}
+
+impl Canonical for QuadTexture {
+    fn write_canonical<W: CanonicalWriter>(&self, writer: &mut W) {
+        writer.write_tag("tex");
+        writer.write_int(self.texture_id as i64);
+        write_quad(&self.uv, writer);
+        writer.write_int(self.layer_idx as i64);
+    }
+}
+
+impl Canonical for NineSlicePrimitive {
+    fn write_canonical<W: CanonicalWriter>(&self, writer: &mut W) {
+        writer.write_tag("nine_slice");
+        write_rect(&self.rect, writer);
+        for inset in self.insets {
+            writer.write_f32(inset);
+        }
+        write_color(&self.color, writer);
+        if let Some(texture) = &self.texture {
+            texture.write_canonical(writer);
+        }
+    }
+}
+
+impl Canonical for NineSliceTexture {
+    fn write_canonical<W: CanonicalWriter>(&self, writer: &mut W) {
+        writer.write_tag("tex");
+        writer.write_int(self.texture_id as i64);
+        write_rect(&self.uv, writer);
+        for inset in self.uv_insets {
+            writer.write_f32(inset);
+        }
+        writer.write_int(self.layer_idx as i64);
+    }
+}
+
+impl Canonical for TextPrimitive {
+    fn write_canonical<W: CanonicalWriter>(&self, writer: &mut W) {
+        writer.write_tag("text");
+        write_point(&self.pos, writer);
+        writer.write_f32(self.rotation);
+        // the job hash is computed with a stable hasher, see `util::stable_hash`
+        writer.write_int(self.layout.job_hash as i64);
+        writer.write_int(self.layout.subpixel_text as i64);
+    }
+}
+
+impl Canonical for LinePrimitive {
+    fn write_canonical<W: CanonicalWriter>(&self, writer: &mut W) {
+        writer.write_tag("line");
+        writer.write_f32(self.width);
+        write_color(&self.color, writer);
+        writer.write_int(self.points.len() as i64);
+        for point in &self.points {
+            write_point(point, writer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{math::Size, util::stable_hash};
+
+    fn primitives() -> Vec<ClippedPrimitive> {
+        let clip_rect = Rect::from_size(Size::new(800.0, 600.0));
+        vec![
+            ClippedPrimitive {
+                clip_rect,
+                primitive: DrawPrimitive::Rect(RectPrimitive::new(
+                    Rect::from_origin_and_size(Point::new(10.0, 20.5), Size::new(0.1, 0.2)),
+                    Srgba::new(255, 128, 0, 255),
+                    Some(RectTexture::new(3, Rect::from_size(Size::new(1.0, 1.0)), 2)),
+                )),
+            },
+            ClippedPrimitive {
+                clip_rect,
+                primitive: DrawPrimitive::Quad(QuadPrimitive::with_colors(
+                    Quad::from_size(Size::new(4.0, 4.0)),
+                    [
+                        Srgba::WHITE,
+                        Srgba::WHITE,
+                        Srgba::TRANSPARENT,
+                        Srgba::TRANSPARENT,
+                    ],
+                    None,
+                )),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_canonical_dump() {
+        assert_eq!(
+            dump(&primitives()),
+            "clip 0 0 800 600 rect 10 20.5 10.1015625 20.69921875 4286578943 tex 3 0 0 1 1 2\n\
+             clip 0 0 800 600 quad 0 0 4 0 4 4 0 4 4294967295 4294967295 0 0\n"
+        );
+    }
+
+    #[test]
+    fn test_canonical_hash_ignores_float_noise() {
+        let mut noisy = primitives();
+        if let DrawPrimitive::Rect(rect_primitive) = &mut noisy[0].primitive {
+            rect_primitive.rect.max.x += 1e-5;
+        }
+        assert_eq!(stable_hash(&primitives()), stable_hash(&noisy));
+    }
+
+    #[test]
+    fn test_canonical_hash_is_stable() {
+        assert_eq!(stable_hash(&primitives()), 10538956189332834087);
+    }
+}
@@ -1,6 +1,21 @@
 use crate::{color::Srgba, renderer::textures::TextureOptions};
-use image::{DynamicImage, RgbaImage};
-use std::{io::Read, num::NonZeroU32, path::Path};
+use image::{
+    AnimationDecoder, DynamicImage, ImageFormat, RgbaImage,
+    codecs::{gif::GifDecoder, webp::WebPDecoder},
+};
+use std::{
+    io::{Cursor, Read},
+    num::NonZeroU32,
+    path::Path,
+    time::Duration,
+};
+
+/// Frames of an animation beyond this are dropped. Matches the array layer limit
+/// the device is requested with.
+const MAX_ANIMATION_FRAMES: usize = 512;
+/// Frames that would take the decoded animation beyond this are dropped as well,
+/// every frame takes up the full canvas
+const MAX_ANIMATION_SIZE: usize = 256 << 20;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ImageDelta {
@@ -15,6 +30,19 @@ impl ImageDelta {
             options,
         }
     }
+
+    /// Estimated GPU memory of the texture created from this delta, including
+    /// generated mipmaps, which add about a third.
+    pub fn gpu_bytes(&self) -> usize {
+        let generates_mipmaps = self.options.generate_mipmaps
+            && self.image.mipmap_count.get() == 1
+            && !self.image.format.is_compressed();
+        if generates_mipmaps {
+            self.image.bytes.len() * 4 / 3
+        } else {
+            self.image.bytes.len()
+        }
+    }
 }
 
 /// Order in which data is laid out.
@@ -48,6 +76,11 @@ pub struct ImageData {
     pub array_layers: u32,
     pub mipmap_count: NonZeroU32,
     pub data_order: DataOrder,
+    /// Color channels are multiplied by alpha, see [`Self::premultiply_alpha`]
+    pub premultiplied_alpha: bool,
+    /// How long each array layer is shown if the image is an animation, whose
+    /// frames are stored as layers. Empty for still images.
+    pub frame_durations: Vec<Duration>,
     pub bytes: Vec<u8>,
 }
 
@@ -62,9 +95,27 @@ impl ImageData {
             array_layers: 1,
             mipmap_count: NonZeroU32::new(1).expect("1 is non-zero"),
             data_order: Default::default(),
+            premultiplied_alpha: false,
+            frame_durations: Vec::new(),
             bytes: RgbaImage::from_pixel(width, height, color.0.into()).into_raw(),
         }
     }
+
+    /// Multiplies the color channels of uncompressed RGBA8 images by alpha, so that
+    /// filtering doesn't mix in the color of transparent texels. Returns whether the
+    /// image is premultiplied afterwards.
+    pub fn premultiply_alpha(&mut self) -> bool {
+        if !self.premultiplied_alpha && self.format == wgpu::TextureFormat::Rgba8Unorm {
+            for pixel in self.bytes.chunks_exact_mut(4) {
+                let alpha = pixel[3] as u32;
+                for channel in &mut pixel[..3] {
+                    *channel = ((*channel as u32 * alpha + 127) / 255) as u8;
+                }
+            }
+            self.premultiplied_alpha = true;
+        }
+        self.premultiplied_alpha
+    }
 }
 
 impl From<DynamicImage> for ImageData {
@@ -76,6 +127,8 @@ impl From<DynamicImage> for ImageData {
             array_layers: 1,
             mipmap_count: NonZeroU32::new(1).expect("1 is non-zero"),
             data_order: Default::default(),
+            premultiplied_alpha: false,
+            frame_durations: Vec::new(),
             bytes: image.to_rgba8().into_raw(),
         }
     }
@@ -90,6 +143,8 @@ impl From<RgbaImage> for ImageData {
             array_layers: 1,
             mipmap_count: NonZeroU32::new(1).expect("1 is non-zero"),
             data_order: Default::default(),
+            premultiplied_alpha: false,
+            frame_durations: Vec::new(),
             bytes: image.into_raw(),
         }
     }
@@ -103,6 +158,8 @@ impl std::fmt::Debug for ImageData {
             .field("height", &self.height)
             .field("array_layers", &self.array_layers)
             .field("mipmap_count", &self.mipmap_count)
+            .field("premultiplied_alpha", &self.premultiplied_alpha)
+            .field("frame_durations", &self.frame_durations)
             .finish()
     }
 }
@@ -114,11 +171,70 @@ pub fn load_image_file<P: AsRef<Path>>(path: P) -> anyhow::Result<ImageData> {
         load_compressed_dds(&path)
     } else {
         // let image crate deal with other file types
-        let image = image::ImageReader::open(&path)?.decode()?;
-        Ok(image.into())
+        let reader = image::ImageReader::open(&path)?;
+        if matches!(reader.format(), Some(ImageFormat::WebP | ImageFormat::Gif)) {
+            // may be animated
+            return load_image_bytes(&std::fs::read(&path)?);
+        }
+        Ok(reader.decode()?.into())
     }
 }
 
+/// Decodes an image in memory, e.g. a PNG. The format is guessed from its content.
+pub fn load_image_bytes(bytes: &[u8]) -> anyhow::Result<ImageData> {
+    match image::guess_format(bytes).ok() {
+        Some(ImageFormat::WebP) => {
+            let decoder = WebPDecoder::new(Cursor::new(bytes))?;
+            if decoder.has_animation() {
+                return load_animation(decoder.into_frames());
+            }
+        }
+        // GIFs with a single frame are loaded like animations with one frame
+        Some(ImageFormat::Gif) => {
+            return load_animation(GifDecoder::new(Cursor::new(bytes))?.into_frames());
+        }
+        _ => {}
+    }
+    let image = image::load_from_memory(bytes)?;
+    Ok(image.into())
+}
+
+/// Decodes the frames of an animated WebP or GIF image into the layers of an
+/// array texture.
+fn load_animation(frames: image::Frames) -> anyhow::Result<ImageData> {
+    let mut image: Option<ImageData> = None;
+    for (i, frame) in frames.enumerate() {
+        if i == MAX_ANIMATION_FRAMES {
+            log::warn!("Animation has more than {MAX_ANIMATION_FRAMES} frames, dropping the rest");
+            break;
+        }
+        let frame = frame?;
+        let duration = Duration::from(frame.delay());
+        // frames are composited onto the full canvas, so all have the same size
+        let buffer = frame.into_buffer();
+        match &mut image {
+            Some(image) if image.bytes.len() + buffer.len() > MAX_ANIMATION_SIZE => {
+                log::warn!(
+                    "Animation is larger than {} MiB, dropping the remaining frames",
+                    MAX_ANIMATION_SIZE >> 20
+                );
+                break;
+            }
+            Some(image) => {
+                image.bytes.extend_from_slice(buffer.as_raw());
+                image.array_layers += 1;
+                image.frame_durations.push(duration);
+            }
+            None => {
+                let mut first = ImageData::from(buffer);
+                first.frame_durations.push(duration);
+                image = Some(first);
+            }
+        }
+    }
+    image.ok_or_else(|| anyhow::anyhow!("Animation has no frames"))
+}
+
 /// Attempts to find the file, trying lowercase filename if it doesn't exist.
 ///
 /// NOTE: PoB2 assumes a case insensitive filesystem, so checking the lowercase name
@@ -185,6 +301,45 @@ fn load_compressed_dds<P: AsRef<Path>>(path: P) -> anyhow::Result<ImageData> {
         array_layers: header.array_size(),
         mipmap_count: header.mipmap_count(),
         data_order: DataOrder::LayerMajor,
+        premultiplied_alpha: false,
+        frame_durations: Vec::new(),
         bytes: pixel_data,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_premultiply_alpha() {
+        let mut image = ImageData::from_solid_color([2, 1], Srgba::new(255, 128, 0, 128));
+        assert!(image.premultiply_alpha());
+        assert_eq!(image.bytes, [128, 64, 0, 128, 128, 64, 0, 128]);
+
+        // converting again doesn't change the image
+        assert!(image.premultiply_alpha());
+        assert_eq!(image.bytes[..4], [128, 64, 0, 128]);
+    }
+
+    #[test]
+    fn test_load_animated_gif() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/animated.gif");
+        let image = load_image_file(path).unwrap();
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(image.array_layers, 3);
+        assert_eq!(
+            image.frame_durations,
+            [100, 200, 300].map(Duration::from_millis)
+        );
+
+        // a red, a green and a blue frame
+        let frame_size = 2 * 2 * 4;
+        for (frame, color) in [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]]
+            .iter()
+            .enumerate()
+        {
+            assert_eq!(image.bytes[frame * frame_size..][..4], *color);
+        }
+    }
+}
@@ -12,6 +12,11 @@ struct Globals {
 
 @group(0) @binding(0) var<uniform> r_globals: Globals;
 
+// Set when the output is an extended-range linear (scRGB) surface for HDR displays.
+override linear_output: bool = false;
+// Brightness of white relative to scRGB's reference white of 80 nits.
+override white_scale: f32 = 1.0;
+
 // [u8; 4] as u32 -> [f32; 4]
 fn unpack_color(color: u32) -> vec4<f32> {
     return vec4<f32>(
@@ -22,6 +27,28 @@ fn unpack_color(color: u32) -> vec4<f32> {
     ) / 255.0;
 }
 
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+    let lower = color / 12.92;
+    let higher = pow((color + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(higher, lower, color <= vec3<f32>(0.04045));
+}
+
+// Converts an sRGB color to the color space of the output
+fn encode_output(color: vec4<f32>) -> vec4<f32> {
+    if linear_output {
+        return vec4<f32>(srgb_to_linear(color.rgb) * white_scale, color.a);
+    }
+    return color;
+}
+
+// Converts a premultiplied sRGB color to the color space of the output
+fn encode_premultiplied_output(color: vec4<f32>) -> vec4<f32> {
+    if linear_output && color.a > 0.0 {
+        return vec4<f32>(srgb_to_linear(color.rgb / color.a) * white_scale * color.a, color.a);
+    }
+    return color;
+}
+
 fn position_from_screen(screen_pos: vec2<f32>) -> vec4<f32> {
     return vec4<f32>(
         2.0 * screen_pos.x / r_globals.screen_size.x - 1.0,
@@ -56,7 +83,18 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     // Vertex colors, texture samples, and the output color are all in sRGB.
     // Texture formats and output surface formats are selected such that no automatic
     // conversion between linear <-> sRGB is performed.
+    // The exception are linear HDR outputs, where blending happens in linear space.
     let tex_color = textureSample(r_tex_color, r_tex_sampler, in.tex_coord, in.layer_idx);
     var out_color = in.color * tex_color;
-    return out_color;
+    return encode_output(out_color);
+}
+
+@fragment
+fn fs_premultiplied(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Textures with premultiplied alpha are filtered without mixing in the color of
+    // transparent texels, which PoB's straight alpha leaves as dark fringes.
+    // The output is premultiplied as well and blended accordingly.
+    let tex_color = textureSample(r_tex_color, r_tex_sampler, in.tex_coord, in.layer_idx);
+    let vertex_color = vec4<f32>(in.color.rgb * in.color.a, in.color.a);
+    return encode_premultiplied_output(vertex_color * tex_color);
 }
@@ -1,11 +1,12 @@
 use crate::{
     color::Srgba,
     dpi::{
-        LogicalPoint, LogicalQuad, LogicalRect, NormalizedPoint, NormalizedQuad, NormalizedRect,
+        LogicalPoint, LogicalQuad, LogicalRect, NormalizedPoint, NormalizedQuad, NormalizedRect, Uv,
     },
     math::Corners,
     renderer::textures::TextureId,
 };
+use std::sync::Arc;
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
@@ -24,6 +25,8 @@ pub struct Mesh {
     pub vertices: Vec<Vertex>,
     pub indices: Vec<u32>,
     pub texture_id: TextureId,
+    /// Mesh consists of subpixel text and needs to be drawn with the subpixel pipeline
+    pub subpixel_text: bool,
 }
 
 impl Mesh {
@@ -74,6 +77,18 @@ impl Mesh {
         uv: NormalizedQuad,
         color: Srgba,
         layer_idx: u32,
+    ) {
+        self.add_gradient_quad(quad, uv, [color; 4], layer_idx);
+    }
+
+    /// Adds a quad with a separate color for each corner.
+    #[inline]
+    pub fn add_gradient_quad(
+        &mut self,
+        quad: LogicalQuad<f32>,
+        uv: NormalizedQuad,
+        colors: [Srgba; 4],
+        layer_idx: u32,
     ) {
         let i = self.vertices.len() as u32;
         self.indices
@@ -83,37 +98,90 @@ impl Mesh {
             Vertex {
                 pos: quad.p0,
                 uv: uv.p0,
-                color,
+                color: colors[0],
                 layer_idx,
             },
             Vertex {
                 pos: quad.p1,
                 uv: uv.p1,
-                color,
+                color: colors[1],
                 layer_idx,
             },
             Vertex {
                 pos: quad.p2,
                 uv: uv.p2,
-                color,
+                color: colors[2],
                 layer_idx,
             },
             Vertex {
                 pos: quad.p3,
                 uv: uv.p3,
-                color,
+                color: colors[3],
                 layer_idx,
             },
         ]);
     }
 
+    /// Adds an untextured vertex and returns its index.
+    #[inline]
+    pub fn add_colored_vertex(&mut self, pos: LogicalPoint<f32>, color: Srgba) -> u32 {
+        let i = self.vertices.len() as u32;
+        self.vertices.push(Vertex {
+            pos,
+            uv: NormalizedPoint::white_uv(),
+            color,
+            layer_idx: 0,
+        });
+        i
+    }
+
+    #[inline]
+    pub fn add_triangle(&mut self, a: u32, b: u32, c: u32) {
+        self.indices.extend_from_slice(&[a, b, c]);
+    }
+
     pub fn is_empty(&self) -> bool {
         self.vertices.is_empty() && self.indices.is_empty()
     }
+
+    /// Appends the vertices and indices of `other`.
+    pub fn append(&mut self, other: Mesh) {
+        let offset = self.vertices.len() as u32;
+        self.indices
+            .extend(other.indices.iter().map(|index| index + offset));
+        self.vertices.extend(other.vertices);
+    }
+}
+
+/// Identifies a mesh across frames. The meshes of a [`PrimitiveGroup`] are numbered
+/// in drawing order.
+///
+/// [`PrimitiveGroup`]: crate::renderer::primitives::PrimitiveGroup
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MeshId {
+    pub group: u64,
+    pub index: usize,
 }
 
+#[derive(Clone)]
 pub struct ClippedMesh {
     // Only parts of the mesh that intersect with this will be rendered
     pub clip_rect: LogicalRect<f32>,
-    pub mesh: Mesh,
+    /// Shared with the tessellator's cache, so unchanged meshes aren't copied
+    /// every frame
+    pub mesh: Arc<Mesh>,
+    /// `None` for primitives outside of any group
+    pub id: Option<MeshId>,
+    /// Mesh differs from the one with the same id in the last frame
+    pub is_dirty: bool,
+}
+
+impl ClippedMesh {
+    /// Whether `other` can be drawn as part of this mesh.
+    pub fn can_append(&self, other: &ClippedMesh) -> bool {
+        self.id.map(|id| id.group) == other.id.map(|id| id.group)
+            && self.clip_rect == other.clip_rect
+            && self.mesh.texture_id == other.mesh.texture_id
+            && self.mesh.subpixel_text == other.mesh.subpixel_text
+    }
 }
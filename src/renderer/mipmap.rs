@@ -1,6 +1,160 @@
-/// Generate entire mipmap chain for all layers starting from mip level 0 image data.
+use ahash::HashMap;
+use std::borrow::Cow;
+
+/// Generates mipmaps on the GPU by rendering each mip level from the previous one.
+///
+/// Requires views with a different format than the texture (the sRGB variant), so
+/// it isn't available on every adapter, see [`Self::supports`].
+pub struct MipmapGenerator {
+    shader_module: wgpu::ShaderModule,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    // one per format of the sRGB views that are rendered to and whether the
+    // texture has premultiplied alpha
+    pipelines: HashMap<(wgpu::TextureFormat, bool), wgpu::RenderPipeline>,
+}
+
+impl MipmapGenerator {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("mipmap_shader_module"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("mipmap.wgsl"))),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("mipmap_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            }],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("mipmap_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        Self {
+            shader_module,
+            bind_group_layout,
+            pipeline_layout,
+            pipelines: HashMap::default(),
+        }
+    }
+
+    /// Whether mipmaps of textures with `format` can be generated on the GPU.
+    /// Image data is sRGB encoded, so the format needs an sRGB variant.
+    pub fn supports(format: wgpu::TextureFormat) -> bool {
+        !format.is_compressed() && format.add_srgb_suffix().is_srgb()
+    }
+
+    /// Generates all mip levels of all layers from mip level 0.
+    /// The texture needs `RENDER_ATTACHMENT` usage and its sRGB variant as view format.
+    pub fn generate(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+        premultiplied_alpha: bool,
+    ) {
+        let view_format = texture.format().add_srgb_suffix();
+        let key = (view_format, premultiplied_alpha);
+        let pipeline = self.pipelines.entry(key).or_insert_with(|| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("mipmap_pipeline"),
+                layout: Some(&self.pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &self.shader_module,
+                    entry_point: Some("vs_main"),
+                    compilation_options: Default::default(),
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &self.shader_module,
+                    entry_point: Some("fs_main"),
+                    compilation_options: wgpu::PipelineCompilationOptions {
+                        constants: &[(
+                            "premultiplied_alpha",
+                            if premultiplied_alpha { 1.0 } else { 0.0 },
+                        )],
+                        ..Default::default()
+                    },
+                    targets: &[Some(view_format.into())],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        });
+
+        let view = |layer: u32, mip: u32| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("mipmap_view"),
+                format: Some(view_format),
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_mip_level: mip,
+                mip_level_count: Some(1),
+                base_array_layer: layer,
+                array_layer_count: Some(1),
+                ..Default::default()
+            })
+        };
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("mipmap_encoder"),
+        });
+        for layer in 0..texture.depth_or_array_layers() {
+            for mip in 1..texture.mip_level_count() {
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("mipmap_bind_group"),
+                    layout: &self.bind_group_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&view(layer, mip - 1)),
+                    }],
+                });
+                let target = view(layer, mip);
+
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("mipmap_render_pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &target,
+                        depth_slice: None,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    ..Default::default()
+                });
+                render_pass.set_pipeline(pipeline);
+                render_pass.set_bind_group(0, &bind_group, &[]);
+                render_pass.draw(0..3, 0..1);
+            }
+        }
+        queue.submit([encoder.finish()]);
+    }
+}
+
+/// Generate entire mipmap chain for all layers starting from mip level 0 image data
+/// on the CPU. Used if [`MipmapGenerator`] isn't available.
 /// Assumes data to contain one mip level 0 image for each layer in RGBA format.
-pub fn generate_mipmap_chain(queue: &wgpu::Queue, texture: &wgpu::Texture, data: &[u8]) {
+pub fn generate_mipmap_chain(
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    data: &[u8],
+    premultiplied_alpha: bool,
+) {
     assert!(!texture.format().is_compressed());
     assert!(texture.format().components() == 4);
 
@@ -8,69 +162,22 @@ pub fn generate_mipmap_chain(queue: &wgpu::Queue, texture: &wgpu::Texture, data:
     let block_size = texture.format().block_copy_size(None).unwrap_or(4);
     let data_size = (size.width * size.height * block_size) as usize;
 
-    let srgb_mapper = fast_image_resize::create_srgb_mapper();
-
     let mut data_offset = 0;
     for layer in 0..texture.depth_or_array_layers() {
-        let bytes = data[data_offset..data_size].to_owned();
-        let mut src_image = fast_image_resize::images::Image::from_vec_u8(
-            size.width,
-            size.height,
-            bytes,
-            fast_image_resize::PixelType::U8x4,
-        )
-        .unwrap();
+        let mut src_image = data[data_offset..data_offset + data_size].to_owned();
+        let mut src_size = (size.width, size.height);
 
         for mip in 1..texture.mip_level_count() {
             let mut target_size = size.mip_level_size(mip, wgpu::TextureDimension::D2);
             target_size.depth_or_array_layers = 1; // copying layers separately
 
-            // intermediate buffer that stores linear source image data in a higher
-            // bit depth to avoid loss of information
-            let mut linear_src_image = fast_image_resize::images::Image::new(
-                src_image.width(),
-                src_image.height(),
-                fast_image_resize::PixelType::U16x4,
+            let dst_image = downsample(
+                &src_image,
+                src_size,
+                (target_size.width, target_size.height),
+                premultiplied_alpha,
             );
 
-            // convert from sRGB space into linear space
-            // resizing needs to be performed in linear space to get the correct visual result
-            srgb_mapper
-                .forward_map(&src_image, &mut linear_src_image)
-                .unwrap();
-
-            let mut dst_image = fast_image_resize::images::Image::new(
-                target_size.width,
-                target_size.height,
-                fast_image_resize::PixelType::U8x4,
-            );
-
-            let mut linear_dst_image = fast_image_resize::images::Image::new(
-                dst_image.width(),
-                dst_image.height(),
-                fast_image_resize::PixelType::U16x4,
-            );
-
-            let mut resizer = fast_image_resize::Resizer::new();
-            resizer
-                .resize(
-                    &linear_src_image,
-                    &mut linear_dst_image,
-                    Some(&fast_image_resize::ResizeOptions {
-                        algorithm: fast_image_resize::ResizeAlg::Convolution(
-                            fast_image_resize::FilterType::Bilinear,
-                        ),
-                        cropping: fast_image_resize::SrcCropping::None,
-                        mul_div_alpha: true,
-                    }),
-                )
-                .unwrap();
-
-            // texture needs to be in sRGB, so map back from resized linear image
-            srgb_mapper
-                .backward_map(&linear_dst_image, &mut dst_image)
-                .unwrap();
-
             queue.write_texture(
                 wgpu::TexelCopyTextureInfo {
                     texture,
@@ -82,7 +189,7 @@ pub fn generate_mipmap_chain(queue: &wgpu::Queue, texture: &wgpu::Texture, data:
                     },
                     aspect: wgpu::wgt::TextureAspect::All,
                 },
-                dst_image.buffer(),
+                &dst_image,
                 wgpu::TexelCopyBufferLayout {
                     offset: 0,
                     bytes_per_row: Some(block_size * target_size.width),
@@ -92,8 +199,98 @@ pub fn generate_mipmap_chain(queue: &wgpu::Queue, texture: &wgpu::Texture, data:
             );
 
             src_image = dst_image;
+            src_size = (target_size.width, target_size.height);
         }
 
         data_offset += data_size;
     }
 }
+
+/// Downsamples an sRGB RGBA image into the next mip level the same way as
+/// `mipmap.wgsl`: each texel is the average of the 2x2 source texels in linear
+/// space, with colors weighted by alpha unless they're premultiplied already.
+fn downsample(
+    src: &[u8],
+    (src_width, src_height): (u32, u32),
+    (dst_width, dst_height): (u32, u32),
+    premultiplied_alpha: bool,
+) -> Vec<u8> {
+    let to_linear = srgb_to_linear_table();
+    let mut dst = Vec::with_capacity((dst_width * dst_height * 4) as usize);
+
+    for dst_y in 0..dst_height {
+        for dst_x in 0..dst_width {
+            let mut sum = [0.0f32; 4];
+            for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let src_x = (dst_x * 2 + x).min(src_width - 1);
+                let src_y = (dst_y * 2 + y).min(src_height - 1);
+                let i = ((src_y * src_width + src_x) * 4) as usize;
+                let alpha = src[i + 3] as f32 / 255.0;
+                let weight = if premultiplied_alpha { 1.0 } else { alpha };
+                for c in 0..3 {
+                    sum[c] += to_linear[src[i + c] as usize] * weight;
+                }
+                sum[3] += alpha;
+            }
+
+            let color_scale = if premultiplied_alpha {
+                0.25
+            } else if sum[3] == 0.0 {
+                0.0
+            } else {
+                1.0 / sum[3]
+            };
+            for c in &sum[..3] {
+                dst.push(linear_to_srgb(c * color_scale));
+            }
+            dst.push((sum[3] / 4.0 * 255.0).round() as u8);
+        }
+    }
+    dst
+}
+
+fn srgb_to_linear_table() -> &'static [f32; 256] {
+    static TABLE: std::sync::OnceLock<[f32; 256]> = std::sync::OnceLock::new();
+    TABLE.get_or_init(|| {
+        std::array::from_fn(|i| {
+            let c = i as f32 / 255.0;
+            if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        })
+    })
+}
+
+fn linear_to_srgb(c: f32) -> u8 {
+    let c = c.clamp(0.0, 1.0);
+    let srgb = if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_downsample_weights_colors_by_alpha() {
+        // one opaque red texel next to transparent black ones
+        let src = [255, 0, 0, 255, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(downsample(&src, (2, 2), (1, 1), false), [255, 0, 0, 64]);
+
+        // premultiplied colors are averaged as they are
+        let src = [255, 255, 255, 255, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let dst = downsample(&src, (2, 2), (1, 1), true);
+        assert_eq!(dst[3], 64);
+        assert_eq!(dst[0], linear_to_srgb(0.25));
+
+        // odd sizes clamp to the last row and column
+        let src = [10, 20, 30, 255];
+        assert_eq!(downsample(&src, (1, 1), (1, 1), false), [10, 20, 30, 255]);
+    }
+}
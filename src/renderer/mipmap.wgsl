@@ -0,0 +1,44 @@
+// Downsamples a mip level into the next one. Both are bound through sRGB views,
+// so texels are converted to linear space when loaded and back when stored.
+
+@group(0) @binding(0) var src_texture: texture_2d<f32>;
+
+// Set when the texture's colors are premultiplied by alpha.
+override premultiplied_alpha: bool = false;
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    // triangle that covers the whole target
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let src_max = vec2<i32>(textureDimensions(src_texture)) - 1;
+    let dst = vec2<i32>(position.xy);
+
+    // average of the 2x2 source texels, weighted by alpha so that the color of
+    // transparent texels doesn't bleed into their neighbours. Premultiplied
+    // colors are already weighted.
+    var sum = vec4<f32>(0.0);
+    for (var y = 0; y < 2; y++) {
+        for (var x = 0; x < 2; x++) {
+            let texel = textureLoad(src_texture, min(dst * 2 + vec2<i32>(x, y), src_max), 0);
+            if premultiplied_alpha {
+                sum += texel;
+            } else {
+                sum += vec4<f32>(texel.rgb * texel.a, texel.a);
+            }
+        }
+    }
+
+    if premultiplied_alpha {
+        return sum / 4.0;
+    }
+
+    if sum.a == 0.0 {
+        return vec4<f32>(0.0);
+    }
+    return vec4<f32>(sum.rgb / sum.a, sum.a / 4.0);
+}
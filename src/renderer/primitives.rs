@@ -2,34 +2,53 @@ use crate::{
     color::Srgba,
     dpi::{LogicalPoint, LogicalQuad, LogicalRect, LogicalVector, NormalizedQuad, NormalizedRect},
     fonts::Layout,
-    math::Point,
-    renderer::textures::TextureId,
+    math::{Point, Rect},
+    renderer::{
+        canonical::{Canonical, HashWriter},
+        textures::TextureId,
+    },
 };
-use ordered_float::OrderedFloat;
 use std::{
     hash::{Hash, Hasher},
     sync::Arc,
 };
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct ClippedPrimitive {
     pub clip_rect: LogicalRect<f32>,
     pub primitive: DrawPrimitive,
 }
 
+/// Consecutive primitives, e.g. those of a draw layer, that are tessellated into
+/// their own meshes. Meshes of groups that aren't dirty are identical to the last
+/// frame's and don't need to be uploaded again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PrimitiveGroup {
+    /// Stable across frames
+    pub id: u64,
+    /// Number of primitives in the group
+    pub len: usize,
+    /// Hash of the group's primitives
+    pub hash: u64,
+    /// Primitives changed since the last frame
+    pub is_dirty: bool,
+}
+
+// Hashing uses the canonical representation so that frame hashes are deterministic
+// across platforms.
 impl Hash for ClippedPrimitive {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        hash_pos(&self.clip_rect.min, state);
-        hash_pos(&self.clip_rect.max, state);
-        self.primitive.hash(state);
+        self.write_canonical(&mut HashWriter(state));
     }
 }
 
-#[derive(Clone, Hash)]
+#[derive(Clone, PartialEq)]
 pub enum DrawPrimitive {
     Rect(RectPrimitive),
     Quad(QuadPrimitive),
+    NineSlice(NineSlicePrimitive),
     Text(TextPrimitive),
+    Line(LinePrimitive),
 }
 
 impl DrawPrimitive {
@@ -41,12 +60,23 @@ impl DrawPrimitive {
             DrawPrimitive::Quad(quad_primitive) => quad_primitive
                 .texture
                 .map_or_else(TextureId::default, |tex| tex.texture_id),
+            DrawPrimitive::NineSlice(nine_slice) => nine_slice
+                .texture
+                .map_or_else(TextureId::default, |tex| tex.texture_id),
             _ => TextureId::default(),
         }
     }
+
+    /// Whether this is text made of RGB subpixel masks that needs dual-source blending.
+    pub fn is_subpixel_text(&self) -> bool {
+        match self {
+            DrawPrimitive::Text(text_primitive) => text_primitive.layout.subpixel_text,
+            _ => false,
+        }
+    }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 pub struct RectPrimitive {
     pub rect: LogicalRect<f32>,
     pub color: Srgba,
@@ -67,16 +97,7 @@ impl RectPrimitive {
     }
 }
 
-impl Hash for RectPrimitive {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        hash_pos(&self.rect.min, state);
-        hash_pos(&self.rect.max, state);
-        self.color.hash(state);
-        self.texture.hash(state);
-    }
-}
-
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 pub struct RectTexture {
     pub texture_id: TextureId,
     pub uv: NormalizedRect,
@@ -93,27 +114,28 @@ impl RectTexture {
     }
 }
 
-impl Hash for RectTexture {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.texture_id.hash(state);
-        hash_pos(&self.uv.min, state);
-        hash_pos(&self.uv.max, state);
-        self.layer_idx.hash(state);
-    }
-}
-
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 pub struct QuadPrimitive {
     pub quad: LogicalQuad<f32>,
-    pub color: Srgba,
+    /// Color of each corner, in the same order as the quad's points
+    pub colors: [Srgba; 4],
     pub texture: Option<QuadTexture>,
 }
 
 impl QuadPrimitive {
     pub fn new(quad: LogicalQuad<f32>, color: Srgba, texture: Option<QuadTexture>) -> Self {
+        Self::with_colors(quad, [color; 4], texture)
+    }
+
+    /// Creates a quad whose colors are interpolated between its corners.
+    pub fn with_colors(
+        quad: LogicalQuad<f32>,
+        colors: [Srgba; 4],
+        texture: Option<QuadTexture>,
+    ) -> Self {
         Self {
             quad,
-            color,
+            colors,
             texture,
         }
     }
@@ -123,18 +145,7 @@ impl QuadPrimitive {
     }
 }
 
-impl Hash for QuadPrimitive {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        hash_pos(&self.quad.p0, state);
-        hash_pos(&self.quad.p1, state);
-        hash_pos(&self.quad.p2, state);
-        hash_pos(&self.quad.p3, state);
-        self.color.hash(state);
-        self.texture.hash(state);
-    }
-}
-
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 pub struct QuadTexture {
     pub texture_id: TextureId,
     pub uv: NormalizedQuad,
@@ -151,14 +162,109 @@ impl QuadTexture {
     }
 }
 
-impl Hash for QuadTexture {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.texture_id.hash(state);
-        hash_pos(&self.uv.p0, state);
-        hash_pos(&self.uv.p1, state);
-        hash_pos(&self.uv.p2, state);
-        hash_pos(&self.uv.p3, state);
-        self.layer_idx.hash(state);
+/// A rect drawn as a 3x3 grid, e.g. the border of a panel. The corners keep their
+/// size while the edges and the center stretch to fill the rect.
+#[derive(Clone, Copy, PartialEq)]
+pub struct NineSlicePrimitive {
+    pub rect: LogicalRect<f32>,
+    /// Size of the border in points: left, top, right, bottom
+    pub insets: [f32; 4],
+    pub color: Srgba,
+    pub texture: Option<NineSliceTexture>,
+}
+
+impl NineSlicePrimitive {
+    pub fn new(
+        rect: LogicalRect<f32>,
+        insets: [f32; 4],
+        color: Srgba,
+        texture: Option<NineSliceTexture>,
+    ) -> Self {
+        Self {
+            rect,
+            insets,
+            color,
+            texture,
+        }
+    }
+
+    pub fn translate(&mut self, direction: LogicalVector<f32>) {
+        self.rect = self.rect.translate(direction);
+    }
+
+    /// The non-empty cells of the grid as rects, row by row.
+    pub fn slices(&self) -> impl Iterator<Item = RectPrimitive> {
+        let [left, top, right, bottom] = self.insets;
+        let (min, max) = (self.rect.min, self.rect.max);
+        // borders that don't fit into the rect are shrunk proportionally
+        let scale_x = (self.rect.width() / (left + right)).min(1.0);
+        let scale_y = (self.rect.height() / (top + bottom)).min(1.0);
+        let xs = [
+            min.x,
+            min.x + left * scale_x,
+            max.x - right * scale_x,
+            max.x,
+        ];
+        let ys = [
+            min.y,
+            min.y + top * scale_y,
+            max.y - bottom * scale_y,
+            max.y,
+        ];
+
+        let texture = self.texture.map(|texture| {
+            let [left, top, right, bottom] = texture.uv_insets;
+            let (min, max) = (texture.uv.min, texture.uv.max);
+            let us = [min.x, min.x + left, max.x - right, max.x];
+            let vs = [min.y, min.y + top, max.y - bottom, max.y];
+            (texture, us, vs)
+        });
+
+        let color = self.color;
+        (0..9).filter_map(move |i| {
+            let (col, row) = (i % 3, i / 3);
+            let rect = Rect::new(
+                Point::new(xs[col], ys[row]),
+                Point::new(xs[col + 1], ys[row + 1]),
+            );
+            if rect.is_empty() {
+                return None;
+            }
+            let texture = texture.map(|(texture, us, vs)| {
+                let uv = Rect::new(
+                    Point::new(us[col], vs[row]),
+                    Point::new(us[col + 1], vs[row + 1]),
+                );
+                RectTexture::new(texture.texture_id, uv, texture.layer_idx)
+            });
+            Some(RectPrimitive::new(rect, color, texture))
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct NineSliceTexture {
+    pub texture_id: TextureId,
+    /// Region of the texture that's sliced
+    pub uv: NormalizedRect,
+    /// Size of the border within `uv`: left, top, right, bottom
+    pub uv_insets: [f32; 4],
+    pub layer_idx: u32,
+}
+
+impl NineSliceTexture {
+    pub fn new(
+        texture_id: TextureId,
+        uv: NormalizedRect,
+        uv_insets: [f32; 4],
+        layer_idx: u32,
+    ) -> Self {
+        Self {
+            texture_id,
+            uv,
+            uv_insets,
+            layer_idx,
+        }
     }
 }
 
@@ -166,11 +272,33 @@ impl Hash for QuadTexture {
 pub struct TextPrimitive {
     pub pos: LogicalPoint<f32>,
     pub layout: Arc<Layout>,
+    /// Clockwise rotation in radians around the layout origin
+    pub rotation: f32,
+}
+
+// layouts are compared by their job like in the canonical representation, the
+// same text is laid out again when the layout cache evicted it
+impl PartialEq for TextPrimitive {
+    fn eq(&self, other: &Self) -> bool {
+        self.pos == other.pos
+            && self.rotation == other.rotation
+            && self.layout.job_hash == other.layout.job_hash
+            && self.layout.subpixel_text == other.layout.subpixel_text
+    }
 }
 
 impl TextPrimitive {
     pub fn new(pos: LogicalPoint<f32>, layout: Arc<Layout>) -> Self {
-        Self { pos, layout }
+        Self {
+            pos,
+            layout,
+            rotation: 0.0,
+        }
+    }
+
+    pub fn with_rotation(mut self, rotation: f32) -> Self {
+        self.rotation = rotation;
+        self
     }
 
     #[inline(always)]
@@ -179,14 +307,26 @@ impl TextPrimitive {
     }
 }
 
-impl Hash for TextPrimitive {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        hash_pos(&self.pos, state);
-        self.layout.hash(state);
-    }
+#[derive(Clone, PartialEq)]
+pub struct LinePrimitive {
+    /// Points of the polyline. Consecutive points are connected by segments.
+    pub points: Vec<LogicalPoint<f32>>,
+    pub width: f32,
+    pub color: Srgba,
 }
 
-fn hash_pos<H: Hasher, U>(pos: &Point<f32, U>, state: &mut H) {
-    OrderedFloat(pos.x).hash(state);
-    OrderedFloat(pos.y).hash(state);
+impl LinePrimitive {
+    pub fn new(points: Vec<LogicalPoint<f32>>, width: f32, color: Srgba) -> Self {
+        Self {
+            points,
+            width,
+            color,
+        }
+    }
+
+    pub fn translate(&mut self, direction: LogicalVector<f32>) {
+        for point in &mut self.points {
+            *point += direction;
+        }
+    }
 }
@@ -0,0 +1,20 @@
+// Appended to main.wgsl when the device supports dual-source blending.
+// NOTE: the `enable dual_source_blending;` directive needs to be at the start of
+// the module and is therefore prepended separately.
+
+struct SubpixelOutput {
+    @location(0) @blend_src(0) color: vec4<f32>,
+    // per-channel blend factors
+    @location(0) @blend_src(1) mask: vec4<f32>,
+};
+
+@fragment
+fn fs_subpixel(in: VertexOutput) -> SubpixelOutput {
+    // Glyphs are stored as RGB subpixel masks, with one coverage value per color channel.
+    // Each channel of the destination is blended separately using its own coverage.
+    let tex_color = textureSample(r_tex_color, r_tex_sampler, in.tex_coord, in.layer_idx);
+    var out: SubpixelOutput;
+    out.color = encode_output(vec4<f32>(in.color.rgb, in.color.a * tex_color.a));
+    out.mask = vec4<f32>(in.color.a * tex_color.rgb, in.color.a * tex_color.a);
+    return out;
+}
@@ -1,36 +1,182 @@
 use crate::{
-    dpi::{ConvertToLogical, ConvertToPhysical, Normalize, NormalizedQuad, NormalizedRect, Uv},
+    color::Srgba,
+    dpi::{
+        ConvertToLogical, ConvertToPhysical, LogicalQuad, LogicalVector, Normalize, NormalizedQuad,
+        NormalizedRect, PhysicalPoint, Uv,
+    },
     fonts::FontAtlasSize,
     renderer::{
-        mesh::{ClippedMesh, Mesh},
+        mesh::{ClippedMesh, Mesh, MeshId},
         primitives::{
-            ClippedPrimitive, DrawPrimitive, QuadPrimitive, QuadTexture, RectPrimitive,
-            RectTexture, TextPrimitive,
+            ClippedPrimitive, DrawPrimitive, LinePrimitive, PrimitiveGroup, QuadPrimitive,
+            QuadTexture, RectPrimitive, RectTexture, TextPrimitive,
         },
         textures::TextureId,
     },
 };
+use ahash::HashMap;
+use euclid::Rotation2D;
+use rayon::prelude::*;
+use std::sync::Arc;
+
+/// Primitives of a changed group are tessellated in chunks of this size
+const CHUNK_SIZE: usize = 512;
+
+/// Fewer primitives are tessellated on the calling thread, since distributing them
+/// to the thread pool takes longer than tessellating them. Compare the
+/// `tessellate_serial` and `tessellate_parallel` profiling scopes when tuning.
+const PARALLEL_MIN_PRIMITIVES: usize = 2048;
 
 /// Converts [`DrawPrimitive`]s into [`Mesh`]es.
 #[derive(Default)]
 pub struct Tessellator {
     last_clipped_meshes_size: usize,
+    // text meshes depend on these, so all meshes are dirty when they change
+    last_font_atlas_size: FontAtlasSize,
+    last_font_atlas_generation: u64,
+    last_pixels_per_point: f32,
+    // meshes of the groups in the last frame by group id, with the hash of the
+    // group's primitives
+    cache: HashMap<u64, (u64, Vec<ClippedMesh>)>,
+}
+
+/// Meshes of a group, being tessellated unless they were cached
+struct GroupMeshes {
+    group: PrimitiveGroup,
+    is_cached: bool,
+    meshes: Vec<ClippedMesh>,
 }
 
 impl Tessellator {
+    /// Converts primitives into meshes. Primitives of different `groups` never share
+    /// a mesh.
     pub fn convert_clipped_primitives(
         &mut self,
-        clipped_primitives: impl Iterator<Item = ClippedPrimitive>,
+        mut clipped_primitives: impl Iterator<Item = ClippedPrimitive>,
+        groups: &[PrimitiveGroup],
         font_atlas_size: FontAtlasSize,
+        font_atlas_generation: u64,
         pixels_per_point: f32,
     ) -> Vec<ClippedMesh> {
         profiling::scope!("convert_primitives");
 
+        let is_all_dirty = font_atlas_size != self.last_font_atlas_size
+            || font_atlas_generation != self.last_font_atlas_generation
+            || pixels_per_point != self.last_pixels_per_point;
+        self.last_font_atlas_size = font_atlas_size;
+        self.last_font_atlas_generation = font_atlas_generation;
+        self.last_pixels_per_point = pixels_per_point;
+
+        let mut previous_cache = std::mem::take(&mut self.cache);
+        if is_all_dirty {
+            previous_cache.clear();
+        }
+
+        // groups in drawing order and the primitives of changed groups split into
+        // chunks, which are tessellated independently
+        let mut group_meshes = Vec::with_capacity(groups.len());
+        let mut chunks = Vec::new();
+        for group in groups {
+            let mut primitives = clipped_primitives.by_ref().take(group.len);
+
+            // unchanged groups reuse their meshes from the last frame
+            let cached = previous_cache
+                .remove(&group.id)
+                .filter(|(hash, _)| *hash == group.hash);
+            if let Some((_, meshes)) = cached {
+                primitives.for_each(drop);
+                group_meshes.push(GroupMeshes {
+                    group: *group,
+                    is_cached: true,
+                    meshes,
+                });
+                continue;
+            }
+
+            loop {
+                let chunk: Vec<_> = primitives.by_ref().take(CHUNK_SIZE).collect();
+                if chunk.is_empty() {
+                    break;
+                }
+                chunks.push((group_meshes.len(), chunk));
+            }
+            group_meshes.push(GroupMeshes {
+                group: PrimitiveGroup {
+                    is_dirty: group.is_dirty || is_all_dirty,
+                    ..*group
+                },
+                is_cached: false,
+                meshes: Vec::new(),
+            });
+        }
+
+        let tessellate = |(index, chunk): (usize, Vec<ClippedPrimitive>)| {
+            let group: &GroupMeshes = &group_meshes[index];
+            let mut meshes = Vec::new();
+            for clipped_primitive in chunk {
+                self.convert_clipped_primitive(
+                    clipped_primitive,
+                    Some(group.group),
+                    font_atlas_size,
+                    pixels_per_point,
+                    &mut meshes,
+                );
+            }
+            (index, meshes)
+        };
+        let primitive_count: usize = chunks.iter().map(|(_, chunk)| chunk.len()).sum();
+        let chunk_meshes: Vec<_> = if primitive_count >= PARALLEL_MIN_PRIMITIVES {
+            profiling::scope!("tessellate_parallel");
+            chunks.into_par_iter().map(tessellate).collect()
+        } else {
+            profiling::scope!("tessellate_serial");
+            chunks.into_iter().map(tessellate).collect()
+        };
+
+        // merge the chunks in order, meshes at their edges may need to be joined
+        for (index, meshes) in chunk_meshes {
+            let out = &mut group_meshes[index].meshes;
+            for clipped_mesh in meshes {
+                match out.last_mut() {
+                    Some(last) if last.can_append(&clipped_mesh) => {
+                        Arc::make_mut(&mut last.mesh)
+                            .append(Arc::unwrap_or_clone(clipped_mesh.mesh));
+                    }
+                    _ => out.push(clipped_mesh),
+                }
+            }
+        }
+
         let mut clipped_meshes = Vec::with_capacity(self.last_clipped_meshes_size);
+        for GroupMeshes {
+            group,
+            is_cached,
+            mut meshes,
+        } in group_meshes
+        {
+            if is_cached {
+                clipped_meshes.extend(meshes.iter().cloned().map(|clipped_mesh| ClippedMesh {
+                    is_dirty: false,
+                    ..clipped_mesh
+                }));
+            } else {
+                // chunks were numbered separately
+                for (index, clipped_mesh) in meshes.iter_mut().enumerate() {
+                    clipped_mesh.id = Some(MeshId {
+                        group: group.id,
+                        index,
+                    });
+                }
+                clipped_meshes.extend(meshes.iter().cloned());
+            }
+            self.cache.insert(group.id, (group.hash, meshes));
+        }
 
+        // primitives outside of any group, e.g. overlays
         for clipped_primitive in clipped_primitives {
             self.convert_clipped_primitive(
                 clipped_primitive,
+                None,
                 font_atlas_size,
                 pixels_per_point,
                 &mut clipped_meshes,
@@ -42,8 +188,9 @@ impl Tessellator {
     }
 
     pub fn convert_clipped_primitive(
-        &mut self,
+        &self,
         clipped_primitive: ClippedPrimitive,
+        group: Option<PrimitiveGroup>,
         font_atlas_size: FontAtlasSize,
         pixels_per_point: f32,
         out_clipped_meshes: &mut Vec<ClippedMesh>,
@@ -57,43 +204,67 @@ impl Tessellator {
             return;
         }
 
+        let group_id = group.map(|group| group.id);
+        let last_id = out_clipped_meshes.last().and_then(|last| last.id);
+
         let start_new_mesh = match out_clipped_meshes.last() {
             None => true,
             Some(last_clipped_mesh) => {
-                // append to previous mesh if clip_rect and texture_id match.
-                // otherwise, start a new mesh.
-                !(last_clipped_mesh.clip_rect == clip_rect
-                    && last_clipped_mesh.mesh.texture_id == primitive.texture_id())
+                // append to previous mesh if group, clip_rect, texture_id, and pipeline
+                // match. otherwise, start a new mesh.
+                !(last_id.map(|id| id.group) == group_id
+                    && last_clipped_mesh.clip_rect == clip_rect
+                    && last_clipped_mesh.mesh.texture_id == primitive.texture_id()
+                    && last_clipped_mesh.mesh.subpixel_text == primitive.is_subpixel_text())
             }
         };
 
         if start_new_mesh {
+            let id = group.map(|group| MeshId {
+                group: group.id,
+                index: match last_id {
+                    Some(last_id) if last_id.group == group.id => last_id.index + 1,
+                    _ => 0,
+                },
+            });
             out_clipped_meshes.push(ClippedMesh {
                 clip_rect,
-                mesh: Mesh::default(),
+                mesh: Arc::new(Mesh {
+                    subpixel_text: primitive.is_subpixel_text(),
+                    ..Default::default()
+                }),
+                id,
+                is_dirty: group.is_none_or(|group| group.is_dirty),
             });
         }
 
         let last_clipped_mesh = out_clipped_meshes.last_mut().unwrap();
+        // the mesh was created for this frame, so it isn't shared yet
+        let mesh = Arc::make_mut(&mut last_clipped_mesh.mesh);
 
         match primitive {
             DrawPrimitive::Rect(rect_primitive) => {
-                self.convert_rect_primitive(rect_primitive, &mut last_clipped_mesh.mesh)
+                self.convert_rect_primitive(rect_primitive, mesh)
             }
             DrawPrimitive::Quad(quad_primitive) => {
-                self.convert_quad_primitive(quad_primitive, &mut last_clipped_mesh.mesh)
+                self.convert_quad_primitive(quad_primitive, mesh)
+            }
+            DrawPrimitive::NineSlice(nine_slice_primitive) => {
+                for rect_primitive in nine_slice_primitive.slices() {
+                    self.convert_rect_primitive(rect_primitive, mesh);
+                }
+            }
+            DrawPrimitive::Text(text_primitive) => {
+                self.convert_text_primitive(text_primitive, font_atlas_size, pixels_per_point, mesh)
+            }
+            DrawPrimitive::Line(line_primitive) => {
+                self.convert_line_primitive(line_primitive, pixels_per_point, mesh)
             }
-            DrawPrimitive::Text(text_primitive) => self.convert_text_primitive(
-                text_primitive,
-                font_atlas_size,
-                pixels_per_point,
-                &mut last_clipped_mesh.mesh,
-            ),
         }
 
         // This can be empty if a new mesh was started but the conversion from a text primitive
         // didn't add any vertices. Our renderer doesn't support empty meshes so remove it
-        if last_clipped_mesh.mesh.is_empty() {
+        if mesh.is_empty() {
             out_clipped_meshes.pop();
         }
     }
@@ -121,7 +292,7 @@ impl Tessellator {
     fn convert_quad_primitive(&self, quad_primitive: QuadPrimitive, out: &mut Mesh) {
         let QuadPrimitive {
             quad,
-            color,
+            colors,
             texture,
         } = quad_primitive;
 
@@ -134,7 +305,7 @@ impl Tessellator {
             None => (TextureId::default(), NormalizedQuad::white_uv(), 0),
         };
 
-        out.add_quad(quad, uv, color, layer_idx);
+        out.add_gradient_quad(quad, uv, colors, layer_idx);
         out.texture_id = texture_id;
     }
 
@@ -148,6 +319,7 @@ impl Tessellator {
         let TextPrimitive {
             pos: layout_pos,
             layout,
+            rotation,
         } = text_primitive;
 
         if layout.rows.is_empty() {
@@ -161,17 +333,258 @@ impl Tessellator {
         // that each glyph aligns correctly. Glyphs are positioned relative
         // to the layout origin and assume that it ends up on the start of
         // a physical pixel.
-        let layout_pos = layout_pos
-            .to_physical::<f32, _>(pixels_per_point)
-            .round()
-            .to_logical(pixels_per_point);
+        // Glyphs were rounded to the pixel grid relative to the origin, so the
+        // origin is chosen such that the first baseline ends up on the pixel row
+        // nearest to it, instead of rounding the origin and the baseline separately.
+        let physical_pos = layout_pos.to_physical::<f32, _>(pixels_per_point);
+        let baseline = layout.baseline * pixels_per_point;
+        let layout_pos = PhysicalPoint::new(
+            physical_pos.x.round(),
+            (physical_pos.y + baseline).round() - baseline.round(),
+        )
+        .to_logical(pixels_per_point);
 
-        for row in &layout.rows {
-            for glyph in &row.glyphs {
-                let rect = glyph.rect.translate(layout_pos.to_vector());
-                let normalized_uv = glyph.uv.normalize(font_atlas_size);
-                out.add_rect(rect, normalized_uv, glyph.color, 0);
+        if rotation == 0.0 {
+            for row in &layout.rows {
+                for glyph in &row.glyphs {
+                    let rect = glyph.rect.translate(layout_pos.to_vector());
+                    let normalized_uv = glyph.uv.normalize(font_atlas_size);
+                    out.add_rect(rect, normalized_uv, glyph.color, 0);
+                }
+            }
+        } else {
+            // glyph rects are relative to the layout origin, so they can be rotated
+            // before being moved into place
+            let rotation = Rotation2D::radians(rotation);
+            for row in &layout.rows {
+                for glyph in &row.glyphs {
+                    let LogicalQuad { p0, p1, p2, p3 } = LogicalQuad::from(glyph.rect);
+                    let quad = LogicalQuad::new(
+                        rotation.transform_point(p0),
+                        rotation.transform_point(p1),
+                        rotation.transform_point(p2),
+                        rotation.transform_point(p3),
+                    )
+                    .translate(layout_pos.to_vector());
+                    let normalized_uv = NormalizedQuad::from(glyph.uv.normalize(font_atlas_size));
+                    out.add_quad(quad, normalized_uv, glyph.color, 0);
+                }
             }
         }
     }
+
+    /// Lines are anti-aliased by fading out the color over one physical pixel along
+    /// their edges. Each point gets a round cap/join.
+    ///
+    /// NOTE: caps/joins overlap with the segments, which is only visible for
+    /// translucent colors.
+    fn convert_line_primitive(
+        &self,
+        line_primitive: LinePrimitive,
+        pixels_per_point: f32,
+        out: &mut Mesh,
+    ) {
+        let LinePrimitive {
+            points,
+            width,
+            mut color,
+        } = line_primitive;
+
+        if points.is_empty() || width.is_nan() || width <= 0.0 {
+            return;
+        }
+
+        let feather = 1.0 / pixels_per_point;
+        if width < feather {
+            // lines thinner than a pixel get faded out instead
+            color.0[3] = (color.0[3] as f32 * width / feather).round() as u8;
+        }
+        let inner = (width * 0.5 - feather * 0.5).max(0.0);
+        let outer = inner + feather;
+        let transparent = Srgba::new(color.0[0], color.0[1], color.0[2], 0);
+
+        for segment in points.windows(2) {
+            let (a, b) = (segment[0], segment[1]);
+            let length = (b - a).length();
+            if length <= 0.0 {
+                continue;
+            }
+            let direction = (b - a) / length;
+            let normal = LogicalVector::new(-direction.y, direction.x);
+
+            let [a_outer_l, a_inner_l, a_inner_r, a_outer_r] = [
+                out.add_colored_vertex(a + normal * outer, transparent),
+                out.add_colored_vertex(a + normal * inner, color),
+                out.add_colored_vertex(a - normal * inner, color),
+                out.add_colored_vertex(a - normal * outer, transparent),
+            ];
+            let [b_outer_l, b_inner_l, b_inner_r, b_outer_r] = [
+                out.add_colored_vertex(b + normal * outer, transparent),
+                out.add_colored_vertex(b + normal * inner, color),
+                out.add_colored_vertex(b - normal * inner, color),
+                out.add_colored_vertex(b - normal * outer, transparent),
+            ];
+
+            for (a0, a1, b1, b0) in [
+                (a_outer_l, a_inner_l, b_inner_l, b_outer_l),
+                (a_inner_l, a_inner_r, b_inner_r, b_inner_l),
+                (a_inner_r, a_outer_r, b_outer_r, b_inner_r),
+            ] {
+                out.add_triangle(a0, a1, b1);
+                out.add_triangle(a0, b1, b0);
+            }
+        }
+
+        // round caps and joins
+        let circumference = std::f32::consts::TAU * outer * pixels_per_point;
+        let num_of_steps = ((circumference / 4.0).ceil() as u32).clamp(8, 64);
+        for point in points {
+            let center = out.add_colored_vertex(point, color);
+            let mut previous = None;
+            for step in 0..=num_of_steps {
+                let angle = std::f32::consts::TAU * step as f32 / num_of_steps as f32;
+                let offset = LogicalVector::new(angle.cos(), angle.sin());
+                let inner_idx = out.add_colored_vertex(point + offset * inner, color);
+                let outer_idx = out.add_colored_vertex(point + offset * outer, transparent);
+                if let Some((prev_inner, prev_outer)) = previous {
+                    out.add_triangle(center, prev_inner, inner_idx);
+                    out.add_triangle(prev_inner, prev_outer, outer_idx);
+                    out.add_triangle(prev_inner, outer_idx, inner_idx);
+                }
+                previous = Some((inner_idx, outer_idx));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dpi::LogicalRect,
+        renderer::primitives::{NineSlicePrimitive, NineSliceTexture},
+    };
+
+    fn rect(x: f32) -> ClippedPrimitive {
+        ClippedPrimitive {
+            clip_rect: LogicalRect::new((0.0, 0.0).into(), (100.0, 100.0).into()),
+            primitive: DrawPrimitive::Rect(RectPrimitive::new(
+                LogicalRect::new((x, 0.0).into(), (x + 1.0, 1.0).into()),
+                Srgba::WHITE,
+                None,
+            )),
+        }
+    }
+
+    #[test]
+    fn test_group_cache() {
+        let mut tessellator = Tessellator::default();
+        let mut convert = |groups: &[PrimitiveGroup], primitives: Vec<ClippedPrimitive>| {
+            tessellator.convert_clipped_primitives(
+                primitives.into_iter(),
+                groups,
+                FontAtlasSize::new(256, 256),
+                0,
+                1.0,
+            )
+        };
+        let group = |id, hash, is_dirty| PrimitiveGroup {
+            id,
+            len: 1,
+            hash,
+            is_dirty,
+        };
+
+        // groups don't share meshes even if they could be merged
+        let meshes = convert(
+            &[group(1, 10, true), group(2, 20, true)],
+            vec![rect(0.0), rect(1.0)],
+        );
+        assert_eq!(meshes.len(), 2);
+        assert!(meshes.iter().all(|mesh| mesh.is_dirty));
+
+        // the cached mesh of group 1 is used even though the primitive differs
+        let first_meshes = meshes;
+        let meshes = convert(
+            &[group(1, 10, false), group(2, 21, true)],
+            vec![rect(5.0), rect(2.0)],
+        );
+        assert_eq!(meshes[0].id, Some(MeshId { group: 1, index: 0 }));
+        assert!(!meshes[0].is_dirty);
+        assert_eq!(meshes[0].mesh.vertices[0].pos.x, 0.0);
+        assert!(Arc::ptr_eq(&meshes[0].mesh, &first_meshes[0].mesh));
+        assert!(meshes[1].is_dirty);
+        assert_eq!(meshes[1].mesh.vertices[0].pos.x, 2.0);
+
+        // chunks tessellated in parallel are joined into a single mesh
+        let len = PARALLEL_MIN_PRIMITIVES + 1;
+        let primitives = (0..len).map(|i| rect(i as f32 / 100.0)).collect();
+        let meshes = convert(
+            &[PrimitiveGroup {
+                len,
+                ..group(3, 30, true)
+            }],
+            primitives,
+        );
+        assert_eq!(meshes.len(), 1);
+        assert_eq!(meshes[0].mesh.vertices.len(), len * 4);
+        assert_eq!(
+            *meshes[0].mesh.indices.last().unwrap() as usize,
+            len * 4 - 1
+        );
+    }
+
+    #[test]
+    fn test_nine_slice() {
+        let convert = |primitive| {
+            let clipped_primitive = ClippedPrimitive {
+                clip_rect: LogicalRect::new((0.0, 0.0).into(), (100.0, 100.0).into()),
+                primitive: DrawPrimitive::NineSlice(primitive),
+            };
+            let meshes = Tessellator::default().convert_clipped_primitives(
+                std::iter::once(clipped_primitive),
+                &[PrimitiveGroup {
+                    id: 1,
+                    len: 1,
+                    hash: 0,
+                    is_dirty: true,
+                }],
+                FontAtlasSize::new(256, 256),
+                0,
+                1.0,
+            );
+            meshes[0].mesh.clone()
+        };
+        let texture = NineSliceTexture::new(
+            1,
+            NormalizedRect::from_size((1.0, 1.0).into()),
+            [0.25; 4],
+            0,
+        );
+
+        let mesh = convert(NineSlicePrimitive::new(
+            LogicalRect::new((10.0, 10.0).into(), (50.0, 30.0).into()),
+            [4.0; 4],
+            Srgba::WHITE,
+            Some(texture),
+        ));
+        assert_eq!(mesh.vertices.len(), 9 * 4);
+        assert_eq!(mesh.texture_id, 1);
+        // the corners keep their size, the center stretches
+        let center = &mesh.vertices[4 * 4..5 * 4];
+        assert_eq!(center[0].pos, (14.0, 14.0).into());
+        assert_eq!(center[2].pos, (46.0, 26.0).into());
+        assert_eq!(center[0].uv, (0.25, 0.25).into());
+        assert_eq!(center[2].uv, (0.75, 0.75).into());
+
+        // borders that don't fit are shrunk and leave out the empty center
+        let mesh = convert(NineSlicePrimitive::new(
+            LogicalRect::new((0.0, 0.0).into(), (4.0, 20.0).into()),
+            [4.0; 4],
+            Srgba::WHITE,
+            Some(texture),
+        ));
+        assert_eq!(mesh.vertices.len(), 6 * 4);
+        assert_eq!(mesh.vertices[4].pos, (2.0, 0.0).into());
+    }
 }
@@ -1,16 +1,24 @@
 use std::{
     collections::hash_map::Entry,
     path::Path,
-    sync::{Arc, RwLock},
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
 };
 
 use ahash::HashMap;
 use anyhow::bail;
 
 use crate::{
+    app::{EventProxy, UserEvent},
     color::Srgba,
-    renderer::image::{ImageData, ImageDelta, load_image_file},
-    worker_pool::WorkerPool,
+    renderer::{
+        TextureCompression, bc7,
+        image::{ImageData, ImageDelta, load_image_bytes, load_image_file},
+    },
+    worker_pool::{Priority, WorkerPool},
 };
 
 pub type TextureId = u64;
@@ -36,6 +44,25 @@ impl TextureHandle {
             .get_meta_data(self.id)
             .map_or([0, 0], |tex| tex.size)
     }
+
+    /// Error of the last failed load into this texture, if any.
+    pub fn last_error(&self) -> Option<String> {
+        self.tex_mngr
+            .read()
+            .unwrap()
+            .get_meta_data(self.id)
+            .and_then(|tex| tex.last_error.clone())
+    }
+
+    /// Durations of the frames if the texture is an animation, see
+    /// [`ImageData::frame_durations`].
+    pub fn frame_durations(&self) -> Vec<Duration> {
+        self.tex_mngr
+            .read()
+            .unwrap()
+            .get_meta_data(self.id)
+            .map_or_else(Vec::new, |tex| tex.frame_durations.clone())
+    }
 }
 
 impl Drop for TextureHandle {
@@ -66,14 +93,88 @@ impl TexturesDelta {
     }
 }
 
+/// Where the image of a texture was loaded from, so that it can be loaded again.
+#[derive(Clone, PartialEq, Eq)]
+pub enum TextureSource {
+    /// Path of an image file
+    File(String),
+    /// Encoded image in memory, e.g. a PNG downloaded from the trade API
+    Bytes(Arc<[u8]>),
+}
+
+impl std::fmt::Debug for TextureSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TextureSource::File(path) => f.debug_tuple("File").field(path).finish(),
+            TextureSource::Bytes(bytes) => write!(f, "Bytes({} bytes)", bytes.len()),
+        }
+    }
+}
+
 /// Metadata about an allocated texture.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct TextureMetaData {
     pub name: String,
     pub size: [usize; 2],
+    /// Durations of the frames stored in the array layers of an animation
+    pub frame_durations: Vec<Duration>,
     /// Texture is freed when this reaches zero
     retain_count: usize,
     pub options: TextureOptions,
+    /// Error of the last failed load. Cleared once an image is assigned.
+    pub last_error: Option<String>,
+    /// Image the texture was loaded from. Used to re-create the texture if the GPU
+    /// device is lost. `None` for textures whose owners upload them again.
+    pub source: Option<TextureSource>,
+    /// Estimated GPU memory of the uploaded image
+    pub gpu_bytes: usize,
+    /// Frame in which the texture was drawn last, see [`TextureManager::mark_drawn`]
+    last_drawn: u64,
+    /// Freed on the GPU to stay within the memory budget. Reloaded from `source`
+    /// when it's drawn again.
+    is_evicted: bool,
+}
+
+impl TextureMetaData {
+    /// Image file the texture was loaded from, if any
+    pub fn source_path(&self) -> Option<&str> {
+        match &self.source {
+            Some(TextureSource::File(path)) => Some(path),
+            Some(TextureSource::Bytes(_)) | None => None,
+        }
+    }
+
+    fn new(name: String, options: TextureOptions) -> Self {
+        Self {
+            name,
+            size: [0, 0],
+            frame_durations: Vec::new(),
+            retain_count: 1,
+            options,
+            last_error: None,
+            source: None,
+            gpu_bytes: 0,
+            last_drawn: 0,
+            is_evicted: false,
+        }
+    }
+}
+
+/// GPU memory used by textures
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TextureMemory {
+    pub bytes: usize,
+    pub texture_count: usize,
+    pub evicted_count: usize,
+}
+
+/// An image that couldn't be loaded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoadFailure {
+    /// Texture the image was loaded into, `None` if none was created
+    pub id: Option<TextureId>,
+    pub image_path: String,
+    pub error: String,
 }
 
 #[derive(Default)]
@@ -81,6 +182,14 @@ pub struct TextureManager {
     next_id: u64,
     meta_data: HashMap<TextureId, TextureMetaData>,
     delta: TexturesDelta,
+    // failed loads since last call to `take_load_failures`
+    load_failures: Vec<LoadFailure>,
+    // textures whose async load finished since last call to `take_finished_loads`
+    finished_loads: Vec<TextureId>,
+    // priorities of async loads into a texture, see `load_priority`
+    load_priorities: HashMap<TextureId, Priority>,
+    // incremented by `end_frame`
+    frame: u64,
 }
 
 impl TextureManager {
@@ -89,16 +198,16 @@ impl TextureManager {
         let id = self.next_id;
         self.next_id += 1;
 
+        let delta = ImageDelta::new(image, options);
         self.meta_data.entry(id).or_insert_with(|| TextureMetaData {
-            name,
-            size: [image.width as usize, image.height as usize],
-            retain_count: 1,
-            options,
+            size: [delta.image.width as usize, delta.image.height as usize],
+            frame_durations: delta.image.frame_durations.clone(),
+            gpu_bytes: delta.gpu_bytes(),
+            last_drawn: self.frame,
+            ..TextureMetaData::new(name, options)
         });
 
-        self.delta
-            .update
-            .push((id, ImageDelta::new(image, options)));
+        self.delta.update.push((id, delta));
 
         id
     }
@@ -109,10 +218,8 @@ impl TextureManager {
         self.next_id += 1;
 
         self.meta_data.entry(id).or_insert_with(|| TextureMetaData {
-            name,
-            size: [0, 0],
-            retain_count: 1,
-            options,
+            last_drawn: self.frame,
+            ..TextureMetaData::new(name, options)
         });
 
         id
@@ -122,6 +229,13 @@ impl TextureManager {
     pub fn set(&mut self, id: TextureId, delta: ImageDelta) {
         if let Some(meta_data) = self.meta_data.get_mut(&id) {
             meta_data.size = [delta.image.width as usize, delta.image.height as usize];
+            meta_data.frame_durations = delta.image.frame_durations.clone();
+            meta_data.gpu_bytes = delta.gpu_bytes();
+            meta_data.is_evicted = false;
+            meta_data.options = delta.options;
+            meta_data.last_error = None;
+            // loaders record the source after assigning the image
+            meta_data.source = None;
             // discard all old enqueued deltas
             self.delta.update.retain(|(x, _)| x != &id);
             self.delta.update.push((id, delta));
@@ -130,6 +244,48 @@ impl TextureManager {
         }
     }
 
+    /// Records the image the current content of a texture was loaded from.
+    pub fn set_source(&mut self, id: TextureId, source: Option<TextureSource>) {
+        if let Some(meta_data) = self.meta_data.get_mut(&id) {
+            meta_data.source = source;
+        }
+    }
+
+    /// Records a failed load. The error is attached to the texture if `id` is given.
+    pub fn set_error(&mut self, id: Option<TextureId>, image_path: String, error: String) {
+        if let Some(meta_data) = id.and_then(|id| self.meta_data.get_mut(&id)) {
+            meta_data.last_error = Some(error.clone());
+        }
+        self.load_failures.push(LoadFailure {
+            id,
+            image_path,
+            error,
+        });
+    }
+
+    /// Clears the error of a texture, e.g. when a new load is started.
+    pub fn clear_error(&mut self, id: TextureId) {
+        if let Some(meta_data) = self.meta_data.get_mut(&id) {
+            meta_data.last_error = None;
+        }
+    }
+
+    /// Take failed loads since last call.
+    pub fn take_load_failures(&mut self) -> Vec<LoadFailure> {
+        std::mem::take(&mut self.load_failures)
+    }
+
+    /// Take textures whose async load succeeded since last call.
+    pub fn take_finished_loads(&mut self) -> Vec<TextureId> {
+        std::mem::take(&mut self.finished_loads)
+    }
+
+    /// Priority of async loads into a texture. Loads with higher priority are
+    /// started first, it can be changed while they're queued.
+    pub fn load_priority(&mut self, id: TextureId) -> Priority {
+        self.load_priorities.entry(id).or_default().clone()
+    }
+
     /// Frees an existing texture.
     pub fn free(&mut self, id: TextureId) {
         if let Entry::Occupied(mut entry) = self.meta_data.entry(id) {
@@ -137,6 +293,7 @@ impl TextureManager {
             meta.retain_count -= 1;
             if meta.retain_count == 0 {
                 entry.remove();
+                self.load_priorities.remove(&id);
                 self.delta.free.push(id);
             }
         } else {
@@ -167,11 +324,86 @@ impl TextureManager {
     pub fn take_delta(&mut self) -> TexturesDelta {
         std::mem::take(&mut self.delta)
     }
+
+    /// Records that a texture was drawn in the current frame. Returns the name and
+    /// the image to reload it from if it was evicted.
+    fn mark_drawn(&mut self, id: TextureId) -> Option<(String, TextureSource, TextureOptions)> {
+        let meta_data = self.meta_data.get_mut(&id)?;
+        meta_data.last_drawn = self.frame;
+        if !meta_data.is_evicted {
+            return None;
+        }
+        // the texture stays evicted until the reload assigns the image
+        let source = meta_data.source.take()?;
+        Some((meta_data.name.clone(), source, meta_data.options))
+    }
+
+    /// Frees least recently drawn textures on the GPU until their memory fits into
+    /// `budget`. Textures drawn in the current frame and textures that can't be
+    /// reloaded are kept.
+    fn evict_to_budget(&mut self, budget: usize) {
+        let mut used = self.memory().bytes;
+        if used <= budget {
+            return;
+        }
+
+        let mut candidates: Vec<_> = self
+            .meta_data
+            .iter()
+            .filter(|(_, meta)| {
+                !meta.is_evicted
+                    && meta.source.is_some()
+                    && meta.gpu_bytes > 0
+                    && meta.last_drawn < self.frame
+            })
+            .map(|(id, meta)| (meta.last_drawn, *id))
+            .collect();
+        candidates.sort_unstable();
+
+        for (_, id) in candidates {
+            if used <= budget {
+                break;
+            }
+            let meta = self.meta_data.get_mut(&id).unwrap();
+            log::debug!("Evicting texture {} ({} bytes)", meta.name, meta.gpu_bytes);
+            meta.is_evicted = true;
+            used -= meta.gpu_bytes;
+            self.delta.free.push(id);
+        }
+    }
+
+    pub fn memory(&self) -> TextureMemory {
+        let mut memory = TextureMemory::default();
+        for meta in self.meta_data.values() {
+            if meta.is_evicted {
+                memory.evicted_count += 1;
+            } else {
+                memory.bytes += meta.gpu_bytes;
+                memory.texture_count += 1;
+            }
+        }
+        memory
+    }
 }
 
 pub struct WrappedTextureManager {
     manager: Arc<RwLock<TextureManager>>,
     worker_pool: WorkerPool,
+    // number of async loads that haven't finished yet
+    pending_loads: Arc<AtomicUsize>,
+    /// GPU memory that textures may use before the least recently drawn ones
+    /// are evicted. `None` disables eviction.
+    pub memory_budget: Option<usize>,
+    /// Wakes up the event loop when an async load finishes
+    pub event_proxy: Option<EventProxy>,
+    /// Whether large RGBA images are transcoded to BC7 after loading
+    pub compression: TextureCompression,
+    /// Images with a smaller width or height are uploaded uncompressed
+    pub compression_min_size: u32,
+    /// Whether images are converted to premultiplied alpha unless their options
+    /// say otherwise. PoB blends with straight alpha, which leaves dark fringes
+    /// where filtering mixes in the color of transparent texels.
+    premultiply_alpha: bool,
 }
 
 impl WrappedTextureManager {
@@ -188,7 +420,55 @@ impl WrappedTextureManager {
         Self {
             manager,
             worker_pool: WorkerPool::new(4),
+            pending_loads: Arc::new(AtomicUsize::new(0)),
+            memory_budget: None,
+            event_proxy: None,
+            compression: TextureCompression::Off,
+            compression_min_size: 256,
+            premultiply_alpha: false,
+        }
+    }
+
+    pub fn premultiply_alpha(&self) -> bool {
+        self.premultiply_alpha
+    }
+
+    /// Changes whether images are converted to premultiplied alpha by default and
+    /// reloads the textures that are affected.
+    pub fn set_premultiply_alpha(&mut self, premultiply_alpha: bool) {
+        if premultiply_alpha == self.premultiply_alpha {
+            return;
+        }
+        self.premultiply_alpha = premultiply_alpha;
+        self.reload_textures_where(|options| options.premultiply_alpha.is_none());
+    }
+
+    /// Converts a loaded image as configured before it's assigned to a texture.
+    fn prepare_image(&self, mut image: ImageData, options: TextureOptions) -> ImageData {
+        if options.premultiply_alpha.unwrap_or(self.premultiply_alpha) {
+            image.premultiply_alpha();
+        }
+        image
+    }
+
+    /// Records the textures drawn in the current frame, reloads the evicted ones
+    /// among them and evicts textures that exceed the memory budget.
+    pub fn end_frame(&self, drawn: impl Iterator<Item = TextureId>) -> TextureMemory {
+        let mut manager = self.manager.write().unwrap();
+        let reloads: Vec<_> = drawn
+            .filter_map(|id| Some((id, manager.mark_drawn(id)?)))
+            .collect();
+        if let Some(budget) = self.memory_budget {
+            manager.evict_to_budget(budget);
         }
+        manager.frame += 1;
+        let memory = manager.memory();
+        drop(manager);
+
+        for (id, (name, source, options)) in reloads {
+            self.load_source_async(id, name, source, options);
+        }
+        memory
     }
 
     #[inline]
@@ -204,6 +484,185 @@ impl WrappedTextureManager {
         self.manager.write().unwrap().take_delta()
     }
 
+    #[inline]
+    pub fn take_load_failures(&self) -> Vec<LoadFailure> {
+        self.manager.write().unwrap().take_load_failures()
+    }
+
+    #[inline]
+    pub fn take_finished_loads(&self) -> Vec<TextureId> {
+        self.manager.write().unwrap().take_finished_loads()
+    }
+
+    /// Sets the priority of async loads into a texture, see [`TextureManager::load_priority`].
+    pub fn set_load_priority(&self, id: TextureId, priority: i32) {
+        self.manager
+            .write()
+            .unwrap()
+            .load_priority(id)
+            .set(priority);
+    }
+
+    pub fn get_meta_data(&self, id: TextureId) -> Option<TextureMetaData> {
+        self.manager.read().unwrap().get_meta_data(id).cloned()
+    }
+
+    /// Size of a texture in pixels, zero while it's loading. Cheaper than cloning
+    /// its metadata.
+    pub fn texture_size(&self, id: TextureId) -> [usize; 2] {
+        self.manager
+            .read()
+            .unwrap()
+            .get_meta_data(id)
+            .map_or([0, 0], |tex| tex.size)
+    }
+
+    /// Creates a texture from an image in memory.
+    pub fn alloc_texture(
+        &self,
+        name: String,
+        image: ImageData,
+        options: TextureOptions,
+    ) -> TextureHandle {
+        let id = self.manager.write().unwrap().alloc(name, image, options);
+        TextureHandle::new(Arc::clone(&self.manager), id)
+    }
+
+    /// Metadata of all allocated textures, ordered by id.
+    pub fn textures(&self) -> Vec<(TextureId, TextureMetaData)> {
+        let manager = self.manager.read().unwrap();
+        let mut textures: Vec<_> = manager
+            .meta_data
+            .iter()
+            .map(|(id, meta_data)| (*id, meta_data.clone()))
+            .collect();
+        textures.sort_by_key(|(id, _)| *id);
+        textures
+    }
+
+    /// Number of async loads (including reloads) that haven't finished yet.
+    #[inline]
+    pub fn pending_loads(&self) -> usize {
+        self.pending_loads.load(Ordering::Relaxed)
+    }
+
+    /// Loads an image into texture `id` in a background worker.
+    fn load_async(&self, id: TextureId, image_path: String, options: TextureOptions) {
+        let source = Some(TextureSource::File(image_path.clone()));
+        self.spawn_load(id, image_path, source, options, |path| {
+            load_image_file(Path::new(path))
+        });
+    }
+
+    /// Decodes an image in memory into texture `id` in a background worker.
+    fn load_bytes_async(
+        &self,
+        id: TextureId,
+        name: String,
+        bytes: Arc<[u8]>,
+        options: TextureOptions,
+    ) {
+        let source = Some(TextureSource::Bytes(Arc::clone(&bytes)));
+        self.spawn_load(id, name, source, options, move |_| load_image_bytes(&bytes));
+    }
+
+    /// Loads texture `id` again from the image it was loaded from.
+    fn load_source_async(
+        &self,
+        id: TextureId,
+        name: String,
+        source: TextureSource,
+        options: TextureOptions,
+    ) {
+        match source {
+            TextureSource::File(image_path) => self.load_async(id, image_path, options),
+            TextureSource::Bytes(bytes) => self.load_bytes_async(id, name, bytes, options),
+        }
+    }
+
+    /// Runs `load` in a background worker and assigns its image to texture `id`.
+    /// `name` is passed to `load` and identifies the image in errors.
+    fn spawn_load(
+        &self,
+        id: TextureId,
+        name: String,
+        source: Option<TextureSource>,
+        options: TextureOptions,
+        load: impl FnOnce(&str) -> anyhow::Result<ImageData> + Send + 'static,
+    ) {
+        let manager = Arc::clone(&self.manager);
+        let pending_loads = Arc::clone(&self.pending_loads);
+        pending_loads.fetch_add(1, Ordering::Relaxed);
+        // compressed textures would lose their generated mipmaps
+        let compression = match options.generate_mipmaps {
+            true => TextureCompression::Off,
+            false => self.compression,
+        };
+        let compression_min_size = self.compression_min_size;
+        let premultiply_alpha = options.premultiply_alpha.unwrap_or(self.premultiply_alpha);
+        let priority = manager.write().unwrap().load_priority(id);
+        let event_proxy = self.event_proxy.clone();
+
+        self.worker_pool.execute(priority, move || {
+            match load(&name) {
+                Ok(mut image) => {
+                    if premultiply_alpha {
+                        image.premultiply_alpha();
+                    }
+                    let image = bc7::encode_image(&image, compression, compression_min_size)
+                        .unwrap_or(image);
+                    let mut manager = manager.write().unwrap();
+                    manager.set(id, ImageDelta::new(image, options));
+                    manager.set_source(id, source);
+                    manager.finished_loads.push(id);
+                }
+                Err(e) => {
+                    log::warn!("Unable to load image from {}: {}", &name, e);
+                    manager
+                        .write()
+                        .unwrap()
+                        .set_error(Some(id), name, e.to_string());
+                }
+            }
+            pending_loads.fetch_sub(1, Ordering::Relaxed);
+            if let Some(event_proxy) = event_proxy {
+                let _ = event_proxy.send_event(UserEvent::ImageLoaded);
+            }
+        });
+    }
+
+    /// Reloads all textures from their image files or bytes and returns how many
+    /// are being reloaded.
+    ///
+    /// Used after the GPU device was recreated, which loses all texture data.
+    /// Textures without a source, like the font atlas, need to be uploaded again
+    /// by their owners.
+    pub fn reload_textures(&self) -> usize {
+        self.reload_textures_where(|_| true)
+    }
+
+    fn reload_textures_where(&self, filter: impl Fn(&TextureOptions) -> bool) -> usize {
+        let textures: Vec<_> = self
+            .manager
+            .read()
+            .unwrap()
+            .meta_data
+            .iter()
+            // evicted textures are reloaded once they're drawn again
+            .filter(|(_, meta_data)| !meta_data.is_evicted && filter(&meta_data.options))
+            .filter_map(|(id, meta_data)| {
+                let source = meta_data.source.clone()?;
+                Some((*id, meta_data.name.clone(), source, meta_data.options))
+            })
+            .collect();
+
+        let count = textures.len();
+        for (id, name, source, options) in textures {
+            self.load_source_async(id, name, source, options);
+        }
+        count
+    }
+
     pub fn load_texture(
         &self,
         image_path: String,
@@ -218,28 +677,25 @@ impl WrappedTextureManager {
                 .unwrap()
                 .reserve(image_path.clone(), options);
 
-            // load image in background worker
-            let mngr_clone = Arc::clone(&manager);
-            self.worker_pool
-                .execute(move || match load_image_file(Path::new(&image_path)) {
-                    Ok(image) => {
-                        mngr_clone
-                            .write()
-                            .unwrap()
-                            .set(id, ImageDelta::new(image, options));
-                    }
-                    Err(e) => log::warn!("Unable to load image fron {}: {}", &image_path, e),
-                });
+            self.load_async(id, image_path, options);
 
             TextureHandle::new(manager, id)
         } else {
             match load_image_file(Path::new(&image_path)) {
                 Ok(image) => {
-                    let id = manager.write().unwrap().alloc(image_path, image, options);
+                    let image = self.prepare_image(image, options);
+                    let mut mngr = manager.write().unwrap();
+                    let id = mngr.alloc(image_path.clone(), image, options);
+                    mngr.set_source(id, Some(TextureSource::File(image_path)));
+                    drop(mngr);
                     TextureHandle::new(manager, id)
                 }
                 Err(e) => {
-                    log::warn!("Unable to load image fron {}: {}", &image_path, e);
+                    log::warn!("Unable to load image from {}: {}", &image_path, e);
+                    manager
+                        .write()
+                        .unwrap()
+                        .set_error(None, image_path, e.to_string());
                     bail!(e);
                 }
             }
@@ -256,27 +712,23 @@ impl WrappedTextureManager {
         is_async: bool,
     ) -> anyhow::Result<()> {
         if is_async {
-            let mngr_clone = Arc::clone(&self.manager);
-            self.worker_pool
-                .execute(move || match load_image_file(Path::new(&image_path)) {
-                    Ok(image) => {
-                        mngr_clone
-                            .write()
-                            .unwrap()
-                            .set(texture_id, ImageDelta::new(image, options));
-                    }
-                    Err(e) => log::warn!("Unable to load image fron {}: {}", &image_path, e),
-                });
+            self.manager.write().unwrap().clear_error(texture_id);
+            self.load_async(texture_id, image_path, options);
         } else {
             match load_image_file(Path::new(&image_path)) {
                 Ok(image) => {
-                    self.manager
-                        .write()
-                        .unwrap()
-                        .set(texture_id, ImageDelta::new(image, options));
+                    let image = self.prepare_image(image, options);
+                    let mut mngr = self.manager.write().unwrap();
+                    mngr.set(texture_id, ImageDelta::new(image, options));
+                    mngr.set_source(texture_id, Some(TextureSource::File(image_path)));
                 }
                 Err(e) => {
-                    log::warn!("Unable to load image fron {}: {}", &image_path, e);
+                    log::warn!("Unable to load image from {}: {}", &image_path, e);
+                    self.manager.write().unwrap().set_error(
+                        Some(texture_id),
+                        image_path,
+                        e.to_string(),
+                    );
                     bail!(e);
                 }
             }
@@ -284,6 +736,77 @@ impl WrappedTextureManager {
 
         Ok(())
     }
+
+    /// Creates a texture from an encoded image in memory, e.g. a PNG downloaded
+    /// from the trade API. `name` identifies the image in errors.
+    pub fn load_texture_from_bytes(
+        &self,
+        name: String,
+        bytes: Arc<[u8]>,
+        options: TextureOptions,
+        is_async: bool,
+    ) -> anyhow::Result<TextureHandle> {
+        let manager = Arc::clone(&self.manager);
+
+        if is_async {
+            let id = manager.write().unwrap().reserve(name.clone(), options);
+            self.load_bytes_async(id, name, bytes, options);
+            return Ok(TextureHandle::new(manager, id));
+        }
+
+        match load_image_bytes(&bytes) {
+            Ok(image) => {
+                let image = self.prepare_image(image, options);
+                let mut mngr = manager.write().unwrap();
+                let id = mngr.alloc(name, image, options);
+                mngr.set_source(id, Some(TextureSource::Bytes(bytes)));
+                drop(mngr);
+                Ok(TextureHandle::new(manager, id))
+            }
+            Err(e) => {
+                log::warn!("Unable to load image from {}: {}", &name, e);
+                manager
+                    .write()
+                    .unwrap()
+                    .set_error(None, name, e.to_string());
+                bail!(e);
+            }
+        }
+    }
+
+    /// Replaces the image of an existing texture with an encoded image in memory.
+    pub fn update_texture_from_bytes(
+        &self,
+        texture_id: TextureId,
+        name: String,
+        bytes: Arc<[u8]>,
+        options: TextureOptions,
+        is_async: bool,
+    ) -> anyhow::Result<()> {
+        if is_async {
+            self.manager.write().unwrap().clear_error(texture_id);
+            self.load_bytes_async(texture_id, name, bytes, options);
+            return Ok(());
+        }
+
+        match load_image_bytes(&bytes) {
+            Ok(image) => {
+                let image = self.prepare_image(image, options);
+                let mut mngr = self.manager.write().unwrap();
+                mngr.set(texture_id, ImageDelta::new(image, options));
+                mngr.set_source(texture_id, Some(TextureSource::Bytes(bytes)));
+                Ok(())
+            }
+            Err(e) => {
+                log::warn!("Unable to load image from {}: {}", &name, e);
+                self.manager
+                    .write()
+                    .unwrap()
+                    .set_error(Some(texture_id), name, e.to_string());
+                bail!(e);
+            }
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -293,6 +816,9 @@ pub struct TextureOptions {
     pub wrap_mode: wgpu::AddressMode,
     pub mipmap_mode: wgpu::FilterMode,
     pub generate_mipmaps: bool,
+    /// Whether loaded images are converted to premultiplied alpha. `None` follows
+    /// [`WrappedTextureManager::premultiply_alpha`]
+    pub premultiply_alpha: Option<bool>,
 }
 
 impl TextureOptions {
@@ -302,6 +828,7 @@ impl TextureOptions {
         wrap_mode: wgpu::AddressMode::Repeat,
         mipmap_mode: wgpu::FilterMode::Linear,
         generate_mipmaps: false,
+        premultiply_alpha: None,
     };
 
     pub const LINEAR: Self = Self {
@@ -310,6 +837,7 @@ impl TextureOptions {
         wrap_mode: wgpu::AddressMode::ClampToEdge,
         mipmap_mode: wgpu::FilterMode::Linear,
         generate_mipmaps: false,
+        premultiply_alpha: None,
     };
 }
 
@@ -327,3 +855,80 @@ impl std::hash::Hash for TextureOptions {
         self.mipmap_mode.hash(state);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evict_least_recently_drawn() {
+        let mut manager = TextureManager::default();
+        let ids: Vec<_> = (0..3)
+            .map(|i| {
+                let image = ImageData::from_solid_color([16, 16], Srgba::WHITE);
+                let id = manager.alloc(format!("{i}"), image, TextureOptions::default());
+                manager.set_source(id, Some(TextureSource::File(format!("{i}.png"))));
+                id
+            })
+            .collect();
+        manager.take_delta();
+        let texture_bytes = 16 * 16 * 4;
+
+        // the first texture was drawn least recently, the last one in this frame
+        manager.frame = 2;
+        manager.mark_drawn(ids[1]);
+        manager.frame = 3;
+        manager.mark_drawn(ids[2]);
+        manager.evict_to_budget(texture_bytes);
+        assert_eq!(manager.take_delta().free, [ids[0], ids[1]]);
+        assert_eq!(manager.memory().bytes, texture_bytes);
+
+        // drawing an evicted texture reloads it
+        assert_eq!(
+            manager.mark_drawn(ids[0]),
+            Some((
+                String::from("0"),
+                TextureSource::File(String::from("0.png")),
+                TextureOptions::default()
+            ))
+        );
+        assert_eq!(manager.mark_drawn(ids[0]), None);
+    }
+
+    #[test]
+    fn test_load_texture_from_bytes() {
+        let mut png = Vec::new();
+        image::RgbaImage::from_pixel(3, 2, image::Rgba([255, 0, 0, 255]))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let manager = WrappedTextureManager::new();
+
+        let handle = manager
+            .load_texture_from_bytes(
+                "png".into(),
+                png.clone().into(),
+                TextureOptions::default(),
+                false,
+            )
+            .unwrap();
+        assert_eq!(handle.size(), [3, 2]);
+        // reloaded from the bytes after the graphics context is rebuilt
+        assert_eq!(
+            manager.get_meta_data(handle.id()).unwrap().source,
+            Some(TextureSource::Bytes(png.into()))
+        );
+
+        let garbage = Arc::from(&b"not an image"[..]);
+        assert!(
+            manager
+                .load_texture_from_bytes(
+                    "garbage".into(),
+                    garbage,
+                    TextureOptions::default(),
+                    false
+                )
+                .is_err()
+        );
+        assert_eq!(manager.take_load_failures()[0].image_path, "garbage");
+    }
+}
@@ -0,0 +1,220 @@
+//! Visual regression tests of the tessellator, renderer and fonts, see
+//! [`crate::gfx::offscreen`].
+
+use crate::{
+    color::Srgba,
+    dpi::{
+        LogicalPoint, LogicalQuad, LogicalRect, LogicalSize, NormalizedQuad, NormalizedRect, Uv,
+    },
+    fonts::{Alignment, FontStyle, LayoutJob},
+    gfx::offscreen::{assert_matches_golden, render_scaled_scene, render_scene},
+    layers::Layers,
+};
+use parley::FontFamily;
+use std::borrow::Cow;
+
+const SIZE: LogicalSize<u32> = LogicalSize::new(128, 96);
+
+fn rect(x: f32, y: f32, width: f32, height: f32) -> LogicalRect<f32> {
+    LogicalRect::from_origin_and_size(LogicalPoint::new(x, y), LogicalSize::new(width, height))
+}
+
+fn draw_rect(layers: &mut Layers, rect: LogicalRect<f32>, color: Srgba) {
+    layers.set_draw_color(color);
+    layers.draw_rect(None, rect, NormalizedRect::white_uv(), 0);
+}
+
+#[test]
+fn test_rects() {
+    let Some(image) = render_scene(SIZE, |layers, _| {
+        draw_rect(
+            layers,
+            rect(8.0, 8.0, 48.0, 32.0),
+            Srgba::from_rgb(200, 40, 40),
+        );
+        // fractional positions are anti-aliased by the tessellator
+        draw_rect(
+            layers,
+            rect(64.5, 8.5, 47.25, 31.75),
+            Srgba::from_rgb(40, 200, 40),
+        );
+        // semi-transparent rects are blended in sRGB space like PoB does
+        draw_rect(
+            layers,
+            rect(24.0, 48.0, 64.0, 40.0),
+            Srgba::new(40, 40, 200, 255),
+        );
+        draw_rect(
+            layers,
+            rect(56.0, 32.0, 64.0, 40.0),
+            Srgba::new(255, 255, 255, 128),
+        );
+    }) else {
+        return;
+    };
+    assert_matches_golden("rects", &image);
+}
+
+#[test]
+fn test_quads() {
+    let Some(image) = render_scene(SIZE, |layers, _| {
+        layers.set_draw_color(Srgba::from_rgb(230, 180, 40));
+        let quad = LogicalQuad::new(
+            LogicalPoint::new(32.0, 4.0),
+            LogicalPoint::new(60.0, 32.0),
+            LogicalPoint::new(32.0, 60.0),
+            LogicalPoint::new(4.0, 32.0),
+        );
+        layers.draw_quad(None, quad, NormalizedQuad::white_uv(), 0);
+
+        let gradient = LogicalQuad::new(
+            LogicalPoint::new(68.0, 8.0),
+            LogicalPoint::new(124.0, 16.0),
+            LogicalPoint::new(120.0, 88.0),
+            LogicalPoint::new(72.0, 80.0),
+        );
+        let colors = [
+            Srgba::from_rgb(255, 0, 0),
+            Srgba::from_rgb(0, 255, 0),
+            Srgba::from_rgb(0, 0, 255),
+            Srgba::new(255, 255, 255, 0),
+        ];
+        layers.draw_gradient_quad(None, gradient, NormalizedQuad::white_uv(), colors, 0);
+
+        layers.set_draw_color(Srgba::WHITE);
+        let points = vec![
+            LogicalPoint::new(8.0, 88.0),
+            LogicalPoint::new(32.0, 68.0),
+            LogicalPoint::new(56.0, 88.0),
+        ];
+        layers.draw_line(points, 3.0);
+    }) else {
+        return;
+    };
+    assert_matches_golden("quads", &image);
+}
+
+#[test]
+fn test_text() {
+    let Some(image) = render_scene(SIZE, |layers, fonts| {
+        let fonts_and_heights = [
+            ("Liberation Sans", 16.0),
+            ("Bitstream Vera Sans Mono", 14.0),
+            ("Fontin", 20.0),
+        ];
+        let mut y = 4.0;
+        for (family, line_height) in fonts_and_heights {
+            let mut job = LayoutJob::new(
+                FontFamily::Named(Cow::Borrowed(family)),
+                line_height - 2.0,
+                line_height,
+                Some(Alignment::Min),
+                None,
+                FontStyle::default(),
+            );
+            job.append("Path of Building", Srgba::WHITE);
+            let layout = fonts.layout(job, 1.0);
+            layers.draw_text(LogicalPoint::new(4.0, y), layout, 0.0, false);
+            y += line_height + 4.0;
+        }
+
+        let mut job = LayoutJob::new(
+            FontFamily::Named(Cow::Borrowed("Liberation Sans")),
+            14.0,
+            16.0,
+            Some(Alignment::Center),
+            Some(700.0),
+            FontStyle::default(),
+        );
+        job.append("Rotated", Srgba::from_rgb(120, 200, 255));
+        let layout = fonts.layout(job, 1.0);
+        layers.draw_text(
+            LogicalPoint::new(100.0, 72.0),
+            layout,
+            -std::f32::consts::FRAC_PI_6,
+            false,
+        );
+    }) else {
+        return;
+    };
+    assert_matches_golden("text", &image);
+}
+
+#[test]
+fn test_text_scale_factors() {
+    for pixels_per_point in [1.0, 1.25, 1.5, 2.0] {
+        let Some(image) = render_scaled_scene(SIZE, pixels_per_point, |layers, fonts| {
+            // fractional positions, like those of scaled UI elements
+            let mut y = 4.3;
+            for alignment in [Alignment::Min, Alignment::Center, Alignment::Max] {
+                let mut job = LayoutJob::new(
+                    FontFamily::Named(Cow::Borrowed("Liberation Sans")),
+                    12.0,
+                    14.0,
+                    Some(alignment),
+                    None,
+                    FontStyle::default(),
+                );
+                job.append("Life: 1234\nMana: 567", Srgba::WHITE);
+                let layout = fonts.layout(job, pixels_per_point);
+                let x = match alignment {
+                    Alignment::Min => 4.4,
+                    Alignment::Center => 64.5,
+                    Alignment::Max => 123.6,
+                };
+                layers.draw_text(LogicalPoint::new(x, y), layout, 0.0, false);
+                y += 30.7;
+            }
+        }) else {
+            return;
+        };
+        assert_matches_golden(&format!("text_scale_{pixels_per_point:.2}"), &image);
+    }
+}
+
+#[test]
+fn test_layer_ordering() {
+    let Some(image) = render_scene(SIZE, |layers, _| {
+        // drawn first, but in a higher layer
+        layers.set_draw_layer(1, 0);
+        draw_rect(
+            layers,
+            rect(32.0, 24.0, 64.0, 48.0),
+            Srgba::from_rgb(40, 200, 40),
+        );
+
+        layers.set_draw_layer(0, 0);
+        draw_rect(
+            layers,
+            rect(8.0, 8.0, 64.0, 48.0),
+            Srgba::from_rgb(200, 40, 40),
+        );
+        // sublayers are drawn in order within their layer
+        layers.set_draw_sublayer(1);
+        draw_rect(
+            layers,
+            rect(16.0, 40.0, 48.0, 48.0),
+            Srgba::from_rgb(40, 40, 200),
+        );
+
+        // negative layers are drawn below layer 0
+        layers.set_draw_layer(-1, 0);
+        draw_rect(
+            layers,
+            rect(0.0, 0.0, 128.0, 96.0),
+            Srgba::from_rgb(60, 60, 60),
+        );
+
+        // viewports clip and translate primitives
+        layers.set_draw_layer(2, 0);
+        layers.set_viewport(rect(80.0, 60.0, 40.0, 30.0));
+        draw_rect(
+            layers,
+            rect(10.0, 10.0, 100.0, 100.0),
+            Srgba::from_rgb(230, 180, 40),
+        );
+    }) else {
+        return;
+    };
+    assert_matches_golden("layer_ordering", &image);
+}
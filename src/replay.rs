@@ -0,0 +1,147 @@
+//! Replay of captures made with `--capture`, see [`crate::capture`].
+
+use crate::{
+    app::AppState,
+    capture::Capture,
+    color::Srgba,
+    dpi::LogicalSize,
+    mode::{AppEvent, ModeFrameOutput, ModeTransition},
+    renderer::{
+        image::ImageData,
+        primitives::ClippedPrimitive,
+        textures::{TextureHandle, TextureId},
+    },
+};
+use ahash::HashMap;
+use std::path::Path;
+use winit::keyboard::{Key, NamedKey};
+
+const PLACEHOLDER_COLOR: Srgba = Srgba::from_rgb(128, 128, 128);
+
+/// Execution mode that draws the frames of a capture made with `--capture`.
+///
+/// No Lua code is run. Textures are loaded from the files they were loaded from
+/// when capturing, missing ones are replaced by gray placeholders. Frames are
+/// stepped through with the arrow keys, Home and End, Space toggles playback.
+pub struct ReplayMode {
+    capture: Capture,
+    // captured texture ids and the textures replacing them
+    textures: HashMap<TextureId, TextureHandle>,
+    current_frame: usize,
+    is_playing: bool,
+    // window size and scale factor that were last applied for a frame
+    window_frame: Option<(LogicalSize<f32>, f32)>,
+}
+
+impl ReplayMode {
+    pub fn new(path: &Path, app_state: &mut AppState) -> anyhow::Result<Self> {
+        let capture = Capture::read(path)?;
+        if capture.frames.is_empty() {
+            anyhow::bail!("{} contains no frames", path.display());
+        }
+        log::info!(
+            "Replaying {} frames from {}",
+            capture.frames.len(),
+            path.display()
+        );
+
+        let mut textures = HashMap::default();
+        for texture in &capture.textures {
+            let source = app_state.script_dir.join(&texture.source);
+            let handle = match source.is_file() {
+                true => app_state.texture_manager.load_texture(
+                    source.to_string_lossy().into_owned(),
+                    texture.options,
+                    true,
+                ),
+                false => Err(anyhow::anyhow!("{} doesn't exist", source.display())),
+            };
+            let handle = handle.unwrap_or_else(|err| {
+                log::warn!("Replacing texture {}: {err}", texture.source);
+                let size = texture.size.map(|len| len.max(1));
+                app_state.texture_manager.alloc_texture(
+                    texture.source.clone(),
+                    ImageData::from_solid_color(size, PLACEHOLDER_COLOR),
+                    texture.options,
+                )
+            });
+            textures.insert(texture.id, handle);
+        }
+
+        Ok(Self {
+            capture,
+            textures,
+            current_frame: 0,
+            is_playing: false,
+            window_frame: None,
+        })
+    }
+
+    pub fn frame(&mut self, app_state: &mut AppState) -> anyhow::Result<ModeFrameOutput> {
+        if self.is_playing {
+            self.current_frame = (self.current_frame + 1) % self.capture.frames.len();
+        }
+        let frame = &self.capture.frames[self.current_frame];
+        app_state.window.set_window_title(&format!(
+            "Replay - frame {}/{} ({}x{} at {}x)",
+            self.current_frame + 1,
+            self.capture.frames.len(),
+            frame.size.width,
+            frame.size.height,
+            frame.pixels_per_point,
+        ));
+
+        // frames are drawn at the window size and scale they were captured at
+        if self.window_frame != Some((frame.size, frame.pixels_per_point)) {
+            self.window_frame = Some((frame.size, frame.pixels_per_point));
+            app_state
+                .window
+                .set_scale_factor_override(Some(frame.pixels_per_point));
+            app_state.window.request_logical_size(frame.size);
+        }
+
+        let pixels_per_point = frame.pixels_per_point;
+        let mut layout = |job| app_state.fonts.layout(job, pixels_per_point);
+        let texture_id = |id| self.textures.get(&id).map_or(id, TextureHandle::id);
+        let primitives: Vec<ClippedPrimitive> = frame
+            .primitives
+            .iter()
+            .map(|primitive| primitive.to_primitive(&mut layout, texture_id))
+            .collect();
+
+        Ok(ModeFrameOutput {
+            primitives: Box::new(primitives.into_iter()),
+            groups: Vec::new(),
+            can_elide: false,
+            should_continue: self.is_playing,
+        })
+    }
+
+    pub fn update(&mut self, _app_state: &mut AppState) -> anyhow::Result<Option<ModeTransition>> {
+        Ok(None)
+    }
+
+    pub fn handle_event(
+        &mut self,
+        _app_state: &mut AppState,
+        event: AppEvent,
+    ) -> anyhow::Result<()> {
+        let last_frame = self.capture.frames.len() - 1;
+        if let AppEvent::KeyDown {
+            key: Key::Named(key),
+        } = event
+        {
+            match key {
+                NamedKey::ArrowLeft => self.current_frame = self.current_frame.saturating_sub(1),
+                NamedKey::ArrowRight => {
+                    self.current_frame = (self.current_frame + 1).min(last_frame)
+                }
+                NamedKey::Home => self.current_frame = 0,
+                NamedKey::End => self.current_frame = last_frame,
+                NamedKey::Space => self.is_playing = !self.is_playing,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
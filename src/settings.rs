@@ -0,0 +1,438 @@
+//! Overlay for adjusting the app's own options, opened with Ctrl+Comma or the
+//! shortcut configured as `settings_shortcut`.
+//!
+//! PoB's Lua code doesn't know about options like the render scale or MSAA, so
+//! they are exposed through this overlay instead. It is drawn on top of PoB's UI
+//! like the command palette and changes are applied immediately.
+
+use crate::{
+    app::AppState,
+    color::Srgba,
+    command_palette,
+    config::{self, Config},
+    console,
+    dpi::{LogicalPoint, LogicalRect, LogicalSize, LogicalVector, NormalizedRect, Uv},
+    fonts::{Alignment, FontStyle, LayoutJob},
+    layers::Layers,
+    shortcut::Shortcut,
+};
+use parley::{FontFamily, GenericFamily};
+use winit::keyboard::{Key, ModifiersState, NamedKey};
+
+const WIDTH: f32 = 480.0;
+const TOP: f32 = 80.0;
+const PADDING: f32 = 8.0;
+const ROW_HEIGHT: f32 = 24.0;
+const FONT_SIZE: f32 = 16.0;
+const LINE_HEIGHT: f32 = 18.0;
+
+const BACKGROUND_COLOR: Srgba = Srgba::new(24, 24, 24, 240);
+const SELECTION_COLOR: Srgba = Srgba::new(60, 80, 120, 255);
+const TEXT_COLOR: Srgba = Srgba::WHITE;
+const HINT_COLOR: Srgba = Srgba::new(150, 150, 150, 255);
+
+const RENDER_SCALE_STEP: f32 = 0.25;
+const UI_SCALE_STEP: f32 = 0.25;
+const MSAA_SAMPLE_COUNTS: [u32; 4] = [1, 2, 4, 8];
+
+/// Shortcut unless another one is configured
+pub const TOGGLE_SHORTCUT: Shortcut = Shortcut::ctrl(',');
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Setting {
+    UiScale,
+    RenderScale,
+    IntegerScaling,
+    Msaa,
+    Hdr,
+    Vsync,
+    LinearBlending,
+    PremultipliedAlpha,
+    AlwaysOnTop,
+    PowerSaving,
+    SettingsShortcut,
+    ConsoleShortcut,
+    PaletteShortcut,
+}
+
+impl Setting {
+    const ALL: [Setting; 13] = [
+        Setting::UiScale,
+        Setting::RenderScale,
+        Setting::IntegerScaling,
+        Setting::Msaa,
+        Setting::Hdr,
+        Setting::Vsync,
+        Setting::LinearBlending,
+        Setting::PremultipliedAlpha,
+        Setting::AlwaysOnTop,
+        Setting::PowerSaving,
+        Setting::SettingsShortcut,
+        Setting::ConsoleShortcut,
+        Setting::PaletteShortcut,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            Setting::UiScale => "UI scale",
+            Setting::RenderScale => "Render scale",
+            Setting::IntegerScaling => "Integer scaling",
+            Setting::Msaa => "MSAA",
+            Setting::Hdr => "HDR output",
+            Setting::Vsync => "VSync",
+            Setting::LinearBlending => "Linear blending",
+            Setting::PremultipliedAlpha => "Premultiplied alpha",
+            Setting::AlwaysOnTop => "Always on top",
+            Setting::PowerSaving => "Power saving",
+            Setting::SettingsShortcut => "Settings shortcut",
+            Setting::ConsoleShortcut => "Console shortcut",
+            Setting::PaletteShortcut => "Command palette shortcut",
+        }
+    }
+
+    /// Config option and default of the shortcuts that are rebound by pressing
+    /// the new shortcut
+    fn shortcut(&self) -> Option<(&'static str, Shortcut)> {
+        match self {
+            Setting::SettingsShortcut => Some((config::SETTINGS_SHORTCUT, TOGGLE_SHORTCUT)),
+            Setting::ConsoleShortcut => Some((config::CONSOLE_SHORTCUT, console::TOGGLE_SHORTCUT)),
+            Setting::PaletteShortcut => {
+                Some((config::PALETTE_SHORTCUT, command_palette::TOGGLE_SHORTCUT))
+            }
+            _ => None,
+        }
+    }
+
+    fn value_text(&self, app_state: &AppState) -> String {
+        let on_off = |value: bool| String::from(if value { "On" } else { "Off" });
+        match self {
+            Setting::UiScale => match app_state.window.scale_factor_override() {
+                Some(scale_factor) => format!("{:.0}%", scale_factor * 100.0),
+                None => format!("Auto ({:.0}%)", app_state.window.scale_factor() * 100.0),
+            },
+            Setting::RenderScale => format!("{:.2}x", app_state.window.render_scale),
+            Setting::IntegerScaling => on_off(app_state.window.integer_scaling),
+            Setting::Msaa => match app_state.graphics_options.msaa_sample_count {
+                1 => String::from("Off"),
+                samples => format!("{samples}x"),
+            },
+            Setting::Hdr => on_off(app_state.graphics_options.hdr),
+            Setting::Vsync => on_off(app_state.graphics_options.vsync),
+            Setting::LinearBlending if !app_state.graphics_capabilities.linear_blending => {
+                String::from("Unsupported")
+            }
+            Setting::LinearBlending => on_off(app_state.graphics_options.linear_blending),
+            Setting::PremultipliedAlpha => on_off(app_state.texture_manager.premultiply_alpha()),
+            Setting::AlwaysOnTop => on_off(app_state.window.is_always_on_top()),
+            Setting::PowerSaving => on_off(app_state.window.power_saving),
+            Setting::SettingsShortcut | Setting::ConsoleShortcut | Setting::PaletteShortcut => {
+                let (name, default) = self.shortcut().unwrap();
+                app_state.config.get_shortcut(name, default).to_string()
+            }
+        }
+    }
+
+    /// Changes the setting by `step` increments. Toggles ignore the direction.
+    fn adjust(&self, app_state: &mut AppState, step: i32) {
+        match self {
+            Setting::UiScale => {
+                let window = &mut app_state.window;
+                let scale_factor_override =
+                    step_ui_scale(window.scale_factor_override(), window.scale_factor(), step);
+                window.set_scale_factor_override(scale_factor_override);
+            }
+            Setting::RenderScale => {
+                let render_scale = app_state.window.render_scale + step as f32 * RENDER_SCALE_STEP;
+                app_state.window.render_scale = render_scale.clamp(0.5, 2.0);
+            }
+            Setting::IntegerScaling => {
+                app_state.window.integer_scaling = !app_state.window.integer_scaling;
+            }
+            Setting::Msaa => {
+                let options = &mut app_state.graphics_options;
+                let idx = MSAA_SAMPLE_COUNTS
+                    .iter()
+                    .position(|&samples| samples == options.msaa_sample_count)
+                    .unwrap_or(0) as i32;
+                let idx = (idx + step).rem_euclid(MSAA_SAMPLE_COUNTS.len() as i32);
+                options.msaa_sample_count = MSAA_SAMPLE_COUNTS[idx as usize];
+            }
+            Setting::Hdr => app_state.graphics_options.hdr = !app_state.graphics_options.hdr,
+            Setting::Vsync => {
+                app_state.graphics_options.vsync = !app_state.graphics_options.vsync;
+            }
+            Setting::LinearBlending => {
+                let options = &mut app_state.graphics_options;
+                options.linear_blending = !options.linear_blending;
+            }
+            Setting::PremultipliedAlpha => {
+                let texture_manager = &mut app_state.texture_manager;
+                texture_manager.set_premultiply_alpha(!texture_manager.premultiply_alpha());
+            }
+            Setting::AlwaysOnTop => app_state.window.toggle_always_on_top(),
+            Setting::PowerSaving => app_state.window.power_saving = !app_state.window.power_saving,
+            // changed by `SettingsOverlay::record_shortcut`
+            Setting::SettingsShortcut | Setting::ConsoleShortcut | Setting::PaletteShortcut => {
+                return;
+            }
+        }
+        self.save(app_state);
+    }
+
+    /// Writes the current value to the config so that it's restored on the next
+    /// start.
+    fn save(&self, app_state: &mut AppState) {
+        let config = &mut app_state.config;
+        let result = match self {
+            Setting::UiScale => app_state.window.save_scale_factor_override(config),
+            Setting::AlwaysOnTop
+            | Setting::SettingsShortcut
+            | Setting::ConsoleShortcut
+            | Setting::PaletteShortcut => Ok(()),
+            Setting::RenderScale => config.set(config::RENDER_SCALE, app_state.window.render_scale),
+            Setting::IntegerScaling => {
+                config.set(config::INTEGER_SCALING, app_state.window.integer_scaling)
+            }
+            Setting::Msaa => config.set(config::MSAA, app_state.graphics_options.msaa_sample_count),
+            Setting::Hdr => config.set(config::HDR, app_state.graphics_options.hdr),
+            Setting::Vsync => config.set(config::VSYNC, app_state.graphics_options.vsync),
+            Setting::LinearBlending => config.set(
+                config::LINEAR_BLENDING,
+                app_state.graphics_options.linear_blending,
+            ),
+            Setting::PremultipliedAlpha => config.set(
+                config::PREMULTIPLIED_ALPHA,
+                app_state.texture_manager.premultiply_alpha(),
+            ),
+            Setting::PowerSaving => config.set(config::POWER_SAVING, app_state.window.power_saving),
+        };
+        if let Err(err) = result {
+            log::warn!("Unable to save setting: {err}");
+        }
+    }
+}
+
+/// The UI scale override after `step` increments. Stepping from automatic starts
+/// at the step next to the `current` scale factor, stepping below the smallest
+/// override switches back to automatic.
+fn step_ui_scale(scale_factor_override: Option<f32>, current: f32, step: i32) -> Option<f32> {
+    let new_scale_factor = match scale_factor_override {
+        Some(scale_factor) => scale_factor + step as f32 * UI_SCALE_STEP,
+        None => {
+            let steps = current / UI_SCALE_STEP;
+            let steps = if step > 0 {
+                steps.floor()
+            } else {
+                steps.ceil()
+            };
+            (steps + step as f32) * UI_SCALE_STEP
+        }
+    };
+    (new_scale_factor >= 0.5).then_some(new_scale_factor)
+}
+
+/// Result of handling an event while the overlay is open.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SettingsAction {
+    None,
+    Close,
+}
+
+#[derive(Default)]
+pub struct SettingsOverlay {
+    selected: usize,
+    // shortcut setting whose new shortcut is the next key press
+    recording: Option<Setting>,
+}
+
+impl SettingsOverlay {
+    /// Whether `key` is the shortcut that opens and closes the overlay.
+    pub fn is_toggle_shortcut(config: &Config, key: &Key, modifiers: ModifiersState) -> bool {
+        config
+            .get_shortcut(config::SETTINGS_SHORTCUT, TOGGLE_SHORTCUT)
+            .matches(key, modifiers)
+    }
+
+    /// Whether the next key press rebinds a shortcut. Key presses need to reach
+    /// the overlay before other shortcuts are handled then.
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    pub fn handle_key(&mut self, app_state: &mut AppState, key: &Key) -> SettingsAction {
+        if let Some(setting) = self.recording {
+            self.record_shortcut(app_state, setting, key);
+            return SettingsAction::None;
+        }
+
+        let setting = Setting::ALL[self.selected];
+        match key {
+            Key::Named(NamedKey::Escape) => return SettingsAction::Close,
+            Key::Named(NamedKey::ArrowUp) => {
+                self.selected = self.selected.saturating_sub(1);
+            }
+            Key::Named(NamedKey::ArrowDown) => {
+                self.selected = (self.selected + 1).min(Setting::ALL.len() - 1);
+            }
+            Key::Named(NamedKey::ArrowLeft) => setting.adjust(app_state, -1),
+            Key::Named(NamedKey::ArrowRight | NamedKey::Enter | NamedKey::Space) => {
+                self.activate(app_state, setting)
+            }
+            _ => {}
+        }
+        SettingsAction::None
+    }
+
+    /// Increases the value of `setting` or starts rebinding it if it's a shortcut.
+    fn activate(&mut self, app_state: &mut AppState, setting: Setting) {
+        if setting.shortcut().is_some() {
+            self.recording = Some(setting);
+        } else {
+            setting.adjust(app_state, 1);
+        }
+    }
+
+    /// Saves `key` with the pressed modifiers as the new shortcut of `setting`.
+    /// Escape cancels and Backspace restores the default. Keys that can't be
+    /// shortcuts, like modifiers on their own, are ignored.
+    fn record_shortcut(&mut self, app_state: &mut AppState, setting: Setting, key: &Key) {
+        let (name, default) = setting.shortcut().unwrap();
+        let shortcut = match key {
+            Key::Named(NamedKey::Escape) => {
+                self.recording = None;
+                return;
+            }
+            Key::Named(NamedKey::Backspace) => default,
+            key => match Shortcut::from_key(key, app_state.input.key_modifiers) {
+                Some(shortcut) => shortcut,
+                None => return,
+            },
+        };
+        self.recording = None;
+        if let Err(err) = app_state.config.set(name, shortcut.to_string()) {
+            log::warn!("Unable to save setting: {err}");
+        }
+    }
+
+    /// Clicking a row increases its value, clicking outside of the overlay closes it.
+    pub fn handle_click(&mut self, app_state: &mut AppState) -> SettingsAction {
+        let mouse_pos = app_state.input.mouse_pos();
+        let screen_size = app_state.window.logical_size().cast();
+        if !Self::rect(screen_size).contains(mouse_pos) {
+            return SettingsAction::Close;
+        }
+
+        let row = (0..Setting::ALL.len())
+            .find(|&row| Self::row_rect(screen_size, row + 1).contains(mouse_pos));
+        if let Some(row) = row {
+            self.selected = row;
+            self.activate(app_state, Setting::ALL[row]);
+        }
+        SettingsAction::None
+    }
+
+    fn rect(screen_size: LogicalSize<f32>) -> LogicalRect<f32> {
+        let width = WIDTH.min(screen_size.width - 2.0 * PADDING);
+        // one row for the title and one for the hint
+        let height = ROW_HEIGHT * (Setting::ALL.len() + 2) as f32 + 2.0 * PADDING;
+        LogicalRect::from_origin_and_size(
+            LogicalPoint::new((screen_size.width - width) / 2.0, TOP),
+            LogicalSize::new(width, height),
+        )
+    }
+
+    fn row_rect(screen_size: LogicalSize<f32>, row: usize) -> LogicalRect<f32> {
+        let rect = Self::rect(screen_size);
+        LogicalRect::from_origin_and_size(
+            rect.min + LogicalVector::new(PADDING, PADDING + ROW_HEIGHT * row as f32),
+            LogicalSize::new(rect.width() - 2.0 * PADDING, ROW_HEIGHT),
+        )
+    }
+
+    pub fn draw(&self, layers: &mut Layers, app_state: &mut AppState) {
+        let screen_size = app_state.window.logical_size().cast();
+        let pixels_per_point = app_state.window.pixels_per_point();
+        let values: Vec<_> = Setting::ALL
+            .iter()
+            .map(|setting| match self.recording {
+                Some(recording) if recording == *setting => String::from("Press a shortcut..."),
+                _ => setting.value_text(app_state),
+            })
+            .collect();
+        let fonts = &mut app_state.fonts;
+
+        layers.set_viewport(LogicalRect::from_size(screen_size));
+        layers.set_draw_layer(i32::MAX, 0);
+
+        layers.set_draw_color(BACKGROUND_COLOR);
+        layers.draw_rect(
+            None,
+            Self::rect(screen_size),
+            NormalizedRect::default_uv(),
+            0,
+        );
+
+        let mut draw_text = |layers: &mut Layers,
+                             row: usize,
+                             text: &str,
+                             color: Srgba,
+                             alignment: Option<Alignment>| {
+            let row_rect = Self::row_rect(screen_size, row);
+            let mut job = LayoutJob::new(
+                FontFamily::Generic(GenericFamily::SansSerif),
+                FONT_SIZE,
+                LINE_HEIGHT,
+                alignment,
+                None,
+                FontStyle::Normal,
+            );
+            job.append(text, color);
+            let layout = fonts.layout(job, pixels_per_point);
+            let x = match alignment {
+                Some(Alignment::Max) => row_rect.max.x - PADDING,
+                _ => row_rect.min.x + PADDING,
+            };
+            let pos = LogicalPoint::new(x, row_rect.min.y + (ROW_HEIGHT - LINE_HEIGHT) / 2.0);
+            layers.draw_text(pos, layout, 0.0, true);
+        };
+
+        draw_text(layers, 0, "Settings", TEXT_COLOR, None);
+
+        for (row, (setting, value)) in Setting::ALL.iter().zip(&values).enumerate() {
+            if row == self.selected {
+                layers.set_draw_color(SELECTION_COLOR);
+                layers.draw_rect(
+                    None,
+                    Self::row_rect(screen_size, row + 1),
+                    NormalizedRect::default_uv(),
+                    0,
+                );
+            }
+            draw_text(layers, row + 1, setting.label(), TEXT_COLOR, None);
+            draw_text(layers, row + 1, value, TEXT_COLOR, Some(Alignment::Max));
+        }
+
+        let hint = if self.is_recording() {
+            "Backspace to restore the default, Esc to cancel"
+        } else {
+            "Arrow keys or click to change, Esc to close"
+        };
+        draw_text(layers, Setting::ALL.len() + 1, hint, HINT_COLOR, None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_step_ui_scale() {
+        assert_eq!(step_ui_scale(None, 1.0, 1), Some(1.25));
+        assert_eq!(step_ui_scale(None, 1.0, -1), Some(0.75));
+        assert_eq!(step_ui_scale(None, 1.1, 1), Some(1.25));
+        assert_eq!(step_ui_scale(None, 1.1, -1), Some(1.0));
+        assert_eq!(step_ui_scale(None, 0.5, -1), None);
+        assert_eq!(step_ui_scale(Some(1.5), 1.5, -1), Some(1.25));
+        assert_eq!(step_ui_scale(Some(0.5), 0.5, -1), None);
+    }
+}
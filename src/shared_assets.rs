@@ -0,0 +1,291 @@
+//! The runtime Lua libraries in `lua/` of the script directory are the same for
+//! both games and all profiles. Identical files share a single copy in a shared
+//! directory, named after the hash of their content. How installs use it is set
+//! with the `share_runtime_assets` option:
+//!
+//! - `"copy"` (default) replaces them with copies of the shared copy, which only
+//!   take up space once they are written to on copy-on-write file systems, e.g.
+//!   APFS and Btrfs. Installs never see each other's changes.
+//! - `"link"` hard-links the files of every install to the shared copy. A write
+//!   into one of them changes the file in every install, so this relies on PoB's
+//!   updater replacing files instead of writing into them.
+//! - `"off"` leaves installs alone
+//!
+//! Every install keeps a hard link to each shared copy it uses in [`REFERENCES_DIR`],
+//! which PoB never writes to. Shared copies that no install refers to anymore are
+//! removed by `prune`. Sharing runs on every startup, so files that PoB replaced
+//! are shared again. Files can't be shared on file systems without hard links.
+
+use crate::{
+    args::data_root,
+    config::{self, Config},
+    integrity::sha1_hex,
+};
+use ahash::HashSet;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Directory in the script directory with links to the shared copies the install uses
+const REFERENCES_DIR: &str = "rpob-shared";
+
+pub fn shared_assets_dir() -> PathBuf {
+    data_root().join("RustyPathOfBuilding").join("shared")
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sharing {
+    Link,
+    Copy,
+}
+
+impl Sharing {
+    /// The mode set with the `share_runtime_assets` option, None if sharing is
+    /// turned off.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        match config.get_str(config::SHARE_RUNTIME_ASSETS) {
+            None | Some("copy") => Some(Self::Copy),
+            Some("link") => Some(Self::Link),
+            _ => None,
+        }
+    }
+}
+
+/// Replaces the runtime assets of an install with links to or copies of the
+/// shared copies and returns how many were replaced. Files that are already
+/// shared are skipped, so this is cheap to run again.
+pub fn share(script_dir: &Path, shared_dir: &Path, sharing: Sharing) -> io::Result<usize> {
+    let mut files = Vec::new();
+    find_files(&script_dir.join("lua"), &mut files)?;
+    let references_dir = script_dir.join(REFERENCES_DIR);
+    fs::create_dir_all(shared_dir)?;
+    fs::create_dir_all(&references_dir)?;
+
+    let mut used = HashSet::default();
+    let mut shared = 0;
+    for path in files {
+        let hash = sha1_hex(&fs::read(&path)?);
+        let shared_path = shared_dir.join(&hash);
+        if !shared_path.exists() {
+            // the first install with this file provides the shared copy
+            copy_file(&path, &shared_path)?;
+        }
+        let reference_path = references_dir.join(&hash);
+        if !reference_path.exists()
+            && let Err(err) = fs::hard_link(&shared_path, &reference_path)
+        {
+            log::debug!("Keeping copies of runtime assets: {err}");
+            return Ok(shared);
+        }
+        used.insert(hash);
+
+        if is_shared(&path, &shared_path, sharing)? {
+            continue;
+        }
+        // create it next to the file first, so that it's never missing
+        let temp_path = path.with_extension("rpob-link");
+        let _ = fs::remove_file(&temp_path);
+        match sharing {
+            Sharing::Link => fs::hard_link(&shared_path, &temp_path)?,
+            Sharing::Copy => copy_file(&shared_path, &temp_path)?,
+        }
+        fs::rename(&temp_path, &path)?;
+        shared += 1;
+    }
+
+    // drop the references to shared copies of files that PoB replaced
+    for entry in fs::read_dir(&references_dir)? {
+        let entry = entry?;
+        if !used.contains(entry.file_name().to_string_lossy().as_ref()) {
+            fs::remove_file(entry.path())?;
+        }
+    }
+
+    Ok(shared)
+}
+
+/// Whether `path` already is a link to or a copy of `shared_path`. Copies get the
+/// modification time of the shared copy, links to it are only made in link mode.
+fn is_shared(path: &Path, shared_path: &Path, sharing: Sharing) -> io::Result<bool> {
+    Ok(match sharing {
+        Sharing::Link => link_count(path)? > 1,
+        Sharing::Copy => {
+            link_count(path)? == 1
+                && fs::metadata(path)?.modified()? == fs::metadata(shared_path)?.modified()?
+        }
+    })
+}
+
+/// Copies `from` to `to` with its modification time. `fs::copy` clones the file on
+/// copy-on-write file systems.
+fn copy_file(from: &Path, to: &Path) -> io::Result<()> {
+    fs::copy(from, to)?;
+    let modified = fs::metadata(from)?.modified()?;
+    fs::File::options()
+        .write(true)
+        .open(to)?
+        .set_modified(modified)
+}
+
+/// Removes the shared copies that no install refers to anymore, e.g. after PoB
+/// updated them, and returns how many were removed.
+pub fn prune(shared_dir: &Path) -> io::Result<usize> {
+    let entries = match fs::read_dir(shared_dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+    let mut removed = 0;
+    for entry in entries {
+        let path = entry?.path();
+        if link_count(&path)? == 1 {
+            fs::remove_file(&path)?;
+            removed += 1;
+        }
+    }
+    if removed > 0 {
+        log::info!("Removed {removed} unused shared runtime assets");
+    }
+    Ok(removed)
+}
+
+#[cfg(unix)]
+fn link_count(path: &Path) -> io::Result<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Ok(fs::metadata(path)?.nlink())
+}
+
+#[cfg(windows)]
+fn link_count(path: &Path) -> io::Result<u64> {
+    use std::os::windows::io::AsRawHandle;
+    use windows::Win32::{
+        Foundation::HANDLE,
+        Storage::FileSystem::{BY_HANDLE_FILE_INFORMATION, GetFileInformationByHandle},
+    };
+
+    let file = fs::File::open(path)?;
+    let mut info = BY_HANDLE_FILE_INFORMATION::default();
+    // SAFETY: the handle stays open until `file` is dropped
+    unsafe { GetFileInformationByHandle(HANDLE(file.as_raw_handle()), &mut info) }
+        .map_err(io::Error::other)?;
+    Ok(info.nNumberOfLinks.into())
+}
+
+fn find_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            find_files(&entry.path(), files)?;
+        } else {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::Write,
+        time::{Duration, SystemTime},
+    };
+
+    /// Creates the install `poe{index}`, whose files are modified at distinct times
+    fn install(root: &Path, index: u64, files: &[(&str, &str)]) -> PathBuf {
+        let install = root.join(format!("poe{index}"));
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1000 * (index + 1));
+        for (file, contents) in files {
+            let path = install.join("lua").join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, contents).unwrap();
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+        install
+    }
+
+    fn read(install: &Path, file: &str) -> String {
+        fs::read_to_string(install.join("lua").join(file)).unwrap()
+    }
+
+    fn shared_count(shared_dir: &Path) -> usize {
+        fs::read_dir(shared_dir).unwrap().count()
+    }
+
+    #[test]
+    fn test_share_copies() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        let shared_dir = root.join("shared");
+        let installs = [0, 1].map(|i| {
+            let xml = format!("return {i}");
+            install(
+                root,
+                i,
+                &[("dkjson.lua", "return json"), ("xml/init.lua", &xml)],
+            )
+        });
+
+        assert_eq!(share(&installs[0], &shared_dir, Sharing::Copy).unwrap(), 0);
+        assert_eq!(share(&installs[1], &shared_dir, Sharing::Copy).unwrap(), 1);
+        // already shared files are skipped
+        assert_eq!(share(&installs[1], &shared_dir, Sharing::Copy).unwrap(), 0);
+        // one copy of dkjson.lua and both versions of init.lua
+        assert_eq!(shared_count(&shared_dir), 3);
+        assert_eq!(prune(&shared_dir).unwrap(), 0);
+
+        // writing into a file doesn't change the other install
+        fs::OpenOptions::new()
+            .append(true)
+            .open(installs[0].join("lua/dkjson.lua"))
+            .unwrap()
+            .write_all(b" changed")
+            .unwrap();
+        assert_eq!(read(&installs[1], "dkjson.lua"), "return json");
+
+        // files replaced by PoB are shared again, unused copies are removed
+        let path = installs[0].join("lua/xml/init.lua");
+        fs::remove_file(&path).unwrap();
+        fs::write(&path, "return 1").unwrap();
+        assert_eq!(share(&installs[0], &shared_dir, Sharing::Copy).unwrap(), 1);
+        assert_eq!(read(&installs[0], "xml/init.lua"), "return 1");
+        assert_eq!(prune(&shared_dir).unwrap(), 1);
+        // both versions of dkjson.lua and the init.lua both installs use now
+        assert_eq!(shared_count(&shared_dir), 3);
+    }
+
+    #[test]
+    fn test_share_links() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        let shared_dir = root.join("shared");
+        let installs = [0, 1].map(|i| install(root, i, &[("dkjson.lua", "json")]));
+
+        assert_eq!(share(&installs[0], &shared_dir, Sharing::Link).unwrap(), 1);
+        assert_eq!(share(&installs[1], &shared_dir, Sharing::Link).unwrap(), 1);
+        assert_eq!(share(&installs[1], &shared_dir, Sharing::Link).unwrap(), 0);
+        // the shared copy, the files of both installs and their references
+        assert_eq!(link_count(&installs[1].join("lua/dkjson.lua")).unwrap(), 5);
+
+        // switching to copies replaces the links
+        assert_eq!(share(&installs[0], &shared_dir, Sharing::Copy).unwrap(), 1);
+        assert_eq!(link_count(&installs[0].join("lua/dkjson.lua")).unwrap(), 1);
+        assert_eq!(read(&installs[0], "dkjson.lua"), "json");
+
+        fs::remove_dir_all(&installs[0]).unwrap();
+        fs::remove_dir_all(&installs[1]).unwrap();
+        assert_eq!(prune(&shared_dir).unwrap(), 1);
+        assert_eq!(shared_count(&shared_dir), 0);
+    }
+}
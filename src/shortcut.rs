@@ -0,0 +1,206 @@
+//! Key combinations that toggle the app's overlays. They can be changed with the
+//! `*_shortcut` options in `config.toml`, e.g. `console_shortcut = "Ctrl+Shift+L"`.
+
+use anyhow::bail;
+use std::{fmt, str::FromStr};
+use winit::keyboard::{Key, ModifiersState, NamedKey};
+
+/// Function keys that shortcuts may use instead of a character
+const FUNCTION_KEYS: [NamedKey; 12] = [
+    NamedKey::F1,
+    NamedKey::F2,
+    NamedKey::F3,
+    NamedKey::F4,
+    NamedKey::F5,
+    NamedKey::F6,
+    NamedKey::F7,
+    NamedKey::F8,
+    NamedKey::F9,
+    NamedKey::F10,
+    NamedKey::F11,
+    NamedKey::F12,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShortcutKey {
+    /// Lowercase character
+    Character(char),
+    /// Function key with the given number, starting at 1
+    Function(u8),
+}
+
+/// Key with modifiers. `Ctrl` also matches the Command key, so that the same
+/// shortcuts work on macOS.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Shortcut {
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+    pub key: ShortcutKey,
+}
+
+impl Shortcut {
+    pub const fn ctrl(key: char) -> Self {
+        Self {
+            ctrl: true,
+            shift: false,
+            alt: false,
+            key: ShortcutKey::Character(key),
+        }
+    }
+
+    pub const fn ctrl_shift(key: char) -> Self {
+        Self {
+            shift: true,
+            ..Self::ctrl(key)
+        }
+    }
+
+    /// Shortcut of `key` pressed with `modifiers`, e.g. when it's rebound in the
+    /// settings overlay. `None` for keys that can't be shortcuts, like modifiers
+    /// on their own.
+    pub fn from_key(key: &Key, modifiers: ModifiersState) -> Option<Self> {
+        let key = match key {
+            Key::Character(text) => {
+                let mut chars = text.chars().flat_map(char::to_lowercase);
+                match (chars.next(), chars.next()) {
+                    (Some(ch), None) => ShortcutKey::Character(ch),
+                    _ => return None,
+                }
+            }
+            Key::Named(named) => {
+                let index = FUNCTION_KEYS.iter().position(|key| key == named)?;
+                ShortcutKey::Function(index as u8 + 1)
+            }
+            _ => return None,
+        };
+        let shortcut = Self {
+            ctrl: modifiers.control_key() || modifiers.super_key(),
+            shift: modifiers.shift_key(),
+            alt: modifiers.alt_key(),
+            key,
+        };
+        shortcut.is_valid().then_some(shortcut)
+    }
+
+    // characters without Ctrl or Alt are needed for typing
+    fn is_valid(&self) -> bool {
+        !matches!(self.key, ShortcutKey::Character(_)) || self.ctrl || self.alt
+    }
+
+    /// Whether `key` pressed with exactly these `modifiers` triggers the shortcut.
+    pub fn matches(&self, key: &Key, modifiers: ModifiersState) -> bool {
+        let is_key = match (self.key, key) {
+            (ShortcutKey::Character(ch), Key::Character(text)) => {
+                text.chars().flat_map(char::to_lowercase).eq([ch])
+            }
+            (ShortcutKey::Function(number), Key::Named(named)) => {
+                FUNCTION_KEYS.get(number as usize - 1) == Some(named)
+            }
+            _ => false,
+        };
+        is_key
+            && self.ctrl == (modifiers.control_key() || modifiers.super_key())
+            && self.shift == modifiers.shift_key()
+            && self.alt == modifiers.alt_key()
+    }
+}
+
+impl FromStr for Shortcut {
+    type Err = anyhow::Error;
+
+    /// Parses shortcuts like `Ctrl+Shift+P`, `Ctrl+,` or `F5`.
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (modifiers, key) = match s.strip_suffix("++") {
+            Some(modifiers) => (modifiers, "+"),
+            None => s.rsplit_once('+').unwrap_or(("", s)),
+        };
+
+        let mut chars = key.chars();
+        let key = match (chars.next(), chars.next()) {
+            (Some(ch), None) => ShortcutKey::Character(ch.to_lowercase().next().unwrap_or(ch)),
+            _ => match key
+                .strip_prefix(['F', 'f'])
+                .and_then(|number| number.parse::<u8>().ok())
+            {
+                Some(number @ 1..=12) => ShortcutKey::Function(number),
+                _ => bail!("Invalid key {key:?} in shortcut {s:?}"),
+            },
+        };
+
+        let mut shortcut = Self {
+            ctrl: false,
+            shift: false,
+            alt: false,
+            key,
+        };
+        for modifier in modifiers.split('+').filter(|modifier| !modifier.is_empty()) {
+            match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "cmd" => shortcut.ctrl = true,
+                "shift" => shortcut.shift = true,
+                "alt" => shortcut.alt = true,
+                _ => bail!("Invalid modifier {modifier:?} in shortcut {s:?}"),
+            }
+        }
+
+        if !shortcut.is_valid() {
+            bail!("Shortcut {s:?} needs Ctrl or Alt");
+        }
+        Ok(shortcut)
+    }
+}
+
+impl fmt::Display for Shortcut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (is_pressed, name) in [
+            (self.ctrl, "Ctrl+"),
+            (self.shift, "Shift+"),
+            (self.alt, "Alt+"),
+        ] {
+            if is_pressed {
+                f.write_str(name)?;
+            }
+        }
+        match self.key {
+            ShortcutKey::Character(ch) => write!(f, "{}", ch.to_uppercase()),
+            ShortcutKey::Function(number) => write!(f, "F{number}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_match() {
+        let shortcut: Shortcut = "Ctrl+Shift+P".parse().unwrap();
+        assert_eq!(shortcut, Shortcut::ctrl_shift('p'));
+        assert_eq!(shortcut.to_string(), "Ctrl+Shift+P");
+        let ctrl_shift = ModifiersState::CONTROL | ModifiersState::SHIFT;
+        assert!(shortcut.matches(&Key::Character("P".into()), ctrl_shift));
+        assert!(!shortcut.matches(&Key::Character("p".into()), ModifiersState::CONTROL));
+
+        assert_eq!("cmd+,".parse::<Shortcut>().unwrap(), Shortcut::ctrl(','));
+        assert_eq!("Alt++".parse::<Shortcut>().unwrap().to_string(), "Alt++");
+        let f5: Shortcut = "F5".parse().unwrap();
+        assert!(f5.matches(&Key::Named(NamedKey::F5), ModifiersState::empty()));
+
+        assert_eq!(
+            Shortcut::from_key(&Key::Character("P".into()), ctrl_shift),
+            Some(shortcut)
+        );
+        assert_eq!(
+            Shortcut::from_key(&Key::Character("p".into()), ModifiersState::SHIFT),
+            None
+        );
+        assert_eq!(
+            Shortcut::from_key(&Key::Named(NamedKey::Shift), ModifiersState::SHIFT),
+            None
+        );
+
+        assert!("P".parse::<Shortcut>().is_err());
+        assert!("Ctrl+F13".parse::<Shortcut>().is_err());
+        assert!("Meta+P".parse::<Shortcut>().is_err());
+    }
+}
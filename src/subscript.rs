@@ -1,4 +1,9 @@
-use crate::{api::get_callback, lua::LuaInstance};
+use crate::{
+    api::get_callback,
+    integrity::{IntegrityMode, restrict_native_modules},
+    lua::LuaInstance,
+    rate_limit,
+};
 use anyhow::{Result, anyhow};
 use mlua::{Function, Integer, IntoLuaMulti, Lua, MultiValue, Number, Result as LuaResult, Value};
 use std::{
@@ -6,10 +11,15 @@ use std::{
     collections::VecDeque,
     path::PathBuf,
     rc::Rc,
-    sync::mpsc::{Receiver, Sender, TryRecvError, channel},
+    sync::mpsc::{Receiver, RecvTimeoutError, SendError, Sender, TryRecvError, channel},
     thread::JoinHandle,
+    time::Duration,
 };
 
+/// Time a subscript waits for the return values of a blocking call before it
+/// fails. The main thread answers once per frame, but may be busy for a while.
+const BLOCKING_CALL_TIMEOUT: Duration = Duration::from_secs(60);
+
 #[derive(Debug)]
 pub enum SubscriptResult {
     SubscriptFinished {
@@ -24,16 +34,26 @@ pub enum SubscriptResult {
 
 pub struct SubscriptManager {
     current_id: u64,
+    // incremented when the main instance restarts, see `abort_all`
+    generation: u64,
+    // running and queued subscripts in launch order
     scripts: Vec<Subscript>,
+    workers: Vec<Worker>,
+    max_workers: usize,
     script_dir: PathBuf,
+    integrity_mode: IntegrityMode,
 }
 
 impl SubscriptManager {
-    pub fn new(script_dir: PathBuf) -> Self {
+    pub fn new(script_dir: PathBuf, integrity_mode: IntegrityMode, max_workers: usize) -> Self {
         Self {
             current_id: 0,
+            generation: 0,
             scripts: Vec::new(),
+            workers: Vec::new(),
+            max_workers: max_workers.max(1),
             script_dir,
+            integrity_mode,
         }
     }
 
@@ -49,25 +69,40 @@ impl SubscriptManager {
 
         let subscript = Subscript::new(
             id,
+            self.generation,
             script_text,
             blocking_calls,
             nonblocking_calls,
             arguments,
-            self.script_dir.clone(),
         );
         self.scripts.push(subscript);
+        self.dispatch();
         id
     }
 
     pub fn process(&mut self, lua: &LuaInstance) -> Vec<SubscriptResult> {
         let mut results = vec![];
 
+        let generation = self.generation;
         self.scripts.retain_mut(|subscript| {
-            subscript.handle_calls(lua);
+            let is_aborted = subscript.generation != generation;
+            match is_aborted {
+                // the instance that launched the subscript is gone
+                true => subscript.cancel_calls("subscript was aborted"),
+                false => subscript.handle_calls(lua),
+            }
 
-            if let Some(event) = subscript.try_join() {
-                results.push(event);
-                // subscript has finished or errored, remove it
+            if let Some(event) = subscript.try_finish() {
+                match is_aborted {
+                    true => log::debug!("Discarding result of aborted subscript {}", subscript.id),
+                    false => results.push(event),
+                }
+                // subscript has finished or errored, free its worker and remove it
+                for worker in &mut self.workers {
+                    if worker.current == Some(subscript.id) {
+                        worker.current = None;
+                    }
+                }
                 false
             } else {
                 // subscript has not finished yet, keep it
@@ -75,12 +110,81 @@ impl SubscriptManager {
             }
         });
 
+        // workers whose subscript panicked are replaced on demand
+        self.workers.retain(|worker| !worker.handle.is_finished());
+        self.dispatch();
+
         results
     }
 
+    /// Fails the blocking calls that subscripts are waiting on, so that they don't
+    /// wait for a main instance that was restarted or failed.
+    pub fn cancel_blocking_calls(&mut self, reason: &str) {
+        for subscript in &mut self.scripts {
+            subscript.cancel_calls(reason);
+        }
+    }
+
+    /// Aborts all subscripts, e.g. because the main instance restarts. Queued
+    /// subscripts are removed. Running ones can't be stopped, but their calls fail
+    /// and their results are discarded once they finish.
+    pub fn abort_all(&mut self) {
+        self.cancel_blocking_calls("subscript was aborted");
+        self.scripts.retain(|subscript| subscript.job.is_none());
+        if !self.scripts.is_empty() {
+            log::info!("Aborting {} running subscripts", self.scripts.len());
+        }
+        self.generation += 1;
+    }
+
+    /// Whether the subscript `id` is queued or running and wasn't aborted.
+    fn is_running(&self, id: u64) -> bool {
+        self.scripts
+            .iter()
+            .any(|subscript| subscript.id == id && subscript.generation == self.generation)
+    }
+
+    /// Whether subscripts are queued or running, including aborted ones that
+    /// haven't finished yet.
     pub fn has_running_subscripts(&self) -> bool {
         !self.scripts.is_empty()
     }
+
+    /// Hands queued subscripts to idle workers in launch order. New workers are
+    /// spawned until `max_workers` subscripts run concurrently.
+    fn dispatch(&mut self) {
+        for subscript in self.scripts.iter_mut().filter(|s| s.job.is_some()) {
+            let idx = match self.workers.iter().position(|w| w.current.is_none()) {
+                Some(idx) => idx,
+                None if self.workers.len() < self.max_workers => {
+                    let worker = Worker::spawn(
+                        self.workers.len(),
+                        self.script_dir.clone(),
+                        self.integrity_mode,
+                    );
+                    match worker {
+                        Ok(worker) => self.workers.push(worker),
+                        Err(err) => {
+                            log::error!("Unable to start subscript worker: {err}");
+                            break;
+                        }
+                    }
+                    self.workers.len() - 1
+                }
+                None => break,
+            };
+
+            let worker = &mut self.workers[idx];
+            match worker.job_sender.send(subscript.job.take().unwrap()) {
+                Ok(()) => worker.current = Some(subscript.id),
+                // worker has died, try again once it was removed
+                Err(SendError(job)) => {
+                    subscript.job = Some(job);
+                    break;
+                }
+            }
+        }
+    }
 }
 
 enum SubscriptCall {
@@ -96,14 +200,136 @@ enum SubscriptCall {
     },
 }
 
+/// Everything a worker needs to run a subscript.
+struct Job {
+    script_text: String,
+    blocking_calls: Vec<String>,
+    nonblocking_calls: Vec<String>,
+    arguments: NativeMultiValue,
+    call_sender: Sender<SubscriptCall>,
+    result_sender: Sender<Result<NativeMultiValue>>,
+}
+
+impl Job {
+    fn run(self, lua: &Lua) -> Result<NativeMultiValue> {
+        for function_name in self.blocking_calls {
+            let thread_tx = self.call_sender.clone();
+            lua.globals().set(
+                function_name.clone(),
+                lua.create_function(move |_, args: MultiValue| {
+                    let (tx_return, rx_return) = channel();
+                    thread_tx
+                        .send(SubscriptCall::Blocking {
+                            function_name: function_name.clone(),
+                            arguments: args.try_into()?,
+                            return_values_sender: tx_return,
+                        })
+                        .map_err(|e| anyhow!("{}", e))?;
+                    // this blocks until we receive return values
+                    let return_values = match rx_return.recv_timeout(BLOCKING_CALL_TIMEOUT) {
+                        Ok(return_values) => return_values?,
+                        Err(RecvTimeoutError::Timeout) => {
+                            return Err(anyhow!("Blocking call {function_name} timed out").into());
+                        }
+                        Err(RecvTimeoutError::Disconnected) => {
+                            return Err(anyhow!("Blocking call {function_name} was dropped").into());
+                        }
+                    };
+                    Ok(return_values)
+                })?,
+            )?;
+        }
+
+        for function_name in self.nonblocking_calls {
+            let thread_tx = self.call_sender.clone();
+            lua.globals().set(
+                function_name.clone(),
+                lua.create_function(move |_, args: MultiValue| {
+                    thread_tx
+                        .send(SubscriptCall::NonBlocking {
+                            function_name: function_name.clone(),
+                            arguments: args.try_into()?,
+                        })
+                        .map_err(|e| anyhow!("{}", e))?;
+                    Ok(())
+                })?,
+            )?;
+        }
+
+        let result = lua
+            .load(self.script_text)
+            .call::<MultiValue>(self.arguments)?;
+        result.try_into()
+    }
+}
+
+/// Thread that runs one subscript at a time, each in a fresh Lua state.
+struct Worker {
+    job_sender: Sender<Job>,
+    handle: JoinHandle<()>,
+    // id of the subscript that is currently running
+    current: Option<u64>,
+}
+
+impl Worker {
+    fn spawn(
+        index: usize,
+        script_dir: PathBuf,
+        integrity_mode: IntegrityMode,
+    ) -> std::io::Result<Self> {
+        let (job_sender, job_receiver) = channel::<Job>();
+
+        let thread = std::thread::Builder::new().name(format!("Subscript Worker {index}"));
+        let handle = thread.spawn(move || {
+            profiling::register_thread!();
+
+            loop {
+                // prepare the state for the next subscript while waiting for it
+                let lua = Self::create_lua_state(&script_dir, integrity_mode);
+                // sender is dropped together with the manager
+                let Ok(job) = job_receiver.recv() else {
+                    break;
+                };
+                let result_sender = job.result_sender.clone();
+                let result = lua
+                    .map_err(anyhow::Error::from)
+                    .and_then(|lua| job.run(&lua));
+                let _ = result_sender.send(result);
+            }
+        })?;
+
+        Ok(Self {
+            job_sender,
+            handle,
+            current: None,
+        })
+    }
+
+    fn create_lua_state(script_dir: &PathBuf, integrity_mode: IntegrityMode) -> LuaResult<Lua> {
+        // unsafe required to load C modules (curl)
+        let lua = unsafe { Lua::unsafe_new() };
+
+        // add ./lua to package.path and package.cpath
+        LuaInstance::register_package_paths(&lua, script_dir)?;
+        restrict_native_modules(&lua, script_dir, integrity_mode)?;
+        rate_limit::pace_lcurl(&lua)?;
+        Ok(lua)
+    }
+}
+
 pub struct Subscript {
     id: u64,
-    handle: Option<JoinHandle<anyhow::Result<NativeMultiValue>>>,
+    // generation of the manager when the subscript was launched
+    generation: u64,
+    // set until the subscript is handed to a worker
+    job: Option<Job>,
     receiver: Receiver<SubscriptCall>,
+    result_receiver: Receiver<Result<NativeMultiValue>>,
 }
 
 // Subscripts are lua scripts that are executed in their own instance on a separate
-// thread.
+// thread. Worker threads are reused, but every subscript gets a fresh instance.
+// Subscripts are queued if the maximum number of concurrent subscripts is reached.
 //
 // When a subscript needs to call a function defined in the main instance, a
 // `SubscriptCall` message is send over a channel. At the beginning of each frame,
@@ -116,69 +342,30 @@ pub struct Subscript {
 // Subscripts are required to explicitly specify the names of all (non)-blocking
 // function calls that appear in the script.
 impl Subscript {
-    pub fn new(
+    fn new(
         id: u64,
+        generation: u64,
         script_text: String,
         blocking_calls: Vec<String>,
         nonblocking_calls: Vec<String>,
         arguments: NativeMultiValue,
-        script_dir: PathBuf,
     ) -> Self {
-        let (tx, rx) = channel();
-
-        let handle = std::thread::spawn(move || {
-            profiling::register_thread!(format!("Subscript {} Thread", id));
-
-            // unsafe required to load C modules (curl)
-            let lua = unsafe { Lua::unsafe_new() };
-
-            // add ./lua to package.path and package.cpath
-            LuaInstance::register_package_paths(&lua, &script_dir)?;
-
-            for function_name in blocking_calls {
-                let thread_tx = tx.clone();
-                lua.globals().set(
-                    function_name.clone(),
-                    lua.create_function(move |_, args: MultiValue| {
-                        let (tx_return, rx_return) = channel();
-                        thread_tx
-                            .send(SubscriptCall::Blocking {
-                                function_name: function_name.clone(),
-                                arguments: args.try_into()?,
-                                return_values_sender: tx_return,
-                            })
-                            .unwrap();
-                        // this blocks until we receive return values
-                        let return_values = rx_return.recv().map_err(|e| anyhow!("{}", e))??;
-                        Ok(return_values)
-                    })?,
-                )?;
-            }
-
-            for function_name in nonblocking_calls {
-                let thread_tx = tx.clone();
-                lua.globals().set(
-                    function_name.clone(),
-                    lua.create_function(move |_, args: MultiValue| {
-                        thread_tx
-                            .send(SubscriptCall::NonBlocking {
-                                function_name: function_name.clone(),
-                                arguments: args.try_into()?,
-                            })
-                            .map_err(|e| anyhow!("{}", e))?;
-                        Ok(())
-                    })?,
-                )?;
-            }
-
-            let result = lua.load(script_text).call::<MultiValue>(arguments)?;
-            result.try_into()
-        });
+        let (call_sender, receiver) = channel();
+        let (result_sender, result_receiver) = channel();
 
         Self {
             id,
-            handle: Some(handle),
-            receiver: rx,
+            generation,
+            job: Some(Job {
+                script_text,
+                blocking_calls,
+                nonblocking_calls,
+                arguments,
+                call_sender,
+                result_sender,
+            }),
+            receiver,
+            result_receiver,
         }
     }
 
@@ -226,33 +413,46 @@ impl Subscript {
         }
     }
 
-    fn try_join(&mut self) -> Option<SubscriptResult> {
-        if self
-            .handle
-            .as_ref()
-            .map(|h| h.is_finished())
-            .unwrap_or(false)
-        {
-            let event = match self.handle.take().unwrap().join() {
-                Ok(Ok(return_values)) => SubscriptResult::SubscriptFinished {
-                    id: self.id,
-                    return_values,
-                },
-                Ok(Err(err)) => SubscriptResult::SubscriptError {
-                    id: self.id,
-                    error: err.to_string(),
-                },
-                // the thread panicked
-                Err(_) => SubscriptResult::SubscriptError {
-                    id: self.id,
-                    error: String::from("Subscript thread panicked!"),
-                },
-            };
-            Some(event)
-        } else {
-            None
+    /// Answers pending blocking calls with an error. Pending non-blocking calls
+    /// are dropped.
+    fn cancel_calls(&mut self, reason: &str) {
+        while let Ok(call) = self.receiver.try_recv() {
+            match call {
+                SubscriptCall::Blocking {
+                    function_name,
+                    return_values_sender,
+                    ..
+                } => {
+                    let error = anyhow!("Blocking call {function_name} was cancelled: {reason}");
+                    let _ = return_values_sender.send(Err(error));
+                }
+                SubscriptCall::NonBlocking { function_name, .. } => {
+                    log::debug!("Dropping call {function_name} of subscript {}", self.id);
+                }
+            }
         }
     }
+
+    fn try_finish(&mut self) -> Option<SubscriptResult> {
+        let result = match self.result_receiver.try_recv() {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => return None,
+            // the worker panicked
+            Err(TryRecvError::Disconnected) => Err(anyhow!("Subscript thread panicked!")),
+        };
+
+        let event = match result {
+            Ok(return_values) => SubscriptResult::SubscriptFinished {
+                id: self.id,
+                return_values,
+            },
+            Err(err) => SubscriptResult::SubscriptError {
+                id: self.id,
+                error: err.to_string(),
+            },
+        };
+        Some(event)
+    }
 }
 
 pub fn register_subscript_globals(
@@ -295,13 +495,8 @@ pub fn register_subscript_globals(
     };
 
     let subscripts_clone = Rc::clone(subscripts);
-    let is_subscript_running = move |_: &Lua, subscript_id: u64| {
-        Ok(subscripts_clone
-            .borrow()
-            .scripts
-            .iter()
-            .any(|ss| ss.id == subscript_id))
-    };
+    let is_subscript_running =
+        move |_: &Lua, subscript_id: u64| Ok(subscripts_clone.borrow().is_running(subscript_id));
 
     let abort_subscript = |_: &Lua, _subscript_id: u64| -> LuaResult<()> { unimplemented!() };
 
@@ -371,3 +566,25 @@ impl IntoLuaMulti for NativeMultiValue {
         Ok(MultiValue::from_vec(values))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_abort_all() {
+        let script_dir = std::env::temp_dir();
+        let mut manager = SubscriptManager::new(script_dir, IntegrityMode::Warn, 1);
+        let empty = || NativeMultiValue(VecDeque::new());
+        let running = manager.push("Wait()".into(), vec!["Wait".into()], vec![], empty());
+        let queued = manager.push("return 1".into(), vec![], vec![], empty());
+        assert!(manager.is_running(running) && manager.is_running(queued));
+
+        manager.abort_all();
+        assert!(!manager.is_running(running) && !manager.is_running(queued));
+        // the running subscript is kept until its worker is done with it
+        assert_eq!(manager.scripts.len(), 1);
+        let launched = manager.push("return 1".into(), vec![], vec![], empty());
+        assert!(manager.is_running(launched));
+    }
+}
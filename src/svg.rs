@@ -0,0 +1,501 @@
+//! Exports frames as SVG, see `ExportSVG` and `--export-svg`.
+//!
+//! Rects, quads and lines become shapes, text becomes text elements that use the
+//! same font families, so the export stays sharp at any zoom level. Textures are
+//! embedded once and mapped onto the primitives that draw them, tinted with the
+//! primitive's color. Textures that browsers can't display, like PoB2's compressed
+//! DDS files, are left out. Gradient quads are filled with their first color.
+
+use crate::{
+    app::pob_font_definitions,
+    capture::Capture,
+    color::Srgba,
+    dpi::{LogicalPoint, LogicalRect, LogicalSize},
+    fonts::{Fonts, LayoutJob},
+    renderer::{
+        primitives::{ClippedPrimitive, DrawPrimitive, LinePrimitive, TextPrimitive},
+        textures::TextureId,
+    },
+};
+use ahash::HashMap;
+use base64::Engine;
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Frame that is exported once it's drawn
+pub struct SvgExport {
+    /// Exported part of the frame, the whole frame if `None`
+    pub region: Option<LogicalRect<f32>>,
+    pub path: PathBuf,
+}
+
+impl SvgExport {
+    /// `textures` are the image files of the drawn textures
+    pub fn write(
+        &self,
+        size: LogicalSize<f32>,
+        primitives: &[ClippedPrimitive],
+        textures: &HashMap<TextureId, PathBuf>,
+    ) -> anyhow::Result<()> {
+        let region = self.region.unwrap_or_else(|| LogicalRect::from_size(size));
+        let svg = frame_to_svg(region, primitives, textures);
+        std::fs::write(&self.path, svg)?;
+        log::info!("Exported frame to {}", self.path.display());
+        Ok(())
+    }
+
+    /// Writes the export in a background thread, since embedding the textures
+    /// reads and encodes their image files.
+    pub fn save(
+        self,
+        size: LogicalSize<f32>,
+        primitives: Arc<[ClippedPrimitive]>,
+        textures: Arc<HashMap<TextureId, PathBuf>>,
+    ) {
+        std::thread::spawn(move || {
+            if let Err(err) = self.write(size, &primitives, &textures) {
+                log::warn!("Unable to export {}: {err}", self.path.display());
+            }
+        });
+    }
+}
+
+/// Exports the last frame of a capture made with `--capture`. Textures are loaded
+/// from `script_dir` like when replaying.
+pub fn export_capture(
+    capture_path: &Path,
+    svg_path: &Path,
+    script_dir: &Path,
+) -> anyhow::Result<()> {
+    let capture = Capture::read(capture_path)?;
+    let Some(frame) = capture.frames.last() else {
+        anyhow::bail!("{} contains no frames", capture_path.display());
+    };
+
+    let mut fonts = Fonts::new(pob_font_definitions());
+    fonts.set_pixels_per_point(frame.pixels_per_point);
+    fonts.set_keep_jobs(true);
+    fonts.begin_frame();
+    let primitives: Vec<_> = frame
+        .primitives
+        .iter()
+        .map(|primitive| {
+            primitive.to_primitive(&mut |job| fonts.layout(job, frame.pixels_per_point), |id| {
+                id
+            })
+        })
+        .collect();
+    let textures = capture
+        .textures
+        .iter()
+        .map(|texture| (texture.id, script_dir.join(&texture.source)))
+        .collect();
+
+    SvgExport {
+        region: None,
+        path: svg_path.to_owned(),
+    }
+    .write(frame.size, &primitives, &textures)
+}
+
+pub fn frame_to_svg(
+    region: LogicalRect<f32>,
+    primitives: &[ClippedPrimitive],
+    textures: &HashMap<TextureId, PathBuf>,
+) -> String {
+    let mut writer = SvgWriter::default();
+    let mut clip_rect = None;
+    for primitive in primitives {
+        if clip_rect != Some(primitive.clip_rect) {
+            if clip_rect.is_some() {
+                writer.body.push_str("</g>\n");
+            }
+            clip_rect = Some(primitive.clip_rect);
+            let id = writer.clip_path(&rect_points(&primitive.clip_rect));
+            let _ = writeln!(writer.body, r#"<g clip-path="url(#{id})">"#);
+        }
+        writer.primitive(&primitive.primitive, textures);
+    }
+    if clip_rect.is_some() {
+        writer.body.push_str("</g>\n");
+    }
+
+    let size = region.size();
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="{} {} {} {}">"#,
+        size.width, size.height, region.min.x, region.min.y, size.width, size.height
+    );
+    // PoB clears the screen to black
+    let _ = writeln!(
+        svg,
+        r##"<rect x="{}" y="{}" width="{}" height="{}" fill="#000000"/>"##,
+        region.min.x, region.min.y, size.width, size.height
+    );
+    let _ = writeln!(svg, "<defs>\n{}</defs>", writer.defs);
+    svg.push_str(&writer.body);
+    svg.push_str("</svg>\n");
+    svg
+}
+
+#[derive(Default)]
+struct SvgWriter {
+    defs: String,
+    body: String,
+    // ids of the definitions, which are shared by all primitives that use them
+    clip_paths: HashMap<String, String>,
+    filters: HashMap<Srgba, String>,
+    // size of each embedded texture, `None` if it can't be embedded
+    images: HashMap<TextureId, Option<[f32; 2]>>,
+}
+
+impl SvgWriter {
+    fn primitive(&mut self, primitive: &DrawPrimitive, textures: &HashMap<TextureId, PathBuf>) {
+        let (points, uv, color) = match primitive {
+            DrawPrimitive::Rect(rect) => (
+                rect_points(&rect.rect),
+                rect.texture.map(|texture| {
+                    let uv = texture.uv;
+                    let uv_points = [
+                        [uv.min.x, uv.min.y],
+                        [uv.max.x, uv.min.y],
+                        [uv.max.x, uv.max.y],
+                        [uv.min.x, uv.max.y],
+                    ];
+                    (texture.texture_id, uv_points)
+                }),
+                rect.color,
+            ),
+            DrawPrimitive::Quad(quad) => {
+                let q = quad.quad;
+                let points = [q.p0, q.p1, q.p2, q.p3].map(|p| [p.x, p.y]);
+                let uv = quad.texture.map(|texture| {
+                    let uv = texture.uv;
+                    (
+                        texture.texture_id,
+                        [uv.p0, uv.p1, uv.p2, uv.p3].map(|p| [p.x, p.y]),
+                    )
+                });
+                (points, uv, quad.colors[0])
+            }
+            DrawPrimitive::NineSlice(nine_slice) => {
+                for rect in nine_slice.slices() {
+                    self.primitive(&DrawPrimitive::Rect(rect), textures);
+                }
+                return;
+            }
+            DrawPrimitive::Line(line) => return self.line(line),
+            DrawPrimitive::Text(text) => return self.text(text),
+        };
+
+        match uv {
+            Some((texture_id, uv)) => self.textured_quad(points, texture_id, uv, color, textures),
+            None => {
+                let _ = writeln!(
+                    self.body,
+                    r#"<polygon points="{}"{}/>"#,
+                    format_points(&points),
+                    fill(color)
+                );
+            }
+        }
+    }
+
+    fn textured_quad(
+        &mut self,
+        points: [[f32; 2]; 4],
+        texture_id: TextureId,
+        uv: [[f32; 2]; 4],
+        color: Srgba,
+        textures: &HashMap<TextureId, PathBuf>,
+    ) {
+        let Some([width, height]) = self.image(texture_id, textures) else {
+            return;
+        };
+        let texels = uv.map(|[u, v]| [u * width, v * height]);
+        let Some(matrix) = affine_transform(&texels, &points) else {
+            return;
+        };
+
+        // the clip path is in the texture's coordinates, which lets sprites share it
+        let clip_id = self.clip_path(&texels);
+        let filter = match color == Srgba::WHITE {
+            true => String::new(),
+            false => format!(r#" filter="url(#{})""#, self.tint_filter(color)),
+        };
+        let [a, b, c, d, e, f] = matrix;
+        let _ = writeln!(
+            self.body,
+            r##"<use href="#t{texture_id}" transform="matrix({a} {b} {c} {d} {e} {f})" clip-path="url(#{clip_id})"{filter}/>"##
+        );
+    }
+
+    fn line(&mut self, line: &LinePrimitive) {
+        let points: Vec<_> = line.points.iter().map(|p| [p.x, p.y]).collect();
+        let Srgba([r, g, b, a]) = line.color;
+        let _ = writeln!(
+            self.body,
+            r##"<polyline points="{}" fill="none" stroke="#{r:02x}{g:02x}{b:02x}" stroke-opacity="{}" stroke-width="{}" stroke-linejoin="round"/>"##,
+            format_points(&points),
+            a as f32 / 255.0,
+            line.width
+        );
+    }
+
+    fn text(&mut self, text: &TextPrimitive) {
+        let TextPrimitive {
+            pos,
+            layout,
+            rotation,
+        } = text;
+        let Some(job) = &layout.job else {
+            return;
+        };
+        let transform = match *rotation != 0.0 {
+            true => format!(
+                r#" transform="rotate({} {} {})""#,
+                rotation.to_degrees(),
+                pos.x,
+                pos.y
+            ),
+            false => String::new(),
+        };
+        let _ = writeln!(
+            self.body,
+            r#"<g font-family="{}" font-size="{}"{}{}{transform}>"#,
+            escape(&job.font_family.to_string()),
+            job.font_size,
+            job.font_weight
+                .map(|weight| format!(r#" font-weight="{weight}""#))
+                .unwrap_or_default(),
+            match job.font_style {
+                crate::fonts::FontStyle::Italic => r#" font-style="italic""#,
+                _ => "",
+            }
+        );
+        for (line_idx, line) in layout.lines.iter().enumerate() {
+            let origin = LogicalPoint::new(
+                pos.x + line.x_offset,
+                pos.y + layout.baseline + line_idx as f32 * layout.line_height,
+            );
+            let _ = write!(
+                self.body,
+                r#"<text x="{}" y="{}" xml:space="preserve">"#,
+                origin.x, origin.y
+            );
+            for (segment, color) in line_segments(job, line.text_range.clone()) {
+                let _ = write!(
+                    self.body,
+                    "<tspan{}>{}</tspan>",
+                    fill(color),
+                    escape(segment)
+                );
+            }
+            self.body.push_str("</text>\n");
+        }
+        self.body.push_str("</g>\n");
+    }
+
+    /// Embeds the texture's image file the first time it's drawn and returns its size
+    fn image(
+        &mut self,
+        texture_id: TextureId,
+        textures: &HashMap<TextureId, PathBuf>,
+    ) -> Option<[f32; 2]> {
+        if let Some(size) = self.images.get(&texture_id) {
+            return *size;
+        }
+        let size = textures.get(&texture_id).and_then(|path| {
+            let (mime_type, data, [width, height]) = match read_image(path) {
+                Ok(image) => image,
+                Err(err) => {
+                    log::warn!("Leaving out {}: {err}", path.display());
+                    return None;
+                }
+            };
+            let data = base64::engine::general_purpose::STANDARD.encode(data);
+            let _ = writeln!(
+                self.defs,
+                r#"<image id="t{texture_id}" width="{width}" height="{height}" preserveAspectRatio="none" href="data:{mime_type};base64,{data}"/>"#
+            );
+            Some([width as f32, height as f32])
+        });
+        self.images.insert(texture_id, size);
+        size
+    }
+
+    fn clip_path(&mut self, points: &[[f32; 2]]) -> String {
+        let points = format_points(points);
+        let next_id = format!("c{}", self.clip_paths.len());
+        self.clip_paths
+            .entry(points)
+            .or_insert_with_key(|points| {
+                let _ = writeln!(
+                    self.defs,
+                    r#"<clipPath id="{next_id}"><polygon points="{points}"/></clipPath>"#
+                );
+                next_id
+            })
+            .clone()
+    }
+
+    /// Filter that multiplies the image by `color` in sRGB space, like the renderer
+    fn tint_filter(&mut self, color: Srgba) -> String {
+        let next_id = format!("f{}", self.filters.len());
+        self.filters
+            .entry(color)
+            .or_insert_with(|| {
+                let [r, g, b, a] = color.0.map(|channel| channel as f32 / 255.0);
+                let _ = writeln!(
+                    self.defs,
+                    r#"<filter id="{next_id}" color-interpolation-filters="sRGB"><feColorMatrix type="matrix" values="{r} 0 0 0 0 0 {g} 0 0 0 0 0 {b} 0 0 0 0 0 {a} 0"/></filter>"#
+                );
+                next_id
+            })
+            .clone()
+    }
+}
+
+/// Reads an image file that can be embedded and returns its MIME type, contents
+/// and size.
+fn read_image(path: &Path) -> anyhow::Result<(&'static str, Vec<u8>, [u32; 2])> {
+    let format = image::ImageFormat::from_path(path)?;
+    let mime_type = match format {
+        image::ImageFormat::Png => "image/png",
+        image::ImageFormat::Jpeg => "image/jpeg",
+        image::ImageFormat::WebP => "image/webp",
+        format => anyhow::bail!("{format:?} images can't be embedded"),
+    };
+    let (width, height) = image::image_dimensions(path)?;
+    Ok((mime_type, std::fs::read(path)?, [width, height]))
+}
+
+/// Pieces of text in `range` of the job's text and their colors
+fn line_segments<'a>(
+    job: &'a LayoutJob,
+    range: std::ops::Range<usize>,
+) -> impl Iterator<Item = (&'a str, Srgba)> {
+    let mut start = 0;
+    job.segments.iter().filter_map(move |segment| {
+        let segment_range = start..start + segment.text.len();
+        start = segment_range.end;
+        let from = range.start.max(segment_range.start);
+        let to = range.end.min(segment_range.end);
+        let text = segment
+            .text
+            .get(from - segment_range.start..to.checked_sub(segment_range.start)?)?;
+        let text = text.trim_end_matches('\n');
+        (!text.is_empty()).then_some((text, segment.color))
+    })
+}
+
+/// Corners of a rect in the same order as the points of a quad
+fn rect_points(rect: &LogicalRect<f32>) -> [[f32; 2]; 4] {
+    [
+        [rect.min.x, rect.min.y],
+        [rect.max.x, rect.min.y],
+        [rect.max.x, rect.max.y],
+        [rect.min.x, rect.max.y],
+    ]
+}
+
+/// SVG matrix `[a, b, c, d, e, f]` that maps points `from` to `to`. Uses the first,
+/// second and last point, the quads are parallelograms. `None` if the points of
+/// `from` are on a line.
+fn affine_transform(from: &[[f32; 2]; 4], to: &[[f32; 2]; 4]) -> Option<[f32; 6]> {
+    let sub = |p: [f32; 2], q: [f32; 2]| [p[0] - q[0], p[1] - q[1]];
+    let (e1, e2) = (sub(from[1], from[0]), sub(from[3], from[0]));
+    let (f1, f2) = (sub(to[1], to[0]), sub(to[3], to[0]));
+    let det = e1[0] * e2[1] - e2[0] * e1[1];
+    if det.abs() < f32::EPSILON {
+        return None;
+    }
+
+    let a = (f1[0] * e2[1] - f2[0] * e1[1]) / det;
+    let c = (f2[0] * e1[0] - f1[0] * e2[0]) / det;
+    let b = (f1[1] * e2[1] - f2[1] * e1[1]) / det;
+    let d = (f2[1] * e1[0] - f1[1] * e2[0]) / det;
+    let e = to[0][0] - a * from[0][0] - c * from[0][1];
+    let f = to[0][1] - b * from[0][0] - d * from[0][1];
+    Some([a, b, c, d, e, f])
+}
+
+fn format_points(points: &[[f32; 2]]) -> String {
+    let mut out = String::new();
+    for (i, [x, y]) in points.iter().enumerate() {
+        if i > 0 {
+            out.push(' ');
+        }
+        let _ = write!(out, "{x},{y}");
+    }
+    out
+}
+
+fn fill(color: Srgba) -> String {
+    let Srgba([r, g, b, a]) = color;
+    match a {
+        255 => format!(r##" fill="#{r:02x}{g:02x}{b:02x}""##),
+        a => format!(
+            r##" fill="#{r:02x}{g:02x}{b:02x}" fill-opacity="{}""##,
+            a as f32 / 255.0
+        ),
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            ch => out.push(ch),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::primitives::RectPrimitive;
+
+    #[test]
+    fn test_affine_transform() {
+        // a 10x10 texture region drawn rotated by 90 degrees at (100, 100)
+        let from = [[0.0, 0.0], [10.0, 0.0], [10.0, 10.0], [0.0, 10.0]];
+        let to = [[100.0, 100.0], [100.0, 110.0], [90.0, 110.0], [90.0, 100.0]];
+        let [a, b, c, d, e, f] = affine_transform(&from, &to).unwrap();
+        for (p, q) in from.iter().zip(to) {
+            assert_eq!([a * p[0] + c * p[1] + e, b * p[0] + d * p[1] + f], q);
+        }
+        assert!(affine_transform(&[[0.0, 0.0]; 4], &to).is_none());
+    }
+
+    #[test]
+    fn test_frame_to_svg() {
+        let clip_rect = LogicalRect::from_size(LogicalSize::new(100.0, 50.0));
+        let rect = |color| ClippedPrimitive {
+            clip_rect,
+            primitive: DrawPrimitive::Rect(RectPrimitive::new(clip_rect, color, None)),
+        };
+        let primitives = [
+            rect(Srgba::new(255, 0, 0, 255)),
+            rect(Srgba::new(0, 0, 255, 128)),
+        ];
+        let svg = frame_to_svg(clip_rect, &primitives, &HashMap::default());
+
+        assert!(
+            svg.starts_with(r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="50""#)
+        );
+        // both rects share the clip path
+        assert_eq!(svg.matches("<clipPath").count(), 1);
+        assert!(svg.contains(r##"<polygon points="0,0 100,0 100,50 0,50" fill="#ff0000"/>"##));
+        assert!(svg.contains(r##"fill="#0000ff" fill-opacity="0.5019608""##));
+    }
+}
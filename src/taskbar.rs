@@ -0,0 +1,90 @@
+//! Progress indicator on the taskbar button, e.g. during long trade searches.
+//! winit doesn't support it, so it's implemented with `ITaskbarList3` on Windows
+//! and does nothing on other platforms.
+
+use std::sync::Arc;
+use winit::window::Window;
+
+pub struct TaskbarProgress {
+    #[cfg_attr(not(windows), allow(dead_code))]
+    window: Arc<Window>,
+    #[cfg(windows)]
+    taskbar: Option<windows::Win32::UI::Shell::ITaskbarList3>,
+    progress: Option<f32>,
+}
+
+impl TaskbarProgress {
+    pub fn new(window: Arc<Window>) -> Self {
+        Self {
+            window,
+            #[cfg(windows)]
+            taskbar: create_taskbar_list(),
+            progress: None,
+        }
+    }
+
+    /// Shows `progress` in percent on the taskbar button, `None` hides it.
+    pub fn set(&mut self, progress: Option<f32>) {
+        let progress = progress.map(|progress| progress.clamp(0.0, 100.0));
+        if progress == self.progress {
+            return;
+        }
+        self.progress = progress;
+
+        #[cfg(windows)]
+        if let Some(taskbar) = &self.taskbar
+            && let Err(err) = set_progress(taskbar, &self.window, progress)
+        {
+            log::warn!("Unable to set taskbar progress: {err}");
+        }
+    }
+}
+
+#[cfg(windows)]
+fn create_taskbar_list() -> Option<windows::Win32::UI::Shell::ITaskbarList3> {
+    use windows::Win32::{
+        System::Com::{CLSCTX_INPROC_SERVER, CoCreateInstance},
+        UI::Shell::{ITaskbarList3, TaskbarList},
+    };
+
+    // COM is initialized by winit on the main thread
+    let result = unsafe {
+        CoCreateInstance::<_, ITaskbarList3>(&TaskbarList, None, CLSCTX_INPROC_SERVER)
+            .and_then(|taskbar| taskbar.HrInit().map(|_| taskbar))
+    };
+    match result {
+        Ok(taskbar) => Some(taskbar),
+        Err(err) => {
+            log::warn!("Failed to create taskbar list: {err}");
+            None
+        }
+    }
+}
+
+#[cfg(windows)]
+fn set_progress(
+    taskbar: &windows::Win32::UI::Shell::ITaskbarList3,
+    window: &Window,
+    progress: Option<f32>,
+) -> anyhow::Result<()> {
+    use raw_window_handle::{HasWindowHandle, RawWindowHandle};
+    use windows::Win32::{
+        Foundation::HWND,
+        UI::Shell::{TBPF_NOPROGRESS, TBPF_NORMAL},
+    };
+
+    let RawWindowHandle::Win32(handle) = window.window_handle()?.as_raw() else {
+        anyhow::bail!("Not a Win32 window");
+    };
+    let hwnd = HWND(handle.hwnd.get() as *mut _);
+    unsafe {
+        match progress {
+            Some(progress) => {
+                taskbar.SetProgressState(hwnd, TBPF_NORMAL)?;
+                taskbar.SetProgressValue(hwnd, (progress * 100.0) as u64, 10_000)?;
+            }
+            None => taskbar.SetProgressState(hwnd, TBPF_NOPROGRESS)?,
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,119 @@
+//! Tray icon, built with the `tray` feature. Its menu brings the window back,
+//! imports a build from the clipboard and quits. With `--minimize-to-tray`, a
+//! minimized window is hidden until it's opened from the tray.
+//!
+//! NOTE: The icon has to be created on a thread that runs the platform's event
+//! loop. On Windows and macOS, that's the main thread, on which winit runs its
+//! loop. On Linux, the icon needs a GTK main loop, which gets its own thread.
+
+use crate::app::{EventProxy, UserEvent};
+use tray_icon::{
+    Icon, MouseButton, MouseButtonState, TrayIcon, TrayIconBuilder, TrayIconEvent,
+    menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem},
+};
+
+const OPEN_ID: &str = "open";
+const IMPORT_ID: &str = "import";
+const QUIT_ID: &str = "quit";
+
+/// Menu item or click on the tray icon, sent to the event loop
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrayAction {
+    /// Shows and focuses the window
+    Open,
+    /// Opens the build whose link or code is in the clipboard
+    ImportFromClipboard,
+    Quit,
+}
+
+/// Keeps the tray icon alive, dropping it removes the icon. On Linux, the icon
+/// lives on the GTK thread until the app exits.
+pub struct Tray {
+    _icon: Option<TrayIcon>,
+}
+
+impl Tray {
+    /// Creates the tray icon. Has to be called on the main thread once the event
+    /// loop runs.
+    pub fn new(event_proxy: EventProxy) -> anyhow::Result<Self> {
+        forward_events(event_proxy);
+
+        #[cfg(target_os = "linux")]
+        {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            std::thread::Builder::new()
+                .name("tray".to_owned())
+                .spawn(move || {
+                    if let Err(err) = gtk::init() {
+                        let _ = sender.send(Err(anyhow::anyhow!(err)));
+                        return;
+                    }
+                    match build_icon() {
+                        Ok(_icon) => {
+                            let _ = sender.send(Ok(()));
+                            gtk::main();
+                        }
+                        Err(err) => {
+                            let _ = sender.send(Err(err));
+                        }
+                    }
+                })?;
+            receiver.recv()??;
+            Ok(Self { _icon: None })
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        Ok(Self {
+            _icon: Some(build_icon()?),
+        })
+    }
+}
+
+fn build_icon() -> anyhow::Result<TrayIcon> {
+    let menu = Menu::with_items(&[
+        &MenuItem::with_id(OPEN_ID, "Open Path of Building", true, None),
+        &MenuItem::with_id(IMPORT_ID, "Import build from clipboard", true, None),
+        &PredefinedMenuItem::separator(),
+        &MenuItem::with_id(QUIT_ID, "Quit", true, None),
+    ])?;
+
+    let image = image::load_from_memory(include_bytes!("../assets/icon.png"))?.into_rgba8();
+    let (width, height) = image.dimensions();
+    let icon = Icon::from_rgba(image.into_raw(), width, height)?;
+
+    Ok(TrayIconBuilder::new()
+        .with_menu(Box::new(menu))
+        // left clicks open the window instead
+        .with_menu_on_left_click(false)
+        .with_tooltip("Path of Building")
+        .with_icon(icon)
+        .build()?)
+}
+
+// the handlers are called on the thread of the tray icon
+fn forward_events(event_proxy: EventProxy) {
+    let proxy = event_proxy.clone();
+    MenuEvent::set_event_handler(Some(move |event: MenuEvent| {
+        let action = if event.id == OPEN_ID {
+            TrayAction::Open
+        } else if event.id == IMPORT_ID {
+            TrayAction::ImportFromClipboard
+        } else if event.id == QUIT_ID {
+            TrayAction::Quit
+        } else {
+            return;
+        };
+        let _ = proxy.send_event(UserEvent::Tray(action));
+    }));
+
+    TrayIconEvent::set_event_handler(Some(move |event: TrayIconEvent| {
+        if let TrayIconEvent::Click {
+            button: MouseButton::Left,
+            button_state: MouseButtonState::Up,
+            ..
+        } = event
+        {
+            let _ = event_proxy.send_event(UserEvent::Tray(TrayAction::Open));
+        }
+    }));
+}
@@ -1,11 +1,13 @@
+use ahash::AHasher;
+use base64::{DecodeError, Engine, engine::general_purpose::STANDARD_NO_PAD};
 use std::{
     env,
+    fmt::Write as _,
     hash::{Hash, Hasher},
+    io::Write,
     path::{Path, PathBuf},
 };
 
-use ahash::AHasher;
-
 pub fn get_executable_dir() -> anyhow::Result<PathBuf> {
     let exe_path = env::current_exe()?;
     let exe_parent_dir = exe_path.parent().unwrap().canonicalize()?;
@@ -28,6 +30,169 @@ pub fn calculate_hash<T: Hash>(t: &T) -> u64 {
     state.finish()
 }
 
+/// Hashes a value with [`StableHasher`], giving the same result on every platform.
+/// Slower than [`calculate_hash`], only used for hashes of draw primitives that end
+/// up in frame dumps.
+pub fn stable_hash<T: Hash>(t: &T) -> u64 {
+    let mut state = StableHasher::default();
+    t.hash(&mut state);
+    state.finish()
+}
+
+/// 64-bit FNV-1a hasher with a platform-independent encoding of integers.
+///
+/// Unlike `AHasher`, whose output depends on available CPU features, and the standard
+/// hashers, which encode integers in native byte order and width, the output of this
+/// hasher only depends on the hashed data.
+pub struct StableHasher(u64);
+
+impl StableHasher {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+}
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+}
+
+impl Hasher for StableHasher {
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    #[inline]
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    #[inline]
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    #[inline]
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    #[inline]
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    #[inline]
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    #[inline]
+    fn write_i16(&mut self, i: i16) {
+        self.write_u16(i as u16);
+    }
+
+    #[inline]
+    fn write_i32(&mut self, i: i32) {
+        self.write_u32(i as u32);
+    }
+
+    #[inline]
+    fn write_i64(&mut self, i: i64) {
+        self.write_u64(i as u64);
+    }
+
+    #[inline]
+    fn write_i128(&mut self, i: i128) {
+        self.write_u128(i as u128);
+    }
+
+    #[inline]
+    fn write_isize(&mut self, i: isize) {
+        self.write_i64(i as i64);
+    }
+
+    #[inline]
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Writes `contents` to a temporary file next to `path` and renames it to `path`,
+/// so that readers never see a partially written file.
+pub fn write_atomic<P: AsRef<Path>>(path: P, contents: &[u8]) -> std::io::Result<()> {
+    let path = path.as_ref();
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| std::io::Error::other("path has no file name"))?;
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(file_name);
+    tmp_name.push(format!(".{}.tmp", std::process::id()));
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let result = (|| {
+        let mut file = std::fs::File::create(&tmp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, path)
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Lowercase hex encoding of `bytes`, e.g. of a digest
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+/// Decodes base64 in either the standard or the URL-safe alphabet, with or without
+/// padding. Whitespace is ignored.
+pub fn decode_base64(text: &str) -> Result<Vec<u8>, DecodeError> {
+    let text: String = text
+        .chars()
+        .filter(|ch| !ch.is_whitespace())
+        .map(|ch| match ch {
+            '-' => '+',
+            '_' => '/',
+            ch => ch,
+        })
+        .collect();
+    STANDARD_NO_PAD.decode(text.trim_end_matches('='))
+}
+
+/// Decodes `%XX` escapes. Invalid escapes are kept as they are.
+pub fn percent_decode(bytes: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
 /// Performs replacement only in lines that match a given pattern
 pub fn replace_in_matching_lines(
     input: &str,
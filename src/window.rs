@@ -1,21 +1,98 @@
 use crate::{
+    args,
     clipboard::Clipboard,
-    dpi::{ConvertToLogical, LogicalSize, PhysicalSize},
+    config::{self, Config},
+    dpi::{ConvertToLogical, LogicalRect, LogicalSize, PhysicalSize},
+    svg::SvgExport,
+    taskbar::TaskbarProgress,
 };
 use raw_window_handle::HasDisplayHandle;
-use std::sync::Arc;
-use winit::window::Window;
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use winit::window::{CursorGrabMode, Theme, Window, WindowLevel};
+
+/// How often the UI is updated while power saving is enabled and the window is
+/// focused, so that time-based effects like the blinking text cursor keep working.
+const POWER_SAVING_WAKEUP_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Supported range of the DPI scale override
+const SCALE_FACTOR_OVERRIDE_RANGE: std::ops::RangeInclusive<f32> = 0.5..=4.0;
+
+/// Name of the theme as passed to Lua
+pub fn theme_name(theme: Theme) -> &'static str {
+    match theme {
+        Theme::Light => "LIGHT",
+        Theme::Dark => "DARK",
+    }
+}
+
+/// Region of the next rendered frame that is saved as image, see `CaptureRegion`
+pub struct RegionCapture {
+    pub rect: LogicalRect<f32>,
+    /// Size of the image, defaults to the resolution of the window
+    pub size: Option<PhysicalSize<u32>>,
+    pub path: PathBuf,
+}
+
+impl RegionCapture {
+    /// Saves the captured image in a background thread, the format is derived from
+    /// the file extension.
+    pub fn save(self, image: image::RgbaImage) {
+        std::thread::spawn(move || {
+            // frames are opaque, and JPEG doesn't support alpha
+            let image = image::DynamicImage::ImageRgba8(image).into_rgb8();
+            match image.save(&self.path) {
+                Ok(()) => log::info!("Saved capture to {}", self.path.display()),
+                Err(err) => log::warn!("Unable to save {}: {err}", self.path.display()),
+            }
+        });
+    }
+}
+
+/// Appends the selected profile to `title` so that windows of different profiles
+/// can be told apart.
+pub fn title_with_profile(title: &str) -> String {
+    match args::profile() {
+        Some(profile) => format!("{title} [{profile}]"),
+        None => title.to_string(),
+    }
+}
 
 pub struct WindowState {
     // NOTE: clipboard needs to be destroyed before window
     clipboard: Option<Clipboard>,
+    taskbar_progress: Option<TaskbarProgress>,
     pub window: Option<Arc<Window>>,
     pub size: PhysicalSize<u32>,
     scale_factor: f32,
-    pub scale_factor_override: Option<f32>,
+    scale_factor_override: Option<f32>,
+    /// Render at logical resolution and upscale with nearest filtering when the
+    /// scale factor is a whole number.
+    pub integer_scaling: bool,
+    /// Resolution of the rendered UI relative to the window. Values above 1
+    /// supersample, values below 1 trade sharpness for performance.
+    pub render_scale: f32,
     pending_window_title: std::cell::Cell<Option<String>>,
+    /// Size below which the UI doesn't fit, see `set_min_size`
+    min_size: Option<LogicalSize<u32>>,
     pub is_hovered: bool,
     pub is_focused: bool,
+    /// System theme, `None` if the platform doesn't report it
+    pub theme: Option<Theme>,
+    is_always_on_top: bool,
+    is_cursor_captured: bool,
+    /// Only redraw on input, on a slow timer and when requested from Lua instead of
+    /// every frame while the window is focused or hovered.
+    pub power_saving: bool,
+    needs_redraw: bool,
+    captures: Vec<RegionCapture>,
+    svg_exports: Vec<SvgExport>,
+    animate_until: Option<Instant>,
+    last_frame_start: Option<Instant>,
+    frame_time: Duration,
 }
 
 impl Default for WindowState {
@@ -25,10 +102,24 @@ impl Default for WindowState {
             size: Default::default(),
             scale_factor: 1.0,
             scale_factor_override: None,
+            integer_scaling: false,
+            render_scale: 1.0,
             pending_window_title: std::cell::Cell::new(None),
+            min_size: None,
             clipboard: None,
+            taskbar_progress: None,
             is_hovered: true,
             is_focused: true,
+            theme: None,
+            is_always_on_top: false,
+            is_cursor_captured: false,
+            power_saving: false,
+            needs_redraw: false,
+            captures: Vec::new(),
+            svg_exports: Vec::new(),
+            animate_until: None,
+            last_frame_start: None,
+            frame_time: Duration::ZERO,
         }
     }
 }
@@ -42,17 +133,22 @@ impl WindowState {
         let winit::dpi::PhysicalSize { width, height } = window.inner_size();
         self.size = PhysicalSize::new(width, height);
         self.scale_factor = window.scale_factor() as f32;
+        self.theme = window.theme();
 
         let raw_display_handle = window.display_handle().ok().map(|h| h.as_raw());
         self.clipboard = Some(Clipboard::new(raw_display_handle));
+        self.taskbar_progress = Some(TaskbarProgress::new(Arc::clone(&window)));
         self.window = Some(window);
+        self.apply_min_size();
     }
 
+    /// Sets the window title. The selected profile, if any, is appended.
     pub fn set_window_title(&self, title: &str) {
+        let title = title_with_profile(title);
         if let Some(ref window) = self.window {
-            window.set_title(title);
+            window.set_title(&title);
         } else {
-            self.pending_window_title.set(Some(title.to_string()));
+            self.pending_window_title.set(Some(title));
         }
     }
 
@@ -64,16 +160,203 @@ impl WindowState {
         self.scale_factor_override.unwrap_or(self.scale_factor)
     }
 
+    pub fn scale_factor_override(&self) -> Option<f32> {
+        self.scale_factor_override
+    }
+
+    /// Factor by which the rendered UI is upscaled if integer scaling is active.
+    pub fn integer_scale_factor(&self) -> Option<u32> {
+        let scale_factor = self.scale_factor();
+        (self.integer_scaling && scale_factor > 1.0 && scale_factor.fract() == 0.0)
+            .then_some(scale_factor as u32)
+    }
+
+    /// Scale factor used to rasterize and tessellate the UI.
+    ///
+    /// With integer scaling, the UI is rendered at logical resolution and upscaled
+    /// afterwards. Otherwise, the render scale is applied. Integer scaling takes
+    /// precedence over the render scale.
+    pub fn pixels_per_point(&self) -> f32 {
+        if self.integer_scale_factor().is_some() {
+            1.0
+        } else {
+            self.scale_factor() * self.render_scale
+        }
+    }
+
+    /// Refresh rate of the current monitor in Hz. Assumes 60 Hz if it's unknown.
+    pub fn refresh_rate(&self) -> f64 {
+        let refresh_rate_millihertz = self
+            .window
+            .as_ref()
+            .and_then(|window| window.current_monitor())
+            .and_then(|monitor| monitor.refresh_rate_millihertz())
+            .unwrap_or(60_000);
+        refresh_rate_millihertz as f64 / 1000.0
+    }
+
+    /// Time between two frames at the refresh rate of the current monitor.
+    pub fn frame_interval(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.refresh_rate())
+    }
+
+    /// Records the start of a frame to measure the time since the previous one.
+    pub fn begin_frame(&mut self, frame_start: Instant) {
+        if let Some(last_frame_start) = self.last_frame_start {
+            self.frame_time = frame_start - last_frame_start;
+        }
+        self.last_frame_start = Some(frame_start);
+    }
+
+    /// Measured time between the start of the previous and the current frame.
+    pub fn frame_time(&self) -> Duration {
+        self.frame_time
+    }
+
+    pub fn set_scale_factor_override(&mut self, scale_factor_override: Option<f32>) {
+        self.scale_factor_override = scale_factor_override.map(|scale_factor| {
+            scale_factor.clamp(
+                *SCALE_FACTOR_OVERRIDE_RANGE.start(),
+                *SCALE_FACTOR_OVERRIDE_RANGE.end(),
+            )
+        });
+        self.apply_min_size();
+    }
+
+    /// Restores the scale factor override saved in `config`, so that it's applied
+    /// before PoB is launched.
+    pub fn load_scale_factor_override(&mut self, config: &Config) {
+        let percent = config.get_u32(config::DPI_SCALE_OVERRIDE);
+        self.set_scale_factor_override(percent.map(|percent| percent as f32 / 100.0));
+    }
+
+    /// Saves the scale factor override to `config` as a percentage. Without an
+    /// override, the option is removed.
+    pub fn save_scale_factor_override(&self, config: &mut Config) -> anyhow::Result<()> {
+        match self.scale_factor_override {
+            Some(scale_factor) => config.set(
+                config::DPI_SCALE_OVERRIDE,
+                (scale_factor * 100.0).round() as u32,
+            ),
+            None => config.remove(config::DPI_SCALE_OVERRIDE),
+        }
+    }
+
+    /// Asks for the window to be resized to `size` at the current scale factor. The
+    /// platform may ignore it or limit it, e.g. to the size of the monitor.
+    pub fn request_logical_size(&self, size: LogicalSize<f32>) {
+        if let Some(ref window) = self.window {
+            let scale_factor = self.scale_factor();
+            let _ = window.request_inner_size(winit::dpi::PhysicalSize::new(
+                (size.width * scale_factor).round() as u32,
+                (size.height * scale_factor).round() as u32,
+            ));
+        }
+    }
+
     pub fn set_scale_factor(&mut self, scale_factor: f32) {
         self.scale_factor = scale_factor;
+        self.apply_min_size();
+    }
+
+    /// Prevents the window from being resized below `size`, which is in logical
+    /// pixels so that it stays the same when the scale factor changes. It's limited
+    /// to the size of the current monitor, so that large scale factors don't make
+    /// the window bigger than the screen.
+    pub fn set_min_size(&mut self, size: Option<LogicalSize<u32>>) {
+        self.min_size = size;
+        self.apply_min_size();
+    }
+
+    fn apply_min_size(&self) {
+        let Some(ref window) = self.window else {
+            return;
+        };
+        let scale_factor = self.scale_factor();
+        let monitor_size = window.current_monitor().map(|monitor| monitor.size());
+        let min_size = self.min_size.map(|size| {
+            let mut min_size = winit::dpi::PhysicalSize::new(
+                (size.width as f32 * scale_factor).round() as u32,
+                (size.height as f32 * scale_factor).round() as u32,
+            );
+            if let Some(monitor_size) = monitor_size {
+                min_size.width = min_size.width.min(monitor_size.width);
+                min_size.height = min_size.height.min(monitor_size.height);
+            }
+            min_size
+        });
+        window.set_min_inner_size(min_size);
     }
 
     pub fn focus(&self) {
         if let Some(ref window) = self.window {
+            window.set_visible(true);
+            window.set_minimized(false);
             window.focus_window();
         }
     }
 
+    /// Hides the window if it's minimized, see `--minimize-to-tray`. [`Self::focus`]
+    /// shows it again.
+    pub fn hide_if_minimized(&self) {
+        if let Some(ref window) = self.window
+            && window.is_minimized() == Some(true)
+        {
+            window.set_visible(false);
+        }
+    }
+
+    /// Locks and hides the cursor so that drags continue past the window bounds.
+    /// The cursor position is moved by relative mouse motion while captured.
+    pub fn capture_cursor(&mut self) -> Result<(), winit::error::ExternalError> {
+        let Some(ref window) = self.window else {
+            return Ok(());
+        };
+        // not every platform supports both modes, e.g. X11 can't lock and macOS
+        // can't confine the cursor
+        window
+            .set_cursor_grab(CursorGrabMode::Locked)
+            .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined))?;
+        window.set_cursor_visible(false);
+        self.is_cursor_captured = true;
+        Ok(())
+    }
+
+    pub fn release_cursor(&mut self) {
+        if !self.is_cursor_captured {
+            return;
+        }
+        if let Some(ref window) = self.window {
+            let _ = window.set_cursor_grab(CursorGrabMode::None);
+            window.set_cursor_visible(true);
+        }
+        self.is_cursor_captured = false;
+    }
+
+    pub fn is_cursor_captured(&self) -> bool {
+        self.is_cursor_captured
+    }
+
+    pub fn is_always_on_top(&self) -> bool {
+        self.is_always_on_top
+    }
+
+    pub fn toggle_always_on_top(&mut self) {
+        self.is_always_on_top = !self.is_always_on_top;
+        if let Some(ref window) = self.window {
+            window.set_window_level(if self.is_always_on_top {
+                WindowLevel::AlwaysOnTop
+            } else {
+                WindowLevel::Normal
+            });
+        }
+    }
+
+    pub fn raw_display_handle(&self) -> Option<raw_window_handle::RawDisplayHandle> {
+        let window = self.window.as_ref()?;
+        window.display_handle().ok().map(|handle| handle.as_raw())
+    }
+
     pub fn set_clipboard_text(&mut self, text: String) {
         if let Some(clipboard) = &mut self.clipboard {
             clipboard.set_text(text);
@@ -88,9 +371,85 @@ impl WindowState {
         }
     }
 
+    pub fn set_primary_selection_text(&mut self, text: String) {
+        if let Some(clipboard) = &mut self.clipboard {
+            clipboard.set_primary_text(text);
+        }
+    }
+
+    pub fn get_primary_selection_text(&mut self) -> Option<String> {
+        if let Some(clipboard) = &mut self.clipboard {
+            clipboard.get_primary_text()
+        } else {
+            None
+        }
+    }
+
+    /// Shows `progress` in percent on the taskbar button where supported, `None`
+    /// hides it.
+    pub fn set_taskbar_progress(&mut self, progress: Option<f32>) {
+        if let Some(taskbar_progress) = &mut self.taskbar_progress {
+            taskbar_progress.set(progress);
+        }
+    }
+
     pub fn request_redraw(&self) {
         if let Some(ref window) = self.window {
             window.request_redraw();
         }
     }
+
+    /// Requests one more frame, e.g. because the UI changed without any input.
+    pub fn set_needs_redraw(&mut self) {
+        self.needs_redraw = true;
+        self.request_redraw();
+    }
+
+    /// Saves `rect` of the next rendered frame to `path`.
+    pub fn capture_region(&mut self, capture: RegionCapture) {
+        self.captures.push(capture);
+        self.set_needs_redraw();
+    }
+
+    pub fn take_captures(&mut self) -> Vec<RegionCapture> {
+        std::mem::take(&mut self.captures)
+    }
+
+    /// Exports the next frame as SVG, see [`SvgExport`].
+    pub fn export_svg(&mut self, export: SvgExport) {
+        self.svg_exports.push(export);
+        self.set_needs_redraw();
+    }
+
+    pub fn take_svg_exports(&mut self) -> Vec<SvgExport> {
+        std::mem::take(&mut self.svg_exports)
+    }
+
+    /// Redraws every frame for `duration`, even if the window isn't focused or
+    /// hovered. A zero duration stops an ongoing animation.
+    pub fn animate_for(&mut self, duration: Duration) {
+        self.animate_until = (!duration.is_zero()).then(|| Instant::now() + duration);
+        self.request_redraw();
+    }
+
+    pub fn is_animating(&self) -> bool {
+        self.animate_until
+            .is_some_and(|animate_until| Instant::now() < animate_until)
+    }
+
+    /// Whether a frame was requested with [`Self::set_needs_redraw`]. Resets the request.
+    pub fn take_needs_redraw(&mut self) -> bool {
+        std::mem::take(&mut self.needs_redraw)
+    }
+
+    /// Whether frames are drawn continuously because the user is interacting with
+    /// the window.
+    pub fn is_polling(&self) -> bool {
+        !self.power_saving && (self.is_focused || self.is_hovered)
+    }
+
+    /// Time at which the UI should be updated without any input or redraw request.
+    pub fn next_wakeup(&self, last_frame: Instant) -> Option<Instant> {
+        (self.power_saving && self.is_focused).then(|| last_frame + POWER_SAVING_WAKEUP_INTERVAL)
+    }
 }
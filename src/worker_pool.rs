@@ -1,46 +1,140 @@
 use std::{
-    sync::{Arc, Mutex, mpsc},
+    cmp,
+    collections::BinaryHeap,
+    mem,
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicI32, AtomicU64, Ordering},
+    },
     thread,
 };
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
+/// Priority of a queued job. It can be changed until a worker picks the job up,
+/// jobs with higher priority run first.
+#[derive(Clone, Debug, Default)]
+pub struct Priority(Arc<AtomicI32>);
+
+// incremented whenever a priority changes, queues re-heap their jobs when it differs
+// from the value they've seen last
+static PRIORITY_CHANGES: AtomicU64 = AtomicU64::new(0);
+
+impl Priority {
+    pub fn get(&self) -> i32 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, priority: i32) {
+        if self.0.swap(priority, Ordering::Relaxed) != priority {
+            PRIORITY_CHANGES.fetch_add(1, Ordering::Release);
+        }
+    }
+}
+
+struct QueuedJob {
+    // snapshot of `handle` the heap is ordered by
+    priority: i32,
+    // older jobs run first among jobs with the same priority
+    seq: u64,
+    handle: Priority,
+    job: Job,
+}
+
+impl Ord for QueuedJob {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for QueuedJob {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for QueuedJob {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == cmp::Ordering::Equal
+    }
+}
+
+impl Eq for QueuedJob {}
+
+#[derive(Default)]
+struct Queue {
+    jobs: BinaryHeap<QueuedJob>,
+    next_seq: u64,
+    // value of `PRIORITY_CHANGES` the priorities in `jobs` were taken at
+    seen_changes: u64,
+    is_closed: bool,
+}
+
+impl Queue {
+    fn push(&mut self, priority: Priority, job: Job) {
+        self.jobs.push(QueuedJob {
+            priority: priority.get(),
+            seq: self.next_seq,
+            handle: priority,
+            job,
+        });
+        self.next_seq += 1;
+    }
+
+    /// Removes the job with the highest priority, the oldest one if several have it.
+    fn pop(&mut self) -> Option<Job> {
+        let changes = PRIORITY_CHANGES.load(Ordering::Acquire);
+        if changes != self.seen_changes {
+            self.seen_changes = changes;
+            let mut jobs = mem::take(&mut self.jobs).into_vec();
+            for job in &mut jobs {
+                job.priority = job.handle.get();
+            }
+            self.jobs = BinaryHeap::from(jobs);
+        }
+        self.jobs.pop().map(|queued| queued.job)
+    }
+}
+
+type SharedQueue = Arc<(Mutex<Queue>, Condvar)>;
+
 pub struct WorkerPool {
     workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Job>>,
+    queue: SharedQueue,
 }
 
 impl WorkerPool {
     pub fn new(size: usize) -> Self {
         assert!(size > 0);
 
-        let (tx, rx) = mpsc::channel();
-        let rx = Arc::new(Mutex::new(rx));
+        let queue = SharedQueue::default();
 
         let mut workers = Vec::with_capacity(size);
 
         for id in 0..size {
-            workers.push(Worker::new(id, rx.clone()));
+            workers.push(Worker::new(id, queue.clone()));
         }
 
-        Self {
-            workers,
-            sender: Some(tx),
-        }
+        Self { workers, queue }
     }
 
-    pub fn execute<F>(&self, f: F)
+    pub fn execute<F>(&self, priority: Priority, f: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        let job = Box::new(f);
-        self.sender.as_ref().unwrap().send(job).unwrap();
+        let (queue, condvar) = &*self.queue;
+        queue.lock().unwrap().push(priority, Box::new(f));
+        condvar.notify_one();
     }
 }
 
 impl Drop for WorkerPool {
     fn drop(&mut self) {
-        drop(self.sender.take());
+        let (queue, condvar) = &*self.queue;
+        queue.lock().unwrap().is_closed = true;
+        condvar.notify_all();
 
         for worker in &mut self.workers {
             if let Some(thread) = worker.thread.take() {
@@ -56,14 +150,26 @@ struct Worker {
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Self {
+    fn new(id: usize, queue: SharedQueue) -> Self {
         let thread = thread::spawn(move || {
+            let (queue, condvar) = &*queue;
             loop {
-                let message = receiver.lock().unwrap().recv();
+                let mut guard = queue.lock().unwrap();
+                // queued jobs are finished before the pool shuts down
+                let job = loop {
+                    if let Some(job) = guard.pop() {
+                        break Some(job);
+                    }
+                    if guard.is_closed {
+                        break None;
+                    }
+                    guard = condvar.wait(guard).unwrap();
+                };
+                drop(guard);
 
-                match message {
-                    Ok(job) => job(),
-                    Err(_) => break,
+                match job {
+                    Some(job) => job(),
+                    None => break,
                 }
             }
         });
@@ -74,3 +180,31 @@ impl Worker {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_higher_priority_runs_first() {
+        let mut queue = Queue::default();
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let priorities: Vec<_> = (0..3).map(|_| Priority::default()).collect();
+        for (i, priority) in priorities.iter().enumerate() {
+            let order = order.clone();
+            let job: Job = Box::new(move || order.lock().unwrap().push(i));
+            queue.push(priority.clone(), job);
+        }
+        // priorities may change while the jobs are queued
+        priorities[2].set(1);
+
+        queue.pop().unwrap()();
+        // and after jobs have been taken from the queue
+        priorities[1].set(2);
+
+        while let Some(job) = queue.pop() {
+            job();
+        }
+        assert_eq!(*order.lock().unwrap(), [2, 1, 0]);
+    }
+}
@@ -0,0 +1,180 @@
+-- Stand-in for PoB's Launch.lua, run by the headless integration test in
+-- src/pob/integration_tests.rs. Errors fail the test.
+
+local launch = {}
+SetMainObject(launch)
+
+-- globals PoB relies on, checked before anything else so that a missing one is
+-- reported by name
+local REQUIRED_GLOBALS = {
+	"SetMainObject", "SetCallback", "GetCallback",
+	"GetUserPath", "GetScriptPath", "GetRuntimePath", "GetWorkDir", "SetWorkDir",
+	"MakeDir", "RemoveDir", "ConPrintf", "ConExecute", "ConClear",
+	"GetTime", "StripEscapes", "Exit", "Restart", "OpenURL", "RenderInit",
+	"Inflate", "Deflate", "Sha1", "Md5", "Base64Encode", "Base64Decode",
+	"NewFileSearch", "NewImageHandle", "Copy", "Paste",
+	"GetCursorPos", "IsKeyDown", "GetScreenSize", "GetScreenScale", "SetWindowTitle",
+	"PCall", "LoadModule", "PLoadModule",
+	"SetDrawLayer", "SetViewport", "SetDrawColor", "DrawImage", "DrawImageQuad",
+	"DrawString", "DrawStringWidth", "DrawStringCursorIndex", "GetAsyncCount",
+	"LaunchSubScript", "AbortSubScript", "IsSubScriptRunning", "IsOnline",
+}
+
+function launch:OnInit()
+	for _, name in ipairs(REQUIRED_GLOBALS) do
+		assert(type(_G[name]) == "function", "missing global " .. name)
+	end
+	RenderInit("DPI_AWARE")
+	-- the connectivity check isn't configured in tests
+	assert(IsOnline() == true and arg.offline == false)
+	SetWindowTitle("Integration test")
+	assert(CaptureRegion(0, 0, 100, 50, "capture.png") == true)
+	assert(CaptureRegion(0, 0, 0, 50, "capture.png") == nil)
+	assert(CaptureRegion(0, 0, 100, 50, "capture.png", 201, 100) == true)
+	assert(CaptureRegion(0, 0, 100, 50, "capture.png", 200, 200) == nil)
+	assert(CaptureRegion(0, 0, 100, 50, "capture.png", 100000, 50000) == nil)
+	assert(CaptureRegion(0, 0, 100, 50, "capture.unknown") == nil)
+	assert(ExportSVG("frame.svg") == true)
+	assert(ExportSVG("frame.svg", 0, 0, 100) == nil)
+
+	-- paths and files
+	local userPath = GetUserPath()
+	assert(MakeDir(userPath))
+	local file = userPath .. "/test.txt"
+	assert(WriteFile(file, "build"))
+	assert(ReadFile(file) == "build")
+	assert(RemoveFile(file))
+	assert(not FileExists(file))
+	assert(GetScriptPath() ~= "")
+
+	-- build management
+	local builds = userPath .. "/Builds"
+	assert(MakeDir(builds .. "/Old"))
+	assert(WriteFile(builds .. "/a.xml", "build"))
+	assert(RenamePath(builds .. "/a.xml", "b.xml"))
+	assert(MovePath(builds .. "/b.xml", builds .. "/Old/b.xml"))
+	assert(ReadFile(builds .. "/Old/b.xml") == "build")
+	assert(RemoveDir(builds, true))
+	assert(not RemoveDir(GetScriptPath()))
+	local opened, openError = OpenFolder(builds)
+	assert(opened == nil and type(openError) == "string")
+
+	-- compression and encoding, e.g. of build codes
+	local xml = string.rep("<Build level=\"90\"/>", 20)
+	assert(Inflate(Base64Decode(Base64Encode(Deflate(xml)))) == xml)
+	assert(Sha1("") == "da39a3ee5e6b4b0d3255bfef95601890afd80709")
+
+	-- character-based strings, e.g. of build names
+	assert(Utf8Len("Build 🔥 ビルド") == 11)
+	assert(select(2, Utf8Len("ab\xff")) == 3)
+	assert(Utf8Sub("Build 🔥 ビルド", 7, 7) == "🔥")
+	assert(Utf8Sub("Build 🔥 ビルド", -3) == "ビルド")
+	assert(Utf8Lower("ÄRGER ÉTÉ") == "ärger été")
+	assert(Utf8Upper("straße ä") == "STRASSE Ä")
+
+	-- callbacks
+	local called = false
+	SetCallback("OnTest", function()
+		called = true
+	end)
+	GetCallback("OnTest")()
+	assert(called)
+
+	assert(StripEscapes("^7Life ^xFF0000100") == "Life 100")
+	self.frames = 0
+	self.keys = {}
+	self.callbacks = {}
+end
+
+function launch:OnFrame()
+	self.frames = self.frames + 1
+	local width, height = GetScreenSize()
+	assert(width > 0 and height > 0)
+	SetDrawLayer(nil, 0)
+	SetDrawColor(1, 1, 1)
+	DrawImage(nil, 0, 0, 100, 20)
+	local image = NewImageHandle()
+	assert(image:AdvanceFrame() == 1 and image:GetFrameCount() == 0)
+	DrawString(0, 0, "LEFT", 16, "VAR", "^7Frame " .. self.frames)
+	assert(DrawStringWidth(16, "VAR", "Frame") > 0)
+	DrawImageQuad(nil, 0, 0, 10, 0, 10, 10, 0, 10)
+	DrawImageNineSlice(nil, 0, 0, 100, 50, 4, 4, 4, 4)
+	DrawPolyline({ 0, 0, 10, 10, 20, 0 }, 2)
+	assert(#DrawStringWidthMulti(16, "VAR", { "Life", "Mana" }) == 2)
+
+	-- invalid arguments raise errors instead of aborting the app
+	assert(not pcall(SetDrawColor, 1, 1))
+	assert(not pcall(SetDrawLayer, "top", 0))
+	assert(not pcall(DrawImage, {}, 0, 0, 100, 20))
+	local ok, err = pcall(DrawString, 0, 0, "MIDDLE", 16, "VAR", "text")
+	assert(not ok and err:find("invalid alignment MIDDLE"), err)
+	assert(not pcall(DrawStringWidth, 16, "COMIC SANS", "text"))
+	assert(not pcall(DrawStringWidthMulti, 16, "VAR", { 100 }))
+end
+
+function launch:OnKeyDown(key, doubleClick)
+	table.insert(self.keys, key)
+end
+
+function launch:OnKeyUp(key)
+	assert(self.keys[#self.keys] == key, "OnKeyUp without OnKeyDown")
+end
+
+function launch:OnChar(char)
+	-- keys arrive before the character they produce
+	assert(self.keys[#self.keys] == "a", "OnChar before OnKeyDown")
+	SetWindowTitle("Typed " .. char)
+end
+
+-- optional callbacks, see the module documentation of src/lua.rs
+local OPTIONAL_CALLBACKS = {
+	"OnIdle", "OnImageLoadFailed", "OnFileChanged", "OnOAuthRedirect",
+	"OnProcessExited", "OnClipboardChanged", "OnThemeChanged",
+}
+
+function launch:OnIdle(budget)
+	assert(type(budget) == "number" and budget > 0)
+	self.callbacks.OnIdle = true
+	-- no more work to do
+	return false
+end
+
+function launch:OnImageLoadFailed(path, err)
+	assert(type(path) == "string" and type(err) == "string")
+	self.callbacks.OnImageLoadFailed = true
+end
+
+function launch:OnFileChanged(path)
+	assert(type(path) == "string")
+	self.callbacks.OnFileChanged = true
+end
+
+function launch:OnOAuthRedirect(code, state, err)
+	-- either the code and state, or the error
+	assert((type(code) == "string" and type(state) == "string") or type(err) == "string")
+	self.callbacks.OnOAuthRedirect = true
+end
+
+function launch:OnProcessExited(id, code, err)
+	-- no code if the process was killed or couldn't be started
+	assert(type(id) == "number" and (code == nil or type(code) == "number"))
+	assert(err == nil or type(err) == "string")
+	self.callbacks.OnProcessExited = true
+end
+
+function launch:OnClipboardChanged(sequence)
+	assert(type(sequence) == "number")
+	self.callbacks.OnClipboardChanged = true
+end
+
+function launch:OnThemeChanged(theme)
+	assert(theme == "DARK" or theme == "LIGHT")
+	self.callbacks.OnThemeChanged = true
+end
+
+function launch:OnExit()
+	assert(self.frames > 0, "OnExit before OnFrame")
+	for _, name in ipairs(OPTIONAL_CALLBACKS) do
+		assert(self.callbacks[name], name .. " wasn't called")
+	end
+end